@@ -0,0 +1,111 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use age::armor::{ArmoredReader, ArmoredWriter, Format};
+use age::x25519;
+use serde::{Deserialize, Serialize};
+
+use crate::common::options;
+
+const ARMOR_HEADER: &str = "-----BEGIN AGE ENCRYPTED FILE-----";
+
+/// Plaintext of age encrypted keys files: the keys file and the recipients it is encrypted to, so a rewrite
+/// encrypts to the same recipients. The list is inside the encryption, a list next to it could be edited to
+/// have the next rewrite encrypt the share to someone else
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AgeContent {
+    recipients: Vec<String>,
+    keys_file: String,
+}
+
+// Recipients of the age encrypted keys file loaded last, empty for files of older versions that did not store them
+static LOADED_RECIPIENTS: Mutex<Option<Vec<String>>> = Mutex::new(None);
+
+/// Whether keys file content is an ASCII armored age file
+pub fn is_age_file(data: &str) -> bool {
+    data.trim_start().starts_with(ARMOR_HEADER)
+}
+
+/// Recipients from --age-recipient or of the loaded keys file, empty when keys files are not encrypted with age
+pub fn recipients() -> Vec<String> {
+    let given: Vec<String> = options::get()
+        .age_recipients
+        .iter()
+        .map(|recipient| recipient.trim().to_string())
+        .filter(|recipient| !recipient.is_empty())
+        .collect();
+    if !given.is_empty() {
+        return given;
+    }
+    match LOADED_RECIPIENTS.lock().unwrap().clone() {
+        Some(loaded) if loaded.is_empty() => panic!(
+            "Keys file is encrypted with age by an older tss_cli that did not store its recipients, \
+             give them with --age-recipient to save it"
+        ),
+        Some(loaded) => loaded,
+        None => Vec::new(),
+    }
+}
+
+fn parse_recipient(recipient: &str) -> x25519::Recipient {
+    x25519::Recipient::from_str(recipient).unwrap_or_else(|e| panic!("Invalid age recipient {}: {}", recipient, e))
+}
+
+// X25519 identities of an age identity file, comment and blank lines are skipped
+fn identities() -> Vec<x25519::Identity> {
+    let path = options::get().age_identity.expect("Keys file is encrypted with age, use --age-identity");
+    fs::read_to_string(&path)
+        .expect("Unable to read age identity file !")
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| x25519::Identity::from_str(line).unwrap_or_else(|e| panic!("Invalid age identity in {}: {}", path, e)))
+        .collect()
+}
+
+/// ASCII armored age file of a keys file, decryptable by any of the recipients
+pub fn encrypt(plaintext: &[u8], recipients: Vec<String>) -> String {
+    let content = AgeContent {
+        recipients: recipients.clone(),
+        keys_file: String::from_utf8(plaintext.to_vec()).expect("Keys file must be text"),
+    };
+    let recipients = recipients
+        .iter()
+        .map(|recipient| Box::new(parse_recipient(recipient)) as Box<dyn age::Recipient>)
+        .collect();
+    let mut encrypted = Vec::new();
+    let armor = ArmoredWriter::wrap_output(&mut encrypted, Format::AsciiArmor).expect("age failure!");
+    let mut writer = age::Encryptor::with_recipients(recipients).wrap_output(armor).expect("age failure!");
+    writer.write_all(serde_json::to_string(&content).unwrap().as_bytes()).expect("age failure!");
+    writer.finish().and_then(|armor| armor.finish()).expect("age failure!");
+    String::from_utf8(encrypted).unwrap()
+}
+
+/// Plaintext of an age encrypted keys file, decrypted with the identities of --age-identity.
+/// Its recipients are kept for saving the keys file again
+pub fn decrypt(data: &str) -> Vec<u8> {
+    let decryptor = match age::Decryptor::new(ArmoredReader::new(data.as_bytes())).expect("Invalid age encrypted keys file") {
+        age::Decryptor::Recipients(decryptor) => decryptor,
+        _ => panic!("Passphrase encrypted age files are not supported, encrypt keys files to X25519 recipients"),
+    };
+    let identities = identities();
+    let mut reader = decryptor
+        .decrypt(identities.iter().map(|identity| identity as &dyn age::Identity))
+        .expect("No age identity matches the keys file recipients");
+    let mut plaintext = Vec::new();
+    reader.read_to_end(&mut plaintext).expect("age encrypted keys file is corrupted");
+    match serde_json::from_slice::<AgeContent>(&plaintext) {
+        Ok(content) => {
+            *LOADED_RECIPIENTS.lock().unwrap() = Some(content.recipients);
+            content.keys_file.into_bytes()
+        }
+        // Written by an older version, the plaintext is the keys file itself
+        Err(_) => {
+            *LOADED_RECIPIENTS.lock().unwrap() = Some(Vec::new());
+            plaintext
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use curv::cryptographic_primitives::hashing::hash_sha256::HSha256;
+use curv::cryptographic_primitives::hashing::traits::Hash;
+use curv::elliptic::curves::secp256_k1::GE;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::common::encoding::{compressed_public_key, to_32_bytes};
+use crate::common::{new_client, options};
+
+/// Signing request sent to the approval service
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ApprovalRequest {
+    pub request_id: String,
+    pub public_key: String,
+    pub party_id: u16,
+    pub path: String,
+    pub message: String,
+    pub context: String,
+}
+
+/// Answer of the approval service, status is "approved", "rejected" or "pending".
+/// Decisions carry a secp256k1 signature (64 bytes r||s in hex) of the approval digest
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ApprovalResponse {
+    pub request_id: String,
+    pub status: String,
+    #[serde(default)]
+    pub expires_at: u64,
+    #[serde(default)]
+    pub signature: String,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ApprovalRecord {
+    pub request: ApprovalRequest,
+    pub response: ApprovalResponse,
+    pub timestamp: u64,
+}
+
+/// Approval service of a party: every signing request is posted to url and signed only when the answer
+/// is an approval signed by approval_key. Verified approvals are appended to log_path
+#[derive(Clone)]
+pub struct Approver {
+    pub url: String,
+    pub approval_key: String,
+    pub party_id: u16,
+    pub log_path: String,
+}
+
+/// Digest the approval service signs, binds the decision to every field of the request
+pub fn approval_digest(request: &ApprovalRequest, status: &str, expires_at: u64) -> Vec<u8> {
+    let data = format!(
+        "tss-cli-approval|{}|{}|{}|{}|{}|{}|{}|{}",
+        request.request_id,
+        status,
+        expires_at,
+        request.public_key,
+        request.party_id,
+        request.path,
+        request.message,
+        request.context,
+    );
+    to_32_bytes(&HSha256::create_hash_from_slice(data.as_bytes()))
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+/// Approver of --approval-url, None when no approval service is configured. Every signing entry point asks it
+/// before signing, approvals are logged next to log_base
+pub fn configured(party_id: u16, log_base: &str) -> Option<Approver> {
+    let options = options::get();
+    let approval_key = options.approval_key;
+    options.approval_url.map(|url| {
+        let approval_key = approval_key.expect("--approval-url needs --approval-key");
+        Approver::new(&url, &approval_key, party_id, log_base)
+    })
+}
+
+impl Approver {
+    pub fn new(url: &str, approval_key: &str, party_id: u16, log_base: &str) -> Self {
+        let approver = Approver {
+            url: url.to_string(),
+            approval_key: approval_key.to_string(),
+            party_id,
+            log_path: format!("{}.approvals.jsonl", log_base),
+        };
+        approver.verifying_key();
+        approver
+    }
+
+    /// New request with a fresh id, public_key is the key the signature will verify under
+    pub fn request(&self, public_key: &GE, path: &str, message: &[u8], context: &str) -> ApprovalRequest {
+        ApprovalRequest {
+            request_id: Uuid::new_v4().to_string(),
+            public_key: hex::encode(compressed_public_key(public_key)),
+            party_id: self.party_id,
+            path: path.to_string(),
+            message: hex::encode(message),
+            context: context.to_string(),
+        }
+    }
+
+    fn verifying_key(&self) -> secp256k1::PublicKey {
+        let raw_key = hex::decode(&self.approval_key).expect("Approval key must be in hex format");
+        secp256k1::PublicKey::parse_slice(&raw_key, None).expect("Invalid approval key")
+    }
+
+    fn verify(&self, request: &ApprovalRequest, response: &ApprovalResponse) -> bool {
+        let message = secp256k1::Message::parse_slice(&approval_digest(request, &response.status, response.expires_at)).unwrap();
+        match hex::decode(&response.signature).ok().and_then(|raw| secp256k1::Signature::parse_slice(&raw).ok()) {
+            Some(signature) => secp256k1::verify(&message, &signature, &self.verifying_key()),
+            None => false,
+        }
+    }
+
+    /// Posts the request and waits for a decision, polling while it is pending, for at most
+    /// --approval-timeout seconds. Panics unless the request is approved with a valid token
+    pub fn approve(&self, request: ApprovalRequest) -> ApprovalRecord {
+        let client = new_client();
+        let timeout = options::get().approval_timeout;
+        let started = Instant::now();
+        let response = loop {
+            let response: ApprovalResponse = client
+                .post(&self.url)
+                .json(&request)
+                .send()
+                .and_then(|res| res.error_for_status())
+                .and_then(|res| res.json())
+                .unwrap_or_else(|e| panic!("Approval service request failed: {}", e));
+            if response.request_id != request.request_id {
+                panic!("Approval service answered a different request: {}", response.request_id);
+            }
+            if response.status != "pending" {
+                break response;
+            }
+            if started.elapsed().as_secs() > timeout {
+                panic!("Approval of request {} timed out", request.request_id);
+            }
+            thread::sleep(Duration::from_secs(2));
+        };
+        if !self.verify(&request, &response) {
+            panic!("Approval token of request {} has an invalid signature", request.request_id);
+        }
+        match response.status.as_str() {
+            "approved" if response.expires_at < now() => panic!("Approval of request {} expired", request.request_id),
+            "approved" => {}
+            status => panic!("Signing request {} was not approved: {}", request.request_id, status),
+        }
+
+        let record = ApprovalRecord { request, response, timestamp: now() };
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.log_path)
+            .expect("Unable to open approval log !");
+        writeln!(file, "{}", serde_json::to_string(&record).unwrap()).expect("Unable to save approval log !");
+        record
+    }
+}
@@ -0,0 +1,48 @@
+use std::fs;
+
+use curv::{BigInt, arithmetic::Converter};
+use curv::cryptographic_primitives::hashing::hash_sha256::HSha256;
+use curv::cryptographic_primitives::hashing::traits::Hash;
+use curv::elliptic::curves::secp256_k1::GE;
+use curv::elliptic::curves::traits::ECScalar;
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2018::party_i::SignatureRecid;
+use serde_json::json;
+
+use crate::common::encoding::{der_signature, public_key_pem, to_32_bytes};
+
+/// sha256 digest of the file content, the value that gets signed
+pub fn artifact_digest(artifact_path: &str) -> Vec<u8> {
+    let content = fs::read(artifact_path).expect(
+        format!("Unable to read artifact at location: {}", artifact_path).as_str(),
+    );
+    to_32_bytes(&HSha256::create_hash_from_slice(&content))
+}
+
+/// Writes DER encoded detached signature and PEM public key next to the artifact,
+/// so the signature can be verified with stock openssl
+pub fn write_artifact_signature(artifact_path: &str, sig: &SignatureRecid, y_sum: &GE, context: &str) {
+    let signature_path = format!("{}.sig", artifact_path);
+    let public_key_path = format!("{}.pub.pem", artifact_path);
+
+    let signature = der_signature(&sig.r.to_big_int(), &sig.s.to_big_int());
+    fs::write(&signature_path, &signature).expect("Unable to save signature !");
+    fs::write(&public_key_path, public_key_pem(y_sum)).expect("Unable to save public key !");
+
+    let mut ret_dict = json!({
+        "status": "signature_ready",
+        "artifact": artifact_path,
+        "sha256": hex::encode(artifact_digest(artifact_path)),
+        "signature": signature_path,
+        "public_key": public_key_path,
+        "verify": format!(
+            "openssl dgst -sha256 -verify {} -signature {} {}",
+            public_key_path, signature_path, artifact_path
+        ),
+        "r": BigInt::to_hex(&sig.r.to_big_int()),
+        "s": BigInt::to_hex(&sig.s.to_big_int()),
+    });
+    if !context.is_empty() {
+        ret_dict["context"] = json!(context);
+    }
+    println!("{}", ret_dict.to_string());
+}
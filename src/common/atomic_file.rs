@@ -0,0 +1,45 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Writes data to path so that readers and crashes only ever see the old or the new content:
+/// a temporary file next to it is written and synced, renamed over path, then the directory is synced
+pub fn write_atomic<P: AsRef<Path>>(path: P, data: &[u8]) {
+    let path = path.as_ref();
+    let tmp_path = format!("{}.{}.tmp", path.display(), std::process::id());
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    // Keys files and presignatures hold secrets, the temporary file is private from the start
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(&tmp_path).unwrap_or_else(|e| panic!("Unable to save {}: {}", path.display(), e));
+    file.write_all(data).unwrap_or_else(|e| panic!("Unable to save {}: {}", path.display(), e));
+    file.sync_all().unwrap_or_else(|e| panic!("Unable to save {}: {}", path.display(), e));
+    fs::rename(&tmp_path, path).unwrap_or_else(|e| panic!("Unable to save {}: {}", path.display(), e));
+    // Makes the rename itself durable, directories can't be opened on every platform
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    if let Ok(dir) = File::open(dir) {
+        dir.sync_all().ok();
+    }
+}
+
+/// Copies path to <path>.<unix time>.bak before it is overwritten, returns the backup path.
+/// Nothing is copied when path does not exist yet
+pub fn backup<P: AsRef<Path>>(path: P) -> Option<String> {
+    let path = path.as_ref();
+    if !path.exists() {
+        return None;
+    }
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let mut backup_path = format!("{}.{}.bak", path.display(), timestamp);
+    let mut n = 1;
+    while Path::new(&backup_path).exists() {
+        backup_path = format!("{}.{}-{}.bak", path.display(), timestamp, n);
+        n += 1;
+    }
+    write_atomic(&backup_path, &fs::read(path).unwrap_or_else(|e| panic!("Unable to back up {}: {}", path.display(), e)));
+    Some(backup_path)
+}
@@ -0,0 +1,138 @@
+use std::{thread, time};
+use std::time::{Duration, Instant};
+
+use reqwest::blocking::Client;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::common::{new_client, postb, Entry, Index, ManagerError, ResponseError};
+
+/// Latencies of all manager requests made by one simulated party
+struct PartyStats {
+    set_latencies: Vec<Duration>,
+    get_latencies: Vec<Duration>,
+    failed: usize,
+}
+
+fn timed_post<T>(addr: &String, client: &Client, path: &str, body: T) -> (Result<String, ResponseError>, Duration)
+where
+    T: serde::ser::Serialize,
+{
+    let start_time = Instant::now();
+    let res_body = postb(addr, client, path, body);
+    (res_body, start_time.elapsed())
+}
+
+// One party of a fake room: every round broadcasts a payload and waits for all other parties' payloads,
+// the same set/get pattern the signing rounds produce
+fn run_party(addr: String, room_uuid: String, party_num: u16, parties: u16, rounds: u16, payload: String) -> PartyStats {
+    let client = new_client();
+    let delay = time::Duration::from_millis(25);
+    let mut stats = PartyStats {
+        set_latencies: Vec::new(),
+        get_latencies: Vec::new(),
+        failed: 0,
+    };
+    for round in 0..rounds {
+        let entry = Entry {
+            key: format!("{}-bench{}-{}", party_num, round, room_uuid),
+            value: payload.clone(),
+        };
+        let (res_body, latency) = timed_post(&addr, &client, "set", entry);
+        stats.set_latencies.push(latency);
+        if res_body.is_err() {
+            stats.failed += 1;
+        }
+
+        for i in (1..=parties).filter(|i| *i != party_num) {
+            let index = Index {
+                key: format!("{}-bench{}-{}", i, round, room_uuid),
+            };
+            let start_time = Instant::now();
+            loop {
+                let (res_body, latency) = timed_post(&addr, &client, "get", index.clone());
+                stats.get_latencies.push(latency);
+                let found = match res_body {
+                    Ok(res_body) => matches!(serde_json::from_str::<Result<Entry, ManagerError>>(&res_body), Ok(Ok(_))),
+                    Err(_) => {
+                        stats.failed += 1;
+                        false
+                    }
+                };
+                if found {
+                    break;
+                }
+                if start_time.elapsed().as_secs() > 30 {
+                    stats.failed += 1;
+                    break;
+                }
+                thread::sleep(delay);
+            }
+        }
+    }
+    stats
+}
+
+fn percentile_ms(sorted: &Vec<Duration>, percentile: usize) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let index = ((sorted.len() - 1) * percentile) / 100;
+    sorted[index].as_secs_f64() * 1000.0
+}
+
+fn latency_json(mut latencies: Vec<Duration>) -> serde_json::Value {
+    latencies.sort();
+    json!({
+        "count": latencies.len(),
+        "p50_ms": percentile_ms(&latencies, 50),
+        "p90_ms": percentile_ms(&latencies, 90),
+        "p99_ms": percentile_ms(&latencies, 99),
+        "max_ms": percentile_ms(&latencies, 100),
+    })
+}
+
+/// Simulates `rooms` concurrent rooms of `parties` parties against the manager and reports
+/// request throughput and latency percentiles
+pub fn run_bench(addr: &String, rooms: u16, parties: u16, rounds: u16, payload_size: usize) {
+    let payload = "0".repeat(payload_size);
+    let start_time = Instant::now();
+    let mut handles = Vec::new();
+    for _room in 0..rooms {
+        let room_uuid = Uuid::new_v4().to_string();
+        for party_num in 1..=parties {
+            let addr = addr.clone();
+            let room_uuid = room_uuid.clone();
+            let payload = payload.clone();
+            handles.push(thread::spawn(move || run_party(addr, room_uuid, party_num, parties, rounds, payload)));
+        }
+    }
+
+    let mut set_latencies = Vec::new();
+    let mut get_latencies = Vec::new();
+    let mut failed = 0;
+    for handle in handles {
+        let stats = handle.join().expect("Bench party thread panicked");
+        set_latencies.extend(stats.set_latencies);
+        get_latencies.extend(stats.get_latencies);
+        failed += stats.failed;
+    }
+    let elapsed = start_time.elapsed().as_secs_f64();
+    let requests = set_latencies.len() + get_latencies.len();
+
+    let ret_dict = json!({
+        "status": "bench_done",
+        "manager_addr": addr,
+        "rooms": rooms,
+        "parties": parties,
+        "rounds": rounds,
+        "payload_size": payload_size,
+        "elapsed_s": elapsed,
+        "requests": requests,
+        "failed": failed,
+        "requests_per_s": requests as f64 / elapsed,
+        "set": latency_json(set_latencies),
+        "get": latency_json(get_latencies),
+    });
+    println!("{}", serde_json::to_string_pretty(&ret_dict).unwrap());
+}
@@ -0,0 +1,143 @@
+use curv::elliptic::curves::secp256_k1::GE;
+use ring::digest::{digest, SHA256};
+use ripemd160::{Digest, Ripemd160};
+
+use crate::common::encoding::compressed_public_key;
+
+// No p2tr: a taproot output is spent with a BIP340 Schnorr signature, which threshold ECDSA can't produce
+pub const ADDRESS_TYPES: [&str; 2] = ["p2pkh", "p2wpkh"];
+pub const NETWORKS: [&str; 3] = ["mainnet", "testnet", "regtest"];
+
+const BASE58_ALPHABET: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+const BECH32_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+// Checksum constants of BIP173 bech32 (witness v0) and BIP350 bech32m (witness v1+)
+const BECH32_CONST: u32 = 1;
+const BECH32M_CONST: u32 = 0x2bc8_30a3;
+
+fn sha256(data: &[u8]) -> Vec<u8> {
+    digest(&SHA256, data).as_ref().to_vec()
+}
+
+/// ripemd160(sha256(data)), the hash of a key in P2PKH and P2WPKH outputs
+pub fn hash160(data: &[u8]) -> Vec<u8> {
+    Ripemd160::digest(&sha256(data)).to_vec()
+}
+
+/// First 4 bytes of sha256(sha256(payload)), the checksum of base58check
+pub fn checksum(payload: &[u8]) -> Vec<u8> {
+    sha256(&sha256(payload))[..4].to_vec()
+}
+
+/// Base58 of payload || checksum(payload)
+pub fn base58check(payload: &[u8]) -> String {
+    base58_encode(&[payload, &checksum(payload)].concat(), BASE58_ALPHABET)
+}
+
+/// Base58 in the given alphabet, leading zero bytes become the first character of the alphabet
+pub fn base58_encode(data: &[u8], alphabet: &[u8]) -> String {
+    // Digits in base 58, least significant first
+    let mut digits: Vec<u8> = Vec::new();
+    for byte in data.iter() {
+        let mut carry = *byte as u32;
+        for digit in digits.iter_mut() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+    let zeros = data.iter().take_while(|byte| **byte == 0).count();
+    std::iter::repeat(alphabet[0] as char)
+        .take(zeros)
+        .chain(digits.iter().rev().map(|digit| alphabet[*digit as usize] as char))
+        .collect()
+}
+
+/// Bytes of base58 text in the given alphabet, None for characters outside it
+pub fn base58_decode(text: &str, alphabet: &[u8]) -> Option<Vec<u8>> {
+    // Bytes, least significant first
+    let mut bytes: Vec<u8> = Vec::new();
+    for c in text.bytes() {
+        let mut carry = alphabet.iter().position(|a| *a == c)? as u32;
+        for byte in bytes.iter_mut() {
+            carry += (*byte as u32) * 58;
+            *byte = (carry & 0xff) as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push((carry & 0xff) as u8);
+            carry >>= 8;
+        }
+    }
+    let zeros = text.bytes().take_while(|c| *c == alphabet[0]).count();
+    Some(std::iter::repeat(0u8).take(zeros).chain(bytes.into_iter().rev()).collect())
+}
+
+fn bech32_polymod(values: &[u8]) -> u32 {
+    const GENERATOR: [u32; 5] = [0x3b6a_57b2, 0x2650_8e6d, 0x1ea1_19fa, 0x3d42_33dd, 0x2a14_62b3];
+    values.iter().fold(1u32, |chk, value| {
+        let top = chk >> 25;
+        let chk = ((chk & 0x1ff_ffff) << 5) ^ *value as u32;
+        (0..5).filter(|i| (top >> i) & 1 == 1).fold(chk, |chk, i| chk ^ GENERATOR[i])
+    })
+}
+
+// Regroups bits, e.g. bytes into the 5 bit groups of bech32, padding the last group with zeros
+pub fn convert_bits(data: &[u8], from: u32, to: u32) -> Vec<u8> {
+    let (mut acc, mut bits) = (0u32, 0u32);
+    let mut out = Vec::new();
+    for value in data {
+        acc = (acc << from) | *value as u32;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            out.push(((acc >> bits) & ((1 << to) - 1)) as u8);
+        }
+    }
+    if bits > 0 {
+        out.push(((acc << (to - bits)) & ((1 << to) - 1)) as u8);
+    }
+    out
+}
+
+/// Bech32 string of 5 bit data, bech32m when m is set
+pub fn bech32_encode(hrp: &str, data: &[u8], m: bool) -> String {
+    let mut values: Vec<u8> = hrp.bytes().map(|c| c >> 5).collect();
+    values.push(0);
+    values.extend(hrp.bytes().map(|c| c & 31));
+    values.extend(data);
+    values.extend(&[0u8; 6]);
+    let polymod = bech32_polymod(&values) ^ if m { BECH32M_CONST } else { BECH32_CONST };
+    let checksum = (0..6).map(|i| ((polymod >> (5 * (5 - i))) & 31) as u8);
+    let encoded: String = data.iter().cloned().chain(checksum).map(|value| BECH32_CHARSET[value as usize] as char).collect();
+    format!("{}1{}", hrp, encoded)
+}
+
+/// Segwit address of a witness program, BIP173 for version 0 and BIP350 for later versions
+pub fn segwit_address(hrp: &str, version: u8, program: &[u8]) -> String {
+    let data = [vec![version], convert_bits(program, 8, 5)].concat();
+    bech32_encode(hrp, &data, version > 0)
+}
+
+// (P2PKH version byte, segwit human readable part)
+fn network_params(network: &str) -> (u8, &'static str) {
+    match network {
+        "mainnet" => (0x00, "bc"),
+        "testnet" => (0x6f, "tb"),
+        "regtest" => (0x6f, "bcrt"),
+        _ => panic!("Unknown Bitcoin network {}, supported: {}", network, NETWORKS.join(", ")),
+    }
+}
+
+/// Address of a public key: P2PKH and P2WPKH of the compressed key
+pub fn address(y_sum: &GE, address_type: &str, network: &str) -> String {
+    let (p2pkh_version, hrp) = network_params(network);
+    match address_type {
+        "p2pkh" => base58check(&[vec![p2pkh_version], hash160(&compressed_public_key(y_sum))].concat()),
+        "p2wpkh" => segwit_address(hrp, 0, &hash160(&compressed_public_key(y_sum))),
+        _ => panic!("Unknown address type {}, supported: {}", address_type, ADDRESS_TYPES.join(", ")),
+    }
+}
@@ -0,0 +1,67 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use curv::{BigInt, arithmetic::Converter};
+use curv::elliptic::curves::secp256_k1::GE;
+use curv::elliptic::curves::traits::ECPoint;
+use curv::cryptographic_primitives::hashing::hash_sha256::HSha256;
+use curv::cryptographic_primitives::hashing::traits::Hash;
+use serde_json::json;
+
+use crate::common::{check_sig, hd_keys, signer, Params};
+use crate::common::keyfile::{CanaryResult, KeyFile};
+
+/// Dedicated derivation path for canary signatures, 1952805748 is "test" read as u32
+pub const CANARY_PATH: &str = "1952805748/0";
+
+// Fixed per key, so concurrent canaries of different keys never share a signing room
+fn canary_message(y_sum: &GE) -> Vec<u8> {
+    let public_key = BigInt::to_bytes(&y_sum.bytes_compressed_to_big_int());
+    let message = [b"tss-cli canary".to_vec(), public_key].concat();
+    BigInt::to_bytes(&HSha256::create_hash_from_slice(&message))
+}
+
+/// Signs a fixed message at CANARY_PATH with all parties and verifies the signature. room is the
+/// uuid of the ceremony that made the share, so a rerun never joins the signing room of an earlier one
+pub fn canary_signature(addr: &String, keyfile: &KeyFile, params: &Params, room: &str) -> CanaryResult {
+    let parties = params.parties.parse::<u16>().unwrap();
+    let message = canary_message(&keyfile.y_sum);
+    let path_vector: Vec<BigInt> = hd_keys::parse_path(CANARY_PATH).unwrap();
+    let (y_child, f_l_new) = hd_keys::get_hd_key(&keyfile.y_sum, path_vector);
+
+    let mut vss_scheme_vec = keyfile.vss_scheme_vec.clone();
+    let sig = signer::sign(
+        addr.clone(),
+        keyfile.party_keys.clone(),
+        keyfile.shared_keys.clone(),
+        keyfile.party_id,
+        &mut vss_scheme_vec,
+        keyfile.paillier_key_vec.clone(),
+        &y_child,
+        params,
+        &message,
+        &f_l_new,
+        true,
+        parties,
+        &format!("canary-{}", room),
+    );
+    check_sig(&sig.r, &sig.s, &BigInt::from_bytes(&message), &y_child);
+
+    let mut record = signer::signature_json(&sig, &y_child, &message, "");
+    record["status"] = json!("canary_verified");
+    record["path"] = json!(CANARY_PATH);
+    record["timestamp"] = json!(SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs());
+    println!("{}", record.to_string());
+    CanaryResult {
+        path: CANARY_PATH.to_string(),
+        r: record["r"].as_str().unwrap().to_string(),
+        s: record["s"].as_str().unwrap().to_string(),
+        recid: sig.recid,
+        timestamp: record["timestamp"].as_u64().unwrap(),
+    }
+}
+
+/// Runs the canary with a saved keys file and records the result in its metadata
+pub fn run_canary(addr: &String, keysfile_path: &String, keyfile: KeyFile, params: &Params, room: &str) {
+    let canary = canary_signature(addr, &keyfile, params, room);
+    KeyFile { canary: Some(canary), ..keyfile }.replace(keysfile_path);
+}
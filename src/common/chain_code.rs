@@ -0,0 +1,185 @@
+use std::fs;
+use std::num::NonZeroU32;
+use std::sync::Mutex;
+
+use curv::elliptic::curves::secp256_k1::{FE, GE};
+use curv::elliptic::curves::traits::{ECPoint, ECScalar};
+use curv::{arithmetic::traits::Converter, BigInt};
+use ring::digest::{digest, SHA256};
+use ring::{hmac, pbkdf2};
+
+use crate::common::key_encryption;
+use crate::common::options;
+
+const BIP39_ITERATIONS: u32 = 2048;
+const MNEMONIC_WORD_COUNTS: [usize; 5] = [12, 15, 18, 21, 24];
+// BIP39 English wordlist, one word per line in index order
+const BIP39_ENGLISH: &str = include_str!("bip39_english.txt");
+
+/// Checks a mnemonic is BIP39: 12 to 24 words of the English wordlist whose last bits are the checksum of the
+/// entropy, the first bits of its sha256. A typo or a word from another list fails here instead of giving another
+/// chain code
+pub fn check_mnemonic(words: &[&str]) -> Result<(), String> {
+    if !MNEMONIC_WORD_COUNTS.contains(&words.len()) {
+        return Err(format!("Mnemonic has {} words, BIP39 mnemonics have 12, 15, 18, 21 or 24", words.len()));
+    }
+    let wordlist: Vec<&str> = BIP39_ENGLISH.lines().collect();
+    let mut bits: Vec<bool> = Vec::new();
+    for (position, word) in words.iter().enumerate() {
+        let index = wordlist
+            .binary_search(word)
+            .map_err(|_| format!("Word {} of the mnemonic, {}, is not in the BIP39 English wordlist", position + 1, word))?;
+        bits.extend((0..11).rev().map(|bit| index >> bit & 1 == 1));
+    }
+    // 11 bits per word, one checksum bit per 32 bits of entropy
+    let checksum_bits = bits.len() / 33;
+    let (entropy_bits, checksum) = bits.split_at(bits.len() - checksum_bits);
+    let entropy: Vec<u8> = entropy_bits
+        .chunks(8)
+        .map(|byte| byte.iter().fold(0u8, |acc, bit| acc << 1 | *bit as u8))
+        .collect();
+    let hash = digest(&SHA256, &entropy);
+    let expected = (0..checksum_bits).map(|i| hash.as_ref()[i / 8] >> (7 - i % 8) & 1 == 1);
+    if !expected.eq(checksum.iter().copied()) {
+        return Err("Mnemonic checksum is wrong, a word is misspelled or out of order".to_string());
+    }
+    Ok(())
+}
+
+/// BIP32 master chain code of a BIP39 mnemonic and passphrase: the right half of
+/// HMAC-SHA512("Bitcoin seed", PBKDF2-HMAC-SHA512(mnemonic, "mnemonic" || passphrase, 2048))
+pub fn from_mnemonic(mnemonic: &str, passphrase: &str) -> Vec<u8> {
+    // NFKD normalization is the identity on ASCII, other input would need it
+    if !mnemonic.is_ascii() || !passphrase.is_ascii() {
+        panic!("Only ASCII mnemonics and passphrases are supported");
+    }
+    let words: Vec<&str> = mnemonic.split_whitespace().collect();
+    check_mnemonic(&words).unwrap_or_else(|e| panic!("{}", e));
+    let mut seed = [0u8; 64];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA512,
+        NonZeroU32::new(BIP39_ITERATIONS).unwrap(),
+        format!("mnemonic{}", passphrase).as_bytes(),
+        words.join(" ").as_bytes(),
+        &mut seed,
+    );
+    let master = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA512, b"Bitcoin seed"), &seed);
+    master.as_ref()[32..].to_vec()
+}
+
+// Chain code of the last (mnemonic file, passphrase file), PBKDF2 is too slow to repeat for every derived path
+static MNEMONIC_CHAIN_CODE: Mutex<Option<((String, Option<String>), Vec<u8>)>> = Mutex::new(None);
+
+// Chain code stored in the last loaded keys file, set by KeyFile::load
+static STORED_CHAIN_CODE: Mutex<Option<Vec<u8>>> = Mutex::new(None);
+
+/// Records the chain code of a loaded keys file, derivations use it unless another one is given
+pub fn set_stored(chain_code: Option<Vec<u8>>) {
+    *STORED_CHAIN_CODE.lock().unwrap() = chain_code;
+}
+
+/// Chain code of this run: the one stored in the loaded keys file, or the one given with --chain-code or
+/// --chain-code-mnemonic for keygen and keys files from before v6. None for the default.
+/// A given chain code that differs from the stored one fails, it would derive other keys and addresses
+pub fn chain_code() -> Option<Vec<u8>> {
+    let stored = STORED_CHAIN_CODE.lock().unwrap().clone();
+    match (stored, configured_chain_code().unwrap_or_else(|e| e.exit())) {
+        (Some(stored), Some(configured)) if stored != configured => clap::Error::with_description(
+            &format!(
+                "Chain code {} given does not match chain code {} of the keys file",
+                fingerprint(&configured),
+                fingerprint(&stored)
+            ),
+            clap::ErrorKind::InvalidValue,
+        )
+        .exit(),
+        (Some(stored), _) => Some(stored),
+        (None, configured) => configured,
+    }
+}
+
+/// Checks --chain-code and --chain-code-mnemonic before a command runs, so a malformed chain code is reported
+/// like any other invalid argument instead of failing halfway through the command
+pub fn check_configured() -> Result<(), clap::Error> {
+    configured_chain_code().map(|_| ())
+}
+
+fn invalid_chain_code(description: String) -> clap::Error {
+    clap::Error::with_description(&description, clap::ErrorKind::InvalidValue)
+}
+
+// Chain code of --chain-code or the mnemonic file of --chain-code-mnemonic (with --mnemonic-passphrase-file)
+fn configured_chain_code() -> Result<Option<Vec<u8>>, clap::Error> {
+    let options = options::get();
+    if let Some(chain_code) = options.chain_code {
+        if options.chain_code_mnemonic.is_some() {
+            return Err(clap::Error::with_description(
+                "Give the chain code with --chain-code or --chain-code-mnemonic, not both",
+                clap::ErrorKind::ArgumentConflict,
+            ));
+        }
+        let chain_code = hex::decode(chain_code.trim())
+            .map_err(|_| invalid_chain_code(format!("Chain code {} must be in hex format", chain_code.trim())))?;
+        if chain_code.len() != 32 {
+            return Err(invalid_chain_code(format!(
+                "Chain code is {} bytes, chain codes are 32 bytes",
+                chain_code.len()
+            )));
+        }
+        return Ok(Some(chain_code));
+    }
+    let files = match options.chain_code_mnemonic {
+        Some(mnemonic_file) => (mnemonic_file, options.mnemonic_passphrase_file),
+        None => return Ok(None),
+    };
+    let mut cached = MNEMONIC_CHAIN_CODE.lock().unwrap();
+    match cached.as_ref() {
+        Some((cached_files, chain_code)) if *cached_files == files => Ok(Some(chain_code.clone())),
+        _ => {
+            let mnemonic = fs::read_to_string(&files.0).map_err(|e| {
+                invalid_chain_code(format!("Unable to read chain code mnemonic {}: {}", files.0, e))
+            })?;
+            check_mnemonic(&mnemonic.split_whitespace().collect::<Vec<&str>>()).map_err(invalid_chain_code)?;
+            let passphrase = files.1.as_ref().map_or(String::new(), |path| key_encryption::read_passphrase_file(path));
+            if !mnemonic.is_ascii() || !passphrase.is_ascii() {
+                return Err(invalid_chain_code("Only ASCII mnemonics and passphrases are supported".to_string()));
+            }
+            let chain_code = from_mnemonic(&mnemonic, &passphrase);
+            *cached = Some((files, chain_code.clone()));
+            Ok(Some(chain_code))
+        }
+    }
+}
+
+/// Chain code point hd_keys derives with: c*G for a configured chain code c, the generator by default
+pub fn chain_code_point() -> GE {
+    point(chain_code().as_deref())
+}
+
+/// c*G for the chain code c, the generator for the default chain code
+pub fn point(chain_code: Option<&[u8]>) -> GE {
+    let g: GE = ECPoint::generator();
+    match chain_code {
+        Some(chain_code) => {
+            let c: FE = ECScalar::from(&BigInt::from_bytes(chain_code));
+            if c.to_big_int() == BigInt::from(0) {
+                panic!("Chain code is zero modulo the group order, use another one");
+            }
+            g * &c
+        }
+        None => g,
+    }
+}
+
+/// Keygen commitment of a party to the chain code of this run: sha256 of the keygen uuid, the party number
+/// and the chain code (empty for the default). Parties recompute the commitments of the others with their own
+/// chain code, the chain code itself never leaves a party and a commitment can't be copied from another party
+pub fn commitment(chain_code: Option<&[u8]>, uuid: &str, party_num: u16) -> String {
+    let data = [uuid.as_bytes(), &party_num.to_be_bytes(), chain_code.unwrap_or(&[])].concat();
+    hex::encode(digest(&SHA256, &data))
+}
+
+/// First 8 bytes of sha256 of a chain code in hex, for parties to compare chain codes without revealing them
+pub fn fingerprint(chain_code: &[u8]) -> String {
+    hex::encode(&digest(&SHA256, chain_code).as_ref()[..8])
+}
@@ -0,0 +1,46 @@
+use curv::elliptic::curves::traits::ECScalar;
+use serde_json::json;
+
+use crate::common::keyfile::KeyFile;
+
+/// Compares non-secret structure of two keys files. Secret shares are only compared
+/// for equality, their values never end up in the output.
+pub fn compare_keys(first_path: &str, second_path: &str) {
+    let first = KeyFile::load(first_path);
+    let second = KeyFile::load(second_path);
+
+    let same_public_key = first.y_sum == second.y_sum;
+    let same_party_id = first.party_id == second.party_id;
+    let same_vss_commitments = serde_json::to_string(&first.vss_scheme_vec).unwrap()
+        == serde_json::to_string(&second.vss_scheme_vec).unwrap();
+    let same_paillier_keys = serde_json::to_string(&first.paillier_key_vec).unwrap()
+        == serde_json::to_string(&second.paillier_key_vec).unwrap();
+    let same_share = first.shared_keys.x_i.to_big_int() == second.shared_keys.x_i.to_big_int()
+        && first.party_keys.party_index == second.party_keys.party_index;
+
+    let mut differences: Vec<&str> = Vec::new();
+    if !same_public_key {
+        differences.push("public_key");
+    }
+    if !same_party_id {
+        differences.push("party_id");
+    }
+    if !same_vss_commitments {
+        differences.push("vss_commitments");
+    }
+    if !same_paillier_keys {
+        differences.push("paillier_keys");
+    }
+    if !same_share {
+        differences.push("share");
+    }
+
+    let ret_dict = json!({
+        "status": if differences.is_empty() { "match" } else { "mismatch" },
+        "same_key": same_public_key && same_vss_commitments,
+        "same_share": differences.is_empty(),
+        "party_id": [first.party_id, second.party_id],
+        "differences": differences,
+    });
+    println!("{}", ret_dict.to_string());
+}
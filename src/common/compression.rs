@@ -0,0 +1,62 @@
+#[cfg(feature = "manager")]
+use std::io::{Cursor, Write};
+
+#[cfg(feature = "manager")]
+use flate2::write::GzEncoder;
+#[cfg(feature = "manager")]
+use flate2::Compression;
+#[cfg(feature = "manager")]
+use rocket::fairing::{Fairing, Info, Kind};
+#[cfg(feature = "manager")]
+use rocket::http::Header;
+#[cfg(feature = "manager")]
+use rocket::{Request, Response};
+
+/// Bodies smaller than this are sent as is, gzip would not make them smaller
+#[cfg(feature = "manager")]
+const MIN_COMPRESSED_SIZE: usize = 1024;
+
+/// Whether HTTP compression is enabled for the given switch variable, on unless set to 0
+#[cfg(feature = "manager")]
+pub fn compression_enabled(env_var: &str) -> bool {
+    std::env::var(env_var).map(|value| value != "0").unwrap_or(true)
+}
+
+/// Gzip compresses manager responses for clients sending Accept-Encoding: gzip
+#[cfg(feature = "manager")]
+pub struct Gzip;
+
+#[cfg(feature = "manager")]
+#[rocket::async_trait]
+impl Fairing for Gzip {
+    fn info(&self) -> Info {
+        Info {
+            name: "Gzip response compression",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let accepts_gzip = request
+            .headers()
+            .get("Accept-Encoding")
+            .any(|value| value.split(',').any(|encoding| encoding.trim().starts_with("gzip")));
+        if !accepts_gzip || response.headers().contains("Content-Encoding") {
+            return;
+        }
+        let body = match response.body_mut().to_bytes().await {
+            Ok(body) => body,
+            Err(_) => return,
+        };
+        response.set_header(Header::new("Vary", "Accept-Encoding"));
+        if body.len() < MIN_COMPRESSED_SIZE {
+            response.set_sized_body(body.len(), Cursor::new(body));
+            return;
+        }
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&body).expect("gzip failure!");
+        let compressed = encoder.finish().expect("gzip failure!");
+        response.set_header(Header::new("Content-Encoding", "gzip"));
+        response.set_sized_body(compressed.len(), Cursor::new(compressed));
+    }
+}
@@ -0,0 +1,146 @@
+use std::fs;
+
+use curv::elliptic::curves::traits::ECScalar;
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2018::party_i::SignatureRecid;
+use ring::digest::{digest, SHA256};
+use serde_json::{json, Value};
+
+use crate::common::encoding::to_32_bytes;
+
+/// COSE algorithm ES256K, ECDSA over secp256k1 with sha256 (RFC 8812)
+const ALG_ES256K: i64 = -47;
+const HEADER_ALG: &str = "1";
+const TAG_COSE_SIGN1: u64 = 18;
+
+// CBOR major types
+const UNSIGNED: u8 = 0;
+const NEGATIVE: u8 = 1;
+const BYTES: u8 = 2;
+const TEXT: u8 = 3;
+const ARRAY: u8 = 4;
+const MAP: u8 = 5;
+const TAG: u8 = 6;
+
+fn cbor_head(major: u8, n: u64) -> Vec<u8> {
+    match n {
+        0..=23 => vec![major << 5 | n as u8],
+        24..=0xff => vec![major << 5 | 24, n as u8],
+        0x100..=0xffff => [vec![major << 5 | 25], (n as u16).to_be_bytes().to_vec()].concat(),
+        0x1_0000..=0xffff_ffff => [vec![major << 5 | 26], (n as u32).to_be_bytes().to_vec()].concat(),
+        _ => [vec![major << 5 | 27], n.to_be_bytes().to_vec()].concat(),
+    }
+}
+
+fn cbor_int(n: i64) -> Vec<u8> {
+    match n >= 0 {
+        true => cbor_head(UNSIGNED, n as u64),
+        false => cbor_head(NEGATIVE, (-1 - n) as u64),
+    }
+}
+
+fn cbor_bytes(bytes: &[u8]) -> Vec<u8> {
+    [cbor_head(BYTES, bytes.len() as u64), bytes.to_vec()].concat()
+}
+
+fn cbor_array(items: &[Vec<u8>]) -> Vec<u8> {
+    [cbor_head(ARRAY, items.len() as u64), items.concat()].concat()
+}
+
+// Header label: integer labels are written as JSON object keys, e.g. "1" for alg and "4" for kid
+fn cbor_label(label: &str) -> Vec<u8> {
+    match label.parse::<i64>() {
+        Ok(n) => cbor_int(n),
+        Err(_) => [cbor_head(TEXT, label.len() as u64), label.as_bytes().to_vec()].concat(),
+    }
+}
+
+/// Deterministic CBOR of a JSON header value: integers, text, booleans, arrays and maps, map keys sorted
+/// bytewise. A string "h'<hex>'" is a byte string, as kid values are
+fn cbor(value: &Value) -> Vec<u8> {
+    match value {
+        Value::Number(n) => cbor_int(n.as_i64().unwrap_or_else(|| panic!("COSE header value {} is not an integer", n))),
+        Value::String(text) => match text.strip_prefix("h'").and_then(|rest| rest.strip_suffix('\'')) {
+            Some(hex) => cbor_bytes(&hex::decode(hex).unwrap_or_else(|_| panic!("COSE header value {} is not valid hex", text))),
+            None => [cbor_head(TEXT, text.len() as u64), text.as_bytes().to_vec()].concat(),
+        },
+        Value::Bool(b) => vec![if *b { 0xf5 } else { 0xf4 }],
+        Value::Array(items) => cbor_array(&items.iter().map(cbor).collect::<Vec<_>>()),
+        Value::Object(fields) => {
+            let mut entries: Vec<(Vec<u8>, Vec<u8>)> = fields.iter().map(|(label, value)| (cbor_label(label), cbor(value))).collect();
+            entries.sort();
+            let entries: Vec<u8> = entries.into_iter().flat_map(|(label, value)| [label, value].concat()).collect();
+            [cbor_head(MAP, fields.len() as u64), entries].concat()
+        }
+        Value::Null => panic!("COSE header values must not be null"),
+    }
+}
+
+fn load_header(input: Option<&str>, what: &str) -> Value {
+    let header = match input {
+        Some(input) => {
+            let content = fs::read_to_string(input).unwrap_or_else(|_| input.to_string());
+            serde_json::from_str(&content).unwrap_or_else(|_| panic!("COSE {} header must be a JSON file or JSON", what))
+        }
+        None => json!({}),
+    };
+    if !header.is_object() {
+        panic!("COSE {} header must be a JSON object", what);
+    }
+    header
+}
+
+/// COSE_Sign1 (RFC 9052) signed with ES256K: the protected header gets alg -47 and must not name another
+/// algorithm, the signature is r || s over sha256 of the Sig_structure
+pub struct CoseSign1 {
+    protected: Vec<u8>,
+    unprotected: Vec<u8>,
+    payload: Vec<u8>,
+}
+
+impl CoseSign1 {
+    /// Payload from a file or a string, headers as JSON files or JSON
+    pub fn load(payload: &str, protected: Option<&str>, unprotected: Option<&str>) -> Self {
+        let mut protected = load_header(protected, "protected");
+        match protected.get(HEADER_ALG) {
+            None => protected[HEADER_ALG] = json!(ALG_ES256K),
+            Some(alg) if alg == &json!(ALG_ES256K) => {}
+            Some(alg) => panic!("COSE alg {} is not supported, the threshold key signs ES256K ({})", alg, ALG_ES256K),
+        }
+        CoseSign1 {
+            protected: cbor(&protected),
+            unprotected: cbor(&load_header(unprotected, "unprotected")),
+            payload: fs::read(payload).unwrap_or_else(|_| payload.as_bytes().to_vec()),
+        }
+    }
+
+    /// sha256 of Sig_structure ["Signature1", protected, external_aad, payload], external_aad empty
+    pub fn digest(&self) -> Vec<u8> {
+        let context = cbor(&json!("Signature1"));
+        let sig_structure = cbor_array(&[context, cbor_bytes(&self.protected), cbor_bytes(&[]), cbor_bytes(&self.payload)]);
+        digest(&SHA256, &sig_structure).as_ref().to_vec()
+    }
+
+    /// Tagged COSE_Sign1 [protected, unprotected, payload, signature]
+    pub fn signed(&self, sig: &SignatureRecid) -> Vec<u8> {
+        let signature = [to_32_bytes(&sig.r.to_big_int()), to_32_bytes(&sig.s.to_big_int())].concat();
+        let message = cbor_array(&[
+            cbor_bytes(&self.protected),
+            self.unprotected.clone(),
+            cbor_bytes(&self.payload),
+            cbor_bytes(&signature),
+        ]);
+        [cbor_head(TAG, TAG_COSE_SIGN1), message].concat()
+    }
+}
+
+pub fn signed_cose_json(cose: &CoseSign1, sig: &SignatureRecid, context: &str) -> Value {
+    let mut ret_dict = json!({
+        "status": "cose_signed",
+        "alg": ALG_ES256K,
+        "cose_sign1": hex::encode(cose.signed(sig)),
+    });
+    if !context.is_empty() {
+        ret_dict["context"] = json!(context);
+    }
+    ret_dict
+}
@@ -0,0 +1,115 @@
+use std::fs;
+
+use curv::elliptic::curves::secp256_k1::GE;
+use curv::elliptic::curves::traits::ECScalar;
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2018::party_i::SignatureRecid;
+use ring::digest::{digest, SHA256};
+use serde_json::{json, Value};
+
+use crate::common::bitcoin::{bech32_encode, convert_bits, hash160};
+use crate::common::encoding::{compressed_public_key, to_32_bytes};
+
+pub const DEFAULT_PREFIX: &str = "cosmos";
+
+fn check_prefix(prefix: &str) {
+    if prefix.is_empty() || prefix.len() > 83 || !prefix.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit()) {
+        panic!("Invalid bech32 prefix {:?}, use lowercase letters and digits", prefix);
+    }
+}
+
+/// Account address of a public key: bech32 of ripemd160(sha256(compressed key)) with the chain prefix
+pub fn address(y_sum: &GE, prefix: &str) -> String {
+    check_prefix(prefix);
+    bech32_encode(prefix, &convert_bits(&hash160(&compressed_public_key(y_sum)), 8, 5), false)
+}
+
+// Reads a protobuf varint, returns the value and its length
+fn varint(data: &[u8]) -> (u64, usize) {
+    let mut value = 0u64;
+    for (i, byte) in data.iter().enumerate().take(10) {
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            return (value, i + 1);
+        }
+    }
+    panic!("Invalid SignDoc, truncated varint");
+}
+
+/// SIGN_MODE_DIRECT SignDoc, the protobuf encoded bytes are signed as given
+pub struct SignDoc {
+    pub bytes: Vec<u8>,
+    pub chain_id: String,
+    pub account_number: u64,
+}
+
+impl SignDoc {
+    pub fn parse(bytes: Vec<u8>) -> Self {
+        let (mut chain_id, mut account_number) = (String::new(), 0u64);
+        let (mut body, mut auth_info) = (false, false);
+        let mut pos = 0;
+        while pos < bytes.len() {
+            let (key, len) = varint(&bytes[pos..]);
+            pos += len;
+            match (key >> 3, key & 7) {
+                (4, 0) => {
+                    let (value, len) = varint(&bytes[pos..]);
+                    account_number = value;
+                    pos += len;
+                }
+                (field @ 1..=3, 2) => {
+                    let (size, len) = varint(&bytes[pos..]);
+                    pos += len;
+                    let value = bytes.get(pos..pos + size as usize).expect("Invalid SignDoc, truncated field");
+                    pos += size as usize;
+                    match field {
+                        1 => body = true,
+                        2 => auth_info = true,
+                        _ => chain_id = String::from_utf8(value.to_vec()).expect("Invalid SignDoc, chain_id is not UTF-8"),
+                    }
+                }
+                (field, wire_type) => panic!("Invalid SignDoc, unexpected field {} of wire type {}", field, wire_type),
+            }
+        }
+        if !body || !auth_info || chain_id.is_empty() {
+            panic!("Invalid SignDoc, body_bytes, auth_info_bytes and chain_id are required");
+        }
+        SignDoc { bytes, chain_id, account_number }
+    }
+
+    /// SignDoc bytes from a file, or hex or base64
+    pub fn load(input: &str) -> Self {
+        let bytes = match fs::read(input) {
+            Ok(bytes) => bytes,
+            Err(_) => hex::decode(input)
+                .or_else(|_| base64::decode(input))
+                .expect("SignDoc must be a file, hex or base64"),
+        };
+        SignDoc::parse(bytes)
+    }
+
+    /// sha256 of the SignDoc bytes, what secp256k1 keys sign
+    pub fn digest(&self) -> Vec<u8> {
+        digest(&SHA256, &self.bytes).as_ref().to_vec()
+    }
+}
+
+/// Signature as Cosmos SDK transactions carry it: base64 of the 64 byte r || s, with the public key in
+/// its Any JSON form and the account address
+pub fn signed_sign_doc_json(sign_doc: &SignDoc, sig: &SignatureRecid, y_sum: &GE, prefix: &str, context: &str) -> Value {
+    let signature = [to_32_bytes(&sig.r.to_big_int()), to_32_bytes(&sig.s.to_big_int())].concat();
+    let mut ret_dict = json!({
+        "status": "sign_doc_signed",
+        "chain_id": sign_doc.chain_id,
+        "account_number": sign_doc.account_number.to_string(),
+        "address": address(y_sum, prefix),
+        "pub_key": {
+            "@type": "/cosmos.crypto.secp256k1.PubKey",
+            "key": base64::encode(compressed_public_key(y_sum)),
+        },
+        "signature": base64::encode(&signature),
+    });
+    if !context.is_empty() {
+        ret_dict["context"] = json!(context);
+    }
+    ret_dict
+}
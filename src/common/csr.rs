@@ -0,0 +1,83 @@
+use std::fs;
+
+use curv::BigInt;
+use curv::cryptographic_primitives::hashing::hash_sha256::HSha256;
+use curv::cryptographic_primitives::hashing::traits::Hash;
+use curv::elliptic::curves::secp256_k1::GE;
+use curv::elliptic::curves::traits::ECScalar;
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2018::party_i::SignatureRecid;
+use serde_json::json;
+
+use crate::common::encoding::{
+    der_bit_string, der_integer, der_oid, der_sequence, der_signature, der_tlv, pem, public_key_der,
+    to_32_bytes,
+};
+
+pub const OID_ECDSA_WITH_SHA256: [u8; 8] = [0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02]; // 1.2.840.10045.4.3.2
+
+// Attribute type OIDs of X.520 names, id-at = 2.5.4
+fn name_attribute_oid(name: &str) -> Option<[u8; 3]> {
+    match name.to_uppercase().as_str() {
+        "CN" => Some([0x55, 0x04, 0x03]),
+        "C" => Some([0x55, 0x04, 0x06]),
+        "L" => Some([0x55, 0x04, 0x07]),
+        "ST" => Some([0x55, 0x04, 0x08]),
+        "O" => Some([0x55, 0x04, 0x0a]),
+        "OU" => Some([0x55, 0x04, 0x0b]),
+        _ => None,
+    }
+}
+
+/// Encodes a subject like "CN=Example CA,O=Example,C=LV" as X.501 Name
+pub fn subject_name(subject: &str) -> Vec<u8> {
+    let rdns: Vec<Vec<u8>> = subject
+        .split(',')
+        .filter(|part| !part.trim().is_empty())
+        .map(|part| {
+            let (name, value) = part.split_at(part.find('=').expect("Subject parts must be in NAME=value form"));
+            let name = name.trim();
+            let value = value[1..].trim();
+            let oid = name_attribute_oid(name)
+                .unwrap_or_else(|| panic!("Unsupported subject attribute: {}", name));
+            // countryName must be a PrintableString, everything else is encoded as UTF8String
+            let value_tag = if name.eq_ignore_ascii_case("C") { 0x13 } else { 0x0c };
+            let attribute = der_sequence(&[der_oid(&oid), der_tlv(value_tag, value.as_bytes())]);
+            der_tlv(0x31, &attribute)
+        })
+        .collect();
+    der_sequence(&rdns)
+}
+
+/// CertificationRequestInfo, the part of the CSR covered by the signature
+pub fn certification_request_info(subject: &str, y_sum: &GE) -> Vec<u8> {
+    der_sequence(&[
+        der_integer(&BigInt::from(0)),
+        subject_name(subject),
+        public_key_der(y_sum),
+        der_tlv(0xa0, &[]),
+    ])
+}
+
+pub fn csr_digest(request_info: &[u8]) -> Vec<u8> {
+    to_32_bytes(&HSha256::create_hash_from_slice(request_info))
+}
+
+pub fn write_csr(csr_path: &str, request_info: &[u8], sig: &SignatureRecid, context: &str) {
+    let signature = der_signature(&sig.r.to_big_int(), &sig.s.to_big_int());
+    let csr = der_sequence(&[
+        request_info.to_vec(),
+        der_sequence(&[der_oid(&OID_ECDSA_WITH_SHA256)]),
+        der_bit_string(&signature),
+    ]);
+    fs::write(csr_path, pem("CERTIFICATE REQUEST", &csr)).expect("Unable to save CSR !");
+
+    let mut ret_dict = json!({
+        "status": "csr_ready",
+        "csr": csr_path,
+        "verify": format!("openssl req -in {} -noout -verify -text", csr_path),
+    });
+    if !context.is_empty() {
+        ret_dict["context"] = json!(context);
+    }
+    println!("{}", ret_dict.to_string());
+}
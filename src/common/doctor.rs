@@ -0,0 +1,31 @@
+use std::time::Instant;
+
+use serde_json::json;
+
+use crate::common::{new_client, clock_skew, post_json, CLOCK_SKEW_WARNING};
+
+/// Checks that the manager is reachable and measures local clock skew against it
+pub fn run_doctor(addr: &String) {
+    let client = new_client();
+    let start_time = Instant::now();
+    let answer = post_json::<_, u64>(addr, &client, "time", ());
+    let round_trip = start_time.elapsed();
+
+    let ret_dict = match answer {
+        Ok(server_time) => {
+            let skew = clock_skew(server_time);
+            json!({
+                "status": if skew.abs() > CLOCK_SKEW_WARNING { "clock_skew" } else { "ok" },
+                "manager_addr": addr,
+                "round_trip_ms": round_trip.as_millis() as u64,
+                "clock_skew_s": skew,
+            })
+        }
+        Err(e) => json!({
+            "status": "manager_unreachable",
+            "manager_addr": addr,
+            "error": e.to_string(),
+        }),
+    };
+    println!("{}", ret_dict.to_string());
+}
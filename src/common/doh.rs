@@ -0,0 +1,67 @@
+use std::net::IpAddr;
+
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::common::new_client;
+
+const DNS_TYPE_A: u16 = 1;
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct DohAnswer {
+    #[serde(rename = "type")]
+    pub record_type: u16,
+    pub data: String,
+}
+
+/// JSON answer of a DoH resolver, as served by e.g. Cloudflare and Google
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct DohResponse {
+    #[serde(rename = "Status")]
+    pub status: u16,
+    #[serde(rename = "Answer", default)]
+    pub answer: Vec<DohAnswer>,
+}
+
+/// Resolves an A record of the host with the DoH JSON API at resolver_url
+pub fn resolve(resolver_url: &str, host: &str) -> IpAddr {
+    let client = new_client();
+    let response: DohResponse = client
+        .get(resolver_url)
+        .query(&[("name", host), ("type", "A")])
+        .header(reqwest::header::ACCEPT, "application/dns-json")
+        .send()
+        .and_then(|res| res.error_for_status())
+        .and_then(|res| res.json())
+        .unwrap_or_else(|e| panic!("DNS-over-HTTPS lookup of {} failed: {}", host, e));
+    if response.status != 0 {
+        panic!("DNS-over-HTTPS lookup of {} failed with DNS status {}", host, response.status);
+    }
+    response
+        .answer
+        .iter()
+        .filter(|answer| answer.record_type == DNS_TYPE_A)
+        .find_map(|answer| answer.data.parse::<IpAddr>().ok())
+        .unwrap_or_else(|| panic!("DNS-over-HTTPS lookup of {} returned no A record", host))
+}
+
+/// Replaces the manager host name with the address resolved over DoH when TSS_CLI_DOH_URL is set,
+/// so the lookup never reaches the local resolver. Only plain http managers are supported:
+/// for https the certificate is checked against the host name, which is lost after substitution
+pub fn resolve_manager_addr(addr: String) -> String {
+    let resolver_url = match std::env::var("TSS_CLI_DOH_URL") {
+        Ok(resolver_url) if !resolver_url.is_empty() => resolver_url,
+        _ => return addr,
+    };
+    let mut url = Url::parse(&addr).expect("Invalid manager URL");
+    let host = url.host_str().expect("Manager URL has no host").to_string();
+    if host.parse::<IpAddr>().is_ok() || host.starts_with('[') {
+        return addr;
+    }
+    if url.scheme() != "http" {
+        panic!("DNS-over-HTTPS resolution is only supported for http managers, use the IP address in --addr instead");
+    }
+    let ip = resolve(&resolver_url, &host);
+    url.set_host(Some(&ip.to_string())).unwrap();
+    url.as_str().trim_end_matches('/').to_string()
+}
@@ -0,0 +1,154 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use curv::{BigInt, arithmetic::Converter};
+use curv::elliptic::curves::secp256_k1::GE;
+use curv::elliptic::curves::traits::ECScalar;
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2018::party_i::SignatureRecid;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::common::keyfile;
+use crate::common::{new_client, post_json, sha256_digest, Index, ManagerError, SignedEntry};
+
+/// What to do when the same digest is about to be signed twice under the same key and path
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DuplicateGuard {
+    Off,
+    Warn,
+    Block,
+}
+
+impl DuplicateGuard {
+    pub fn from_str(mode: &str) -> Self {
+        match mode {
+            "off" => DuplicateGuard::Off,
+            "warn" => DuplicateGuard::Warn,
+            "block" => DuplicateGuard::Block,
+            _ => panic!("Unknown duplicate guard mode: {}. Use off, warn or block", mode),
+        }
+    }
+}
+
+/// A completed signature, records written before the key fingerprint was kept don't match any signing
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct SigningRecord {
+    #[serde(default)]
+    pub key_fingerprint: String,
+    pub path: String,
+    #[serde(default)]
+    pub digest: String,
+    pub timestamp: u64,
+}
+
+/// What a duplicate is judged by: the key, the derivation path and the signed digest
+pub struct SigningTarget {
+    pub key_fingerprint: String,
+    pub path: String,
+    pub digest: String,
+}
+
+impl SigningTarget {
+    pub fn new(root_y_sum: &GE, path: &str, message: &[u8]) -> Self {
+        SigningTarget {
+            key_fingerprint: keyfile::fingerprint(root_y_sum),
+            path: path.to_string(),
+            digest: hex::encode(message),
+        }
+    }
+
+    /// Target of a presignature, signed under the key of the fingerprint stored with it
+    pub fn with_fingerprint(key_fingerprint: &str, path: &str, message: &[u8]) -> Self {
+        SigningTarget {
+            key_fingerprint: key_fingerprint.to_string(),
+            path: path.to_string(),
+            digest: hex::encode(message),
+        }
+    }
+
+    // Key of the manager's index, the manager learns neither the key nor the digest
+    fn index_key(&self) -> String {
+        sha256_digest(format!("{}|{}|{}", self.key_fingerprint, self.path, self.digest).as_bytes())
+    }
+}
+
+fn history_path(keysfile_path: &str) -> String {
+    format!("{}.history", keysfile_path)
+}
+
+fn local_history(keysfile_path: &str) -> Vec<SigningRecord> {
+    match fs::read_to_string(history_path(keysfile_path)) {
+        Ok(data) => data
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn manager_signed_count(addr: &String, target: &SigningTarget) -> u16 {
+    let client = new_client();
+    let request = Index { key: target.index_key() };
+    // Older managers don't know the endpoint, treat it as no record
+    match post_json::<_, Result<u16, ManagerError>>(&addr, &client, "signed", request) {
+        Ok(Ok(count)) => count,
+        _ => 0,
+    }
+}
+
+/// Checks local signing history and the manager's index of completed signatures for the same key, path and digest
+pub fn check_duplicate(guard: DuplicateGuard, addr: &String, keysfile_path: &str, target: &SigningTarget) {
+    if guard == DuplicateGuard::Off {
+        return;
+    }
+    let previous_local = local_history(keysfile_path)
+        .into_iter()
+        .filter(|record| {
+            record.key_fingerprint == target.key_fingerprint && record.path == target.path && record.digest == target.digest
+        })
+        .count();
+    let previous_manager = manager_signed_count(addr, target);
+    if previous_local == 0 && previous_manager == 0 {
+        return;
+    }
+
+    let warning = json!({
+        "status": "duplicate_signing",
+        "key_fingerprint": target.key_fingerprint,
+        "path": target.path,
+        "digest": target.digest,
+        "signed_locally": previous_local,
+        "signed_on_manager": previous_manager,
+    });
+    match guard {
+        DuplicateGuard::Block => panic!("Digest already signed, refusing to sign again: {}", warning.to_string()),
+        _ => println!("{}", warning.to_string()),
+    }
+}
+
+/// Records a completed signature locally and on the manager. The manager counts distinct r values,
+/// so the records of all parties of one signing count once
+pub fn record_signing(addr: &String, keysfile_path: &str, target: &SigningTarget, sig: &SignatureRecid) {
+    let record = SigningRecord {
+        key_fingerprint: target.key_fingerprint.clone(),
+        path: target.path.clone(),
+        digest: target.digest.clone(),
+        timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+    };
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(history_path(keysfile_path))
+        .expect("Unable to open signing history !");
+    writeln!(file, "{}", serde_json::to_string(&record).unwrap()).expect("Unable to save signing history !");
+
+    let entry = SignedEntry {
+        key: target.index_key(),
+        r: BigInt::from_bytes(&(sig.r.get_element())[..]).to_str_radix(16),
+    };
+    if let Err(e) = post_json::<_, Result<(), ManagerError>>(&addr, &new_client(), "record-signed", entry) {
+        eprintln!("{}", json!({"status": "signature_not_recorded", "error": e.to_string()}).to_string());
+    }
+}
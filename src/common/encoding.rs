@@ -0,0 +1,114 @@
+use curv::{BigInt, arithmetic::Converter};
+use curv::elliptic::curves::secp256_k1::GE;
+use curv::elliptic::curves::traits::ECPoint;
+use serde_json::{json, Value};
+
+// DER object identifiers, content bytes only
+pub const OID_EC_PUBLIC_KEY: [u8; 7] = [0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01]; // 1.2.840.10045.2.1
+pub const OID_SECP256K1: [u8; 5] = [0x2b, 0x81, 0x04, 0x00, 0x0a]; // 1.3.132.0.10
+
+/// Big endian bytes of a number, left padded with zeros to 32 bytes
+pub fn to_32_bytes(n: &BigInt) -> Vec<u8> {
+    let bytes = BigInt::to_bytes(n);
+    let mut padded = vec![0u8; 32 - bytes.len()];
+    padded.extend(bytes);
+    padded
+}
+
+/// SEC1 uncompressed point: 0x04 || x || y
+pub fn uncompressed_public_key(y_sum: &GE) -> Vec<u8> {
+    let mut key = vec![4u8];
+    key.extend(to_32_bytes(&y_sum.x_coor().unwrap()));
+    key.extend(to_32_bytes(&y_sum.y_coor().unwrap()));
+    key
+}
+
+/// SEC1 compressed point: 0x02/0x03 || x
+pub fn compressed_public_key(y_sum: &GE) -> Vec<u8> {
+    BigInt::to_bytes(&y_sum.bytes_compressed_to_big_int())
+}
+
+pub fn der_tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let len = content.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let len_bytes: Vec<u8> = len.to_be_bytes().iter().cloned().skip_while(|b| *b == 0).collect();
+        out.push(0x80 | len_bytes.len() as u8);
+        out.extend(len_bytes);
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+pub fn der_sequence(items: &[Vec<u8>]) -> Vec<u8> {
+    der_tlv(0x30, &items.concat())
+}
+
+pub fn der_integer(n: &BigInt) -> Vec<u8> {
+    let mut bytes = BigInt::to_bytes(n);
+    if bytes.is_empty() {
+        bytes.push(0);
+    }
+    if bytes[0] & 0x80 != 0 {
+        bytes.insert(0, 0);
+    }
+    der_tlv(0x02, &bytes)
+}
+
+pub fn der_oid(oid: &[u8]) -> Vec<u8> {
+    der_tlv(0x06, oid)
+}
+
+pub fn der_bit_string(bytes: &[u8]) -> Vec<u8> {
+    der_tlv(0x03, &[vec![0u8], bytes.to_vec()].concat())
+}
+
+/// ECDSA-Sig-Value ::= SEQUENCE { r INTEGER, s INTEGER }
+pub fn der_signature(r: &BigInt, s: &BigInt) -> Vec<u8> {
+    der_sequence(&[der_integer(r), der_integer(s)])
+}
+
+/// SubjectPublicKeyInfo of a secp256k1 public key
+pub fn public_key_der(y_sum: &GE) -> Vec<u8> {
+    der_sequence(&[
+        der_sequence(&[der_oid(&OID_EC_PUBLIC_KEY), der_oid(&OID_SECP256K1)]),
+        der_bit_string(&uncompressed_public_key(y_sum)),
+    ])
+}
+
+pub fn pem(label: &str, der: &[u8]) -> String {
+    let encoded = base64::encode(der);
+    let mut out = format!("-----BEGIN {}-----\n", label);
+    for line in encoded.as_bytes().chunks(64) {
+        out.push_str(std::str::from_utf8(line).unwrap());
+        out.push('\n');
+    }
+    out.push_str(&format!("-----END {}-----\n", label));
+    out
+}
+
+pub fn public_key_pem(y_sum: &GE) -> String {
+    pem("PUBLIC KEY", &public_key_der(y_sum))
+}
+
+pub const PUBLIC_KEY_FORMATS: [&str; 5] = ["compressed", "uncompressed", "spki-der-hex", "pem", "jwk"];
+
+/// Public key in an output format: compressed and uncompressed SEC1 points in hex, spki-der-hex the DER
+/// SubjectPublicKeyInfo wrapping the SEC1 point in hex, pem the same in PEM, jwk an RFC 8812 secp256k1 JWK
+pub fn formatted_public_key(y_sum: &GE, format: &str) -> Value {
+    match format {
+        "compressed" => json!(hex::encode(compressed_public_key(y_sum))),
+        "uncompressed" => json!(hex::encode(uncompressed_public_key(y_sum))),
+        "spki-der-hex" => json!(hex::encode(public_key_der(y_sum))),
+        "pem" => json!(public_key_pem(y_sum)),
+        "jwk" => json!({
+            "kty": "EC",
+            "crv": "secp256k1",
+            "x": base64::encode_config(to_32_bytes(&y_sum.x_coor().unwrap()), base64::URL_SAFE_NO_PAD),
+            "y": base64::encode_config(to_32_bytes(&y_sum.y_coor().unwrap()), base64::URL_SAFE_NO_PAD),
+        }),
+        _ => panic!("Unknown public key format {}, supported: {}", format, PUBLIC_KEY_FORMATS.join(", ")),
+    }
+}
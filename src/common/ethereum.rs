@@ -0,0 +1,537 @@
+use std::collections::BTreeSet;
+use std::fs;
+
+use curv::{
+    arithmetic::{BasicOps, Converter},
+    elliptic::curves::secp256_k1::GE,
+    elliptic::curves::traits::ECScalar,
+    BigInt,
+};
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2018::party_i::SignatureRecid;
+use serde_json::{json, Value};
+
+use crate::common::encoding::{to_32_bytes, uncompressed_public_key};
+use crate::common::message_hash::keccak256;
+
+fn strip_0x(hex: &str) -> &str {
+    hex.strip_prefix("0x").or(hex.strip_prefix("0X")).unwrap_or(hex)
+}
+
+fn hex_bytes(value: &Value, what: &str) -> Vec<u8> {
+    let hex = value.as_str().unwrap_or_else(|| panic!("{} must be a hex string", what));
+    hex::decode(strip_0x(hex)).unwrap_or_else(|_| panic!("{} is not valid hex: {}", what, hex))
+}
+
+// Integer as JSON number, decimal string or 0x hex string, returns (negative, absolute value, text)
+fn parse_integer(value: &Value, what: &str) -> (bool, BigInt, String) {
+    let text = match value {
+        Value::Number(number) => number.to_string(),
+        Value::String(text) => text.clone(),
+        _ => panic!("{} must be a number", what),
+    };
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text.as_str()),
+    };
+    let n = match digits.strip_prefix("0x").or(digits.strip_prefix("0X")) {
+        Some("") => Ok(BigInt::from(0)),
+        Some(hex) => BigInt::from_str_radix(hex, 16),
+        None => BigInt::from_str_radix(digits, 10),
+    }
+    .unwrap_or_else(|_| panic!("{} is not a number: {}", what, text));
+    (negative, n, text)
+}
+
+// EIP-712 word of an integer, two's complement for negative ints
+fn integer_word(value: &Value, bits: u32, signed: bool, what: &str) -> Vec<u8> {
+    let (negative, n, text) = parse_integer(value, what);
+    let two = BigInt::from(2);
+    let limit = match signed {
+        true => two.pow(bits - 1),
+        false => two.pow(bits),
+    };
+    if (negative && (!signed || n > limit)) || (!negative && n >= limit) {
+        panic!("{} is out of range for {}int{}: {}", what, if signed { "" } else { "u" }, bits, text);
+    }
+    match negative && n != BigInt::from(0) {
+        true => to_32_bytes(&(two.pow(256) - n)),
+        false => to_32_bytes(&n),
+    }
+}
+
+fn type_bits(name: &str, prefix: &str) -> Option<u32> {
+    let bits = name.strip_prefix(prefix)?;
+    match bits {
+        "" => Some(256),
+        bits => bits.parse::<u32>().ok().filter(|bits| *bits > 0 && *bits <= 256 && bits % 8 == 0),
+    }
+}
+
+/// EIP-712 typed data: { types, primaryType, domain, message }
+pub struct TypedData {
+    types: Value,
+    primary_type: String,
+    domain: Value,
+    message: Value,
+}
+
+impl TypedData {
+    pub fn load(path: &str) -> Self {
+        let content = fs::read_to_string(path).expect("Unable to load EIP-712 typed data !");
+        TypedData::from_value(serde_json::from_str(&content).expect("EIP-712 typed data is not valid JSON"))
+    }
+
+    pub fn from_value(data: Value) -> Self {
+        let field = |name: &str| data.get(name).cloned().unwrap_or_else(|| panic!("EIP-712 typed data has no {}", name));
+        let typed_data = TypedData {
+            types: field("types"),
+            primary_type: field("primaryType").as_str().expect("EIP-712 primaryType must be a string").to_string(),
+            domain: field("domain"),
+            message: field("message"),
+        };
+        if typed_data.types.get("EIP712Domain").is_none() {
+            panic!("EIP-712 types must define EIP712Domain");
+        }
+        typed_data
+    }
+
+    fn fields(&self, type_name: &str) -> Vec<(String, String)> {
+        self.types[type_name]
+            .as_array()
+            .unwrap_or_else(|| panic!("EIP-712 type {} is not defined", type_name))
+            .iter()
+            .map(|field| {
+                let name = field["name"].as_str().unwrap_or_else(|| panic!("EIP-712 type {} has a field without name", type_name));
+                let field_type = field["type"].as_str().unwrap_or_else(|| panic!("EIP-712 field {}.{} has no type", type_name, name));
+                (name.to_string(), field_type.to_string())
+            })
+            .collect()
+    }
+
+    fn is_struct(&self, type_name: &str) -> bool {
+        self.types.get(type_name).is_some()
+    }
+
+    // Struct types a type refers to, itself included
+    fn dependencies(&self, type_name: &str, found: &mut BTreeSet<String>) {
+        let base = type_name.split('[').next().unwrap();
+        if !self.is_struct(base) || found.contains(base) {
+            return;
+        }
+        found.insert(base.to_string());
+        for (_, field_type) in self.fields(base) {
+            self.dependencies(&field_type, found);
+        }
+    }
+
+    /// encodeType: the primary type followed by the types it refers to, sorted by name
+    fn encode_type(&self, type_name: &str) -> String {
+        let mut found = BTreeSet::new();
+        self.dependencies(type_name, &mut found);
+        found.remove(type_name);
+        std::iter::once(type_name.to_string())
+            .chain(found.into_iter())
+            .map(|name| {
+                let fields: Vec<String> = self.fields(&name).iter().map(|(field, field_type)| format!("{} {}", field_type, field)).collect();
+                format!("{}({})", name, fields.join(","))
+            })
+            .collect()
+    }
+
+    fn encode_value(&self, field_type: &str, value: &Value, what: &str) -> Vec<u8> {
+        if field_type.ends_with(']') {
+            let (base, length) = field_type[..field_type.len() - 1].rsplit_once('[').unwrap();
+            let items = value.as_array().unwrap_or_else(|| panic!("{} must be an array", what));
+            if !length.is_empty() && length.parse::<usize>().ok() != Some(items.len()) {
+                panic!("{} must have {} items", what, length);
+            }
+            let encoded: Vec<u8> = items
+                .iter()
+                .enumerate()
+                .flat_map(|(i, item)| self.encode_value(base, item, &format!("{}[{}]", what, i)))
+                .collect();
+            return keccak256(&encoded).to_vec();
+        }
+        if self.is_struct(field_type) {
+            return self.hash_struct(field_type, value, what).to_vec();
+        }
+        match field_type {
+            "string" => keccak256(value.as_str().unwrap_or_else(|| panic!("{} must be a string", what)).as_bytes()).to_vec(),
+            "bytes" => keccak256(&hex_bytes(value, what)).to_vec(),
+            "bool" => {
+                let flag = value.as_bool().unwrap_or_else(|| panic!("{} must be true or false", what));
+                to_32_bytes(&BigInt::from(flag as u64))
+            }
+            "address" => {
+                let address = hex_bytes(value, what);
+                if address.len() != 20 {
+                    panic!("{} must be a 20 byte address", what);
+                }
+                [vec![0u8; 12], address].concat()
+            }
+            _ => {
+                if let Some(size) = field_type.strip_prefix("bytes").and_then(|size| size.parse::<usize>().ok()) {
+                    let bytes = hex_bytes(value, what);
+                    if size == 0 || size > 32 || bytes.len() != size {
+                        panic!("{} must be {} bytes", what, size);
+                    }
+                    return [bytes, vec![0u8; 32 - size]].concat();
+                }
+                if let Some(bits) = type_bits(field_type, "uint") {
+                    return integer_word(value, bits, false, what);
+                }
+                if let Some(bits) = type_bits(field_type, "int") {
+                    return integer_word(value, bits, true, what);
+                }
+                panic!("{} has unsupported EIP-712 type {}", what, field_type)
+            }
+        }
+    }
+
+    /// hashStruct: keccak256(typeHash || encodeData)
+    fn hash_struct(&self, type_name: &str, value: &Value, what: &str) -> [u8; 32] {
+        let mut encoded = keccak256(self.encode_type(type_name).as_bytes()).to_vec();
+        for (name, field_type) in self.fields(type_name) {
+            let field_value = value.get(&name).unwrap_or_else(|| panic!("{} has no field {}", what, name));
+            encoded.extend(self.encode_value(&field_type, field_value, &format!("{}.{}", what, name)));
+        }
+        keccak256(&encoded)
+    }
+
+    pub fn domain_separator(&self) -> [u8; 32] {
+        self.hash_struct("EIP712Domain", &self.domain, "domain")
+    }
+
+    pub fn message_hash(&self) -> [u8; 32] {
+        self.hash_struct(&self.primary_type, &self.message, "message")
+    }
+
+    /// Digest signed for typed data: keccak256(0x19 0x01 || domainSeparator || hashStruct(message))
+    pub fn digest(&self) -> Vec<u8> {
+        keccak256(&[&[0x19u8, 0x01][..], &self.domain_separator(), &self.message_hash()].concat()).to_vec()
+    }
+}
+
+/// RLP item, https://ethereum.org/en/developers/docs/data-structures-and-encoding/rlp/
+#[derive(Clone, PartialEq, Debug)]
+pub enum Rlp {
+    Bytes(Vec<u8>),
+    List(Vec<Rlp>),
+}
+
+fn invalid_rlp() -> ! {
+    panic!("Invalid RLP encoding")
+}
+
+/// Deepest list nesting decoded, transactions nest three lists deep (access list entries)
+const MAX_RLP_DEPTH: usize = 16;
+
+fn rlp_length(len: usize, offset: u8) -> Vec<u8> {
+    if len < 56 {
+        return vec![offset + len as u8];
+    }
+    let len_bytes: Vec<u8> = len.to_be_bytes().iter().cloned().skip_while(|b| *b == 0).collect();
+    [vec![offset + 55 + len_bytes.len() as u8], len_bytes].concat()
+}
+
+impl Rlp {
+    /// Integer as its minimal big endian bytes, zero is the empty string
+    pub fn integer(n: &BigInt) -> Self {
+        match *n == BigInt::from(0) {
+            true => Rlp::Bytes(vec![]),
+            false => Rlp::Bytes(BigInt::to_bytes(n)),
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            Rlp::Bytes(bytes) if bytes.len() == 1 && bytes[0] < 0x80 => bytes.clone(),
+            Rlp::Bytes(bytes) => [rlp_length(bytes.len(), 0x80), bytes.clone()].concat(),
+            Rlp::List(items) => {
+                let payload: Vec<u8> = items.iter().flat_map(|item| item.encode()).collect();
+                [rlp_length(payload.len(), 0xc0), payload].concat()
+            }
+        }
+    }
+
+    // Item at the start of data, returns it with the number of bytes it takes. Only the canonical encoding
+    // is accepted, so an encoded transaction has a single signing hash
+    fn decode_item(data: &[u8], depth: usize) -> (Rlp, usize) {
+        if depth > MAX_RLP_DEPTH {
+            panic!("Invalid RLP encoding, lists nested deeper than {}", MAX_RLP_DEPTH);
+        }
+        let prefix = *data.first().unwrap_or_else(|| invalid_rlp());
+        let (header, len, list) = match prefix {
+            0x00..=0x7f => return (Rlp::Bytes(vec![prefix]), 1),
+            0x80..=0xb7 => (1, (prefix - 0x80) as usize, false),
+            0xc0..=0xf7 => (1, (prefix - 0xc0) as usize, true),
+            _ => {
+                let len_of_len = (if prefix >= 0xf8 { prefix - 0xf7 } else { prefix - 0xb7 }) as usize;
+                let len_bytes = data.get(1..1 + len_of_len).unwrap_or_else(|| invalid_rlp());
+                if len_of_len > 8 || len_bytes[0] == 0 {
+                    invalid_rlp();
+                }
+                let len = len_bytes.iter().fold(0usize, |len, b| (len << 8) | *b as usize);
+                // The long form is only for payloads of 56 bytes or more
+                if len < 56 {
+                    invalid_rlp();
+                }
+                (1 + len_of_len, len, prefix >= 0xf8)
+            }
+        };
+        let end = header.checked_add(len).unwrap_or_else(|| invalid_rlp());
+        let payload = data.get(header..end).unwrap_or_else(|| invalid_rlp());
+        if !list {
+            // A single byte below 0x80 is its own encoding
+            if len == 1 && payload[0] < 0x80 {
+                invalid_rlp();
+            }
+            return (Rlp::Bytes(payload.to_vec()), end);
+        }
+        let mut items = Vec::new();
+        let mut pos = 0;
+        while pos < payload.len() {
+            let (item, item_len) = Rlp::decode_item(&payload[pos..], depth + 1);
+            items.push(item);
+            pos += item_len;
+        }
+        (Rlp::List(items), end)
+    }
+
+    pub fn decode(data: &[u8]) -> Self {
+        let (item, len) = Rlp::decode_item(data, 0);
+        if len != data.len() {
+            panic!("Invalid RLP encoding, {} bytes left over", data.len() - len);
+        }
+        item
+    }
+}
+
+/// Unsigned Ethereum transaction, legacy with EIP-155 replay protection or EIP-1559 (type 2)
+pub struct EthTransaction {
+    pub eip1559: bool,
+    pub chain_id: BigInt,
+    // Fields before the signature: legacy [nonce, gasPrice, gas, to, value, data],
+    // EIP-1559 [chainId, nonce, maxPriorityFeePerGas, maxFeePerGas, gas, to, value, data, accessList]
+    fields: Vec<Rlp>,
+}
+
+// Integer field of a JSON transaction, found under the first of its names present
+fn quantity(tx: &Value, names: &[&str]) -> BigInt {
+    let value = names
+        .iter()
+        .find_map(|name| tx.get(*name).filter(|value| !value.is_null()))
+        .unwrap_or_else(|| panic!("Transaction has no {}", names[0]));
+    let (negative, n, _) = parse_integer(value, names[0]);
+    if negative {
+        panic!("Transaction {} is negative", names[0]);
+    }
+    n
+}
+
+fn rlp_quantity(tx: &Value, names: &[&str]) -> Rlp {
+    Rlp::integer(&quantity(tx, names))
+}
+
+fn rlp_hex(tx: &Value, names: &[&str]) -> Rlp {
+    match names.iter().find_map(|name| tx.get(*name).filter(|value| !value.is_null())) {
+        Some(value) => Rlp::Bytes(hex_bytes(value, names[0])),
+        None => Rlp::Bytes(vec![]),
+    }
+}
+
+fn rlp_to(tx: &Value) -> Rlp {
+    let to = rlp_hex(tx, &["to"]);
+    match &to {
+        Rlp::Bytes(address) if address.is_empty() || address.len() == 20 => to,
+        _ => panic!("Transaction to must be a 20 byte address, or left out for contract creation"),
+    }
+}
+
+// [[address, [storage keys]]] from [{"address": ..., "storageKeys": [...]}]
+fn rlp_access_list(tx: &Value) -> Rlp {
+    let entries = match tx.get("accessList").filter(|list| !list.is_null()) {
+        Some(list) => list.as_array().expect("Transaction accessList must be an array").clone(),
+        None => vec![],
+    };
+    Rlp::List(
+        entries
+            .iter()
+            .map(|entry| {
+                let keys = entry["storageKeys"].as_array().map(|keys| keys.clone()).unwrap_or_default();
+                Rlp::List(vec![
+                    Rlp::Bytes(hex_bytes(&entry["address"], "accessList address")),
+                    Rlp::List(keys.iter().map(|key| Rlp::Bytes(hex_bytes(key, "accessList storage key"))).collect()),
+                ])
+            })
+            .collect(),
+    )
+}
+
+fn rlp_integer_value(item: &Rlp, what: &str) -> BigInt {
+    match item {
+        Rlp::Bytes(bytes) if bytes.first() == Some(&0) => panic!("Transaction {} has leading zero bytes", what),
+        Rlp::Bytes(bytes) => BigInt::from_bytes(bytes),
+        Rlp::List(_) => panic!("Transaction {} must be an integer", what),
+    }
+}
+
+impl EthTransaction {
+    fn from_json(tx: &Value) -> Self {
+        let eip1559 = match tx.get("type").filter(|tx_type| !tx_type.is_null()) {
+            Some(tx_type) => match parse_integer(tx_type, "type").1.to_str_radix(10).as_str() {
+                "0" => false,
+                "2" => true,
+                other => panic!("Transaction type {} is not supported, only legacy (0) and EIP-1559 (2)", other),
+            },
+            None => tx.get("maxFeePerGas").is_some(),
+        };
+        let chain_id = quantity(tx, &["chainId"]);
+        let fields = match eip1559 {
+            true => vec![
+                Rlp::integer(&chain_id),
+                rlp_quantity(tx, &["nonce"]),
+                rlp_quantity(tx, &["maxPriorityFeePerGas"]),
+                rlp_quantity(tx, &["maxFeePerGas"]),
+                rlp_quantity(tx, &["gas", "gasLimit"]),
+                rlp_to(tx),
+                rlp_quantity(tx, &["value"]),
+                rlp_hex(tx, &["data", "input"]),
+                rlp_access_list(tx),
+            ],
+            false => vec![
+                rlp_quantity(tx, &["nonce"]),
+                rlp_quantity(tx, &["gasPrice"]),
+                rlp_quantity(tx, &["gas", "gasLimit"]),
+                rlp_to(tx),
+                rlp_quantity(tx, &["value"]),
+                rlp_hex(tx, &["data", "input"]),
+            ],
+        };
+        EthTransaction { eip1559, chain_id, fields }
+    }
+
+    fn from_rlp(raw: &[u8]) -> Self {
+        match raw.first() {
+            Some(0x02) => match Rlp::decode(&raw[1..]) {
+                Rlp::List(fields) if fields.len() == 9 => {
+                    let chain_id = rlp_integer_value(&fields[0], "chainId");
+                    EthTransaction { eip1559: true, chain_id, fields }
+                }
+                Rlp::List(fields) if fields.len() == 12 => panic!("EIP-1559 transaction is already signed"),
+                _ => panic!("EIP-1559 transaction must be an RLP list of 9 fields"),
+            },
+            Some(prefix) if *prefix >= 0xc0 => match Rlp::decode(raw) {
+                // EIP-155 signing payload: the fields followed by chainId, 0, 0
+                Rlp::List(mut fields) if fields.len() == 9 => {
+                    if rlp_integer_value(&fields[7], "r") != BigInt::from(0) || rlp_integer_value(&fields[8], "s") != BigInt::from(0) {
+                        panic!("Legacy transaction is already signed");
+                    }
+                    let chain_id = rlp_integer_value(&fields[6], "chainId");
+                    fields.truncate(6);
+                    EthTransaction { eip1559: false, chain_id, fields }
+                }
+                Rlp::List(fields) if fields.len() == 6 => {
+                    panic!("Legacy transaction has no chain id, give it in EIP-155 form [nonce, gasPrice, gas, to, value, data, chainId, 0, 0]")
+                }
+                _ => panic!("Legacy transaction must be an RLP list of 9 fields"),
+            },
+            Some(tx_type) if *tx_type < 0x80 => panic!("Transaction type {} is not supported, only legacy and EIP-1559 (2)", tx_type),
+            _ => panic!("Transaction is not RLP encoded"),
+        }
+    }
+
+    /// Unsigned transaction from a JSON file, a JSON string or RLP hex (0x02 prefixed for EIP-1559)
+    pub fn load(input: &str) -> Self {
+        let content = match fs::read_to_string(input) {
+            Ok(content) => content,
+            Err(_) => input.to_string(),
+        };
+        let content = content.trim();
+        let tx = match serde_json::from_str::<Value>(content) {
+            Ok(tx) if tx.is_object() => EthTransaction::from_json(&tx),
+            _ => EthTransaction::from_rlp(
+                &hex::decode(strip_0x(content)).expect("Transaction must be JSON or RLP hex"),
+            ),
+        };
+        if tx.chain_id == BigInt::from(0) {
+            panic!("Transaction chain id must not be 0");
+        }
+        tx
+    }
+
+    fn signing_payload(&self) -> Vec<u8> {
+        match self.eip1559 {
+            true => [vec![0x02u8], Rlp::List(self.fields.clone()).encode()].concat(),
+            false => {
+                let mut fields = self.fields.clone();
+                fields.extend(vec![Rlp::integer(&self.chain_id), Rlp::Bytes(vec![]), Rlp::Bytes(vec![])]);
+                Rlp::List(fields).encode()
+            }
+        }
+    }
+
+    /// Hash the transaction signature is made over
+    pub fn signing_hash(&self) -> Vec<u8> {
+        keccak256(&self.signing_payload()).to_vec()
+    }
+
+    /// v of the signature: the y parity for EIP-1559, chainId * 2 + 35 + recid for EIP-155
+    pub fn v(&self, recid: u8) -> BigInt {
+        match self.eip1559 {
+            true => BigInt::from(recid as u64),
+            false => &(&self.chain_id * &BigInt::from(2)) + &BigInt::from(35 + recid as u64),
+        }
+    }
+
+    /// Raw signed transaction, ready for eth_sendRawTransaction
+    pub fn signed(&self, r: &BigInt, s: &BigInt, recid: u8) -> Vec<u8> {
+        let mut fields = self.fields.clone();
+        fields.extend(vec![Rlp::integer(&self.v(recid)), Rlp::integer(r), Rlp::integer(s)]);
+        match self.eip1559 {
+            true => [vec![0x02u8], Rlp::List(fields).encode()].concat(),
+            false => Rlp::List(fields).encode(),
+        }
+    }
+}
+
+/// Output of sign-eth-tx: the raw signed transaction and its hash
+pub fn signed_transaction_json(tx: &EthTransaction, sig: &SignatureRecid, context: &str) -> Value {
+    let (r, s) = (sig.r.to_big_int(), sig.s.to_big_int());
+    let raw = tx.signed(&r, &s, sig.recid);
+    let mut ret_dict = json!({
+        "status": "transaction_signed",
+        "type": if tx.eip1559 { "eip1559" } else { "legacy" },
+        "chain_id": tx.chain_id.to_str_radix(10),
+        "r": format!("0x{}", r.to_str_radix(16)),
+        "s": format!("0x{}", s.to_str_radix(16)),
+        "v": format!("0x{}", tx.v(sig.recid).to_str_radix(16)),
+        "raw_transaction": format!("0x{}", hex::encode(&raw)),
+        "hash": format!("0x{}", hex::encode(keccak256(&raw))),
+    });
+    if !context.is_empty() {
+        ret_dict["context"] = json!(context);
+    }
+    ret_dict
+}
+
+/// Address of a public key with the EIP-55 mixed case checksum: the last 20 bytes of keccak256 of x || y
+pub fn address(y_sum: &GE) -> String {
+    let hash = keccak256(&uncompressed_public_key(y_sum)[1..]);
+    checksum_address(&hash[12..])
+}
+
+/// EIP-55: a hex letter is upper case when the nibble at its place in keccak256 of the lower case address is 8 or more
+pub fn checksum_address(address: &[u8]) -> String {
+    let address = hex::encode(address);
+    let checksum = hex::encode(keccak256(address.as_bytes()));
+    let checksummed: String = address
+        .chars()
+        .zip(checksum.chars())
+        .map(|(c, check)| match check.to_digit(16).unwrap() >= 8 {
+            true => c.to_ascii_uppercase(),
+            false => c,
+        })
+        .collect();
+    format!("0x{}", checksummed)
+}
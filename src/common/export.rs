@@ -0,0 +1,54 @@
+use curv::elliptic::curves::secp256_k1::GE;
+use curv::elliptic::curves::traits::ECPoint;
+use serde_json::json;
+
+use crate::common::atomic_file;
+use crate::common::chain_code;
+use crate::common::encoding::compressed_public_key;
+use crate::common::keyfile::KeyFile;
+
+fn point_json(point: &GE) -> serde_json::Value {
+    json!({
+        "x": point.x_coor(),
+        "y": point.y_coor(),
+        "compressed": hex::encode(compressed_public_key(point)),
+    })
+}
+
+/// Rebuilds everything public about the key from a single keys file: public key, HD chain code point,
+/// VSS commitments, public shares x_i*G of all parties and their Paillier keys. Nothing secret is included,
+/// the output can provision watch-only systems
+pub fn export_public(keysfile_path: &str, out_path: Option<&str>) {
+    let keyfile = KeyFile::load(keysfile_path);
+    let parameters = &keyfile.vss_scheme_vec[0].parameters;
+    let public_shares: Vec<serde_json::Value> = (1..=keyfile.vss_scheme_vec.len() as u16)
+        .map(|party_id| json!({"party_id": party_id, "public_share": point_json(&keyfile.public_share(party_id))}))
+        .collect();
+    // The chain code stored in the keys file, files from before v6 have none and derive with the one given
+    let chain_code: GE = match &keyfile.chain_code {
+        Some(stored) => chain_code::point(Some(&stored[..])),
+        None => chain_code::chain_code_point(),
+    };
+
+    let mut export = json!({
+        "public_key": point_json(&keyfile.y_sum),
+        "threshold": parameters.threshold,
+        "parties": parameters.share_count,
+        "chain_code": point_json(&chain_code),
+        "public_shares": public_shares,
+        "vss_scheme_vec": keyfile.vss_scheme_vec,
+        "paillier_key_vec": keyfile.paillier_key_vec,
+        "exported_by": keyfile.party_id,
+    });
+    // Imported shares evaluated at other points than the party ids, the public shares are at these points
+    if let Some(share_points) = keyfile.metadata().share_points {
+        export["share_points"] = json!(share_points);
+    }
+    match out_path {
+        Some(out_path) => {
+            atomic_file::write_atomic(out_path, serde_json::to_string_pretty(&export).unwrap().as_bytes());
+            println!("{}", json!({"status": "public_exported", "out": out_path}).to_string());
+        }
+        None => println!("{}", export.to_string()),
+    }
+}
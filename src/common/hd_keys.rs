@@ -1,6 +1,9 @@
 extern crate curv;
 
 use curv::arithmetic::traits::Converter;
+
+use crate::common::chain_code;
+use crate::common::options;
 use curv::cryptographic_primitives::hashing::hmac_sha512;
 use curv::cryptographic_primitives::hashing::traits::KeyedHash;
 use curv::elliptic::curves::traits::*;
@@ -9,11 +12,114 @@ use curv::{
     elliptic::curves::secp256_k1::{FE, GE},
     arithmetic::{BasicOps, One}
 };
+use ring::hmac;
+
+/// Indexes of a derivation path are below 2^31. Every index is derived from the public key, as BIP32
+/// non-hardened indexes are: hardened derivation needs the private key, which no party holds
+pub const MAX_PATH_INDEX: u32 = (1 << 31) - 1;
+
+// Paths are user input, a bad one is reported like any other invalid argument
+fn invalid_path(message: String) -> clap::Error {
+    clap::Error::with_description(&message, clap::ErrorKind::InvalidValue)
+}
+
+fn path_index(index: &str, path: &str) -> Result<u32, clap::Error> {
+    let index = index.trim();
+    if index.ends_with('\'') || index.ends_with('h') || index.ends_with('H') {
+        return Err(invalid_path(format!(
+            "Hardened index {} in derivation path {} is not supported, the threshold key only derives non-hardened \
+             children. Use the index without the suffix, which derives a different key than {} does in BIP32 wallets",
+            index, path, index
+        )));
+    }
+    let n = index
+        .parse::<u64>()
+        .map_err(|_| invalid_path(format!("Invalid index {:?} in derivation path {}, use numbers separated by /", index, path)))?;
+    if n > MAX_PATH_INDEX as u64 {
+        return Err(invalid_path(format!(
+            "Index {} in derivation path {} is out of range, indexes go up to {}",
+            n, path, MAX_PATH_INDEX
+        )));
+    }
+    Ok(n as u32)
+}
+
+/// Indexes of a derivation path like 0/1/2, hardened (' or h suffixed) and out of range indexes are refused
+pub fn parse_path(path: &str) -> Result<Vec<BigInt>, clap::Error> {
+    path.split('/').map(|index| path_index(index, path).map(|index| BigInt::from(index as u64))).collect()
+}
+
+/// Derivation schemes of --derivation: tss-additive for the scheme of hd_key, slip10 for SLIP-0010
+/// (the same as BIP32 on secp256k1) public derivation
+pub const DERIVATIONS: [&str; 2] = ["tss-additive", "slip10"];
+
+pub fn derivation() -> String {
+    let derivation = options::get().derivation;
+    if !DERIVATIONS.contains(&derivation.as_str()) {
+        panic!("Unknown derivation {}, supported: {}", derivation, DERIVATIONS.join(", "));
+    }
+    derivation
+}
+
+/// Key reached by a SLIP-0010 derivation, with what an extended public key of it is made of
+pub struct Slip10Child {
+    pub public_key: GE,
+    /// Sum of the IL of every index, the tweak of the shares
+    pub tweak: FE,
+    pub chain_code: Vec<u8>,
+    /// Key the last index was derived from, None for the key itself
+    pub parent_key: Option<GE>,
+}
+
+/// SLIP-0010 / BIP32 CKDpub from the threshold key and the configured chain code, see slip10_derive_from
+pub fn slip10_derive(y_sum: &GE, path_vector: &[BigInt]) -> Slip10Child {
+    let chain_code = chain_code::chain_code()
+        .unwrap_or_else(|| panic!("slip10 derivation needs the chain code of the key, from --chain-code or --chain-code-mnemonic"));
+    slip10_derive_from(y_sum, chain_code, path_vector)
+}
+
+/// SLIP-0010 / BIP32 CKDpub from a key and chain code: each index adds IL*G, IL the left half of
+/// HMAC-SHA512(chain code, compressed key || index), and the right half is the next chain code. An IL of at least
+/// the group order is hashed again as HMAC-SHA512(chain code, 0x01 || IR || index), as SLIP-0010 does, where BIP32
+/// would skip the index. An empty path gives the key itself
+pub fn slip10_derive_from(y_sum: &GE, mut chain_code: Vec<u8>, path_vector: &[BigInt]) -> Slip10Child {
+    let g: GE = ECPoint::generator();
+    let mut public_key = *y_sum;
+    let mut tweak: FE = ECScalar::zero();
+    let mut parent_key = None;
+    for index in path_vector {
+        let index_bytes = BigInt::to_bytes(index);
+        let mut index_data = vec![0u8; 4 - index_bytes.len()];
+        index_data.extend(index_bytes);
+        let key = hmac::Key::new(hmac::HMAC_SHA512, &chain_code);
+        let mut data = BigInt::to_bytes(&public_key.bytes_compressed_to_big_int());
+        data.extend(&index_data);
+        let mut i = hmac::sign(&key, &data);
+        while BigInt::from_bytes(&i.as_ref()[..32]) >= FE::q() {
+            let data = [&[1u8][..], &i.as_ref()[32..], &index_data[..]].concat();
+            i = hmac::sign(&key, &data);
+        }
+        let i_l: FE = ECScalar::from(&BigInt::from_bytes(&i.as_ref()[..32]));
+        parent_key = Some(public_key);
+        public_key = public_key + g * &i_l;
+        tweak = tweak + &i_l;
+        chain_code = i.as_ref()[32..].to_vec();
+    }
+    Slip10Child { public_key, tweak, chain_code, parent_key }
+}
 
+// Child key and the tweak of the shares
+fn slip10_key(y_sum: &GE, path_vector: &[BigInt]) -> (GE, FE) {
+    let child = slip10_derive(y_sum, path_vector);
+    (child.public_key, child.tweak)
+}
 
 pub fn get_hd_key(y_sum: &GE, path_vector: Vec<BigInt>) -> (GE, FE) {
-    // generate a random but shared chain code, this will do
-    let chain_code = GE::generator();
+    if derivation() == "slip10" {
+        return slip10_key(y_sum, &path_vector);
+    }
+    // shared chain code: the generator, or the point of the configured chain code
+    let chain_code = chain_code::chain_code_point();
     //    println!("chain code {:?}", chain_code);
     // derive a new pubkey and LR sequence, y_sum becomes a new child pub key
     let (y_sum_child, f_l_new, _cc_new) = hd_key(
@@ -68,3 +174,61 @@ pub fn hd_key(
             });
     (public_key_new_child, f_l_new, cc_new)
 }
+
+/// Most paths a batch derivation expands to, against typos like an extra digit in a range end
+pub const MAX_BATCH_PATHS: usize = 1_000_000;
+
+fn path_indexes(path: &str) -> Result<Vec<u64>, clap::Error> {
+    path.split('/').map(|index| path_index(index, path).map(|index| index as u64)).collect()
+}
+
+/// Paths of a batch: a comma separated list, or @file for a file with one path per line (blank lines and
+/// # comments skipped). An item a/b/first..a/b/last, or a/b/first..last, is the range of paths with last indexes
+/// first to last, both included
+pub fn expand_paths(spec: &str) -> Result<Vec<String>, clap::Error> {
+    let items: Vec<String> = match spec.strip_prefix('@') {
+        Some(file) => std::fs::read_to_string(file)
+            .map_err(|e| invalid_path(format!("Unable to read paths file {}: {}", file, e)))?
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| line.to_string())
+            .collect(),
+        None => spec.split(',').map(|item| item.trim().to_string()).collect(),
+    };
+    let mut paths = Vec::new();
+    for item in items {
+        match item.split_once("..") {
+            Some((first, last)) => {
+                let first = path_indexes(first)?;
+                let mut last = path_indexes(last)?;
+                if last.len() == 1 {
+                    last = [&first[..first.len() - 1], &last[..]].concat();
+                }
+                let prefix = &first[..first.len() - 1];
+                if last.len() != first.len() || &last[..last.len() - 1] != prefix || last[last.len() - 1] < first[first.len() - 1] {
+                    return Err(invalid_path(format!(
+                        "Invalid path range {}, the ends must differ in the last index only, in increasing order",
+                        item
+                    )));
+                }
+                let (first, last) = (first[first.len() - 1], last[last.len() - 1]);
+                if paths.len() as u64 + (last - first + 1) > MAX_BATCH_PATHS as u64 {
+                    return Err(invalid_path(format!("More than {} paths in the batch", MAX_BATCH_PATHS)));
+                }
+                let prefix: Vec<String> = prefix.iter().map(|index| index.to_string()).collect();
+                for index in first..=last {
+                    paths.push([&prefix[..], &[index.to_string()]].concat().join("/"));
+                }
+            }
+            None => {
+                path_indexes(&item)?;
+                paths.push(item);
+            }
+        }
+        if paths.len() > MAX_BATCH_PATHS {
+            return Err(invalid_path(format!("More than {} paths in the batch", MAX_BATCH_PATHS)));
+        }
+    }
+    Ok(paths)
+}
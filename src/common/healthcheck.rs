@@ -0,0 +1,70 @@
+use std::time::Instant;
+
+use curv::{BigInt, arithmetic::Converter};
+use curv::cryptographic_primitives::hashing::hash_sha256::HSha256;
+use curv::cryptographic_primitives::hashing::traits::Hash;
+use curv::elliptic::curves::secp256_k1::GE;
+use curv::elliptic::curves::traits::ECPoint;
+use serde_json::json;
+
+use crate::common::canary::CANARY_PATH;
+use crate::common::keyfile::KeyFile;
+use crate::common::{check_sig, hd_keys, signer, take_round_timings, Params};
+
+// Same for all parties of one run, a new nonce gives every run its own room and digest
+fn healthcheck_message(y_sum: &GE, nonce: &str) -> Vec<u8> {
+    let public_key = BigInt::to_bytes(&y_sum.bytes_compressed_to_big_int());
+    let message = [b"tss-cli healthcheck".to_vec(), public_key, nonce.as_bytes().to_vec()].concat();
+    BigInt::to_bytes(&HSha256::create_hash_from_slice(&message))
+}
+
+/// Signs a test message with whichever quorum is online, at the canary path so a real key is never
+/// used, verifies the signature and reports the signing parties and how long each round took.
+/// All parties of a run give the same nonce
+pub fn run_healthcheck(addr: &String, keysfile_path: &str, params: &Params, signers: u16, nonce: &str) {
+    let keyfile = KeyFile::load(keysfile_path);
+    keyfile.check_signable(keysfile_path);
+    let message = healthcheck_message(&keyfile.y_sum, nonce);
+    let path_vector: Vec<BigInt> = hd_keys::parse_path(CANARY_PATH).unwrap();
+    let (y_child, f_l_new) = hd_keys::get_hd_key(&keyfile.y_sum, path_vector);
+
+    take_round_timings();
+    let started = Instant::now();
+    let mut vss_scheme_vec = keyfile.vss_scheme_vec.clone();
+    let sig = signer::sign(
+        addr.clone(),
+        keyfile.party_keys,
+        keyfile.shared_keys,
+        keyfile.party_id,
+        &mut vss_scheme_vec,
+        keyfile.paillier_key_vec,
+        &y_child,
+        params,
+        &message,
+        &f_l_new,
+        true,
+        signers,
+        &format!("healthcheck-{}", nonce),
+    );
+    let total_ms = started.elapsed().as_millis();
+    check_sig(&sig.r, &sig.s, &BigInt::from_bytes(&message), &y_child);
+
+    let rounds: Vec<serde_json::Value> = take_round_timings()
+        .iter()
+        .map(|(round, wait_ms)| json!({"round": round, "wait_ms": wait_ms}))
+        .collect();
+    let parties = params.parties.parse::<u16>().unwrap();
+    let signed_by = signer::last_signers();
+    let offline: Vec<u16> = (1..=parties).filter(|id| !signed_by.contains(id)).collect();
+    let ret_dict = json!({
+        "status": "healthy",
+        "party_id": keyfile.party_id,
+        "signers": signed_by,
+        "not_participating": offline,
+        "rounds": rounds,
+        "total_ms": total_ms,
+        "path": CANARY_PATH,
+        "nonce": nonce,
+    });
+    println!("{}", ret_dict.to_string());
+}
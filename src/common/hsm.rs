@@ -0,0 +1,170 @@
+use std::convert::TryInto;
+use std::sync::Mutex;
+
+use cryptoki::context::{CInitializeArgs, Pkcs11};
+use cryptoki::mechanism::aead::GcmParams;
+use cryptoki::mechanism::Mechanism;
+use cryptoki::object::{Attribute, ObjectClass};
+use cryptoki::session::{Session, SessionFlags, UserType};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::common::keyfile::{restore_secret, take_secret, KeyFile};
+use crate::common::keys_lock::KeysFileLock;
+use crate::common::options;
+
+/// User PIN of the token, asked on the terminal when not set
+pub const PKCS11_PIN_ENV: &str = "TSS_CLI_PKCS11_PIN";
+
+/// AES key on a token wrapping the secret fields of keys files
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct HsmKey {
+    pub module: String,
+    pub key_label: String,
+}
+
+/// PKCS#11 mechanism secrets are wrapped with, authenticated so a modified wrap fails to unwrap
+pub const WRAP_MECHANISM: &str = "CKM_AES_GCM";
+/// Unauthenticated mechanism of wraps written before the mechanism was recorded, only unwrapped
+const LEGACY_WRAP_MECHANISM: &str = "CKM_AES_CBC_PAD";
+const GCM_TAG_BITS: u64 = 128;
+// Binds the wrap to its use, a wrapped presignature or share can't be replayed into another format
+const WRAP_AAD: &[u8] = b"tss-cli hsm wrap";
+
+/// Secret fields of a keys file wrapped by the HSM, binary fields in hex. wrapped ends with the GCM tag
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct HsmWrapped {
+    pub module: String,
+    pub key_label: String,
+    #[serde(default = "legacy_wrap_mechanism")]
+    pub mechanism: String,
+    pub iv: String,
+    pub wrapped: String,
+}
+
+fn legacy_wrap_mechanism() -> String {
+    LEGACY_WRAP_MECHANISM.to_string()
+}
+
+// Key keys files are saved with, set by hsm wrap, keygen or loading an HSM wrapped keys file
+static HSM_KEY: Mutex<Option<HsmKey>> = Mutex::new(None);
+
+pub fn is_hsm_file(data: &Value) -> bool {
+    data.get("hsm").is_some()
+}
+
+pub fn use_hsm(key: Option<HsmKey>) {
+    *HSM_KEY.lock().unwrap() = key;
+}
+
+pub fn hsm_key() -> Option<HsmKey> {
+    HSM_KEY.lock().unwrap().clone()
+}
+
+/// HSM key from --pkcs11-module and the key label
+pub fn hsm_key_from_options(key_label: &str) -> HsmKey {
+    HsmKey {
+        module: options::get().pkcs11_module.expect("PKCS#11 module missing, use --pkcs11-module"),
+        key_label: key_label.to_string(),
+    }
+}
+
+fn pin() -> String {
+    std::env::var(PKCS11_PIN_ENV).unwrap_or_else(|_| {
+        rpassword::read_password_from_tty(Some("Token PIN: ")).expect("Unable to read PIN")
+    })
+}
+
+// Logged in session on the first slot with a token, with the handle of the wrapping key
+fn open_session(key: &HsmKey) -> (Session, cryptoki::object::ObjectHandle) {
+    let pkcs11 = Pkcs11::new(&key.module).unwrap_or_else(|e| panic!("Unable to load PKCS#11 module {}: {}", key.module, e));
+    pkcs11.initialize(CInitializeArgs::OsThreads).expect("Unable to initialize PKCS#11 module");
+    let slot = *pkcs11
+        .get_slots_with_token()
+        .expect("Unable to list PKCS#11 slots")
+        .first()
+        .expect("No token found");
+    let mut flags = SessionFlags::new();
+    flags.set_serial_session(true);
+    let session = pkcs11.open_session_no_callback(slot, flags).expect("Unable to open token session");
+    session.login(UserType::User, Some(&pin())).expect("Token login failed");
+    let handle = *session
+        .find_objects(&[Attribute::Class(ObjectClass::SECRET_KEY), Attribute::Label(key.key_label.as_bytes().to_vec())])
+        .expect("Unable to search token objects")
+        .first()
+        .unwrap_or_else(|| panic!("No secret key labelled {} on the token", key.key_label));
+    (session, handle)
+}
+
+/// Wraps the secret fields of keys file JSON with the HSM key, returns what is left for the disk
+pub fn wrap(mut data: Value, key: &HsmKey) -> Value {
+    let secret = take_secret(&mut data);
+    data["hsm"] = serde_json::to_value(wrap_bytes(secret.to_string().as_bytes(), key)).unwrap();
+    data
+}
+
+/// Unwraps the secret fields of an HSM wrapped keys file in memory
+pub fn unwrap(mut data: Value) -> Value {
+    let wrapped: HsmWrapped = serde_json::from_value(data["hsm"].clone()).expect("Invalid HSM wrapped keys file");
+    let secret = unwrap_bytes(&wrapped);
+    restore_secret(&mut data, &serde_json::from_slice(&secret).expect("Invalid HSM wrapped share"));
+    data.as_object_mut().unwrap().remove("hsm");
+    use_hsm(Some(HsmKey { module: wrapped.module, key_label: wrapped.key_label }));
+    data
+}
+
+/// Wraps secret material with the HSM key, AES-GCM with a random 96 bit nonce
+pub fn wrap_bytes(secret: &[u8], key: &HsmKey) -> HsmWrapped {
+    let mut iv = [0u8; 12];
+    SystemRandom::new().fill(&mut iv).expect("Unable to get random bytes");
+    let (session, handle) = open_session(key);
+    let wrapped = session
+        .encrypt(&Mechanism::AesGcm(GcmParams::new(&iv, WRAP_AAD, GCM_TAG_BITS.into())), handle, secret)
+        .unwrap_or_else(|e| panic!("Token refused to wrap the share: {}", e));
+    HsmWrapped {
+        module: key.module.clone(),
+        key_label: key.key_label.clone(),
+        mechanism: WRAP_MECHANISM.to_string(),
+        iv: hex::encode(iv),
+        wrapped: hex::encode(wrapped),
+    }
+}
+
+/// Secret material wrapped by wrap_bytes. Wraps of older versions (CKM_AES_CBC_PAD) are still read,
+/// the next save wraps them with AES-GCM
+pub fn unwrap_bytes(wrapped: &HsmWrapped) -> Vec<u8> {
+    let key = HsmKey { module: wrapped.module.clone(), key_label: wrapped.key_label.clone() };
+    let iv = hex::decode(&wrapped.iv).expect("Invalid HSM wrapping iv");
+    let ciphertext = hex::decode(&wrapped.wrapped).expect("Invalid HSM wrapped share");
+    let (session, handle) = open_session(&key);
+    let plaintext = match wrapped.mechanism.as_str() {
+        WRAP_MECHANISM if iv.len() == 12 => {
+            session.decrypt(&Mechanism::AesGcm(GcmParams::new(&iv, WRAP_AAD, GCM_TAG_BITS.into())), handle, &ciphertext)
+        }
+        LEGACY_WRAP_MECHANISM => {
+            let iv: [u8; 16] = iv.try_into().expect("Invalid HSM wrapping iv");
+            session.decrypt(&Mechanism::AesCbcPad(iv), handle, &ciphertext)
+        }
+        mechanism => panic!("Unsupported HSM wrapping mechanism {} or iv", mechanism),
+    };
+    plaintext.unwrap_or_else(|e| panic!("Token refused to unwrap the share, it was modified or wrapped by another key: {}", e))
+}
+
+/// Rewrites a keys file with its secret fields wrapped by the HSM key
+pub fn wrap_keys_file(keysfile_path: &str, key: HsmKey) {
+    let _lock = KeysFileLock::acquire(keysfile_path, "hsm wrap");
+    let keyfile = KeyFile::load(keysfile_path);
+    use_hsm(Some(key.clone()));
+    keyfile.replace(keysfile_path);
+    println!("{}", json!({"status": "share_wrapped", "keysfile": keysfile_path, "key_label": key.key_label}).to_string());
+}
+
+/// Rewrites an HSM wrapped keys file with its secret fields in the clear again
+pub fn unwrap_keys_file(keysfile_path: &str) {
+    let _lock = KeysFileLock::acquire(keysfile_path, "hsm unwrap");
+    let keyfile = KeyFile::load(keysfile_path);
+    use_hsm(None);
+    keyfile.replace(keysfile_path);
+    println!("{}", json!({"status": "share_unwrapped", "keysfile": keysfile_path}).to_string());
+}
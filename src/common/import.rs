@@ -0,0 +1,85 @@
+use std::fs;
+
+use curv::{
+    arithmetic::traits::Converter,
+    cryptographic_primitives::secret_sharing::feldman_vss::{ShamirSecretSharing, VerifiableSS},
+    elliptic::curves::secp256_k1::{FE, GE},
+    elliptic::curves::traits::{ECPoint, ECScalar},
+    BigInt,
+};
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2018::party_i::{Keys, SharedKeys};
+use serde_json::json;
+
+use crate::common::chain_code;
+use crate::common::keyfile::KeyFile;
+use crate::common::reshare::{run_reshare, save_reshare_result, OldShare};
+use crate::common::Params;
+
+/// Reads a secp256k1 private key stored as 64 hex characters
+pub fn load_private_key(private_key_path: &str) -> FE {
+    let data = fs::read_to_string(private_key_path).expect("Unable to load private key !");
+    let raw_key = hex::decode(data.trim()).expect("Private key must be in hex format");
+    if raw_key.len() != 32 {
+        panic!("Private key must be 32 bytes");
+    }
+    let k = BigInt::from_bytes(&raw_key);
+    if k == BigInt::from(0) || k >= FE::q() {
+        panic!("Private key is not a valid secp256k1 scalar");
+    }
+    ECScalar::from(&k)
+}
+
+/// The whole key as a 1 of 1 sharing, so the dealer can reshare it like any old share holder
+fn dealer_share(secret: FE) -> OldShare {
+    let g: GE = ECPoint::generator();
+    let y_sum = g * &secret;
+    let party_keys = Keys::create(1);
+    let paillier_key_vec = vec![party_keys.ek.clone()];
+    let keyfile = KeyFile {
+        party_keys,
+        shared_keys: SharedKeys { y: y_sum, x_i: secret },
+        party_id: 1,
+        vss_scheme_vec: vec![VerifiableSS {
+            parameters: ShamirSecretSharing { threshold: 0, share_count: 1 },
+            commitments: vec![y_sum],
+        }],
+        paillier_key_vec,
+        y_sum,
+        chain_code: chain_code::chain_code(),
+        canary: None,
+        share_points: None,
+    };
+    OldShare {
+        keyfile,
+        params: Params { threshold: "0".to_string(), parties: "1".to_string() },
+    }
+}
+
+/// Trusted dealer import: the dealer holding the private key deals it to n parties, each party
+/// generates its own Paillier key and receives a share verified against the dealer's commitments.
+/// The resulting keys files are the same as after keygen for the signer
+pub fn import_key(addr: &String, room: &str, params: &Params, private_key_path: Option<&str>, party_id: Option<u16>, out_path: Option<&str>) {
+    let parties = params.parties.parse::<u16>().unwrap();
+    let room = format!("import-{}", room);
+    match (private_key_path, party_id) {
+        (Some(private_key_path), None) => {
+            let old_share = dealer_share(load_private_key(private_key_path));
+            let result = run_reshare(addr, &room, parties + 1, Some(old_share), None, params);
+            println!(
+                "{}",
+                json!({
+                    "status": "key_dealt",
+                    "parties": result.new_parties,
+                    "x": result.y_sum.x_coor(),
+                    "y": result.y_sum.y_coor(),
+                })
+                .to_string()
+            );
+        }
+        (None, Some(party_id)) => {
+            let result = run_reshare(addr, &room, parties + 1, None, Some(party_id), params);
+            save_reshare_result(addr, result, out_path, params);
+        }
+        _ => panic!("Import runs either as the dealer with a private key or as a party with a party id"),
+    }
+}
@@ -0,0 +1,132 @@
+use std::fs;
+
+use ring::digest::{digest, SHA256};
+use ring::{constant_time, hkdf, hmac};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::common::key_encryption;
+use crate::common::options;
+
+/// MAC over a keys file without its integrity field. key names what the MAC is keyed with:
+/// "file:<key id>" for an integrity key, "passphrase" for the keys file passphrase, "none" for a plain
+/// sha256 checksum that only catches corruption
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Integrity {
+    pub alg: String,
+    pub key: String,
+    pub mac: String,
+}
+
+fn integrity_key() -> Option<Vec<u8>> {
+    // File holding the hex integrity key, from --integrity-key
+    let path = options::get().integrity_key?;
+    let key = hex::decode(fs::read_to_string(&path).expect("Unable to read integrity key !").trim())
+        .expect("Integrity key must be in hex format");
+    if key.len() < 32 {
+        panic!("Integrity key must be at least 32 bytes");
+    }
+    Some(key)
+}
+
+// First 8 bytes of sha256 of the key, tells which key a file was MACed with without revealing it
+fn key_id(key: &[u8]) -> String {
+    hex::encode(&digest(&SHA256, key).as_ref()[..8])
+}
+
+fn passphrase_key(passphrase: &str) -> Vec<u8> {
+    let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, b"tss-cli-keys-file-integrity").extract(passphrase.as_bytes());
+    let mut key = [0u8; 32];
+    prk.expand(&[b"hmac-sha256"], hkdf::HKDF_SHA256)
+        .and_then(|okm| okm.fill(&mut key))
+        .expect("HKDF failure!");
+    key.to_vec()
+}
+
+fn mac(data: &Value, key: Option<&[u8]>) -> String {
+    let content = serde_json::to_vec(data).unwrap();
+    match key {
+        Some(key) => hex::encode(hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, key), &content).as_ref()),
+        None => hex::encode(digest(&SHA256, &content).as_ref()),
+    }
+}
+
+/// Adds the integrity field to keys file JSON, keyed with the integrity key if one is given,
+/// the passphrase the file is saved with otherwise
+pub fn seal(data: &mut Value, passphrase: Option<String>) {
+    let integrity = match (integrity_key(), passphrase) {
+        (Some(key), _) => Integrity {
+            alg: "hmac-sha256".to_string(),
+            key: format!("file:{}", key_id(&key)),
+            mac: mac(data, Some(&key)),
+        },
+        (None, Some(passphrase)) => Integrity {
+            alg: "hmac-sha256".to_string(),
+            key: "passphrase".to_string(),
+            mac: mac(data, Some(&passphrase_key(&passphrase))),
+        },
+        (None, None) => Integrity { alg: "sha256".to_string(), key: "none".to_string(), mac: mac(data, None) },
+    };
+    data["integrity"] = serde_json::to_value(integrity).unwrap();
+}
+
+/// Removes and checks the integrity field of keys file JSON, panics naming the keys file when it is
+/// missing or does not match, before anything in the file is used
+pub fn verify(data: &mut Value, keysfile_path: &str) {
+    let integrity = data.as_object_mut().and_then(|fields| fields.remove("integrity")).unwrap_or_else(|| {
+        panic!("Keys file {} has no integrity MAC, it was stripped or the file is damaged", keysfile_path)
+    });
+    let integrity: Integrity = serde_json::from_value(integrity)
+        .unwrap_or_else(|e| panic!("Keys file {} has an invalid integrity field: {}", keysfile_path, e));
+    let expected = match (integrity.alg.as_str(), integrity.key.as_str()) {
+        ("sha256", "none") => mac(data, None),
+        ("hmac-sha256", "passphrase") => mac(data, Some(&passphrase_key(&key_encryption::passphrase()))),
+        ("hmac-sha256", key) if key.starts_with("file:") => {
+            let integrity_key = integrity_key()
+                .unwrap_or_else(|| panic!("Keys file {} is protected with integrity key {}, use --integrity-key", keysfile_path, &key[5..]));
+            if format!("file:{}", key_id(&integrity_key)) != key {
+                panic!("Keys file {} is protected with integrity key {}, not {}", keysfile_path, &key[5..], key_id(&integrity_key));
+            }
+            mac(data, Some(&integrity_key))
+        }
+        (alg, key) => panic!("Keys file {} has an unsupported integrity MAC {} keyed with {}", keysfile_path, alg, key),
+    };
+    if constant_time::verify_slices_are_equal(expected.as_bytes(), integrity.mac.as_bytes()).is_err() {
+        panic!(
+            "Keys file {} failed its integrity check, it was modified or is corrupted. Restore it from a backup",
+            keysfile_path
+        );
+    }
+}
+
+/// Re-MACs plaintext keys file JSON keyed with the old passphrase for a new one, other MACs are kept
+pub fn rekey(plaintext: &[u8], old_passphrase: &str, new_passphrase: &str) -> Vec<u8> {
+    let mut data: Value = match serde_json::from_slice(plaintext) {
+        Ok(data) => data,
+        Err(_) => return plaintext.to_vec(),
+    };
+    if data["integrity"]["key"] != "passphrase" {
+        return plaintext.to_vec();
+    }
+    let integrity = data.as_object_mut().unwrap().remove("integrity").unwrap();
+    if integrity["mac"] != Value::String(mac(&data, Some(&passphrase_key(old_passphrase)))) {
+        panic!("Keys file failed its integrity check, it was modified or is corrupted. Restore it from a backup");
+    }
+    data["integrity"] = serde_json::to_value(Integrity {
+        alg: "hmac-sha256".to_string(),
+        key: "passphrase".to_string(),
+        mac: mac(&data, Some(&passphrase_key(new_passphrase))),
+    })
+    .unwrap();
+    serde_json::to_vec(&data).unwrap()
+}
+
+/// Keys file JSON without its integrity field, to compare files MACed with different keys
+pub fn without_integrity(plaintext: &[u8]) -> Value {
+    let mut data: Value = serde_json::from_slice(plaintext).expect("Invalid keys file");
+    if let Some(fields) = data.as_object_mut() {
+        fields.remove("integrity");
+    }
+    data
+}
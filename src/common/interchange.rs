@@ -0,0 +1,338 @@
+use std::fs;
+
+use curv::{
+    arithmetic::traits::{Converter, Modulo},
+    cryptographic_primitives::secret_sharing::feldman_vss::{ShamirSecretSharing, VerifiableSS},
+    elliptic::curves::secp256_k1::{FE, GE},
+    elliptic::curves::traits::{ECPoint, ECScalar},
+    BigInt,
+};
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2018::party_i::{Keys, SharedKeys};
+use paillier::{DecryptionKey, EncryptionKey};
+use serde_json::{json, Value};
+
+use crate::common::atomic_file;
+use crate::common::chain_code;
+use crate::common::keyfile::{KeyFile, KEYS_FILE_CURVE};
+use crate::common::keys_lock::KeysFileLock;
+use crate::common::reshare::aggregate_vss_scheme_vec;
+use crate::common::verify_share::share_failures;
+
+/// Key share formats of other tools `import` reads
+pub const IMPORT_FORMATS: [&str; 2] = ["tss-lib", "zengo"];
+/// Key share formats of other tools `export` writes
+pub const EXPORT_FORMATS: [&str; 1] = ["zengo"];
+
+/// Keys file of ZenGo-X multi-party-ecdsa gg18_keygen_client / gg18_sign_client and tooling built on it:
+/// (party_keys, shared_keys, party_id, vss_scheme_vec, paillier_key_vec, y_sum)
+type ZengoKeyFile = (Keys, SharedKeys, u16, Vec<VerifiableSS<GE>>, Vec<EncryptionKey>, GE);
+
+// Go marshals big.Int as bare JSON numbers, serde_json keeps them digit for digit with arbitrary_precision.
+// Quoted numbers are read as well
+fn big_int(value: &Value, field: &str) -> BigInt {
+    let digits = match value {
+        Value::Number(number) => number.to_string(),
+        Value::String(digits) => digits.clone(),
+        _ => panic!("tss-lib key share field {} is missing", field),
+    };
+    if digits.starts_with('-') {
+        panic!("tss-lib key share field {} is negative", field);
+    }
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        panic!("tss-lib key share field {} is not an integer", field);
+    }
+    BigInt::from_str_radix(&digits, 10).unwrap_or_else(|_| panic!("tss-lib key share field {} is not a number", field))
+}
+
+fn scalar(value: &BigInt) -> FE {
+    ECScalar::from(value)
+}
+
+// tss-lib ECPoint, {"Curve": "secp256k1", "Coords": [x, y]}. Older tss-lib versions leave out the curve
+fn point(value: &Value, field: &str) -> GE {
+    match value["Curve"].as_str() {
+        None | Some(KEYS_FILE_CURVE) => {}
+        Some(curve) => panic!("tss-lib key share {} is on curve {}, only {} is supported", field, curve, KEYS_FILE_CURVE),
+    }
+    let x = big_int(&value["Coords"][0], field);
+    let y = big_int(&value["Coords"][1], field);
+    let point = GE::from_coor(&x, &y);
+    if point.x_coor() != Some(x) || point.y_coor() != Some(y) {
+        panic!("tss-lib key share {} is not a point on {}", field, KEYS_FILE_CURVE);
+    }
+    point
+}
+
+// Integer square root, Newton's method
+fn isqrt(n: &BigInt) -> BigInt {
+    if *n < BigInt::from(2) {
+        return n.clone();
+    }
+    let two = BigInt::from(2);
+    let mut x = n.clone();
+    let mut y = (&x + &BigInt::from(1)) / &two;
+    while y < x {
+        x = y;
+        y = (&x + &(n / &x)) / &two;
+    }
+    x
+}
+
+// Paillier primes of a tss-lib PaillierSK. Newer versions store P and Q, older ones only N and PhiN = (P-1)(Q-1),
+// from which P + Q = N - PhiN + 1 and P and Q are the roots of x^2 - (P + Q)x + N
+fn paillier_key(value: &Value) -> (DecryptionKey, EncryptionKey) {
+    let n = big_int(&value["N"], "PaillierSK.N");
+    let (p, q) = match (value.get("P").filter(|p| !p.is_null()), value.get("Q").filter(|q| !q.is_null())) {
+        (Some(p), Some(q)) => (big_int(p, "PaillierSK.P"), big_int(q, "PaillierSK.Q")),
+        _ => {
+            let phi_n = big_int(&value["PhiN"], "PaillierSK.PhiN");
+            let sum = &(&n - &phi_n) + &BigInt::from(1);
+            let discriminant = &(&sum * &sum) - &(&n * &BigInt::from(4));
+            let root = isqrt(&discriminant);
+            let two = BigInt::from(2);
+            (&(&sum + &root) / &two, &(&sum - &root) / &two)
+        }
+    };
+    if &p * &q != n {
+        panic!("tss-lib key share Paillier secret key does not match its public key");
+    }
+    let nn = &n * &n;
+    (DecryptionKey { p, q }, EncryptionKey { n, nn })
+}
+
+// Commitments to the coefficients of the sharing polynomial from its values in the exponent at the given points,
+// through the coefficients of the Lagrange basis polynomials
+fn commitments_from_points(points: &[(FE, GE)]) -> Vec<GE> {
+    let degree = points.len();
+    let mut commitments: Vec<Option<GE>> = vec![None; degree];
+    for (j, (x_j, point_j)) in points.iter().enumerate() {
+        let mut coefficients: Vec<FE> = vec![scalar(&BigInt::from(1))];
+        let mut denominator = scalar(&BigInt::from(1));
+        for (_, (x_m, _)) in points.iter().enumerate().filter(|(m, _)| *m != j) {
+            let minus_x_m = scalar(&(FE::q() - x_m.to_big_int()));
+            let mut next = vec![FE::zero(); coefficients.len() + 1];
+            for (k, coefficient) in coefficients.iter().enumerate() {
+                next[k + 1] = next[k + 1] + *coefficient;
+                next[k] = next[k] + *coefficient * minus_x_m;
+            }
+            coefficients = next;
+            denominator = denominator * scalar(&BigInt::mod_sub(&x_j.to_big_int(), &x_m.to_big_int(), &FE::q()));
+        }
+        let denominator = denominator.invert();
+        for (k, coefficient) in coefficients.iter().enumerate() {
+            let term = *point_j * &(*coefficient * denominator);
+            commitments[k] = Some(match commitments[k] {
+                Some(sum) => sum + term,
+                None => term,
+            });
+        }
+    }
+    commitments.into_iter().map(|commitment| commitment.unwrap()).collect()
+}
+
+fn evaluate_commitments(commitments: &[GE], x: &FE) -> GE {
+    let (head, tail) = commitments.split_at(1);
+    let mut power = *x;
+    tail.iter().fold(head[0], |acc, commitment| {
+        let term = *commitment * &power;
+        power = power * *x;
+        acc + term
+    })
+}
+
+// tss-lib does not keep the threshold, it is the lowest polynomial degree all public shares fit
+fn vss_from_public_shares(public_shares: &[(FE, GE)]) -> VerifiableSS<GE> {
+    let parties = public_shares.len();
+    for threshold in 0..parties {
+        let commitments = commitments_from_points(&public_shares[..threshold + 1]);
+        if public_shares.iter().all(|(x, share)| evaluate_commitments(&commitments, x) == *share) {
+            return VerifiableSS {
+                parameters: ShamirSecretSharing { threshold, share_count: parties },
+                commitments,
+            };
+        }
+    }
+    unreachable!("n points always fit a polynomial of degree n-1")
+}
+
+/// Converts a bnb-chain/tss-lib ECDSA (GG18 and GG20) keygen save data file. tss-lib evaluates shares at the
+/// party keys Ks, the party id here is the position of the share's key in Ks. Shares at keys other than 1..n keep
+/// their keys as share points and only deal in a reshare onto the party ids, signing evaluates shares at 1..n.
+/// The Paillier keys are taken over, tss-lib's NTilde range proof parameters are not used by this protocol
+fn from_tss_lib(data: &Value) -> KeyFile {
+    if data.get("EDDSAPub").is_some() {
+        panic!("tss-lib EdDSA key shares are not supported, only ECDSA");
+    }
+    let ks: Vec<BigInt> = data["Ks"]
+        .as_array()
+        .expect("tss-lib key share has no party keys (Ks)")
+        .iter()
+        .map(|k| big_int(k, "Ks"))
+        .collect();
+    let parties = ks.len();
+    if ks.iter().enumerate().any(|(i, k)| *k == BigInt::from(0) || *k >= FE::q() || ks[..i].contains(k)) {
+        panic!("tss-lib key share party keys (Ks) must be distinct and between 1 and the curve order");
+    }
+    let share_id = big_int(&data["ShareID"], "ShareID");
+    let index = ks
+        .iter()
+        .position(|k| *k == share_id)
+        .expect("tss-lib key share ShareID is not one of its party keys");
+
+    let public_shares: Vec<GE> = data["BigXj"]
+        .as_array()
+        .filter(|points| points.len() == parties)
+        .expect("tss-lib key share must have a public share (BigXj) per party")
+        .iter()
+        .map(|point_j| point(point_j, "BigXj"))
+        .collect();
+    let y_sum = point(&data["ECDSAPub"], "ECDSAPub");
+    let x_i = scalar(&big_int(&data["Xi"], "Xi"));
+    let g: GE = ECPoint::generator();
+    if g * &x_i != public_shares[index] {
+        panic!("tss-lib key share secret Xi does not match its public share");
+    }
+    let points: Vec<(FE, GE)> = ks.iter().map(scalar).zip(public_shares.iter().copied()).collect();
+    let vss_scheme = vss_from_public_shares(&points);
+    if vss_scheme.commitments[0] != y_sum {
+        panic!("tss-lib key share public shares do not add up to its public key");
+    }
+
+    let (dk, ek) = paillier_key(&data["PaillierSK"]);
+    let paillier_key_vec: Vec<EncryptionKey> = data["PaillierPKs"]
+        .as_array()
+        .filter(|keys| keys.len() == parties)
+        .expect("tss-lib key share must have a Paillier key (PaillierPKs) per party")
+        .iter()
+        .map(|key| {
+            let n = big_int(&key["N"], "PaillierPKs");
+            let nn = &n * &n;
+            EncryptionKey { n, nn }
+        })
+        .collect();
+    if paillier_key_vec[index].n != ek.n {
+        panic!("tss-lib key share Paillier key is not the one listed for its party");
+    }
+
+    // u_i is this party's keygen contribution, only used during keygen. The share stands in for it
+    let party_keys = Keys { u_i: x_i, y_i: g * &x_i, dk, ek, party_index: index + 1 };
+    KeyFile {
+        party_keys,
+        shared_keys: SharedKeys { y: y_sum, x_i },
+        party_id: index as u16 + 1,
+        vss_scheme_vec: aggregate_vss_scheme_vec(&vec![vss_scheme], parties as u16),
+        paillier_key_vec,
+        y_sum,
+        chain_code: chain_code::chain_code(),
+        canary: None,
+        share_points: match ks.iter().enumerate().all(|(i, k)| *k == BigInt::from(i as u64 + 1)) {
+            true => None,
+            false => Some(ks),
+        },
+    }
+}
+
+fn from_zengo(content: &str, input_path: &str) -> KeyFile {
+    let (party_keys, shared_keys, party_id, vss_scheme_vec, paillier_key_vec, y_sum): ZengoKeyFile =
+        serde_json::from_str(content)
+            .unwrap_or_else(|e| panic!("Key share file {} is not a ZenGo-X gg18 keys file: {}", input_path, e));
+    KeyFile {
+        party_keys,
+        shared_keys,
+        party_id,
+        vss_scheme_vec,
+        paillier_key_vec,
+        y_sum,
+        chain_code: chain_code::chain_code(),
+        canary: None,
+        share_points: None,
+    }
+}
+
+fn to_zengo(keyfile: &KeyFile) -> String {
+    let zengo: ZengoKeyFile = (
+        keyfile.party_keys.clone(),
+        keyfile.shared_keys.clone(),
+        keyfile.party_id,
+        keyfile.vss_scheme_vec.clone(),
+        keyfile.paillier_key_vec.clone(),
+        keyfile.y_sum,
+    );
+    serde_json::to_string(&zengo).unwrap()
+}
+
+// A converted share must be consistent on its own and keep the public key, checked before anything is written
+fn check_converted(keyfile: &KeyFile, y_sum: &GE, what: &str) {
+    let failures = share_failures(keyfile);
+    if !failures.is_empty() {
+        panic!("{} is inconsistent, failed checks: {}", what, failures.join(", "));
+    }
+    if keyfile.y_sum != *y_sum {
+        panic!("{} does not have the public key of the original share", what);
+    }
+}
+
+/// Converts a key share file of another tool into a keys file, saved like any keys file
+/// (encrypted when a passphrase, KMS key or age recipients are in use)
+pub fn import(format: &str, input_path: &str, out_path: &str) {
+    let content = fs::read_to_string(input_path).expect("Unable to load key share file !");
+    let keyfile = match format {
+        "tss-lib" => {
+            let data: Value = serde_json::from_str(&content)
+                .unwrap_or_else(|e| panic!("Key share file {} is not a tss-lib key share: {}", input_path, e));
+            from_tss_lib(&data)
+        }
+        "zengo" => from_zengo(&content, input_path),
+        _ => panic!("Unknown key share format {}, supported: {}", format, IMPORT_FORMATS.join(", ")),
+    };
+    check_converted(&keyfile, &keyfile.y_sum, &format!("Key share {}", input_path));
+    let _lock = KeysFileLock::acquire(out_path, "import");
+    keyfile.replace(out_path);
+    let metadata = keyfile.metadata();
+    println!(
+        "{}",
+        json!({
+            "status": "keys_imported",
+            "format": format,
+            "keysfile": out_path,
+            "party_id": keyfile.party_id,
+            "threshold": metadata.threshold,
+            "parties": metadata.parties,
+            "fingerprint": metadata.fingerprint,
+            "x": keyfile.y_sum.x_coor(),
+            "y": keyfile.y_sum.y_coor(),
+        })
+        .to_string()
+    );
+}
+
+/// Writes a keys file in the key share format of another tool. The output is plaintext, as the other tools read it,
+/// and is read back and checked against the original public key before the command reports success
+pub fn export(format: &str, keysfile_path: &str, out_path: &str) {
+    if out_path == keysfile_path {
+        panic!("Write the exported key share next to the keys file, it is not replaced");
+    }
+    let keyfile = KeyFile::load(keysfile_path);
+    if keyfile.share_points.is_some() {
+        panic!("Keys file {} holds an imported share not evaluated at the party ids, reshare it before exporting", keysfile_path);
+    }
+    let content = match format {
+        "zengo" => to_zengo(&keyfile),
+        _ => panic!("Unknown key share format {}, supported: {}", format, EXPORT_FORMATS.join(", ")),
+    };
+    check_converted(&from_zengo(&content, out_path), &keyfile.y_sum, &format!("Exported key share {}", out_path));
+    atomic_file::write_atomic(out_path, content.as_bytes());
+    println!(
+        "{}",
+        json!({
+            "status": "keys_exported",
+            "format": format,
+            "out": out_path,
+            "party_id": keyfile.party_id,
+            "encrypted": false,
+            "x": keyfile.y_sum.x_coor(),
+            "y": keyfile.y_sum.y_coor(),
+        })
+        .to_string()
+    );
+}
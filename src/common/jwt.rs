@@ -0,0 +1,68 @@
+use std::fs;
+
+use curv::elliptic::curves::traits::ECScalar;
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2018::party_i::SignatureRecid;
+use ring::digest::{digest, SHA256};
+use serde_json::{json, Value};
+
+use crate::common::encoding::to_32_bytes;
+
+fn base64url(data: &[u8]) -> String {
+    base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+}
+
+fn load_object(input: &str, what: &str) -> Value {
+    let content = fs::read_to_string(input).unwrap_or_else(|_| input.to_string());
+    let value: Value = serde_json::from_str(&content).unwrap_or_else(|_| panic!("JWT {} must be a JSON file or JSON", what));
+    if !value.is_object() {
+        panic!("JWT {} must be a JSON object", what);
+    }
+    value
+}
+
+/// JWS with the ES256K algorithm (RFC 8812): header and claims base64url encoded, the sha256 of
+/// header.claims signed
+pub struct Jwt {
+    pub signing_input: String,
+}
+
+impl Jwt {
+    /// Claims and header from files or JSON strings. The header defaults to {"alg":"ES256K","typ":"JWT"},
+    /// a given header gets alg ES256K and must not name another algorithm
+    pub fn load(claims: &str, header: Option<&str>) -> Self {
+        let claims = load_object(claims, "claims");
+        let mut header = header.map_or(json!({"typ": "JWT"}), |header| load_object(header, "header"));
+        match header.get("alg").and_then(|alg| alg.as_str()) {
+            None | Some("ES256K") => header["alg"] = json!("ES256K"),
+            Some(alg) => panic!("JWT alg {} is not supported, the threshold key signs ES256K", alg),
+        }
+        let signing_input = format!(
+            "{}.{}",
+            base64url(header.to_string().as_bytes()),
+            base64url(claims.to_string().as_bytes())
+        );
+        Jwt { signing_input }
+    }
+
+    pub fn digest(&self) -> Vec<u8> {
+        digest(&SHA256, self.signing_input.as_bytes()).as_ref().to_vec()
+    }
+
+    /// Compact JWS: signing input and the base64url of the 64 byte r || s
+    pub fn compact(&self, sig: &SignatureRecid) -> String {
+        let signature = [to_32_bytes(&sig.r.to_big_int()), to_32_bytes(&sig.s.to_big_int())].concat();
+        format!("{}.{}", self.signing_input, base64url(&signature))
+    }
+}
+
+pub fn signed_jwt_json(jwt: &Jwt, sig: &SignatureRecid, context: &str) -> Value {
+    let mut ret_dict = json!({
+        "status": "jwt_signed",
+        "alg": "ES256K",
+        "jwt": jwt.compact(sig),
+    });
+    if !context.is_empty() {
+        ret_dict["context"] = json!(context);
+    }
+    ret_dict
+}
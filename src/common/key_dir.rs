@@ -0,0 +1,185 @@
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use curv::elliptic::curves::secp256_k1::GE;
+use serde_json::{json, Value};
+
+use crate::common::age_encryption;
+use crate::common::encoding::compressed_public_key;
+use crate::common::keyfile::{KeyFile, KeyMetadata};
+use crate::common::keys_lock::KeysFileLock;
+use crate::common::keystore;
+use crate::common::kms;
+use crate::common::migrations;
+use crate::common::options;
+
+const KEY_EXTENSION: &str = "store";
+
+/// Directory of named keys, from --keys-dir, ~/.tss-cli/keys by default
+pub fn keys_dir() -> PathBuf {
+    match options::get().keys_dir {
+        Some(dir) => PathBuf::from(dir),
+        None => {
+            let home = std::env::var("HOME").expect("HOME is not set, use --keys-dir");
+            Path::new(&home).join(".tss-cli").join("keys")
+        }
+    }
+}
+
+// Names become file names, anything that could leave the directory is refused
+fn check_name(name: &str) {
+    if name.is_empty()
+        || name.starts_with('.')
+        || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+    {
+        panic!("Invalid key name {:?}, use letters, digits, '-', '_' and '.'", name);
+    }
+}
+
+/// Keys file of a named key, <keys dir>/<name>.store
+pub fn key_path(name: &str) -> String {
+    check_name(name);
+    keys_dir().join(format!("{}.{}", name, KEY_EXTENSION)).to_string_lossy().to_string()
+}
+
+/// Keys file path for a new named key, the keys directory is created if needed
+pub fn new_key_path(name: &str) -> String {
+    let path = key_path(name);
+    if Path::new(&path).exists() {
+        panic!("Key {} already exists", name);
+    }
+    fs::create_dir_all(keys_dir()).expect("Unable to create keys directory !");
+    path
+}
+
+/// Keys file of an existing named key
+pub fn existing_key_path(name: &str) -> String {
+    let path = key_path(name);
+    if !Path::new(&path).exists() {
+        panic!("No key named {} in {}", name, keys_dir().display());
+    }
+    path
+}
+
+fn created_at(path: &str) -> u64 {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.created().or(metadata.modified()))
+        .map(|time| time.duration_since(UNIX_EPOCH).unwrap().as_secs())
+        .unwrap_or(0)
+}
+
+fn public_metadata(metadata: &KeyMetadata, y_sum: &GE, party_id: u16) -> Value {
+    json!({
+        "curve": metadata.curve,
+        "threshold": metadata.threshold,
+        "parties": metadata.parties,
+        "params": format!("{}/{}", metadata.threshold, metadata.parties),
+        "party_id": party_id,
+        "public_key": hex::encode(compressed_public_key(y_sum)),
+        "fingerprint": metadata.fingerprint,
+    })
+}
+
+// Metadata of a stored keys file without touching the share: passphrase, keystore, KMS and age encrypted
+// files only tell they are encrypted, keychain and HSM backed files keep public data in the clear
+fn stored_metadata(path: &str) -> Value {
+    let content = fs::read_to_string(path).expect("Unable to load keys file !");
+    let data: Option<Value> = match age_encryption::is_age_file(&content) {
+        true => None,
+        false => serde_json::from_str(&content).ok(),
+    };
+    let data = data.filter(|data| {
+        data.get("encryption").is_none() && !keystore::is_keystore(data) && !kms::is_kms_file(data)
+    });
+    match data {
+        Some(data) => {
+            let (data, _) = migrations::migrate(data).unwrap_or_else(|e| panic!("Keys file {} can't be read: {}", path, e));
+            let data = &data["data"];
+            let key_metadata: KeyMetadata = serde_json::from_value(data["metadata"].clone()).expect("Invalid keys file");
+            let y_sum: GE = serde_json::from_value(data["y_sum"].clone()).expect("Invalid keys file");
+            let party_id = data["party_id"].as_u64().expect("Invalid keys file") as u16;
+            let mut metadata = public_metadata(&key_metadata, &y_sum, party_id);
+            metadata["encrypted"] = json!(false);
+            metadata
+        }
+        None => json!({"encrypted": true}),
+    }
+}
+
+fn key_names() -> Vec<String> {
+    let mut names: Vec<String> = match fs::read_dir(keys_dir()) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && path.extension().map_or(false, |ext| ext == KEY_EXTENSION))
+            .filter_map(|path| path.file_stem().map(|stem| stem.to_string_lossy().to_string()))
+            .collect(),
+        Err(_) => vec![],
+    };
+    names.sort();
+    names
+}
+
+/// Lists the keys of the keys directory, encrypted keys are listed without their metadata
+pub fn list() {
+    let keys: Vec<Value> = key_names()
+        .iter()
+        .map(|name| {
+            let path = key_path(name);
+            let mut metadata = stored_metadata(&path);
+            metadata["name"] = json!(name);
+            metadata["created_at"] = json!(created_at(&path));
+            metadata
+        })
+        .collect();
+    println!("{}", json!({"keys_dir": keys_dir(), "keys": keys}).to_string());
+}
+
+/// Shows the metadata of a named key, an encrypted key is decrypted for it
+pub fn show(name: &str) {
+    let path = existing_key_path(name);
+    let _lock = KeysFileLock::shared(&path);
+    let mut metadata = stored_metadata(&path);
+    if metadata["encrypted"] == json!(true) {
+        let keyfile = KeyFile::load(&path);
+        metadata = public_metadata(&keyfile.metadata(), &keyfile.y_sum, keyfile.party_id);
+        metadata["encrypted"] = json!(true);
+    }
+    metadata["name"] = json!(name);
+    metadata["keysfile"] = json!(path);
+    metadata["created_at"] = json!(created_at(&path));
+    println!("{}", metadata.to_string());
+}
+
+pub fn rename(name: &str, new_name: &str) {
+    let path = existing_key_path(name);
+    let new_path = key_path(new_name);
+    if Path::new(&new_path).exists() {
+        panic!("Key {} already exists", new_name);
+    }
+    let _lock = KeysFileLock::acquire(&path, "key rename");
+    fs::rename(&path, &new_path).expect("Unable to rename key !");
+    fs::remove_file(format!("{}.lock", path)).ok();
+    println!("{}", json!({"status": "key_renamed", "name": new_name, "keysfile": new_path}).to_string());
+}
+
+/// Deletes a named key after the name is typed again, unless confirmed with --yes.
+/// `.bak` copies `rekey` left next to the keys file are not deleted
+pub fn delete(name: &str, confirmed: bool) {
+    let path = existing_key_path(name);
+    if !confirmed {
+        eprint!("The share of key {} will be deleted. Type the key name to confirm: ", name);
+        io::stderr().flush().unwrap();
+        let mut answer = String::new();
+        io::stdin().lock().read_line(&mut answer).expect("Unable to read confirmation");
+        if answer.trim() != name {
+            panic!("Key {} not deleted", name);
+        }
+    }
+    let _lock = KeysFileLock::acquire(&path, "key delete");
+    fs::remove_file(&path).expect("Unable to delete key !");
+    fs::remove_file(format!("{}.lock", path)).ok();
+    println!("{}", json!({"status": "key_deleted", "name": name}).to_string());
+}
@@ -0,0 +1,148 @@
+use std::fs;
+use std::sync::Mutex;
+
+use aes_gcm::aead::{Aead, NewAead, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use argon2::{Algorithm, Argon2, Params, Version};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::common::options;
+
+pub const ENCRYPTION_SCHEME: &str = "argon2id-aes256gcm";
+
+// Default Argon2id cost, 64 MiB and 3 passes
+pub const M_COST: u32 = 64 * 1024;
+pub const T_COST: u32 = 3;
+pub const P_COST: u32 = 1;
+const AAD: &[u8] = b"tss-cli keys file";
+
+// Passphrase of the keys file in use, asked once per process
+static PASSPHRASE: Mutex<Option<String>> = Mutex::new(None);
+
+/// Keys file encrypted with a key derived from a passphrase, all binary fields in hex
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct EncryptedKeyFile {
+    pub encryption: String,
+    pub salt: String,
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+    pub nonce: String,
+    pub ciphertext: String,
+}
+
+pub fn is_encrypted(data: &Value) -> bool {
+    data.get("encryption").is_some()
+}
+
+fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; len];
+    SystemRandom::new().fill(&mut bytes).expect("Unable to get random bytes");
+    bytes
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], m_cost: u32, t_cost: u32, p_cost: u32) -> Vec<u8> {
+    let params = Params::new(m_cost, t_cost, p_cost, Some(32)).expect("Invalid Argon2 parameters");
+    let mut key = vec![0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .expect("Argon2 failure!");
+    key
+}
+
+pub fn encrypt(plaintext: &[u8], passphrase: &str) -> EncryptedKeyFile {
+    encrypt_with_cost(plaintext, passphrase, M_COST, T_COST, P_COST)
+}
+
+/// Same as encrypt with explicit Argon2id cost: memory in KiB, passes and lanes
+pub fn encrypt_with_cost(plaintext: &[u8], passphrase: &str, m_cost: u32, t_cost: u32, p_cost: u32) -> EncryptedKeyFile {
+    let salt = random_bytes(16);
+    let nonce = random_bytes(12);
+    let key = derive_key(passphrase, &salt, m_cost, t_cost, p_cost);
+    let cipher = Aes256Gcm::new(aes_gcm::Key::from_slice(&key));
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext, aad: AAD })
+        .expect("encryption failure!");
+    EncryptedKeyFile {
+        encryption: ENCRYPTION_SCHEME.to_string(),
+        salt: hex::encode(salt),
+        m_cost,
+        t_cost,
+        p_cost,
+        nonce: hex::encode(nonce),
+        ciphertext: hex::encode(ciphertext),
+    }
+}
+
+pub fn decrypt(encrypted: &EncryptedKeyFile, passphrase: &str) -> Vec<u8> {
+    if encrypted.encryption != ENCRYPTION_SCHEME {
+        panic!("Unsupported keys file encryption: {}", encrypted.encryption);
+    }
+    let salt = hex::decode(&encrypted.salt).expect("Invalid encrypted keys file");
+    let nonce = hex::decode(&encrypted.nonce).expect("Invalid encrypted keys file");
+    let ciphertext = hex::decode(&encrypted.ciphertext).expect("Invalid encrypted keys file");
+    let key = derive_key(passphrase, &salt, encrypted.m_cost, encrypted.t_cost, encrypted.p_cost);
+    let cipher = Aes256Gcm::new(aes_gcm::Key::from_slice(&key));
+    cipher
+        .decrypt(Nonce::from_slice(&nonce), Payload { msg: &ciphertext, aad: AAD })
+        .expect("Wrong passphrase or corrupted keys file")
+}
+
+/// Passphrase stored in a file, without the trailing newline
+pub fn read_passphrase_file(path: &str) -> String {
+    let passphrase = fs::read_to_string(path).expect("Unable to read password file !");
+    passphrase.trim_end_matches(|c| c == '\n' || c == '\r').to_string()
+}
+
+// Passphrase of --password-file
+fn read_password_file() -> Option<String> {
+    options::get().password_file.map(|path| read_passphrase_file(&path))
+}
+
+/// Asks for a new passphrase twice on the terminal
+pub fn prompt_new_passphrase() -> String {
+    let passphrase = rpassword::read_password_from_tty(Some("New keys file passphrase: "))
+        .expect("Unable to read passphrase");
+    let repeated = rpassword::read_password_from_tty(Some("Repeat passphrase: "))
+        .expect("Unable to read passphrase");
+    if passphrase != repeated {
+        panic!("Passphrases don't match");
+    }
+    if passphrase.is_empty() {
+        panic!("Passphrase must not be empty");
+    }
+    passphrase
+}
+
+/// Passphrase to unlock a keys file: cached, from the password file, or asked on the terminal
+pub fn passphrase() -> String {
+    let mut cached = PASSPHRASE.lock().unwrap();
+    if cached.is_none() {
+        let passphrase = read_password_file().unwrap_or_else(|| {
+            rpassword::read_password_from_tty(Some("Keys file passphrase: ")).expect("Unable to read passphrase")
+        });
+        *cached = Some(passphrase);
+    }
+    cached.clone().unwrap()
+}
+
+/// Asks for a passphrase to encrypt keys files with, twice unless it comes from the password file
+pub fn new_passphrase() -> String {
+    let passphrase = read_password_file().unwrap_or_else(prompt_new_passphrase);
+    if passphrase.is_empty() {
+        panic!("Passphrase must not be empty");
+    }
+    *PASSPHRASE.lock().unwrap() = Some(passphrase.clone());
+    passphrase
+}
+
+/// Passphrase keys files are saved with: the one in use or from the password file, none for plaintext files
+pub fn save_passphrase() -> Option<String> {
+    let mut cached = PASSPHRASE.lock().unwrap();
+    if cached.is_none() {
+        *cached = read_password_file();
+    }
+    cached.clone()
+}
@@ -0,0 +1,93 @@
+use std::sync::Mutex;
+
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::common::keyfile::{keychain_reference, restore_secret, take_secret, KeyFile};
+use crate::common::keys_lock::KeysFileLock;
+
+/// Service name of keychain entries holding key shares
+pub const KEYCHAIN_SERVICE: &str = "tss-cli";
+
+// Set once a keychain backed keys file is loaded or keygen asked for it, keys files are then saved the same way
+static KEYCHAIN_IN_USE: Mutex<bool> = Mutex::new(false);
+
+pub fn is_keychain_file(data: &Value) -> bool {
+    data.get("keychain").is_some()
+}
+
+pub fn use_keychain() {
+    *KEYCHAIN_IN_USE.lock().unwrap() = true;
+}
+
+pub fn in_use() -> bool {
+    *KEYCHAIN_IN_USE.lock().unwrap()
+}
+
+fn entry(account: &str) -> keyring::Entry {
+    keyring::Entry::new(KEYCHAIN_SERVICE, account)
+}
+
+/// Moves the secret fields of a keys file into a new keychain entry, returns what is left for the disk.
+/// The entry of the share it replaces is removed by KeyFile::replace once the new file loads
+pub fn store(mut data: Value) -> Value {
+    let secret = take_secret(&mut data);
+    let account = Uuid::new_v4().to_string();
+    data["keychain"] = store_entry(&account, secret.to_string().as_bytes());
+    data
+}
+
+/// Stores secret material in the keychain entry of account, returns the reference kept on disk
+pub fn store_entry(account: &str, secret: &[u8]) -> Value {
+    entry(account)
+        .set_password(std::str::from_utf8(secret).expect("Secret material must be text"))
+        .unwrap_or_else(|e| panic!("Unable to store secret material in the keychain: {}", e));
+    json!({"service": KEYCHAIN_SERVICE, "account": account})
+}
+
+/// Secret material of a keychain reference written by store_entry
+pub fn fetch_entry(reference: &Value) -> Vec<u8> {
+    let account = reference["account"].as_str().expect("Invalid keychain reference");
+    entry(account)
+        .get_password()
+        .map(|secret| secret.into_bytes())
+        .unwrap_or_else(|e| panic!("Unable to read {} from the keychain: {}", account, e))
+}
+
+/// Removes the keychain entry of a reference written by store_entry
+pub fn delete_entry(reference: &Value) {
+    let account = reference["account"].as_str().expect("Invalid keychain reference");
+    entry(account)
+        .delete_password()
+        .unwrap_or_else(|e| panic!("Unable to remove keychain entry {}: {}", account, e));
+}
+
+/// Puts the secret fields from the keychain back into a keychain backed keys file
+pub fn fetch(mut data: Value) -> Value {
+    let secret: Value = serde_json::from_slice(&fetch_entry(&data["keychain"])).expect("Invalid keychain entry");
+    restore_secret(&mut data, &secret);
+    data.as_object_mut().unwrap().remove("keychain");
+    use_keychain();
+    data
+}
+
+/// Moves the secret share of a keys file into the keychain, the file keeps public data and the keychain reference
+pub fn move_to_keychain(keysfile_path: &str) {
+    let _lock = KeysFileLock::acquire(keysfile_path, "keychain store");
+    let keyfile = KeyFile::load(keysfile_path);
+    use_keychain();
+    keyfile.replace(keysfile_path);
+    println!("{}", json!({"status": "share_moved_to_keychain", "keysfile": keysfile_path}).to_string());
+}
+
+/// Writes a keychain backed keys file back as a complete keys file, replace removes the keychain entry
+pub fn move_from_keychain(keysfile_path: &str) {
+    let _lock = KeysFileLock::acquire(keysfile_path, "keychain restore");
+    if keychain_reference(keysfile_path).is_none() {
+        panic!("{} is not a keychain backed keys file", keysfile_path);
+    }
+    let keyfile = KeyFile::load(keysfile_path);
+    *KEYCHAIN_IN_USE.lock().unwrap() = false;
+    keyfile.replace(keysfile_path);
+    println!("{}", json!({"status": "share_moved_from_keychain", "keysfile": keysfile_path}).to_string());
+}
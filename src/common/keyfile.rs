@@ -0,0 +1,478 @@
+use std::fs;
+
+use curv::cryptographic_primitives::secret_sharing::feldman_vss::VerifiableSS;
+use curv::elliptic::curves::secp256_k1::{FE, GE};
+use curv::elliptic::curves::traits::ECScalar;
+use curv::arithmetic::traits::Modulo;
+use curv::BigInt;
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2018::party_i::{Keys, SharedKeys};
+use paillier::EncryptionKey;
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+
+use crate::common::key_encryption::{self, EncryptedKeyFile};
+use crate::common::age_encryption;
+use crate::common::atomic_file;
+use crate::common::chain_code;
+use crate::common::encoding::compressed_public_key;
+use crate::common::hsm;
+use crate::common::integrity;
+use crate::common::keychain;
+use crate::common::keystore::{self, Keystore};
+use crate::common::kms::{self, KmsKeyFile};
+use crate::common::migrations;
+use crate::common::Params;
+
+// Keys file content as JSON, decrypted first if the file is encrypted or an EIP-2335 style keystore,
+// with the secret share fetched from the OS keychain or unwrapped by the HSM
+fn read_json(keysfile_path: &str) -> serde_json::Value {
+    let data = read_stored_json(keysfile_path);
+    if keychain::is_keychain_file(&data) {
+        return keychain::fetch(data);
+    }
+    if hsm::is_hsm_file(&data) {
+        return hsm::unwrap(data);
+    }
+    data
+}
+
+fn read_stored_json(keysfile_path: &str) -> serde_json::Value {
+    let data = fs::read_to_string(keysfile_path).expect(
+        format!("Unable to load keys file at location: {}", keysfile_path).as_str(),
+    );
+    let data = parse_json(keysfile_path, &decrypt_stored(&data), "Keys file");
+    if keystore::is_keystore(&data) {
+        let keystore: Keystore = serde_json::from_value(data).expect("Invalid keystore");
+        return parse_json(keysfile_path, &keystore::open(&keystore, &key_encryption::passphrase()), "Keystore");
+    }
+    data
+}
+
+// Stored content without the outer encryption: age, the passphrase or a KMS envelope
+fn decrypt_stored(data: &str) -> Vec<u8> {
+    if age_encryption::is_age_file(data) {
+        return age_encryption::decrypt(data);
+    }
+    match serde_json::from_str::<serde_json::Value>(data) {
+        Ok(json) if key_encryption::is_encrypted(&json) => {
+            let encrypted: EncryptedKeyFile = serde_json::from_value(json).expect("Invalid encrypted keys file");
+            key_encryption::decrypt(&encrypted, &key_encryption::passphrase())
+        }
+        Ok(json) if kms::is_kms_file(&json) => {
+            let encrypted: KmsKeyFile = serde_json::from_value(json).expect("Invalid KMS keys file");
+            kms::decrypt(&encrypted)
+        }
+        _ => data.as_bytes().to_vec(),
+    }
+}
+
+// Whether stored content has an outer encryption: age, the passphrase or a KMS envelope
+fn is_encrypted_stored(data: &str) -> bool {
+    age_encryption::is_age_file(data)
+        || serde_json::from_str::<serde_json::Value>(data)
+            .map(|json| key_encryption::is_encrypted(&json) || kms::is_kms_file(&json))
+            .unwrap_or(false)
+}
+
+// Outer encryption of stored content: envelope encrypted when a KMS key is given, encrypted to age recipients
+// when given, encrypted whenever a passphrase is in use, plaintext otherwise
+fn encrypt_stored(content: String) -> String {
+    let age_recipients = age_encryption::recipients();
+    match (kms::kms_key_id(), key_encryption::save_passphrase()) {
+        (Some(key_id), _) => serde_json::to_string(&kms::encrypt(content.as_bytes(), &key_id)).unwrap(),
+        (None, _) if !age_recipients.is_empty() => age_encryption::encrypt(content.as_bytes(), age_recipients),
+        (None, Some(passphrase)) => serde_json::to_string(&key_encryption::encrypt(content.as_bytes(), &passphrase)).unwrap(),
+        (None, None) => content,
+    }
+}
+
+/// Seals secret material kept next to a keys file, e.g. presignatures, the way the keys file is saved:
+/// in the keychain or wrapped by the HSM, then encrypted with KMS, age or the passphrase in use
+pub fn seal_secret(name: &str, plaintext: &[u8]) -> String {
+    let content = match (keychain::in_use(), hsm::hsm_key()) {
+        (true, _) => serde_json::json!({"keychain": keychain::store_entry(name, plaintext)}).to_string(),
+        (false, Some(key)) => serde_json::json!({"hsm": hsm::wrap_bytes(plaintext, &key)}).to_string(),
+        (false, None) => String::from_utf8(plaintext.to_vec()).expect("Secret material must be text"),
+    };
+    encrypt_stored(content)
+}
+
+/// Plaintext of a file written with seal_secret that is used once, its keychain entry is removed
+pub fn open_secret_once(path: &str) -> Vec<u8> {
+    let data = fs::read_to_string(path).unwrap_or_else(|e| panic!("Unable to load {}: {}", path, e));
+    let data = decrypt_stored(&data);
+    match serde_json::from_slice::<serde_json::Value>(&data) {
+        Ok(json) if keychain::is_keychain_file(&json) => {
+            let secret = keychain::fetch_entry(&json["keychain"]);
+            keychain::delete_entry(&json["keychain"]);
+            secret
+        }
+        Ok(json) if hsm::is_hsm_file(&json) => {
+            hsm::unwrap_bytes(&serde_json::from_value(json["hsm"].clone()).expect("Invalid HSM wrapped file"))
+        }
+        _ => data,
+    }
+}
+
+/// Keychain reference of a stored keys file, None when its share is not in the keychain
+pub fn keychain_reference(keysfile_path: &str) -> Option<serde_json::Value> {
+    Some(read_stored_json(keysfile_path))
+        .filter(keychain::is_keychain_file)
+        .map(|data| data["keychain"].clone())
+}
+
+/// Removes the keychain entry of a share that was replaced at keysfile_path, unless the new file still uses it
+pub fn forget_keychain_entry(replaced_entry: Option<serde_json::Value>, keysfile_path: &str) {
+    if let Some(replaced_entry) = replaced_entry {
+        if keychain_reference(keysfile_path).as_ref() != Some(&replaced_entry) {
+            keychain::delete_entry(&replaced_entry);
+        }
+    }
+}
+
+// Keys file content as JSON, content that is not JSON is reported with the file name instead of a serde position
+fn parse_json(keysfile_path: &str, content: &[u8], what: &str) -> serde_json::Value {
+    serde_json::from_slice(content).unwrap_or_else(|e| {
+        panic!("{} {} does not hold JSON ({}), it is not a keys file or it is damaged", what, keysfile_path, e)
+    })
+}
+
+// Secret fields of a keys file, (object, field). External stores hold them while the rest stays on disk
+const SECRET_FIELDS: [(&str, &str); 3] = [("party_keys", "u_i"), ("party_keys", "dk"), ("shared_keys", "x_i")];
+
+// Object holding the key material: "data" from v4 on, the file itself before
+fn key_data(data: &mut serde_json::Value) -> &mut serde_json::Value {
+    match data.get("data").is_some() {
+        true => &mut data["data"],
+        false => data,
+    }
+}
+
+/// Removes the secret fields from keys file JSON and returns them as one object
+pub fn take_secret(data: &mut serde_json::Value) -> serde_json::Value {
+    let data = key_data(data);
+    let mut secret = serde_json::json!({});
+    for (object, field) in SECRET_FIELDS.iter() {
+        let value = data[*object]
+            .as_object_mut()
+            .and_then(|fields| fields.remove(*field))
+            .unwrap_or_else(|| panic!("Keys file has no {}.{}", object, field));
+        secret[format!("{}.{}", object, field)] = value;
+    }
+    secret
+}
+
+/// Puts secret fields returned by take_secret back into keys file JSON
+pub fn restore_secret(data: &mut serde_json::Value, secret: &serde_json::Value) {
+    let data = key_data(data);
+    for (object, field) in SECRET_FIELDS.iter() {
+        data[*object][*field] = secret[format!("{}.{}", object, field)].clone();
+    }
+}
+
+/// Format tag of keys files from v2 on
+pub const KEYS_FILE_FORMAT: &str = "tss-cli-keys";
+/// Curve and signing protocol of the key material, from v4 on
+pub const KEYS_FILE_CURVE: &str = "secp256k1";
+pub const KEYS_FILE_PROTOCOL: &str = "gg18";
+
+/// Public facts about the key stored next to the share from v3 on, so signing does not need them
+/// repeated on the command line. They are derived from the share and checked against it on load
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeyMetadata {
+    pub curve: String,
+    pub threshold: u16,
+    pub parties: u16,
+    pub fingerprint: String,
+    /// Fingerprint of the HD chain code stored with the share from v6 on, absent for the default chain code
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chain_code_fingerprint: Option<String>,
+    /// Last canary signature made with the share, absent until keygen --canary or a reshare ran one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canary: Option<CanaryResult>,
+    /// Points the shares of the parties are evaluated at, in decimal, when a share was imported from a tool that
+    /// does not evaluate them at the party ids. Such a share only deals in a reshare onto the party ids
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub share_points: Option<Vec<String>>,
+}
+
+/// Canary signature verified with all parties at the canary derivation path
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CanaryResult {
+    pub path: String,
+    pub r: String,
+    pub s: String,
+    pub recid: u8,
+    pub timestamp: u64,
+}
+
+impl KeyMetadata {
+    pub fn new(vss_scheme_vec: &Vec<VerifiableSS<GE>>, y_sum: &GE, chain_code: Option<&[u8]>) -> Self {
+        KeyMetadata {
+            curve: KEYS_FILE_CURVE.to_string(),
+            threshold: vss_scheme_vec[0].parameters.threshold as u16,
+            parties: vss_scheme_vec[0].parameters.share_count as u16,
+            fingerprint: fingerprint(y_sum),
+            chain_code_fingerprint: chain_code.map(chain_code::fingerprint),
+            canary: None,
+            share_points: None,
+        }
+    }
+
+    /// Threshold params as given on the command line
+    pub fn params(&self) -> Params {
+        Params {
+            threshold: self.threshold.to_string(),
+            parties: self.parties.to_string(),
+        }
+    }
+}
+
+/// First 8 bytes of sha256 of the compressed public key, in hex
+pub fn fingerprint(y_sum: &GE) -> String {
+    hex::encode(&digest(&SHA256, &compressed_public_key(y_sum)).as_ref()[..8])
+}
+
+/// Keys file as stored on disk: a versioned envelope naming the curve and protocol of the key material
+/// in data. Unknown or missing fields fail the load, so a file written by another tss_cli version is
+/// never half read into a share
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TaggedKeyFile {
+    format: String,
+    version: u32,
+    curve: String,
+    protocol: String,
+    data: KeyFileData,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyFileData {
+    party_keys: Keys,
+    shared_keys: SharedKeys,
+    party_id: u16,
+    vss_scheme_vec: Vec<VerifiableSS<GE>>,
+    paillier_key_vec: Vec<EncryptionKey>,
+    y_sum: GE,
+    // HD chain code in hex from v6 on, absent for the default chain code (the generator)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    chain_code: Option<String>,
+    metadata: KeyMetadata,
+}
+
+/// Content of a keys file produced by keygen
+pub struct KeyFile {
+    pub party_keys: Keys,
+    pub shared_keys: SharedKeys,
+    pub party_id: u16,
+    pub vss_scheme_vec: Vec<VerifiableSS<GE>>,
+    pub paillier_key_vec: Vec<EncryptionKey>,
+    pub y_sum: GE,
+    /// HD chain code fixed at keygen, None for the default chain code
+    pub chain_code: Option<Vec<u8>>,
+    /// Canary result recorded in the metadata, kept across saves
+    pub canary: Option<CanaryResult>,
+    /// Points the shares are evaluated at when they are not the party ids 1..n, see KeyMetadata
+    pub share_points: Option<Vec<BigInt>>,
+}
+
+impl KeyFile {
+    pub fn load(keysfile_path: &str) -> Self {
+        // Older formats are upgraded in memory, the file itself is only rewritten by keys-version --upgrade
+        let mut data = read_json(keysfile_path);
+        let version = migrations::check_format(&data)
+            .unwrap_or_else(|e| panic!("Keys file {} can't be read: {}", keysfile_path, e));
+        if version >= migrations::INTEGRITY_VERSION {
+            integrity::verify(&mut data, keysfile_path);
+        }
+        if version < migrations::CURRENT_VERSION {
+            // Rewritten with keys-version --upgrade
+            eprintln!(
+                "{}",
+                serde_json::json!({
+                    "status": "keys_converted_in_memory",
+                    "keysfile": keysfile_path,
+                    "version": version,
+                    "current_version": migrations::CURRENT_VERSION,
+                })
+                .to_string()
+            );
+        }
+        let (data, _version) = migrations::migrate(data)
+            .unwrap_or_else(|e| panic!("Keys file {} can't be read: {}", keysfile_path, e));
+        let tagged: TaggedKeyFile = serde_json::from_value(data).unwrap_or_else(|e| {
+            panic!("Keys file {} does not match the {} v{} format: {}", keysfile_path, KEYS_FILE_FORMAT, migrations::CURRENT_VERSION, e)
+        });
+        if tagged.format != KEYS_FILE_FORMAT {
+            panic!("{} is a {} file, not a keys file", keysfile_path, tagged.format);
+        }
+        if tagged.curve != KEYS_FILE_CURVE || tagged.protocol != KEYS_FILE_PROTOCOL {
+            panic!(
+                "Keys file {} holds a {} {} key, this tss_cli supports {} {} only",
+                keysfile_path, tagged.curve, tagged.protocol, KEYS_FILE_CURVE, KEYS_FILE_PROTOCOL
+            );
+        }
+        let data = tagged.data;
+        let chain_code = data
+            .chain_code
+            .as_ref()
+            .map(|chain_code| hex::decode(chain_code).unwrap_or_else(|_| panic!("Keys file {} has an invalid chain code", keysfile_path)));
+        // The canary result and the share points are recorded, not derived from the share
+        let mut expected = KeyMetadata::new(&data.vss_scheme_vec, &data.y_sum, chain_code.as_deref());
+        expected.canary = data.metadata.canary.clone();
+        expected.share_points = data.metadata.share_points.clone();
+        if data.metadata != expected {
+            panic!("Keys file {} metadata does not match its share, the file was edited or is corrupted", keysfile_path);
+        }
+        let share_points = data.metadata.share_points.as_ref().map(|points| {
+            if points.len() != data.vss_scheme_vec.len() {
+                panic!("Keys file {} does not have a share point per party", keysfile_path);
+            }
+            points
+                .iter()
+                .map(|point| {
+                    BigInt::from_str_radix(point, 10)
+                        .unwrap_or_else(|_| panic!("Keys file {} has an invalid share point", keysfile_path))
+                })
+                .collect::<Vec<BigInt>>()
+        });
+        // Derivations use the chain code of the key unless another one is given, which fails
+        chain_code::set_stored(chain_code.clone());
+
+        KeyFile {
+            party_keys: data.party_keys,
+            shared_keys: data.shared_keys,
+            party_id: data.party_id,
+            vss_scheme_vec: data.vss_scheme_vec,
+            paillier_key_vec: data.paillier_key_vec,
+            y_sum: data.y_sum,
+            chain_code,
+            canary: data.metadata.canary,
+            share_points,
+        }
+    }
+
+    pub fn metadata(&self) -> KeyMetadata {
+        let mut metadata = KeyMetadata::new(&self.vss_scheme_vec, &self.y_sum, self.chain_code.as_deref());
+        metadata.canary = self.canary.clone();
+        metadata.share_points = self
+            .share_points
+            .as_ref()
+            .map(|points| points.iter().map(|point| point.to_str_radix(10)).collect());
+        metadata
+    }
+
+    /// Point the share of a party is evaluated at: its party id, or its party key in the tool the share was imported from
+    pub fn share_point(&self, party_id: u16) -> FE {
+        match &self.share_points {
+            Some(points) => ECScalar::from(&points[(party_id - 1) as usize]),
+            None => ECScalar::from(&BigInt::from(party_id as u64)),
+        }
+    }
+
+    /// Lagrange coefficient at zero of the share of party_id among party_ids, the weighted shares add up to the key
+    pub fn lagrange_coefficient(&self, party_id: u16, party_ids: &[u16]) -> FE {
+        let x_i = self.share_point(party_id);
+        let one: FE = ECScalar::from(&BigInt::from(1));
+        party_ids.iter().filter(|id| **id != party_id).fold(one, |acc, id| {
+            let x_j = self.share_point(*id);
+            let difference: FE = ECScalar::from(&BigInt::mod_sub(&x_j.to_big_int(), &x_i.to_big_int(), &FE::q()));
+            acc * x_j * difference.invert()
+        })
+    }
+
+    /// Public share g*x_j of a party, the VSS commitments evaluated at its share point
+    pub fn public_share(&self, party_id: u16) -> GE {
+        let x = self.share_point(party_id);
+        let evaluations = self.vss_scheme_vec.iter().map(|vss| {
+            let (head, tail) = vss.commitments.split_at(1);
+            let mut power = x;
+            tail.iter().fold(head[0], |acc, commitment| {
+                let term = *commitment * &power;
+                power = power * x;
+                acc + term
+            })
+        });
+        evaluations.fold(None, |sum: Option<GE>, evaluation| Some(sum.map_or(evaluation, |sum| sum + evaluation))).unwrap()
+    }
+
+    /// Refuses a share whose points are not the party ids, GG18 signing here evaluates the shares at 1..n
+    pub fn check_signable(&self, keysfile_path: &str) {
+        if self.share_points.is_some() {
+            panic!(
+                "Keys file {} holds an imported share evaluated at the party keys of the tool it came from, \
+                 run reshare with the other parties to move it onto party ids 1..{} before signing",
+                keysfile_path,
+                self.vss_scheme_vec.len()
+            );
+        }
+    }
+
+    /// Format version of the keys file on disk
+    pub fn version(keysfile_path: &str) -> u32 {
+        migrations::detect_version(&read_json(keysfile_path))
+    }
+
+    /// Plaintext keys file in the current format, MACed with the integrity key or the passphrase in use
+    pub fn to_json(&self) -> String {
+        let mut data = serde_json::to_value(&TaggedKeyFile {
+            format: KEYS_FILE_FORMAT.to_string(),
+            version: migrations::CURRENT_VERSION,
+            curve: KEYS_FILE_CURVE.to_string(),
+            protocol: KEYS_FILE_PROTOCOL.to_string(),
+            data: KeyFileData {
+                party_keys: self.party_keys.clone(),
+                shared_keys: self.shared_keys.clone(),
+                party_id: self.party_id,
+                vss_scheme_vec: self.vss_scheme_vec.clone(),
+                paillier_key_vec: self.paillier_key_vec.clone(),
+                y_sum: self.y_sum,
+                chain_code: self.chain_code.as_ref().map(hex::encode),
+                metadata: self.metadata(),
+            },
+        })
+        .unwrap();
+        integrity::seal(&mut data, key_encryption::save_passphrase());
+        data.to_string()
+    }
+
+    pub fn save(&self, keysfile_path: &str) {
+        let keygen_json = match (keychain::in_use(), hsm::hsm_key()) {
+            (true, _) => keychain::store(serde_json::from_str(&self.to_json()).unwrap()).to_string(),
+            (false, Some(key)) => hsm::wrap(serde_json::from_str(&self.to_json()).unwrap(), &key).to_string(),
+            (false, None) => self.to_json(),
+        };
+        let keygen_json = encrypt_stored(keygen_json);
+        // An encrypted keys file is never replaced by a plaintext one because a backend was not given again
+        if !is_encrypted_stored(&keygen_json)
+            && fs::read_to_string(keysfile_path).map(|data| is_encrypted_stored(&data)).unwrap_or(false)
+        {
+            panic!(
+                "Keys file {} is encrypted, the new version would be saved in plaintext. Give the backend it was \
+                 encrypted with (--kms-key-id, --age-recipient or --password-file)",
+                keysfile_path
+            );
+        }
+        atomic_file::write_atomic(keysfile_path, keygen_json.as_bytes());
+    }
+
+    /// Saves over an existing keys file and loads it back. No copy of the previous share is kept on disk,
+    /// when the new file does not load back the previous content is written again from memory.
+    /// The keychain entry of the previous share is removed once the new file loads
+    pub fn replace(&self, keysfile_path: &str) {
+        let previous = fs::read(keysfile_path).ok();
+        let previous_entry = previous.as_ref().and_then(|_| keychain_reference(keysfile_path));
+        self.save(keysfile_path);
+        let loaded = std::panic::catch_unwind(|| KeyFile::load(keysfile_path).to_json());
+        if loaded.ok() != Some(self.to_json()) {
+            if let Some(previous) = previous {
+                atomic_file::write_atomic(keysfile_path, &previous);
+            }
+            panic!("Keys file {} did not load back after saving, the previous content was restored", keysfile_path);
+        }
+        forget_keychain_entry(previous_entry, keysfile_path);
+    }
+}
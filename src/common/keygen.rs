@@ -1,4 +1,4 @@
-use std::{fs, time};
+use std::time;
 
 use curv::{
     arithmetic::traits::Converter,
@@ -10,28 +10,31 @@ use curv::{
     elliptic::curves::secp256_k1::{FE, GE}
 };
 use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2018::party_i::{
-    KeyGenBroadcastMessage1, KeyGenDecommitMessage1, Keys, Parameters,
+    KeyGenBroadcastMessage1, KeyGenDecommitMessage1, Keys, Parameters, SharedKeys,
 };
 use paillier::EncryptionKey;
 use reqwest::blocking::Client;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 
+use crate::common::canary::run_canary;
+use crate::common::chain_code;
+use crate::common::keyfile::KeyFile;
 use crate::common::{
-    aes_decrypt, aes_encrypt, broadcast, poll_for_broadcasts, poll_for_p2p, postb, sendp2p, Params,
+    aes_decrypt_round, aes_encrypt_round, broadcast, new_client, poll_for_broadcasts, poll_for_p2p, post_json, sendp2p, Params,
     PartySignup, AEAD,
 };
 
-pub fn run_keygen(addr: &String, keysfile_path: &String, params: &Vec<&str>) {
+pub fn run_keygen(addr: &String, keysfile_path: &String, params: &Vec<&str>, canary: bool) {
     let THRESHOLD: u16 = params[0].parse::<u16>().unwrap();
     let PARTIES: u16 = params[1].parse::<u16>().unwrap();
 
-    let client = Client::new();
+    let client = new_client();
 
     // delay:
     let delay = time::Duration::from_millis(25);
-    let params = Parameters {
-        threshold: THRESHOLD,
-        share_count: PARTIES,
-    };
+    // read before signup, so a bad chain code fails without holding up the other parties
+    let chain_code = chain_code::chain_code();
 
     //signup:
     let tn_params = Params {
@@ -43,226 +46,344 @@ pub fn run_keygen(addr: &String, keysfile_path: &String, params: &Vec<&str>) {
     };
     println!("number: {:?}, uuid: {:?}", party_num_int, uuid);
 
-    let party_keys = Keys::create(party_num_int as usize);
-    let (bc_i, decom_i) = party_keys.phase1_broadcast_phase3_proof_of_correct_key();
-
-    // send commitment to ephemeral public keys, get round 1 commitments of other parties
+    // commit to the chain code of this run, every party must derive with the same one
     assert!(broadcast(
         &addr,
         &client,
         party_num_int,
-        "round1",
-        serde_json::to_string(&bc_i).unwrap(),
+        "chain_code",
+        chain_code::commitment(chain_code.as_deref(), &uuid, party_num_int),
         uuid.clone(),
     )
     .is_ok());
-    let round1_ans_vec = poll_for_broadcasts(
+    let chain_code_ans_vec = poll_for_broadcasts(
         &addr,
         &client,
         party_num_int,
         PARTIES,
         delay,
-        "round1",
+        "chain_code",
         uuid.clone(),
     );
+    let mismatched: Vec<u16> = (1..=PARTIES)
+        .filter(|i| *i != party_num_int)
+        .zip(chain_code_ans_vec.iter())
+        .filter(|(i, commitment)| **commitment != chain_code::commitment(chain_code.as_deref(), &uuid, *i))
+        .map(|(i, _)| i)
+        .collect();
+    if !mismatched.is_empty() {
+        panic!(
+            "Parties {:?} use another chain code than this party, all parties must give the same --chain-code or \
+             --chain-code-mnemonic (or none)",
+            mismatched
+        );
+    }
+    if let Some(chain_code) = &chain_code {
+        println!("chain code fingerprint: {}", chain_code::fingerprint(chain_code));
+    }
 
-    let mut bc1_vec = round1_ans_vec
-        .iter()
-        .map(|m| serde_json::from_str::<KeyGenBroadcastMessage1>(m).unwrap())
-        .collect::<Vec<_>>();
+    let mut state = KeygenState::start(party_num_int, THRESHOLD, PARTIES, chain_code);
+    let keyfile = loop {
+        let round = state.round();
+        let incoming = match state.outgoing() {
+            Outgoing::Broadcast(message) => {
+                assert!(broadcast(&addr, &client, party_num_int, round, message, uuid.clone()).is_ok());
+                poll_for_broadcasts(&addr, &client, party_num_int, PARTIES, delay, round, uuid.clone())
+            }
+            Outgoing::P2p(messages) => {
+                for (to, message) in messages {
+                    assert!(sendp2p(&addr, &client, party_num_int, to, round, message, uuid.clone()).is_ok());
+                }
+                poll_for_p2p(&addr, &client, party_num_int, PARTIES, delay, round, uuid.clone())
+            }
+        };
+        match state.next(&incoming) {
+            Transition::Next(next) => state = next,
+            Transition::Done(keyfile) => break keyfile,
+        }
+    };
+    keyfile.save(&keysfile_path);
+    println!("Keys data written to file: {:?}", keysfile_path);
 
-    bc1_vec.insert(party_num_int as usize - 1, bc_i);
+    if canary {
+        run_canary(&addr, &keysfile_path, keyfile, &tn_params, &uuid);
+    }
+}
 
-    // send ephemeral public keys and check commitments correctness
-    assert!(broadcast(
-        &addr,
-        &client,
-        party_num_int,
-        "round2",
-        serde_json::to_string(&decom_i).unwrap(),
-        uuid.clone(),
-    )
-    .is_ok());
-    let round2_ans_vec = poll_for_broadcasts(
-        &addr,
-        &client,
-        party_num_int,
-        PARTIES,
-        delay,
-        "round2",
-        uuid.clone(),
-    );
+pub fn keygen_signup(addr: &String, client: &Client, params: &Params) -> Result<PartySignup, ()> {
+    post_json(&addr, &client, "signupkeygen", params).unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// What a party sends in a keygen or signing round: one message to all parties, or one message per other party
+pub enum Outgoing {
+    Broadcast(String),
+    P2p(Vec<(u16, String)>),
+}
+
+pub enum Transition {
+    Next(KeygenState),
+    Done(KeyFile),
+}
+
+/// Keygen of one party as typed round states, decoupled from the transport. Each state holds what the party knows
+/// after the previous round and serializes, `outgoing` is its message of the round and `next` takes the messages
+/// of the other parties in party order, the way poll_for_broadcasts and poll_for_p2p return them.
+/// Failed checks panic as in the rounds before, no keys file is written
+#[derive(Serialize, Deserialize)]
+pub enum KeygenState {
+    Commit(CommitRound),
+    Decommit(DecommitRound),
+    Shares(SharesRound),
+    Vss(VssRound),
+    DlogProof(DlogProofRound),
+}
+
+impl KeygenState {
+    pub fn start(party_num: u16, threshold: u16, parties: u16, chain_code: Option<Vec<u8>>) -> Self {
+        let party_keys = Keys::create(party_num as usize);
+        let (bc_i, decom_i) = party_keys.phase1_broadcast_phase3_proof_of_correct_key();
+        let setup = Setup { party_num, threshold, parties, chain_code };
+        KeygenState::Commit(CommitRound { setup, party_keys, bc_i, decom_i })
+    }
 
-    let mut j = 0;
-    let mut point_vec: Vec<GE> = Vec::new();
-    let mut decom_vec: Vec<KeyGenDecommitMessage1> = Vec::new();
-    let mut enc_keys: Vec<BigInt> = Vec::new();
-    for i in 1..=PARTIES {
-        if i == party_num_int {
-            point_vec.push(decom_i.y_i);
-            decom_vec.push(decom_i.clone());
-        } else {
-            let decom_j: KeyGenDecommitMessage1 = serde_json::from_str(&round2_ans_vec[j]).unwrap();
-            point_vec.push(decom_j.y_i);
-            decom_vec.push(decom_j.clone());
-            enc_keys.push((decom_j.y_i.clone() * party_keys.u_i).x_coor().unwrap());
-            j = j + 1;
+    /// Name of the round on the manager
+    pub fn round(&self) -> &'static str {
+        match self {
+            KeygenState::Commit(_) => "round1",
+            KeygenState::Decommit(_) => "round2",
+            KeygenState::Shares(_) => "round3",
+            KeygenState::Vss(_) => "round4",
+            KeygenState::DlogProof(_) => "round5",
         }
     }
 
-    let (head, tail) = point_vec.split_at(1);
-    let y_sum = tail.iter().fold(head[0], |acc, x| acc + x);
-
-    let (vss_scheme, secret_shares, _index) = party_keys
-        .phase1_verify_com_phase3_verify_correct_key_phase2_distribute(
-            &params, &decom_vec, &bc1_vec,
-        )
-        .expect("invalid key");
-
-    //////////////////////////////////////////////////////////////////////////////
-
-    let mut j = 0;
-    for (k, i) in (1..=PARTIES).enumerate() {
-        if i != party_num_int {
-            // prepare encrypted ss for party i:
-            let key_i = BigInt::to_bytes(&enc_keys[j]);
-            let plaintext = BigInt::to_bytes(&secret_shares[k].to_big_int());
-            let aead_pack_i = aes_encrypt(&key_i, &plaintext);
-            assert!(sendp2p(
-                &addr,
-                &client,
-                party_num_int,
-                i,
-                "round3",
-                serde_json::to_string(&aead_pack_i).unwrap(),
-                uuid.clone(),
-            )
-            .is_ok());
-            j += 1;
+    pub fn outgoing(&self) -> Outgoing {
+        match self {
+            KeygenState::Commit(state) => Outgoing::Broadcast(serde_json::to_string(&state.bc_i).unwrap()),
+            KeygenState::Decommit(state) => Outgoing::Broadcast(serde_json::to_string(&state.decom_i).unwrap()),
+            KeygenState::Shares(state) => Outgoing::P2p(state.encrypted_shares()),
+            KeygenState::Vss(state) => Outgoing::Broadcast(serde_json::to_string(&state.vss_scheme).unwrap()),
+            KeygenState::DlogProof(state) => Outgoing::Broadcast(serde_json::to_string(&state.dlog_proof).unwrap()),
         }
     }
 
-    let round3_ans_vec = poll_for_p2p(
-        &addr,
-        &client,
-        party_num_int,
-        PARTIES,
-        delay,
-        "round3",
-        uuid.clone(),
-    );
+    pub fn next(self, incoming: &[String]) -> Transition {
+        match self {
+            KeygenState::Commit(state) => Transition::Next(KeygenState::Decommit(state.next(incoming))),
+            KeygenState::Decommit(state) => Transition::Next(KeygenState::Shares(state.next(incoming))),
+            KeygenState::Shares(state) => Transition::Next(KeygenState::Vss(state.next(incoming))),
+            KeygenState::Vss(state) => Transition::Next(KeygenState::DlogProof(state.next(incoming))),
+            KeygenState::DlogProof(state) => Transition::Done(state.finish(incoming)),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Setup {
+    party_num: u16,
+    threshold: u16,
+    parties: u16,
+    chain_code: Option<Vec<u8>>,
+}
 
-    let mut j = 0;
-    let mut party_shares: Vec<FE> = Vec::new();
-    for i in 1..=PARTIES {
-        if i == party_num_int {
-            party_shares.push(secret_shares[(i - 1) as usize]);
-        } else {
-            let aead_pack: AEAD = serde_json::from_str(&round3_ans_vec[j]).unwrap();
-            let key_i = BigInt::to_bytes(&enc_keys[j]);
-            let out = aes_decrypt(&key_i, aead_pack);
-            let out_bn = BigInt::from_bytes(&out);
-            let out_fe = ECScalar::from(&out_bn);
-            party_shares.push(out_fe);
-
-            j += 1;
+impl Setup {
+    fn params(&self) -> Parameters {
+        Parameters {
+            threshold: self.threshold,
+            share_count: self.parties,
         }
     }
 
-    // round 4: send vss commitments
-    assert!(broadcast(
-        &addr,
-        &client,
-        party_num_int,
-        "round4",
-        serde_json::to_string(&vss_scheme).unwrap(),
-        uuid.clone(),
-    )
-    .is_ok());
-    let round4_ans_vec = poll_for_broadcasts(
-        &addr,
-        &client,
-        party_num_int,
-        PARTIES,
-        delay,
-        "round4",
-        uuid.clone(),
-    );
+    // Messages of all parties in party order, the own one included
+    fn with_own<T: DeserializeOwned>(&self, incoming: &[String], own: T) -> Vec<T> {
+        let mut messages: Vec<T> = incoming.iter().map(|m| serde_json::from_str(m).unwrap()).collect();
+        messages.insert(self.party_num as usize - 1, own);
+        messages
+    }
+}
+
+/// Round 1: commitment to the ephemeral public key and Paillier key
+#[derive(Serialize, Deserialize)]
+pub struct CommitRound {
+    setup: Setup,
+    party_keys: Keys,
+    bc_i: KeyGenBroadcastMessage1,
+    decom_i: KeyGenDecommitMessage1,
+}
+
+impl CommitRound {
+    pub fn next(self, incoming: &[String]) -> DecommitRound {
+        let bc1_vec = self.setup.with_own(incoming, self.bc_i);
+        DecommitRound { setup: self.setup, party_keys: self.party_keys, bc1_vec, decom_i: self.decom_i }
+    }
+}
+
+/// Round 2: ephemeral public keys, checked against the commitments
+#[derive(Serialize, Deserialize)]
+pub struct DecommitRound {
+    setup: Setup,
+    party_keys: Keys,
+    bc1_vec: Vec<KeyGenBroadcastMessage1>,
+    decom_i: KeyGenDecommitMessage1,
+}
 
-    let mut j = 0;
-    let mut vss_scheme_vec: Vec<VerifiableSS<GE>> = Vec::new();
-    for i in 1..=PARTIES {
-        if i == party_num_int {
-            vss_scheme_vec.push(vss_scheme.clone());
-        } else {
-            let vss_scheme_j: VerifiableSS<GE> = serde_json::from_str(&round4_ans_vec[j]).unwrap();
-            vss_scheme_vec.push(vss_scheme_j);
-            j += 1;
+impl DecommitRound {
+    pub fn next(self, incoming: &[String]) -> SharesRound {
+        let decom_vec = self.setup.with_own(incoming, self.decom_i);
+        let point_vec: Vec<GE> = decom_vec.iter().map(|decom| decom.y_i).collect();
+        // pairwise keys of the share messages, in party order without this party
+        let enc_keys: Vec<Vec<u8>> = decom_vec
+            .iter()
+            .enumerate()
+            .filter(|(k, _)| *k as u16 + 1 != self.setup.party_num)
+            .map(|(_, decom)| BigInt::to_bytes(&(decom.y_i * self.party_keys.u_i).x_coor().unwrap()))
+            .collect();
+
+        let (head, tail) = point_vec.split_at(1);
+        let y_sum = tail.iter().fold(head[0], |acc, x| acc + x);
+
+        let (vss_scheme, secret_shares, _index) = self
+            .party_keys
+            .phase1_verify_com_phase3_verify_correct_key_phase2_distribute(&self.setup.params(), &decom_vec, &self.bc1_vec)
+            .expect("invalid key");
+        SharesRound {
+            setup: self.setup,
+            party_keys: self.party_keys,
+            bc1_vec: self.bc1_vec,
+            point_vec,
+            y_sum,
+            enc_keys,
+            vss_scheme,
+            secret_shares,
         }
     }
+}
 
-    let (shared_keys, dlog_proof) = party_keys
-        .phase2_verify_vss_construct_keypair_phase3_pok_dlog(
-            &params,
-            &point_vec,
-            &party_shares,
-            &vss_scheme_vec,
-            party_num_int as usize,
-        )
-        .expect("invalid vss");
-
-    // round 5: send dlog proof
-    assert!(broadcast(
-        &addr,
-        &client,
-        party_num_int,
-        "round5",
-        serde_json::to_string(&dlog_proof).unwrap(),
-        uuid.clone(),
-    )
-    .is_ok());
-    let round5_ans_vec = poll_for_broadcasts(
-        &addr,
-        &client,
-        party_num_int,
-        PARTIES,
-        delay,
-        "round5",
-        uuid.clone(),
-    );
+/// Round 3: the secret shares, encrypted to each party with its own key
+#[derive(Serialize, Deserialize)]
+pub struct SharesRound {
+    setup: Setup,
+    party_keys: Keys,
+    bc1_vec: Vec<KeyGenBroadcastMessage1>,
+    point_vec: Vec<GE>,
+    y_sum: GE,
+    enc_keys: Vec<Vec<u8>>,
+    vss_scheme: VerifiableSS<GE>,
+    secret_shares: Vec<FE>,
+}
+
+impl SharesRound {
+    fn other_parties(&self) -> Vec<u16> {
+        (1..=self.setup.parties).filter(|i| *i != self.setup.party_num).collect()
+    }
+
+    fn encrypted_shares(&self) -> Vec<(u16, String)> {
+        self.other_parties()
+            .into_iter()
+            .zip(self.enc_keys.iter())
+            .map(|(i, key_i)| {
+                let plaintext = BigInt::to_bytes(&self.secret_shares[i as usize - 1].to_big_int());
+                let aead_pack_i = aes_encrypt_round(key_i, "round3", self.setup.party_num, i, &plaintext);
+                (i, serde_json::to_string(&aead_pack_i).unwrap())
+            })
+            .collect()
+    }
 
-    let mut j = 0;
-    let mut dlog_proof_vec: Vec<DLogProof<GE>> = Vec::new();
-    for i in 1..=PARTIES {
-        if i == party_num_int {
-            dlog_proof_vec.push(dlog_proof.clone());
-        } else {
-            let dlog_proof_j: DLogProof<GE> = serde_json::from_str(&round5_ans_vec[j]).unwrap();
-            dlog_proof_vec.push(dlog_proof_j);
-            j += 1;
+    pub fn next(self, incoming: &[String]) -> VssRound {
+        let received: Vec<FE> = self
+            .other_parties()
+            .into_iter()
+            .zip(self.enc_keys.iter())
+            .zip(incoming.iter())
+            .map(|((i, key_i), message)| {
+                let aead_pack: AEAD = serde_json::from_str(message).unwrap();
+                let out = aes_decrypt_round(key_i, "round3", i, self.setup.party_num, aead_pack)
+                    .unwrap_or_else(|e| panic!("Keygen aborted, no keys file was written: {}", e));
+                ECScalar::from(&BigInt::from_bytes(&out))
+            })
+            .collect();
+        let own_share = self.secret_shares[self.setup.party_num as usize - 1];
+        let mut party_shares = received;
+        party_shares.insert(self.setup.party_num as usize - 1, own_share);
+        VssRound {
+            setup: self.setup,
+            party_keys: self.party_keys,
+            bc1_vec: self.bc1_vec,
+            point_vec: self.point_vec,
+            y_sum: self.y_sum,
+            vss_scheme: self.vss_scheme,
+            party_shares,
         }
     }
-    Keys::verify_dlog_proofs(&params, &dlog_proof_vec, &point_vec).expect("bad dlog proof");
+}
 
-    //save key to file:
-    let paillier_key_vec = (0..PARTIES)
-        .map(|i| bc1_vec[i as usize].e.clone())
-        .collect::<Vec<EncryptionKey>>();
+/// Round 4: the VSS commitments, the received shares are checked against them
+#[derive(Serialize, Deserialize)]
+pub struct VssRound {
+    setup: Setup,
+    party_keys: Keys,
+    bc1_vec: Vec<KeyGenBroadcastMessage1>,
+    point_vec: Vec<GE>,
+    y_sum: GE,
+    vss_scheme: VerifiableSS<GE>,
+    party_shares: Vec<FE>,
+}
 
-    let keygen_json = serde_json::to_string(&(
-        party_keys,
-        shared_keys,
-        party_num_int,
-        vss_scheme_vec,
-        paillier_key_vec,
-        y_sum,
-    ))
-    .unwrap();
-    println!("Keys data written to file: {:?}", keysfile_path);
-    fs::write(&keysfile_path, keygen_json).expect("Unable to save !");
+impl VssRound {
+    pub fn next(self, incoming: &[String]) -> DlogProofRound {
+        let vss_scheme_vec = self.setup.with_own(incoming, self.vss_scheme);
+        let (shared_keys, dlog_proof) = self
+            .party_keys
+            .phase2_verify_vss_construct_keypair_phase3_pok_dlog(
+                &self.setup.params(),
+                &self.point_vec,
+                &self.party_shares,
+                &vss_scheme_vec,
+                self.setup.party_num as usize,
+            )
+            .expect("invalid vss");
+        DlogProofRound {
+            setup: self.setup,
+            party_keys: self.party_keys,
+            bc1_vec: self.bc1_vec,
+            point_vec: self.point_vec,
+            y_sum: self.y_sum,
+            vss_scheme_vec,
+            shared_keys,
+            dlog_proof,
+        }
+    }
 }
 
-pub fn keygen_signup(addr: &String, client: &Client, params: &Params) -> Result<PartySignup, ()> {
-    let res_body = postb(&addr, &client, "signupkeygen", params).unwrap();
-    serde_json::from_str(&res_body).unwrap()
+/// Round 5: proofs of knowledge of the shares, the last round before the keys file
+#[derive(Serialize, Deserialize)]
+pub struct DlogProofRound {
+    setup: Setup,
+    party_keys: Keys,
+    bc1_vec: Vec<KeyGenBroadcastMessage1>,
+    point_vec: Vec<GE>,
+    y_sum: GE,
+    vss_scheme_vec: Vec<VerifiableSS<GE>>,
+    shared_keys: SharedKeys,
+    dlog_proof: DLogProof<GE>,
+}
+
+impl DlogProofRound {
+    pub fn finish(self, incoming: &[String]) -> KeyFile {
+        let dlog_proof_vec = self.setup.with_own(incoming, self.dlog_proof);
+        Keys::verify_dlog_proofs(&self.setup.params(), &dlog_proof_vec, &self.point_vec).expect("bad dlog proof");
+
+        let paillier_key_vec = self.bc1_vec.iter().map(|bc1| bc1.e.clone()).collect::<Vec<EncryptionKey>>();
+        KeyFile {
+            party_keys: self.party_keys,
+            shared_keys: self.shared_keys,
+            party_id: self.setup.party_num,
+            vss_scheme_vec: self.vss_scheme_vec,
+            paillier_key_vec,
+            y_sum: self.y_sum,
+            chain_code: self.setup.chain_code,
+            canary: None,
+            share_points: None,
+        }
+    }
 }
@@ -0,0 +1,89 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use fs2::FileExt;
+use serde_json::json;
+
+use crate::common::options;
+
+/// Advisory lock on <keysfile>.lock, held while a command uses the share. Commands signing with or
+/// rewriting the share hold it exclusively and name themselves in the lock file, commands only reading
+/// the keys file share it. It is released when dropped or when the process exits
+pub struct KeysFileLock {
+    file: File,
+    exclusive: bool,
+}
+
+fn lock_path(keysfile_path: &str) -> String {
+    format!("{}.lock", keysfile_path)
+}
+
+fn holder(keysfile_path: &str) -> String {
+    std::fs::read_to_string(lock_path(keysfile_path))
+        .map(|holder| holder.trim().to_string())
+        .ok()
+        .filter(|holder| !holder.is_empty())
+        .unwrap_or("readers".to_string())
+}
+
+impl KeysFileLock {
+    /// Locks the keys file exclusively for command, queues behind the current holders for at most
+    /// --wait-lock seconds
+    pub fn acquire(keysfile_path: &str, command: &str) -> Self {
+        let mut lock = Self::lock(keysfile_path, true);
+        lock.file.set_len(0).unwrap();
+        write!(lock.file, "pid {} {}", std::process::id(), command).expect("Unable to write keys file lock !");
+        lock
+    }
+
+    /// Shared lock for commands only reading the keys file, waits for a command rewriting it
+    pub fn shared(keysfile_path: &str) -> Self {
+        Self::lock(keysfile_path, false)
+    }
+
+    fn lock(keysfile_path: &str, exclusive: bool) -> Self {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(lock_path(keysfile_path))
+            .expect("Unable to open keys file lock !");
+        let wait = options::get().lock_wait;
+        let started = Instant::now();
+        let mut waiting = false;
+        let try_lock = |file: &File| match exclusive {
+            true => file.try_lock_exclusive(),
+            false => file.try_lock_shared(),
+        };
+        while try_lock(&file).is_err() {
+            if started.elapsed().as_secs() >= wait {
+                panic!(
+                    "Keys file {} is in use by another tss_cli process ({}), concurrent use of a share is refused. \
+                     Retry later or set --wait-lock",
+                    keysfile_path,
+                    holder(keysfile_path)
+                );
+            }
+            if !waiting {
+                eprintln!(
+                    "{}",
+                    json!({"status": "keys_file_in_use", "keysfile": keysfile_path, "holder": holder(keysfile_path)}).to_string()
+                );
+                waiting = true;
+            }
+            thread::sleep(Duration::from_millis(200));
+        }
+        KeysFileLock { file, exclusive }
+    }
+}
+
+impl Drop for KeysFileLock {
+    fn drop(&mut self) {
+        if self.exclusive {
+            let _ = self.file.set_len(0);
+        }
+        let _ = self.file.unlock();
+    }
+}
@@ -0,0 +1,192 @@
+use std::num::NonZeroU32;
+
+use aes::Aes128;
+use ctr::cipher::{NewCipher, StreamCipher};
+use ring::digest::{digest, SHA256};
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use unicode_normalization::UnicodeNormalization;
+use uuid::Uuid;
+
+use crate::common::atomic_file;
+use crate::common::encoding::compressed_public_key;
+use crate::common::key_encryption;
+use crate::common::keyfile::KeyFile;
+use crate::common::keys_lock::KeysFileLock;
+
+type Aes128Ctr = ctr::Ctr128BE<Aes128>;
+
+/// Keystore version of EIP-2335
+pub const KEYSTORE_VERSION: u32 = 4;
+// PBKDF2-HMAC-SHA256 iterations of EIP-2335 test vectors
+const PBKDF2_ITERATIONS: u32 = 262144;
+
+/// kdf, checksum or cipher module of an EIP-2335 keystore, binary values in hex
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct KeystoreModule {
+    pub function: String,
+    pub params: Value,
+    pub message: String,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct KeystoreCrypto {
+    pub kdf: KeystoreModule,
+    pub checksum: KeystoreModule,
+    pub cipher: KeystoreModule,
+}
+
+/// EIP-2335 style keystore, the secret is the whole keys file JSON instead of a single key
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Keystore {
+    pub crypto: KeystoreCrypto,
+    pub description: String,
+    pub pubkey: String,
+    pub path: String,
+    pub uuid: String,
+    pub version: u32,
+}
+
+pub fn is_keystore(data: &Value) -> bool {
+    data.get("crypto").is_some() && data.get("version").and_then(|version| version.as_u64()) == Some(KEYSTORE_VERSION as u64)
+}
+
+fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; len];
+    SystemRandom::new().fill(&mut bytes).expect("Unable to get random bytes");
+    bytes
+}
+
+// EIP-2335 password processing: NFKD normalized, then control characters are stripped
+fn password_bytes(passphrase: &str) -> Vec<u8> {
+    passphrase.nfkd().filter(|c| !c.is_control()).collect::<String>().into_bytes()
+}
+
+// Keystores written here use pbkdf2, scrypt ones of other EIP-2335 tools are read as well
+fn derive_key(passphrase: &str, kdf: &KeystoreModule) -> Vec<u8> {
+    let param = |name: &str| kdf.params.get(name).unwrap_or_else(|| panic!("Keystore kdf param {} missing", name));
+    let number = |name: &str| param(name).as_u64().unwrap_or_else(|| panic!("Invalid keystore kdf param {}", name));
+    if number("dklen") != 32 {
+        panic!("Keystore kdf dklen must be 32");
+    }
+    let salt = hex::decode(param("salt").as_str().unwrap_or("")).expect("Invalid keystore kdf salt");
+    let mut key = vec![0u8; 32];
+    match kdf.function.as_str() {
+        "pbkdf2" if param("prf").as_str() == Some("hmac-sha256") => pbkdf2::derive(
+            pbkdf2::PBKDF2_HMAC_SHA256,
+            NonZeroU32::new(number("c") as u32).expect("Keystore kdf iterations must be positive"),
+            &salt,
+            &password_bytes(passphrase),
+            &mut key,
+        ),
+        "scrypt" => {
+            let n = number("n");
+            if !n.is_power_of_two() || n < 2 {
+                panic!("Keystore scrypt n must be a power of two");
+            }
+            let params = scrypt::Params::new(n.trailing_zeros() as u8, number("r") as u32, number("p") as u32)
+                .expect("Invalid keystore scrypt params");
+            scrypt::scrypt(&password_bytes(passphrase), &salt, &params, &mut key).expect("scrypt failure!");
+        }
+        _ => panic!(
+            "Unsupported keystore kdf: {}, pbkdf2 with hmac-sha256 and scrypt are supported",
+            kdf.function
+        ),
+    }
+    key
+}
+
+// sha256(decryption_key[16..32] || cipher message)
+fn checksum(key: &[u8], ciphertext: &[u8]) -> Vec<u8> {
+    digest(&SHA256, &[&key[16..32], ciphertext].concat()).as_ref().to_vec()
+}
+
+fn apply_cipher(key: &[u8], iv: &[u8], data: &mut [u8]) {
+    Aes128Ctr::new_from_slices(&key[..16], iv)
+        .expect("Invalid keystore cipher iv")
+        .apply_keystream(data);
+}
+
+/// Wraps a keys file into a keystore sealed with the passphrase
+pub fn seal(keyfile_json: &[u8], y_sum_hex: &str, party_id: u16, passphrase: &str) -> Keystore {
+    let kdf = KeystoreModule {
+        function: "pbkdf2".to_string(),
+        params: json!({
+            "dklen": 32,
+            "c": PBKDF2_ITERATIONS,
+            "prf": "hmac-sha256",
+            "salt": hex::encode(random_bytes(32)),
+        }),
+        message: String::new(),
+    };
+    let key = derive_key(passphrase, &kdf);
+    let iv = random_bytes(16);
+    let mut ciphertext = keyfile_json.to_vec();
+    apply_cipher(&key, &iv, &mut ciphertext);
+    Keystore {
+        crypto: KeystoreCrypto {
+            kdf,
+            checksum: KeystoreModule {
+                function: "sha256".to_string(),
+                params: json!({}),
+                message: hex::encode(checksum(&key, &ciphertext)),
+            },
+            cipher: KeystoreModule {
+                function: "aes-128-ctr".to_string(),
+                params: json!({"iv": hex::encode(iv)}),
+                message: hex::encode(ciphertext),
+            },
+        },
+        description: format!("tss-cli key share of party {}", party_id),
+        pubkey: y_sum_hex.to_string(),
+        path: String::new(),
+        uuid: Uuid::new_v4().to_string(),
+        version: KEYSTORE_VERSION,
+    }
+}
+
+/// Keys file JSON inside a keystore, the checksum is verified before decrypting
+pub fn open(keystore: &Keystore, passphrase: &str) -> Vec<u8> {
+    let crypto = &keystore.crypto;
+    if crypto.checksum.function != "sha256" || crypto.cipher.function != "aes-128-ctr" {
+        panic!("Unsupported keystore modules: {} {}", crypto.checksum.function, crypto.cipher.function);
+    }
+    let key = derive_key(passphrase, &crypto.kdf);
+    let mut data = hex::decode(&crypto.cipher.message).expect("Invalid keystore cipher message");
+    if hex::encode(checksum(&key, &data)) != crypto.checksum.message {
+        panic!("Wrong passphrase or corrupted keystore, checksum mismatch");
+    }
+    let iv = crypto.cipher.params.get("iv").and_then(|iv| iv.as_str()).expect("Keystore cipher iv missing");
+    apply_cipher(&key, &hex::decode(iv).expect("Invalid keystore cipher iv"), &mut data);
+    data
+}
+
+/// Converts a keys file into a keystore. The passphrase of an encrypted keys file is kept,
+/// a new one is asked for plaintext keys files
+pub fn export(keysfile_path: &str, out_path: &str) {
+    let _lock = KeysFileLock::shared(keysfile_path);
+    let keyfile = KeyFile::load(keysfile_path);
+    let passphrase = key_encryption::save_passphrase().unwrap_or_else(key_encryption::new_passphrase);
+    let keystore = seal(
+        &keyfile.to_json().into_bytes(),
+        &hex::encode(compressed_public_key(&keyfile.y_sum)),
+        keyfile.party_id,
+        &passphrase,
+    );
+    atomic_file::write_atomic(out_path, serde_json::to_string_pretty(&keystore).unwrap().as_bytes());
+    println!("{}", json!({"status": "keystore_exported", "keystore": out_path, "uuid": keystore.uuid}).to_string());
+}
+
+/// Converts a keystore back into a keys file, encrypted with the keystore passphrase unless plaintext is asked for
+pub fn import(keystore_path: &str, out_path: &str, plaintext: bool) {
+    let _lock = KeysFileLock::acquire(out_path, "keystore import");
+    let keyfile = KeyFile::load(keystore_path);
+    if plaintext {
+        atomic_file::write_atomic(out_path, keyfile.to_json().as_bytes());
+    } else {
+        keyfile.replace(out_path);
+    }
+    println!("{}", json!({"status": "keystore_imported", "keysfile": out_path, "encrypted": !plaintext}).to_string());
+}
@@ -0,0 +1,134 @@
+use std::fs;
+use std::process::Command;
+use std::sync::Mutex;
+
+use aes_gcm::aead::{Aead, NewAead, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::common::options;
+
+const AAD: &[u8] = b"tss-cli keys file kms";
+
+/// Key management service holding the master key, data keys are generated and unwrapped by it.
+/// Implement it for other services and add them to provider()
+pub trait KmsProvider {
+    fn name(&self) -> &'static str;
+
+    /// New 32 byte data key: (plaintext, data key encrypted under key_id)
+    fn generate_data_key(&self, key_id: &str) -> (Vec<u8>, Vec<u8>);
+
+    /// Plaintext of a data key encrypted under key_id
+    fn decrypt_data_key(&self, key_id: &str, encrypted_data_key: &[u8]) -> Vec<u8>;
+}
+
+/// AWS KMS through the aws CLI, credentials come from the instance role or the usual AWS environment
+pub struct AwsKms;
+
+// Runs the aws CLI and parses its JSON output
+fn aws(args: &[&str]) -> Value {
+    let output = Command::new("aws")
+        .args(args)
+        .args(&["--output", "json"])
+        .output()
+        .expect("Unable to run the aws CLI, is it installed?");
+    if !output.status.success() {
+        panic!("aws {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim());
+    }
+    serde_json::from_slice(&output.stdout).expect("Invalid aws CLI output")
+}
+
+fn base64_field(value: &Value, field: &str) -> Vec<u8> {
+    base64::decode(value[field].as_str().unwrap_or_else(|| panic!("aws CLI output has no {}", field)))
+        .expect("Invalid base64 in aws CLI output")
+}
+
+impl KmsProvider for AwsKms {
+    fn name(&self) -> &'static str {
+        "aws"
+    }
+
+    fn generate_data_key(&self, key_id: &str) -> (Vec<u8>, Vec<u8>) {
+        let output = aws(&["kms", "generate-data-key", "--key-id", key_id, "--key-spec", "AES_256"]);
+        (base64_field(&output, "Plaintext"), base64_field(&output, "CiphertextBlob"))
+    }
+
+    fn decrypt_data_key(&self, key_id: &str, encrypted_data_key: &[u8]) -> Vec<u8> {
+        // The blob goes through a file, the CLI versions disagree on how binary arguments are encoded
+        let blob_path = std::env::temp_dir().join(format!("tss-cli-kms-{}", Uuid::new_v4()));
+        fs::write(&blob_path, encrypted_data_key).expect("Unable to write KMS blob !");
+        let blob_arg = format!("fileb://{}", blob_path.display());
+        let output = aws(&["kms", "decrypt", "--key-id", key_id, "--ciphertext-blob", &blob_arg]);
+        fs::remove_file(&blob_path).ok();
+        base64_field(&output, "Plaintext")
+    }
+}
+
+pub fn provider(name: &str) -> Box<dyn KmsProvider> {
+    match name {
+        "aws" => Box::new(AwsKms),
+        _ => panic!("Unsupported KMS provider: {}", name),
+    }
+}
+
+/// Keys file envelope encrypted with a KMS data key, binary fields in hex except the data key (base64)
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct KmsKeyFile {
+    pub kms: String,
+    pub key_id: String,
+    pub encrypted_data_key: String,
+    pub nonce: String,
+    pub ciphertext: String,
+}
+
+pub fn is_kms_file(data: &Value) -> bool {
+    data.get("kms").is_some()
+}
+
+// Key id of the envelope encrypted keys file loaded last, keys files are then saved under the same key
+static LOADED_KEY_ID: Mutex<Option<String>> = Mutex::new(None);
+
+/// KMS key id from --kms-key-id or of the loaded keys file, None when keys files are not envelope encrypted
+pub fn kms_key_id() -> Option<String> {
+    options::get().kms_key_id.or_else(|| LOADED_KEY_ID.lock().unwrap().clone())
+}
+
+pub fn encrypt(plaintext: &[u8], key_id: &str) -> KmsKeyFile {
+    let provider = provider("aws");
+    let (data_key, encrypted_data_key) = provider.generate_data_key(key_id);
+    let mut nonce = [0u8; 12];
+    SystemRandom::new().fill(&mut nonce).expect("Unable to get random bytes");
+    let cipher = Aes256Gcm::new(aes_gcm::Key::from_slice(&data_key));
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext, aad: AAD })
+        .expect("encryption failure!");
+    KmsKeyFile {
+        kms: provider.name().to_string(),
+        key_id: key_id.to_string(),
+        encrypted_data_key: base64::encode(encrypted_data_key),
+        nonce: hex::encode(nonce),
+        ciphertext: hex::encode(ciphertext),
+    }
+}
+
+/// Decrypts an envelope, a key id given with --kms-key-id must be the one the file was sealed with
+pub fn decrypt(encrypted: &KmsKeyFile) -> Vec<u8> {
+    if let Some(key_id) = options::get().kms_key_id {
+        if key_id != encrypted.key_id {
+            panic!("Keys file is encrypted with KMS key {}, not {}", encrypted.key_id, key_id);
+        }
+    }
+    let encrypted_data_key = base64::decode(&encrypted.encrypted_data_key).expect("Invalid KMS keys file");
+    let data_key = provider(&encrypted.kms).decrypt_data_key(&encrypted.key_id, &encrypted_data_key);
+    let nonce = hex::decode(&encrypted.nonce).expect("Invalid KMS keys file");
+    let ciphertext = hex::decode(&encrypted.ciphertext).expect("Invalid KMS keys file");
+    let cipher = Aes256Gcm::new(aes_gcm::Key::from_slice(&data_key));
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(&nonce), Payload { msg: &ciphertext, aad: AAD })
+        .expect("KMS keys file is corrupted");
+    *LOADED_KEY_ID.lock().unwrap() = Some(encrypted.key_id.clone());
+    plaintext
+}
@@ -1,5 +1,5 @@
 use std::sync::RwLock;
-use std::time::{Duration};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use rocket::{Ignite, post, Rocket, routes, State};
 use rocket::serde::json::Json;
@@ -9,7 +9,8 @@ use ttlhashmap::TtlHashMap;
 
 use uuid::Uuid;
 
-use crate::common::{Entry, Index, Key, ManagerError, Params, PartySignup, PartySignupRequestBody, SigningPartySignup};
+use crate::common::{Entry, Index, Key, ManagerError, ObservedMessage, Params, PartySignup, PartySignupRequestBody, DropoutReport, RoomObservation, RoomRequest, sha256_digest, SignedEntry, SigningPartySignup};
+use crate::common::compression::{compression_enabled, Gzip};
 use crate::common::signing_room::SigningRoom;
 
 #[rocket::main]
@@ -20,14 +21,25 @@ pub async fn run_manager() -> Result<(), rocket::Error> {
         .unwrap_or("300".to_string()).parse::<u64>().unwrap();
     let db: TtlHashMap<Key, String> = TtlHashMap::new(Duration::from_secs(ttl));
     let db_mtx = RwLock::new(db);
+    let observation_log = ObservationLog(RwLock::new(TtlHashMap::new(Duration::from_secs(ttl))));
     //rocket::custom(my_config).mount("/", routes![get, set]).manage(db_mtx).launch();
 
     /////////////////////////////////////////////////////////////////
-    rocket::build()
-        .mount("/", routes![get, set, signup_keygen, signup_sign])
+    let rocket = rocket::build()
+        .mount("/", routes![get, set, signup_keygen, signup_sign, observe, signed, record_signed, dropout, server_time])
         .manage(db_mtx)
-        .launch()
-        .await
+        .manage(observation_log);
+    let rocket = match compression_enabled("TSS_MANAGER_COMPRESSION") {
+        true => rocket.attach(Gzip),
+        false => rocket,
+    };
+    rocket.launch().await
+}
+
+/// Manager's unix time, parties compare it with their own clock
+#[post("/time")]
+fn server_time() -> Json<u64> {
+    Json(SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs())
 }
 
 #[post("/get", format = "json", data = "<request>")]
@@ -54,14 +66,82 @@ fn get(
     }
 }
 
+// Keys the manager writes itself, clients can't set them
+const MANAGER_KEY_PREFIXES: [&str; 3] = ["signup-", "signed-", "dropout-"];
+
 #[post("/set", format = "json", data = "<request>")]
-fn set(db_mtx: &State<RwLock<TtlHashMap<Key, String>>>, request: Json<Entry>) -> Json<Result<(), ()>> {
+fn set(
+    db_mtx: &State<RwLock<TtlHashMap<Key, String>>>,
+    observation_log: &State<ObservationLog>,
+    request: Json<Entry>,
+) -> Json<Result<(), ()>> {
     let entry: Entry = request.0;
+    if MANAGER_KEY_PREFIXES.iter().any(|prefix| entry.key.starts_with(prefix)) {
+        return Json(Err(()));
+    }
     let mut hm = db_mtx.write().unwrap();
     hm.insert(entry.key.clone(), entry.value.clone());
+    observation_log.record(&entry);
     Json(Ok(()))
 }
 
+const UUID_LENGTH: usize = 36;
+
+/// Digest-only trail of every message posted to a room, keyed by the room uuid. It is kept apart from the
+/// key-value store, no endpoint writes it except through set, which only appends
+pub struct ObservationLog(RwLock<TtlHashMap<String, Vec<ObservedMessage>>>);
+
+impl ObservationLog {
+    // Message keys end with the room uuid: {party}-{round}-{uuid} or {from}-{to}-{round}-{uuid}
+    fn record(&self, entry: &Entry) {
+        if entry.key.len() <= UUID_LENGTH + 1 {
+            return;
+        }
+        let (message_key, room_uuid) = entry.key.split_at(entry.key.len() - UUID_LENGTH);
+        if Uuid::parse_str(room_uuid).is_err() {
+            return;
+        }
+        let message = ObservedMessage {
+            key: message_key.trim_end_matches('-').to_string(),
+            digest: sha256_digest(entry.value.as_bytes()),
+            size: entry.value.len(),
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+        };
+        // Appended in place, a room's trail grows with every /set and is not copied for it
+        let mut log = self.0.write().unwrap();
+        match log.get_mut(&room_uuid.to_string()) {
+            Some(messages) => messages.push(message),
+            None => {
+                log.insert(room_uuid.to_string(), vec![message]);
+            }
+        }
+    }
+
+    fn messages(&self, room_uuid: &str) -> Vec<ObservedMessage> {
+        self.0.write().unwrap().get(&room_uuid.to_string()).cloned().unwrap_or_default()
+    }
+}
+
+#[post("/observe", format = "json", data = "<request>")]
+fn observe(
+    db_mtx: &State<RwLock<TtlHashMap<Key, String>>>,
+    observation_log: &State<ObservationLog>,
+    request: Json<RoomRequest>,
+) -> Json<Result<RoomObservation, ManagerError>> {
+    let key = format!("signup-sign-{}", request.room_id);
+    let mut hm = db_mtx.write().unwrap();
+
+    let signing_room: SigningRoom = match hm.get(&key) {
+        Some(o) => serde_json::from_str(o).unwrap(),
+        None => {
+            return Json(Err(ManagerError{
+                error: "Room not found: ".to_string() + request.room_id.as_str()
+            }));
+        }
+    };
+    Json(Ok(signing_room.observe(observation_log.messages(&signing_room.room_uuid))))
+}
+
 #[post("/signupkeygen", format = "json", data = "<request>")]
 fn signup_keygen(
     db_mtx: &State<RwLock<TtlHashMap<Key, String>>>,
@@ -103,6 +183,7 @@ fn signup_sign(
     request: Json<PartySignupRequestBody>,
 ) -> Json<Result<SigningPartySignup, ManagerError>> {
     let threshold = request.clone().threshold;
+    let room_size = if request.room_size > threshold { request.room_size } else { threshold + 1 };
     let room_id = request.room_id.clone();
     let party_uuid = request.party_uuid.clone();
     let new_signup_request = party_uuid.is_empty();
@@ -112,9 +193,9 @@ fn signup_sign(
 
     let mut hm = db_mtx.write().unwrap();
 
-    let mut signing_room = match hm.get(&key) {
+    let mut signing_room: SigningRoom = match hm.get(&key) {
         Some(o) => serde_json::from_str(o).unwrap(),
-        None => SigningRoom::new(room_id.clone(), threshold+1),
+        None => SigningRoom::new(room_id.clone(), room_size),
     };
 
     if signing_room.last_stage != "signup" {
@@ -129,7 +210,7 @@ fn signup_sign(
                 "fragment.index": party_number,
             });
             println!("{}", serde_json::to_string_pretty(&debug).unwrap());
-            signing_room = SigningRoom::new(room_id, threshold + 1)
+            signing_room = SigningRoom::new(room_id.clone(), room_size)
         }
         else {
             return Json(Err(ManagerError{
@@ -138,6 +219,20 @@ fn signup_sign(
         }
     }
 
+    // The first signup fixes the room size, a party asking for another size must not change it for everyone.
+    // A room left behind by parties that all timed out is renewed instead
+    if signing_room.room_size != room_size {
+        if signing_room.has_active_members() {
+            return Json(Err(ManagerError{
+                error: format!(
+                    "Room size mismatch: the room is for {} parties, the signup asks for {}",
+                    signing_room.room_size, room_size
+                )
+            }));
+        }
+        signing_room = SigningRoom::new(room_id.clone(), room_size);
+    }
+
     if signing_room.is_full() && signing_room.are_all_members_active() && new_signup_request {
         return Json(Err(ManagerError{
             error: "Room is full, all members active".to_string()
@@ -172,3 +267,58 @@ fn signup_sign(
     hm.insert(key.clone(), serde_json::to_string(&signing_room).unwrap());
     Json(Ok(party_signup))
 }
+
+/// Parties that dropped out of a room: the first report is kept and returned to every party, so all parties
+/// restart the signing with the same survivors
+#[post("/dropout", format = "json", data = "<request>")]
+fn dropout(
+    db_mtx: &State<RwLock<TtlHashMap<Key, String>>>,
+    request: Json<DropoutReport>,
+) -> Json<Result<Vec<u16>, ManagerError>> {
+    if request.missing.is_empty() {
+        return Json(Err(ManagerError { error: "Dropout report names no party".to_string() }));
+    }
+    let key = format!("dropout-{}", request.room_uuid);
+    let mut hm = db_mtx.write().unwrap();
+    let missing: Vec<u16> = match hm.get(&key) {
+        Some(o) => serde_json::from_str(o).unwrap(),
+        None => {
+            hm.insert(key, serde_json::to_string(&request.missing).unwrap());
+            request.missing.clone()
+        }
+    };
+    Json(Ok(missing))
+}
+
+// r values of the completed signatures under one key, path and digest
+fn signed_values(hm: &mut TtlHashMap<Key, String>, key: &str) -> Vec<String> {
+    match hm.get(&format!("signed-{}", key)) {
+        Some(o) => serde_json::from_str(o).unwrap_or_default(),
+        None => Vec::new(),
+    }
+}
+
+/// Number of completed signatures under one key, path and digest, used by clients to detect duplicate signing
+#[post("/signed", format = "json", data = "<request>")]
+fn signed(
+    db_mtx: &State<RwLock<TtlHashMap<Key, String>>>,
+    request: Json<Index>,
+) -> Json<Result<u16, ManagerError>> {
+    let mut hm = db_mtx.write().unwrap();
+    Json(Ok(signed_values(&mut hm, &request.key).len() as u16))
+}
+
+/// Records a completed signature, every party of a signing reports the same r so it counts once
+#[post("/record-signed", format = "json", data = "<request>")]
+fn record_signed(
+    db_mtx: &State<RwLock<TtlHashMap<Key, String>>>,
+    request: Json<SignedEntry>,
+) -> Json<Result<(), ManagerError>> {
+    let mut hm = db_mtx.write().unwrap();
+    let mut values = signed_values(&mut hm, &request.key);
+    if !values.contains(&request.r) {
+        values.push(request.r.clone());
+    }
+    hm.insert(format!("signed-{}", request.key), serde_json::to_string(&values).unwrap());
+    Json(Ok(()))
+}
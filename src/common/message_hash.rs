@@ -0,0 +1,65 @@
+use std::fs;
+use std::io::{self, Read};
+
+use ring::digest::{digest, SHA256};
+use serde_json::json;
+use tiny_keccak::{Hasher, Keccak};
+
+/// Hashes sign applies to the message before signing, none signs the message itself
+pub const MESSAGE_HASHES: [&str; 3] = ["none", "sha256", "keccak256"];
+
+/// Keccak-256 as Ethereum uses it, the original Keccak padding rather than SHA3-256
+pub fn keccak256(data: &[u8]) -> [u8; 32] {
+    let mut hasher = Keccak::v256();
+    hasher.update(data);
+    let mut hash = [0u8; 32];
+    hasher.finalize(&mut hash);
+    hash
+}
+
+/// Digest to sign for a message. With none the message itself is signed as a number, as sign always did, see
+/// check_digest_length for messages over 32 bytes
+pub fn hash_message(message: &[u8], hash: &str, strict: bool) -> Result<Vec<u8>, clap::Error> {
+    match hash {
+        "none" => check_digest_length(message, hash, strict).map(|_| message.to_vec()),
+        "sha256" => Ok(digest(&SHA256, message).as_ref().to_vec()),
+        "keccak256" => Ok(keccak256(message).to_vec()),
+        _ => panic!("Unknown message hash {}, supported: {}", hash, MESSAGE_HASHES.join(", ")),
+    }
+}
+
+/// A message over 32 bytes under --hash none: signing keeps its last 32 bytes only, so every message with the same
+/// tail gets the same signature. It is signed with a warning on stderr, refused with --strict-digest
+pub fn check_digest_length(message: &[u8], hash: &str, strict: bool) -> Result<(), clap::Error> {
+    if hash != "none" || message.len() <= 32 {
+        return Ok(());
+    }
+    if strict {
+        return Err(clap::Error::with_description(
+            &format!(
+                "Message is {} bytes, --hash none signs a digest of at most 32 bytes. Use --hash sha256 or --hash keccak256",
+                message.len()
+            ),
+            clap::ErrorKind::InvalidValue,
+        ));
+    }
+    let warning = json!({
+        "status": "message_truncated",
+        "message_len": message.len(),
+        "message": "Only the last 32 bytes of the message are signed, use --hash sha256 or --hash keccak256",
+    });
+    eprintln!("{}", warning.to_string());
+    Ok(())
+}
+
+/// Raw bytes of a message file, - for stdin. The content is signed as is, without hex decoding
+pub fn read_message_file(path: &str) -> Vec<u8> {
+    match path {
+        "-" => {
+            let mut message = Vec::new();
+            io::stdin().lock().read_to_end(&mut message).expect("Unable to read the message from stdin");
+            message
+        }
+        _ => fs::read(path).unwrap_or_else(|e| panic!("Unable to read message file {}: {}", path, e)),
+    }
+}
@@ -0,0 +1,221 @@
+use curv::cryptographic_primitives::secret_sharing::feldman_vss::VerifiableSS;
+use curv::elliptic::curves::secp256_k1::GE;
+use serde_json::{json, Value};
+
+use crate::common::atomic_file;
+use crate::common::integrity;
+use crate::common::key_encryption;
+use crate::common::keyfile::{KeyFile, KeyMetadata, KEYS_FILE_CURVE, KEYS_FILE_FORMAT, KEYS_FILE_PROTOCOL};
+
+/// Keys file format written by this version
+pub const CURRENT_VERSION: u32 = 6;
+
+/// Converter from version n to n+1, MIGRATIONS[i] upgrades version i+1
+type Migration = fn(Value) -> Value;
+
+// Append converters here when the format changes, never edit or reorder existing ones
+const MIGRATIONS: [Migration; (CURRENT_VERSION - 1) as usize] = [v1_to_v2, v2_to_v3, v3_to_v4, v4_to_v5, v5_to_v6];
+
+/// Inverse converters, DOWNGRADES[i] turns version i+2 back into version i+1
+// Append the inverse of every new converter here
+const DOWNGRADES: [Migration; (CURRENT_VERSION - 1) as usize] = [v2_to_v1, v3_to_v2, v4_to_v3, v5_to_v4, v6_to_v5];
+
+// v2 names the tuple fields and adds the format tag
+fn v1_to_v2(data: Value) -> Value {
+    let items = data.as_array().unwrap();
+    json!({
+        "format": KEYS_FILE_FORMAT,
+        "version": 2,
+        "party_keys": items[0],
+        "shared_keys": items[1],
+        "party_id": items[2],
+        "vss_scheme_vec": items[3],
+        "paillier_key_vec": items[4],
+        "y_sum": items[5],
+    })
+}
+
+// v3 adds the key metadata, derived from the VSS parameters and the public key
+fn v2_to_v3(mut data: Value) -> Value {
+    let vss_scheme_vec: Vec<VerifiableSS<GE>> =
+        serde_json::from_value(data["vss_scheme_vec"].clone()).expect("Invalid keys file");
+    let y_sum: GE = serde_json::from_value(data["y_sum"].clone()).expect("Invalid keys file");
+    data["metadata"] = serde_json::to_value(KeyMetadata::new(&vss_scheme_vec, &y_sum, None)).unwrap();
+    data["version"] = json!(3);
+    data
+}
+
+// v4 moves the key material into data, under an envelope naming its curve and protocol
+fn v3_to_v4(mut data: Value) -> Value {
+    let fields = data.as_object_mut().unwrap();
+    fields.remove("format");
+    fields.remove("version");
+    json!({
+        "format": KEYS_FILE_FORMAT,
+        "version": 4,
+        "curve": KEYS_FILE_CURVE,
+        "protocol": KEYS_FILE_PROTOCOL,
+        "data": data,
+    })
+}
+
+// v5 adds the integrity MAC, it is written on the next save. Files from v5 on must carry one
+fn v4_to_v5(mut data: Value) -> Value {
+    data["version"] = json!(5);
+    data
+}
+
+// v6 stores the HD chain code fixed at keygen, older files have none recorded and derive with the one given
+fn v5_to_v6(mut data: Value) -> Value {
+    data["version"] = json!(6);
+    data
+}
+
+fn v2_to_v1(data: Value) -> Value {
+    json!([
+        data["party_keys"],
+        data["shared_keys"],
+        data["party_id"],
+        data["vss_scheme_vec"],
+        data["paillier_key_vec"],
+        data["y_sum"],
+    ])
+}
+
+fn v3_to_v2(mut data: Value) -> Value {
+    data.as_object_mut().unwrap().remove("metadata");
+    data["version"] = json!(2);
+    data
+}
+
+fn v4_to_v3(data: Value) -> Value {
+    let mut flat = data["data"].clone();
+    flat["format"] = data["format"].clone();
+    flat["version"] = json!(3);
+    flat
+}
+
+// The MAC was checked when the file was loaded, older versions have no place for it
+fn v5_to_v4(mut data: Value) -> Value {
+    data.as_object_mut().unwrap().remove("integrity");
+    data["version"] = json!(4);
+    data
+}
+
+// v5 has no place for the chain code or the canary result, the chain code has to be given again to the older
+// tss_cli. The MAC is made again for the smaller file
+fn v6_to_v5(mut data: Value) -> Value {
+    let fields = data.as_object_mut().unwrap();
+    fields.remove("integrity");
+    let key_data = fields["data"].as_object_mut().unwrap();
+    key_data.remove("chain_code");
+    let metadata = key_data["metadata"].as_object_mut().unwrap();
+    metadata.remove("chain_code_fingerprint");
+    metadata.remove("canary");
+    data["version"] = json!(5);
+    integrity::seal(&mut data, key_encryption::save_passphrase());
+    data
+}
+
+/// First version whose files carry an integrity MAC
+pub const INTEGRITY_VERSION: u32 = 5;
+
+/// Format version of keys file content, or why it is not a keys file this version can read. This is the one check
+/// of the version, a version returned is always 1 to CURRENT_VERSION.
+/// v1 is the plain (keys, shared_keys, party_id, vss_scheme_vec, paillier_key_vec, y_sum) tuple,
+/// later versions are objects carrying a "format" tag and a "version" field
+pub fn check_format(data: &Value) -> Result<u32, String> {
+    let fields = match data {
+        Value::Array(items) if items.len() == 6 => return Ok(1),
+        Value::Array(items) => {
+            return Err(format!("it is a list of {} items, v1 keys files are a list of 6", items.len()))
+        }
+        Value::Object(fields) => fields,
+        _ => return Err("it is neither a list nor an object".to_string()),
+    };
+    if fields.contains_key("ECDSAPub") || fields.contains_key("EDDSAPub") {
+        return Err("it is a bnb-chain/tss-lib key share, convert it with import --format tss-lib".to_string());
+    }
+    if fields.contains_key("crypto") {
+        return Err(format!(
+            "it is a version {} keystore, only EIP-2335 version 4 keystores are read",
+            fields.get("version").unwrap_or(&Value::Null)
+        ));
+    }
+    match fields.get("format").and_then(|format| format.as_str()) {
+        Some(KEYS_FILE_FORMAT) => {}
+        Some(format) => return Err(format!("it is a {} file, not a keys file", format)),
+        None => return Err("it has no format tag, it was not written by tss_cli".to_string()),
+    }
+    let version = fields
+        .get("version")
+        .and_then(|version| version.as_u64())
+        .ok_or("its format version is missing".to_string())?;
+    if version < 2 || version > CURRENT_VERSION as u64 {
+        return Err(format!(
+            "format version {} is not supported, this tss_cli reads versions 1 to {}{}",
+            version,
+            CURRENT_VERSION,
+            if version > CURRENT_VERSION as u64 { ", upgrade tss_cli" } else { "" }
+        ));
+    }
+    Ok(version as u32)
+}
+
+/// Format version of keys file content, panics when it is not a keys file
+pub fn detect_version(data: &Value) -> u32 {
+    check_format(data).unwrap_or_else(|e| panic!("Unknown keys file format: {}", e))
+}
+
+/// Runs the converters from the file's version up to CURRENT_VERSION, returns (data, original version)
+/// or why the file can't be migrated
+pub fn migrate(mut data: Value) -> Result<(Value, u32), String> {
+    // check_format only returns versions 1 to CURRENT_VERSION, the converters are indexed from v1
+    let version = check_format(&data)?;
+    for migration in MIGRATIONS.iter().skip((version - 1) as usize) {
+        data = migration(data);
+    }
+    Ok((data, version))
+}
+
+/// Writes a keys file in an older format for older tss_cli deployments, running the inverse converters
+/// from the current version down. The output is plaintext, older formats can't hold what encrypts or
+/// MACs the current one
+pub fn downgrade(keysfile_path: &str, version: u32, out_path: &str) {
+    if version < 1 || version >= CURRENT_VERSION {
+        panic!("Keys files can be downgraded to versions 1 to {}", CURRENT_VERSION - 1);
+    }
+    if out_path == keysfile_path {
+        panic!("Write the downgraded keys file next to the original, it is not replaced");
+    }
+    let keyfile = KeyFile::load(keysfile_path);
+    if keyfile.share_points.is_some() {
+        panic!("Keys file {} holds an imported share older versions can't read, reshare it first", keysfile_path);
+    }
+    let mut data: Value = serde_json::from_str(&keyfile.to_json()).unwrap();
+    for downgrade in DOWNGRADES.iter().skip((version - 1) as usize).rev() {
+        data = downgrade(data);
+    }
+    // write_atomic creates the file with mode 0600, the plaintext share is never readable by others
+    atomic_file::write_atomic(out_path, data.to_string().as_bytes());
+    println!(
+        "{}",
+        json!({"status": "keys_downgraded", "keysfile": out_path, "version": version, "encrypted": false}).to_string()
+    );
+}
+
+/// Prints the keys file version, with upgrade the file is rewritten in the current format.
+/// downgrade writes the old format again when an older tss_cli needs it
+pub fn keys_version(keysfile_path: &str, upgrade: bool) {
+    let version = KeyFile::version(keysfile_path);
+    let mut ret_dict = json!({
+        "version": version,
+        "current_version": CURRENT_VERSION,
+        "upgrade_needed": version < CURRENT_VERSION,
+    });
+    if upgrade && version < CURRENT_VERSION {
+        KeyFile::load(keysfile_path).replace(keysfile_path);
+        ret_dict["upgraded_to"] = json!(CURRENT_VERSION);
+    }
+    println!("{}", ret_dict.to_string());
+}
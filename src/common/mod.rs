@@ -1,29 +1,148 @@
+#[cfg(feature = "signer")]
+pub mod age_encryption;
+#[cfg(feature = "signer")]
+pub mod approval;
+#[cfg(feature = "signer")]
+pub mod artifact;
+#[cfg(feature = "signer")]
+pub mod atomic_file;
+#[cfg(feature = "signer")]
+pub mod bench;
+#[cfg(feature = "signer")]
+pub mod bitcoin;
+#[cfg(feature = "signer")]
+pub mod canary;
+#[cfg(feature = "signer")]
+pub mod chain_code;
+#[cfg(feature = "signer")]
+pub mod compare;
+pub mod compression;
+#[cfg(feature = "signer")]
+pub mod cose;
+#[cfg(feature = "signer")]
+pub mod cosmos;
+#[cfg(feature = "signer")]
+pub mod csr;
+#[cfg(feature = "signer")]
+pub mod doctor;
+#[cfg(feature = "signer")]
+pub mod doh;
+#[cfg(feature = "signer")]
+pub mod duplicates;
+#[cfg(feature = "signer")]
+pub mod encoding;
+#[cfg(feature = "signer")]
+pub mod ethereum;
+#[cfg(feature = "signer")]
+pub mod export;
+#[cfg(feature = "signer")]
 pub mod hd_keys;
+#[cfg(feature = "signer")]
+pub mod healthcheck;
+#[cfg(feature = "signer")]
+pub mod hsm;
+#[cfg(feature = "signer")]
+pub mod import;
+#[cfg(feature = "signer")]
+pub mod integrity;
+#[cfg(feature = "signer")]
+pub mod interchange;
+#[cfg(feature = "signer")]
+pub mod jwt;
+#[cfg(feature = "signer")]
+pub mod key_dir;
+#[cfg(feature = "signer")]
+pub mod key_encryption;
+#[cfg(feature = "signer")]
+pub mod keychain;
+#[cfg(feature = "signer")]
+pub mod keyfile;
+#[cfg(feature = "signer")]
 pub mod keygen;
+#[cfg(feature = "signer")]
+pub mod keys_lock;
+#[cfg(feature = "signer")]
+pub mod keystore;
+#[cfg(feature = "signer")]
+pub mod kms;
+#[cfg(feature = "manager")]
 pub mod manager;
+#[cfg(feature = "signer")]
+pub mod message_hash;
+#[cfg(feature = "signer")]
+pub mod migrations;
+#[cfg(feature = "signer")]
+pub mod observer;
+#[cfg(feature = "signer")]
+pub mod options;
+#[cfg(all(feature = "signer", feature = "manager"))]
+pub mod pool;
+#[cfg(feature = "signer")]
+pub mod preflight;
+#[cfg(feature = "signer")]
+pub mod presign;
+#[cfg(feature = "signer")]
+pub mod psbt;
+#[cfg(feature = "signer")]
+pub mod recover;
+#[cfg(feature = "signer")]
+pub mod refresh;
+#[cfg(feature = "signer")]
+pub mod rekey;
+#[cfg(feature = "signer")]
+pub mod reshare;
+#[cfg(feature = "signer")]
+pub mod schema;
+#[cfg(all(feature = "signer", feature = "manager"))]
+pub mod selftest;
+#[cfg(feature = "signer")]
 pub mod signer;
+#[cfg(feature = "manager")]
 pub mod signing_room;
-
-use std::{iter::repeat, thread, time, time::Duration};
-use std::time::Instant;
-
+#[cfg(feature = "signer")]
+pub mod taproot;
+#[cfg(feature = "signer")]
+pub mod verify;
+#[cfg(feature = "signer")]
+pub mod verify_share;
+#[cfg(feature = "signer")]
+pub mod xpub;
+#[cfg(feature = "signer")]
+pub mod xrp;
+
+#[cfg(feature = "signer")]
+use std::{fmt, iter::repeat, thread, time, time::Duration};
+#[cfg(feature = "signer")]
+use std::io::Read;
+#[cfg(feature = "signer")]
+use std::sync::Mutex;
+#[cfg(feature = "signer")]
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "signer")]
 use aes_gcm::{Aes256Gcm, Nonce};
+#[cfg(feature = "signer")]
 use aes_gcm::aead::{NewAead, Aead, Payload};
 
+#[cfg(feature = "signer")]
 use curv::{
     elliptic::curves::secp256_k1::{FE, GE},
     elliptic::curves::traits::{ECPoint, ECScalar},
     BigInt,
 };
 use curv::arithmetic::Converter;
+#[cfg(feature = "signer")]
 use reqwest::blocking::Client;
 use serde::{Deserialize, Serialize};
 use curv::cryptographic_primitives::hashing::hash_sha256::HSha256;
 use curv::cryptographic_primitives::hashing::traits::Hash;
+#[cfg(feature = "signer")]
+use ring::{constant_time, hkdf};
 
 
 pub type Key = String;
 
+#[cfg(feature = "signer")]
 #[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct AEAD {
     pub ciphertext: Vec<u8>,
@@ -35,7 +154,9 @@ pub struct PartySignupRequestBody {
     pub threshold: u16,
     pub room_id: String,
     pub party_number: u16,  // It's better to rename this to fragment_index
-    pub party_uuid: String
+    pub party_uuid: String,
+    #[serde(default)]
+    pub room_size: u16, // Number of signers expected in the room, 0 means threshold+1
 }
 
 #[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
@@ -50,8 +171,11 @@ pub struct SigningPartySignup {
     pub party_uuid: String,
     pub room_uuid: String,
     pub total_joined: u16,
+    #[serde(default)]
+    pub server_time: u64, // manager's unix time, used to measure clock skew
 }
 
+#[cfg(feature = "manager")]
 #[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct SigningPartyInfo {
     pub party_id: String,
@@ -70,19 +194,81 @@ pub struct Entry {
     pub value: String,
 }
 
+/// Tag and version of the envelope every round message is stored in on the manager
+#[cfg(feature = "signer")]
+pub const ROUND_MESSAGE_FORMAT: &str = "tss-cli-round";
+#[cfg(feature = "signer")]
+pub const ROUND_MESSAGE_VERSION: u32 = 1;
+
+/// Envelope of a round message, so a message of another round, sender or client version is rejected
+/// on arrival instead of being parsed into the wrong type
+#[cfg(feature = "signer")]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RoundMessage {
+    pub format: String,
+    pub version: u32,
+    pub round: String,
+    pub from: u16,
+    pub payload: String,
+}
+
 #[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct ManagerError {
     pub error: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct RoomRequest {
+    pub room_id: String,
+}
+
+/// Parties a signing party saw drop out of a room, numbered in the room
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct DropoutReport {
+    pub room_uuid: String,
+    pub missing: Vec<u16>,
+}
+
+/// A completed signature for the manager's duplicate index, key hashes the key fingerprint, path and digest
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct SignedEntry {
+    pub key: Key,
+    pub r: String,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ObservedMessage {
+    pub key: Key, // Message key without the room uuid, so observers can not fetch the payload
+    pub digest: String, // sha256 of the stored payload
+    pub size: usize,
+    pub timestamp: u64,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct RoomObservation {
+    pub room_id: String,
+    pub last_stage: String,
+    pub room_size: u16,
+    pub total_joined: u16,
+    pub messages: Vec<ObservedMessage>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Params {
     pub parties: String,
     pub threshold: String,
 }
 
+#[cfg(feature = "signer")]
 #[allow(dead_code)]
 pub fn aes_encrypt(key: &[u8], plaintext: &[u8]) -> AEAD {
+    let out_tag: Vec<u8> = repeat(0).take(16).collect();
+    aes_encrypt_with_aad(key, plaintext, &out_tag)
+}
+
+#[cfg(feature = "signer")]
+fn aes_encrypt_with_aad(key: &[u8], plaintext: &[u8], aad: &[u8]) -> AEAD {
 
     let mut full_length_key:[u8; 32] = [0; 32];
     full_length_key[(32 - key.len())..].copy_from_slice(key);//Pad key with zeros
@@ -93,11 +279,9 @@ pub fn aes_encrypt(key: &[u8], plaintext: &[u8]) -> AEAD {
     let nonce_vector: Vec<u8> = repeat(3).take(12).collect();
     let nonce = Nonce::from_slice(nonce_vector.as_slice());
 
-    let out_tag: Vec<u8> = repeat(0).take(16).collect();
-
     let text_payload = Payload {
         msg: plaintext,
-        aad: &out_tag.as_slice()
+        aad
     };
 
     let ciphertext = cipher.encrypt(nonce, text_payload)
@@ -105,12 +289,13 @@ pub fn aes_encrypt(key: &[u8], plaintext: &[u8]) -> AEAD {
 
     AEAD {
         ciphertext: ciphertext,
-        tag: out_tag.to_vec(),
+        tag: aad.to_vec(),
     }
 }
 
+#[cfg(feature = "signer")]
 #[allow(dead_code)]
-pub fn aes_decrypt(key: &[u8], aead_pack: AEAD) -> Vec<u8> {
+pub fn aes_decrypt(key: &[u8], aead_pack: AEAD) -> Result<Vec<u8>, String> {
 
     let mut full_length_key:[u8; 32] = [0; 32];
     full_length_key[(32 - key.len())..].copy_from_slice(key);//Pad key with zeros
@@ -127,37 +312,220 @@ pub fn aes_decrypt(key: &[u8], aead_pack: AEAD) -> Vec<u8> {
         aad: aead_pack.tag.as_slice()
     };
 
-    let out = gcm.decrypt(nonce, text_payload);
-    out.unwrap_or_default()
+    // An empty plaintext would be read as a zero share, a wrong key or tampered message must stop the protocol
+    gcm.decrypt(nonce, text_payload).map_err(|_| "message failed authentication".to_string())
 }
 
-pub fn postb<T>(addr: &String, client: &Client, path: &str, body: T) -> Option<String>
+// HKDF-SHA256 of the pairwise shared secret, label separates keys of different purposes
+#[cfg(feature = "signer")]
+fn derive_pairwise_key(shared_secret: &[u8], label: &str, round: &str, from: u16, to: u16) -> Vec<u8> {
+    let info = format!("tss-cli|{}|{}|{}|{}", label, round, from, to);
+    let info = [info.as_bytes()];
+    let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, b"tss-cli-pairwise-channel").extract(shared_secret);
+    let mut key = [0u8; 32];
+    prk.expand(&info, hkdf::HKDF_SHA256)
+        .and_then(|okm| okm.fill(&mut key))
+        .expect("HKDF failure!");
+    key.to_vec()
+}
+
+/// Encrypts a p2p message of a round from party `from` to party `to`. Every round and direction
+/// gets its own AES key derived from the pairwise shared secret, so a key or nonce is never used
+/// for two messages. The tag carries a key confirmation value the receiver checks before decrypting
+#[cfg(feature = "signer")]
+pub fn aes_encrypt_round(shared_secret: &[u8], round: &str, from: u16, to: u16, plaintext: &[u8]) -> AEAD {
+    let key = derive_pairwise_key(shared_secret, "aes-key", round, from, to);
+    let confirmation = derive_pairwise_key(shared_secret, "key-confirmation", round, from, to);
+    let mut aead_pack = aes_encrypt_with_aad(&key, plaintext, &confirmation[..16]);
+    aead_pack.tag = confirmation[..16].to_vec();
+    aead_pack
+}
+
+/// Decrypts a message of aes_encrypt_round. Fails when the sender used a different key, e.g. a tss_cli from before
+/// the per-round keys, or the message was altered; the caller stops the ceremony before the share is used
+#[cfg(feature = "signer")]
+pub fn aes_decrypt_round(shared_secret: &[u8], round: &str, from: u16, to: u16, aead_pack: AEAD) -> Result<Vec<u8>, String> {
+    let key = derive_pairwise_key(shared_secret, "aes-key", round, from, to);
+    let confirmation = derive_pairwise_key(shared_secret, "key-confirmation", round, from, to);
+    if constant_time::verify_slices_are_equal(&aead_pack.tag, &confirmation[..16]).is_err() {
+        return Err(format!("key confirmation of {} message from party {} failed, parties derived different keys", round, from));
+    }
+    aes_decrypt(&key, aead_pack).map_err(|e| format!("{} message from party {}: {}", round, from, e))
+}
+
+/// HTTP client for manager requests, bound to the local address of --local-addr or --bind-interface if set,
+/// so traffic leaves through the intended interface on hosts with several networks.
+/// Gzip responses are accepted unless --no-compression is given, requests time out after --manager-timeout seconds
+#[cfg(feature = "signer")]
+pub fn new_client() -> Client {
+    let options = options::get();
+    let builder = Client::builder().gzip(options.compression);
+    let builder = match options.manager_timeout {
+        0 => builder,
+        timeout => builder.timeout(Duration::from_secs(timeout)),
+    };
+    let builder = match options.local_addr {
+        Some(local_addr) => builder.local_address(local_addr),
+        None => builder,
+    };
+    builder.build().expect("Unable to create HTTP client")
+}
+
+/// Largest manager response accepted, anything bigger is not a manager answer
+#[cfg(feature = "signer")]
+const MAX_RESPONSE_SIZE: u64 = 16 * 1024 * 1024;
+#[cfg(feature = "signer")]
+const RESPONSE_SNIPPET_LENGTH: usize = 200;
+
+/// Failed manager request, with enough of the response to tell a manager error from e.g. a proxy error page
+#[cfg(feature = "signer")]
+#[derive(Debug)]
+pub struct ResponseError {
+    pub path: String,
+    pub status: Option<u16>,
+    pub error: String,
+    pub body: String,
+    pub retryable: bool,
+}
+
+#[cfg(feature = "signer")]
+impl fmt::Display for ResponseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Manager request /{} failed: {}", self.path, self.error)?;
+        if let Some(status) = self.status {
+            write!(f, ", HTTP status {}", status)?;
+        }
+        if !self.body.is_empty() {
+            write!(f, ", response: {:?}", self.body)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "signer")]
+fn response_error(path: &str, status: Option<u16>, error: String, body: &[u8], retryable: bool) -> ResponseError {
+    let body = String::from_utf8_lossy(body);
+    ResponseError {
+        path: path.to_string(),
+        status,
+        error,
+        body: body.chars().take(RESPONSE_SNIPPET_LENGTH).collect(),
+        retryable,
+    }
+}
+
+#[cfg(feature = "signer")]
+fn read_response(path: &str, res: reqwest::blocking::Response) -> Result<String, ResponseError> {
+    let status = res.status();
+    let content_type = res
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("")
+        .to_string();
+    let mut body = Vec::new();
+    res.take(MAX_RESPONSE_SIZE + 1)
+        .read_to_end(&mut body)
+        .map_err(|e| response_error(path, Some(status.as_u16()), e.to_string(), &body, true))?;
+
+    // Gateways and rate limiters answer with these while the manager is restarting or overloaded
+    if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        return Err(response_error(path, Some(status.as_u16()), "server error".to_string(), &body, true));
+    }
+    if !status.is_success() {
+        return Err(response_error(path, Some(status.as_u16()), "unexpected status".to_string(), &body, false));
+    }
+    if body.len() as u64 > MAX_RESPONSE_SIZE {
+        return Err(response_error(path, Some(status.as_u16()), "response too large".to_string(), &[], false));
+    }
+    if !content_type.starts_with("application/json") {
+        let error = format!("unexpected content type {:?}", content_type);
+        return Err(response_error(path, Some(status.as_u16()), error, &body, false));
+    }
+    String::from_utf8(body.clone())
+        .map_err(|_| response_error(path, Some(status.as_u16()), "response is not UTF-8".to_string(), &body, false))
+}
+
+// Manager endpoints that can be posted twice with the same effect. A retried signup could instead take a second
+// party number after the first request reached the manager but its answer was lost
+#[cfg(feature = "signer")]
+const IDEMPOTENT_PATHS: [&str; 7] = ["get", "set", "time", "observe", "dropout", "signed", "record-signed"];
+
+/// Posts to the manager, retrying only on connection errors and timeouts, 5xx and 429 responses, and only for
+/// idempotent endpoints. Signups are sent once
+#[cfg(feature = "signer")]
+pub fn postb<T>(addr: &String, client: &Client, path: &str, body: T) -> Result<String, ResponseError>
 where
     T: serde::ser::Serialize,
 {
-    //    let mut addr = env::args()
-    //        .nth(4)
-    //        .unwrap_or_else(|| "http://127.0.0.1:8001".to_string());
-    //    for argument in env::args() {
-    //        if argument.contains("://") {
-    //            let addr_parts: Vec<&str> = argument.split("http:").collect();
-    //            addr = format!("http:{}", addr_parts[1]);
-    //        }
-    //    }
-    let retries = 3;
+    let retries = match IDEMPOTENT_PATHS.contains(&path) {
+        true => 3,
+        false => 1,
+    };
     let retry_delay = time::Duration::from_millis(250);
-    for _i in 1..retries {
-        let addr = format!("{}/{}", addr, path);
-        let res = client.post(&addr).json(&body).send();
-
-        if let Ok(res) = res {
-            return Some(res.text().unwrap());
+    let url = format!("{}/{}", addr, path);
+    let mut attempt = 1;
+    loop {
+        let result = match client.post(&url).json(&body).send() {
+            Ok(res) => read_response(path, res),
+            Err(e) => Err(response_error(path, None, e.to_string(), &[], true)),
+        };
+        match result {
+            Err(e) if e.retryable && attempt < retries => {
+                attempt += 1;
+                thread::sleep(retry_delay);
+            }
+            result => return result,
         }
-        thread::sleep(retry_delay);
     }
-    None
 }
 
+/// Posts to the manager and parses the JSON answer
+#[cfg(feature = "signer")]
+pub fn post_json<T, R>(addr: &String, client: &Client, path: &str, body: T) -> Result<R, ResponseError>
+where
+    T: serde::ser::Serialize,
+    R: serde::de::DeserializeOwned,
+{
+    let res_body = postb(addr, client, path, body)?;
+    serde_json::from_str(&res_body)
+        .map_err(|e| response_error(path, None, format!("invalid response: {}", e), res_body.as_bytes(), false))
+}
+
+#[cfg(feature = "signer")]
+fn seal_round_message(party_from: u16, round: &str, payload: String) -> String {
+    serde_json::to_string(&RoundMessage {
+        format: ROUND_MESSAGE_FORMAT.to_string(),
+        version: ROUND_MESSAGE_VERSION,
+        round: round.to_string(),
+        from: party_from,
+        payload,
+    })
+    .unwrap()
+}
+
+// Payload of a round message after checking it is the expected round from the expected party
+#[cfg(feature = "signer")]
+fn open_round_message(value: &str, party_from: u16, round: &str) -> String {
+    let message: RoundMessage = serde_json::from_str(value).unwrap_or_else(|e| {
+        panic!("[{:?}] party {:?} sent a malformed round message, is it running another tss_cli version? {}", round, party_from, e)
+    });
+    if message.format != ROUND_MESSAGE_FORMAT || message.version != ROUND_MESSAGE_VERSION {
+        panic!(
+            "[{:?}] party {:?} sent a {} v{} message, expected {} v{}, upgrade all parties to the same tss_cli version",
+            round, party_from, message.format, message.version, ROUND_MESSAGE_FORMAT, ROUND_MESSAGE_VERSION
+        );
+    }
+    if message.round != round || message.from != party_from {
+        panic!(
+            "[{:?}] party {:?} message is for round {:?} from party {:?}",
+            round, party_from, message.round, message.from
+        );
+    }
+    message.payload
+}
+
+#[cfg(feature = "signer")]
 pub fn broadcast(
     addr: &String,
     client: &Client,
@@ -169,13 +537,13 @@ pub fn broadcast(
     let key = format!("{}-{}-{}", party_num, round, sender_uuid);
     let entry = Entry {
         key: key.clone(),
-        value: data,
+        value: seal_round_message(party_num, round, data),
     };
 
-    let res_body = postb(&addr, &client, "set", entry).unwrap();
-    serde_json::from_str(&res_body).unwrap()
+    post_json(&addr, &client, "set", entry).unwrap_or_else(|e| panic!("{}", e))
 }
 
+#[cfg(feature = "signer")]
 pub fn sendp2p(
     addr: &String,
     client: &Client,
@@ -189,13 +557,13 @@ pub fn sendp2p(
 
     let entry = Entry {
         key: key.clone(),
-        value: data,
+        value: seal_round_message(party_from, round, data),
     };
 
-    let res_body = postb(&addr, &client, "set", entry).unwrap();
-    serde_json::from_str(&res_body).unwrap()
+    post_json(&addr, &client, "set", entry).unwrap_or_else(|e| panic!("{}", e))
 }
 
+#[cfg(feature = "signer")]
 pub fn poll_for_broadcasts(
     addr: &String,
     client: &Client,
@@ -205,40 +573,13 @@ pub fn poll_for_broadcasts(
     round: &str,
     sender_uuid: String,
 ) -> Vec<String> {
-    let mut ans_vec = Vec::new();
-    let timeout = std::env::var("TSS_CLI_POLL_TIMEOUT")
-        .unwrap_or("30".to_string()).parse::<u64>().unwrap();
-    for i in 1..=n {
-        if i != party_num {
-            let key = format!("{}-{}-{}", i, round, sender_uuid);
-            let index = Index { key };
-            let start_time = Instant::now();
-            loop {
-                // add delay to allow the server to process request:
-                let res_body = postb(&addr, &client, "get", index.clone()).unwrap();
-                let answer: Result<Entry, ManagerError> = serde_json::from_str(&res_body).unwrap();
-                match answer {
-                    Ok(answer) => {
-                        ans_vec.push(answer.value);
-                        println!("[{:?}] party {:?} => party {:?}", round, i, party_num);
-                        break;
-                    },
-                    Err(ManagerError{error}) => {
-                        #[cfg(debug_assertions)]
-                        println!("[{:?}] party {:?} => party {:?}, error: {:?}", round, i, party_num, error);
-                    }
-                }
-                if start_time.elapsed().as_secs() > timeout {
-                    panic!("Polling timed out! No response received from party number {:?}", i);
-                };
-
-                thread::sleep(delay);
-            }
-        }
+    match try_poll_for_broadcasts(addr, client, party_num, n, delay, round, sender_uuid) {
+        Ok(ans_vec) => ans_vec,
+        Err(missing) => panic!("Polling timed out! No response received from party number {:?}", missing[0]),
     }
-    ans_vec
 }
 
+#[cfg(feature = "signer")]
 pub fn poll_for_p2p(
     addr: &String,
     client: &Client,
@@ -248,55 +589,130 @@ pub fn poll_for_p2p(
     round: &str,
     sender_uuid: String,
 ) -> Vec<String> {
+    match try_poll_for_p2p(addr, client, party_num, n, delay, round, sender_uuid) {
+        Ok(ans_vec) => ans_vec,
+        Err(missing) => panic!("Polling timed out! No response received in {:?} from party number {:?}", round, missing[0]),
+    }
+}
+
+/// Same as poll_for_broadcasts, but on timeout returns the numbers of all parties that did not respond
+#[cfg(feature = "signer")]
+pub fn try_poll_for_broadcasts(
+    addr: &String,
+    client: &Client,
+    party_num: u16,
+    n: u16,
+    delay: Duration,
+    round: &str,
+    sender_uuid: String,
+) -> Result<Vec<String>, Vec<u16>> {
+    let keys = (1..=n)
+        .filter(|i| *i != party_num)
+        .map(|i| (i, format!("{}-{}-{}", i, round, sender_uuid)))
+        .collect();
+    poll_for_keys(addr, client, party_num, keys, delay, round)
+}
+
+/// Same as poll_for_p2p, but on timeout returns the numbers of all parties that did not respond
+#[cfg(feature = "signer")]
+pub fn try_poll_for_p2p(
+    addr: &String,
+    client: &Client,
+    party_num: u16,
+    n: u16,
+    delay: Duration,
+    round: &str,
+    sender_uuid: String,
+) -> Result<Vec<String>, Vec<u16>> {
+    let keys = (1..=n)
+        .filter(|i| *i != party_num)
+        .map(|i| (i, format!("{}-{}-{}-{}", i, party_num, round, sender_uuid)))
+        .collect();
+    poll_for_keys(addr, client, party_num, keys, delay, round)
+}
+
+// Time each round waited for the other parties, read by healthcheck
+#[cfg(feature = "signer")]
+static ROUND_TIMINGS: Mutex<Vec<(String, u128)>> = Mutex::new(Vec::new());
+
+/// Rounds polled since the last call with the milliseconds spent waiting for them
+#[cfg(feature = "signer")]
+pub fn take_round_timings() -> Vec<(String, u128)> {
+    std::mem::take(&mut *ROUND_TIMINGS.lock().unwrap())
+}
+
+#[cfg(feature = "signer")]
+fn poll_for_keys(
+    addr: &String,
+    client: &Client,
+    party_num: u16,
+    keys: Vec<(u16, String)>,
+    delay: Duration,
+    round: &str,
+) -> Result<Vec<String>, Vec<u16>> {
+    let round_start = Instant::now();
     let mut ans_vec = Vec::new();
+    let mut missing = Vec::new();
     let timeout = std::env::var("TSS_CLI_POLL_TIMEOUT")
         .unwrap_or("30".to_string()).parse::<u64>().unwrap();
-    for i in 1..=n {
-        if i != party_num {
-            let key = format!("{}-{}-{}-{}", i, party_num, round, sender_uuid);
-            let index = Index { key };
-            let start_time = Instant::now();
-            loop {
-                // add delay to allow the server to process request:
-                thread::sleep(delay);
-                let res_body = postb(&addr, &client, "get", index.clone()).unwrap();
-                let answer: Result<Entry, ManagerError> = serde_json::from_str(&res_body).unwrap();
-                match answer {
-                    Ok(answer) => {
-                        ans_vec.push(answer.value);
-                        println!("[{:?}] party {:?} => party {:?}", round, i, party_num);
-                        break;
-                    },
-                    Err(ManagerError{error}) => {
-                        if start_time.elapsed().as_secs() > timeout {
-                            panic!("Polling timed out! No response received in {:?} from party number {:?}", round, i);
-                        };
-                        #[cfg(debug_assertions)]
-                        println!("[{:?}] party {:?} => party {:?}, error: {:?}", round, i, party_num, error);
-                    }
+    for (i, key) in keys {
+        let index = Index { key };
+        let start_time = Instant::now();
+        loop {
+            // add delay to allow the server to process request:
+            let answer: Result<Entry, ManagerError> =
+                post_json(&addr, &client, "get", index.clone()).unwrap_or_else(|e| panic!("{}", e));
+            match answer {
+                Ok(answer) => {
+                    ans_vec.push(open_round_message(&answer.value, i, round));
+                    println!("[{:?}] party {:?} => party {:?}", round, i, party_num);
+                    break;
+                },
+                Err(ManagerError{error}) => {
+                    #[cfg(debug_assertions)]
+                    println!("[{:?}] party {:?} => party {:?}, error: {:?}", round, i, party_num, error);
                 }
             }
+            if start_time.elapsed().as_secs() > timeout {
+                println!("[{:?}] party {:?} => party {:?}, timed out", round, i, party_num);
+                missing.push(i);
+                break;
+            };
+
+            thread::sleep(delay);
         }
     }
-    ans_vec
+    ROUND_TIMINGS.lock().unwrap().push((round.to_string(), round_start.elapsed().as_millis()));
+    if missing.is_empty() {
+        Ok(ans_vec)
+    } else {
+        Err(missing)
+    }
 }
 
-#[allow(dead_code)]
-pub fn check_sig(r: &FE, s: &FE, msg: &BigInt, pk: &GE) {
-    use secp256k1::{verify, Message, PublicKey, PublicKeyFormat, Signature};
-
+#[cfg(feature = "signer")]
+fn secp_message(msg: &BigInt) -> secp256k1::Message {
     let raw_msg = BigInt::to_bytes(&msg);
     let mut msg: Vec<u8> = Vec::new(); // padding
     msg.extend(vec![0u8; 32 - raw_msg.len()]);
     msg.extend(raw_msg.iter());
 
-    let msg = Message::parse_slice(msg.as_slice()).unwrap();
+    secp256k1::Message::parse_slice(msg.as_slice()).unwrap()
+}
+
+#[cfg(feature = "signer")]
+fn secp_public_key(pk: &GE) -> secp256k1::PublicKey {
+    use secp256k1::{PublicKey, PublicKeyFormat};
+
     let mut raw_pk = pk.pk_to_key_slice();
     if raw_pk.len() == 64 {
         raw_pk.insert(0, 4u8);
     }
-    let pk = PublicKey::parse_slice(&raw_pk, Some(PublicKeyFormat::Full)).unwrap();
+    PublicKey::parse_slice(&raw_pk, Some(PublicKeyFormat::Full)).unwrap()
+}
 
+#[cfg(feature = "signer")]
+fn secp_signature(r: &FE, s: &FE) -> secp256k1::Signature {
     let mut compact: Vec<u8> = Vec::new();
     let bytes_r = &r.get_element()[..];
     compact.extend(vec![0u8; 32 - bytes_r.len()]);
@@ -306,12 +722,46 @@ pub fn check_sig(r: &FE, s: &FE, msg: &BigInt, pk: &GE) {
     compact.extend(vec![0u8; 32 - bytes_s.len()]);
     compact.extend(bytes_s.iter());
 
-    let secp_sig = Signature::parse_slice(compact.as_slice()).unwrap();
+    secp256k1::Signature::parse_slice(compact.as_slice()).unwrap()
+}
+
+#[cfg(feature = "signer")]
+pub fn verify_sig(r: &FE, s: &FE, msg: &BigInt, pk: &GE) -> bool {
+    secp256k1::verify(&secp_message(msg), &secp_signature(r, s), &secp_public_key(pk))
+}
 
-    let is_correct = verify(&msg, &secp_sig, &pk);
+#[cfg(feature = "signer")]
+pub fn check_sig(r: &FE, s: &FE, msg: &BigInt, pk: &GE) {
+    let is_correct = verify_sig(r, s, msg, pk);
     assert!(is_correct);
 }
 
+/// Recovery id of a signature, the one pk is recovered with. output_signature derives it from the parity of R.y,
+/// which misses ids 2 and 3 for an R.x beyond the group order, so it is confirmed by recovering the key
+#[cfg(feature = "signer")]
+pub fn recovery_id(r: &FE, s: &FE, msg: &BigInt, pk: &GE) -> u8 {
+    let (msg, sig, pk) = (secp_message(msg), secp_signature(r, s), secp_public_key(pk));
+    (0..4u8)
+        .find(|recid| {
+            secp256k1::RecoveryId::parse(*recid)
+                .ok()
+                .and_then(|recid| secp256k1::recover(&msg, &sig, &recid).ok())
+                .map_or(false, |recovered| recovered.serialize()[..] == pk.serialize()[..])
+        })
+        .expect("Public key can't be recovered from the signature")
+}
+
+
+/// Skew in seconds beyond which parties are warned, the manager signup timeout is a few seconds
+#[cfg(feature = "signer")]
+pub const CLOCK_SKEW_WARNING: i64 = 2;
+
+/// Local clock minus manager clock in seconds
+#[cfg(feature = "signer")]
+pub fn clock_skew(server_time: u64) -> i64 {
+    let local_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    local_time as i64 - server_time as i64
+}
 
 fn sha256_digest(input: &[u8]) -> String {
     return HSha256::create_hash_from_slice(input).to_hex();
@@ -0,0 +1,25 @@
+use serde_json::json;
+
+use crate::common::{new_client, post_json, ManagerError, RoomRequest, RoomObservation};
+
+pub fn observe_room(addr: &String, room_id: &String) {
+    let client = new_client();
+    let request = RoomRequest {
+        room_id: room_id.clone(),
+    };
+    let answer: Result<RoomObservation, ManagerError> =
+        post_json(&addr, &client, "observe", request).unwrap_or_else(|e| panic!("{}", e));
+    match answer {
+        Ok(observation) => {
+            println!("{}", serde_json::to_string_pretty(&observation).unwrap());
+        }
+        Err(ManagerError { error }) => {
+            let ret_dict = json!({
+                "status": "error",
+                "error": error,
+                "room_id": room_id,
+            });
+            println!("{}", ret_dict.to_string());
+        }
+    }
+}
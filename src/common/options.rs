@@ -0,0 +1,164 @@
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+use clap::ArgMatches;
+
+/// Global command line options the protocol and storage code needs deep inside a command. They are read from
+/// the command line once, before any client, worker pool or lock is created, instead of through environment variables
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Local address manager connections are bound to, from --local-addr or --bind-interface
+    pub local_addr: Option<IpAddr>,
+    pub password_file: Option<String>,
+    pub age_identity: Option<String>,
+    pub age_recipients: Vec<String>,
+    pub kms_key_id: Option<String>,
+    pub pkcs11_module: Option<String>,
+    pub integrity_key: Option<String>,
+    pub keys_dir: Option<String>,
+    /// Seconds to wait for a keys file in use, 0 fails at once
+    pub lock_wait: u64,
+    pub chain_code: Option<String>,
+    pub chain_code_mnemonic: Option<String>,
+    pub mnemonic_passphrase_file: Option<String>,
+    pub derivation: String,
+    pub no_normalize: bool,
+    /// Blind the local share and nonce in the signing arithmetic done by this crate
+    pub paranoid: bool,
+    /// Worker threads of the MtA pool, 0 for one per core
+    pub threads: usize,
+    pub compression: bool,
+    /// Approval service asked before every signature, with the key its decisions are signed with
+    pub approval_url: Option<String>,
+    pub approval_key: Option<String>,
+    pub approval_timeout: u64,
+    /// Seconds a manager request may take before it is retried, 0 waits as long as the connection stays open
+    pub manager_timeout: u64,
+    /// Seconds to wait for a signing room to fill, TSS_CLI_SIGNUP_TIMEOUT
+    pub signup_timeout: u64,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            local_addr: None,
+            password_file: None,
+            age_identity: None,
+            age_recipients: Vec::new(),
+            kms_key_id: None,
+            pkcs11_module: None,
+            integrity_key: None,
+            keys_dir: None,
+            lock_wait: 0,
+            chain_code: None,
+            chain_code_mnemonic: None,
+            mnemonic_passphrase_file: None,
+            derivation: "tss-additive".to_string(),
+            no_normalize: false,
+            paranoid: false,
+            threads: 0,
+            compression: true,
+            approval_url: None,
+            approval_key: None,
+            approval_timeout: 300,
+            manager_timeout: 30,
+            signup_timeout: signup_timeout(),
+        }
+    }
+}
+
+fn signup_timeout() -> u64 {
+    std::env::var("TSS_CLI_SIGNUP_TIMEOUT")
+        .unwrap_or("30".to_string())
+        .parse::<u64>()
+        .expect("TSS_CLI_SIGNUP_TIMEOUT must be a number")
+}
+
+static OPTIONS: Mutex<Option<Options>> = Mutex::new(None);
+
+// Value of a global argument, given before or after the subcommand
+fn global_value<'a>(matches: &'a ArgMatches, name: &str) -> Option<&'a str> {
+    matches
+        .value_of(name)
+        .or_else(|| matches.subcommand().1.and_then(|sub_matches| sub_matches.value_of(name)))
+        .filter(|value| !value.is_empty())
+}
+
+fn global_number<T: std::str::FromStr>(matches: &ArgMatches, name: &str, default: T) -> T {
+    match global_value(matches, name) {
+        Some(value) => value.parse::<T>().unwrap_or_else(|_| panic!("--{} must be a number", name.replace('_', "-"))),
+        None => default,
+    }
+}
+
+// First address of a network interface, IPv4 preferred
+fn interface_addr(interface: &str) -> IpAddr {
+    let addrs: Vec<IpAddr> = if_addrs::get_if_addrs()
+        .expect("Unable to list network interfaces")
+        .into_iter()
+        .filter(|iface| iface.name == interface)
+        .map(|iface| iface.ip())
+        .collect();
+    addrs
+        .iter()
+        .find(|addr| addr.is_ipv4())
+        .or(addrs.first())
+        .copied()
+        .unwrap_or_else(|| panic!("Network interface {} not found or has no address", interface))
+}
+
+impl Options {
+    pub fn from_matches(matches: &ArgMatches) -> Self {
+        let local_addr = match (global_value(matches, "local_addr"), global_value(matches, "bind_interface")) {
+            (Some(_), Some(_)) => panic!("Use --local-addr or --bind-interface, not both"),
+            (Some(local_addr), None) => Some(
+                local_addr
+                    .parse::<IpAddr>()
+                    .unwrap_or_else(|_| panic!("Invalid local address: {}", local_addr)),
+            ),
+            (None, Some(interface)) => Some(interface_addr(interface)),
+            (None, None) => None,
+        };
+        let string = |name: &str| global_value(matches, name).map(|value| value.to_string());
+        Options {
+            local_addr,
+            password_file: string("password_file"),
+            age_identity: string("age_identity"),
+            age_recipients: matches
+                .values_of("age_recipient")
+                .or_else(|| matches.subcommand().1.and_then(|sub_matches| sub_matches.values_of("age_recipient")))
+                .map(|recipients| recipients.map(|recipient| recipient.to_string()).collect())
+                .unwrap_or_default(),
+            kms_key_id: string("kms_key_id"),
+            pkcs11_module: string("pkcs11_module"),
+            integrity_key: string("integrity_key"),
+            keys_dir: string("keys_dir"),
+            lock_wait: global_number(matches, "lock_wait", 0),
+            chain_code: string("chain_code"),
+            chain_code_mnemonic: string("chain_code_mnemonic"),
+            mnemonic_passphrase_file: string("mnemonic_passphrase_file"),
+            derivation: string("derivation").unwrap_or("tss-additive".to_string()),
+            no_normalize: matches.subcommand().1.map_or(false, |sub_matches| sub_matches.is_present("no_normalize")),
+            paranoid: matches.is_present("paranoid")
+                || matches.subcommand().1.map_or(false, |sub_matches| sub_matches.is_present("paranoid")),
+            threads: global_number(matches, "threads", 0),
+            compression: !matches.is_present("no_compression")
+                && !matches.subcommand().1.map_or(false, |sub_matches| sub_matches.is_present("no_compression")),
+            approval_url: string("approval_url"),
+            approval_key: string("approval_key"),
+            approval_timeout: global_number(matches, "approval_timeout", 300),
+            manager_timeout: global_number(matches, "manager_timeout", 30),
+            signup_timeout: signup_timeout(),
+        }
+    }
+}
+
+/// Sets the options of this run, at startup or between ceremonies
+pub fn init(options: Options) {
+    *OPTIONS.lock().unwrap() = Some(options);
+}
+
+/// Options of this run, the defaults when init was not called (tests, selftest parties)
+pub fn get() -> Options {
+    OPTIONS.lock().unwrap().clone().unwrap_or_default()
+}
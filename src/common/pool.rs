@@ -0,0 +1,281 @@
+use std::fs;
+use std::thread;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+
+use curv::elliptic::curves::secp256_k1::{FE, GE};
+use rocket::{get, post, routes, State};
+use rocket::serde::json::Json;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::common::{broadcast, new_client, poll_for_broadcasts, presign, sha256_digest, signer, Params};
+use crate::common::approval::Approver;
+use crate::common::atomic_file;
+use crate::common::duplicates::DuplicateGuard;
+use crate::common::encoding::compressed_public_key;
+use crate::common::keyfile::KeyFile;
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct PoolSignRequest {
+    pub message: String,
+}
+
+/// Presignatures are files `<seq>.json` in `<keysfile>.pool`, consumed ones are replaced by empty `<seq>.json.used`
+/// markers, their names keep sequence numbers from being reused
+#[derive(Clone)]
+pub struct PresignPool {
+    pub dir: String,
+    pub size: u16,
+    pub manager_addr: String,
+    pub approver: Option<Approver>,
+    /// Compressed public key in hex, names the rooms of this pool
+    pub key_id: String,
+    pub party_id: u16,
+    pub threshold: u16,
+    pub signers: u16,
+    /// Context label the presignatures of the pool are made and signed under
+    pub context: String,
+}
+
+impl PresignPool {
+    pub fn new(
+        keysfile_path: &str,
+        y_sum: &GE,
+        party_id: u16,
+        params: &Params,
+        signers: u16,
+        size: u16,
+        manager_addr: String,
+        approver: Option<Approver>,
+        context: &str,
+    ) -> Self {
+        let dir = format!("{}.pool", keysfile_path);
+        fs::create_dir_all(&dir).expect("Unable to create presignature pool directory !");
+        // Pools of earlier versions kept the content of used presignatures
+        for entry in fs::read_dir(&dir).expect("Unable to read presignature pool !") {
+            let entry = entry.unwrap();
+            if entry.file_name().to_string_lossy().ends_with(".json.used") && entry.metadata().unwrap().len() > 0 {
+                atomic_file::write_atomic(entry.path(), b"");
+            }
+        }
+        PresignPool {
+            dir,
+            size,
+            manager_addr,
+            approver,
+            key_id: hex::encode(compressed_public_key(y_sum)),
+            party_id,
+            threshold: params.threshold.parse::<u16>().unwrap(),
+            signers,
+            context: context.to_string(),
+        }
+    }
+
+    // One broadcast round among the pools of all signers, returns the values of all parties, own included,
+    // and the uuid the manager gave this instance of the room
+    fn exchange<T: Serialize + DeserializeOwned + Clone>(&self, room: &str, round: &str, value: &T) -> (Vec<T>, String) {
+        let client = new_client();
+        let delay = Duration::from_millis(25);
+        let room_id = sha256_digest(room.as_bytes());
+        let (party_signup, total_parties) =
+            signer::signup(&self.manager_addr, &client, self.threshold, room_id, self.party_id, self.signers).unwrap();
+        assert!(broadcast(
+            &self.manager_addr,
+            &client,
+            party_signup.number,
+            round,
+            serde_json::to_string(value).unwrap(),
+            party_signup.uuid.clone(),
+        )
+        .is_ok());
+        let mut values: Vec<T> = poll_for_broadcasts(
+            &self.manager_addr,
+            &client,
+            party_signup.number,
+            total_parties,
+            delay,
+            round,
+            party_signup.uuid.clone(),
+        )
+        .iter()
+        .map(|m| serde_json::from_str(m).unwrap())
+        .collect();
+        values.push(value.clone());
+        (values, party_signup.uuid)
+    }
+
+    // Sequence numbers of presignatures, (available, used)
+    fn sequences(&self) -> (Vec<u64>, Vec<u64>) {
+        let mut available = Vec::new();
+        let mut used = Vec::new();
+        for entry in fs::read_dir(&self.dir).expect("Unable to read presignature pool !") {
+            let name = entry.unwrap().file_name().to_string_lossy().to_string();
+            if let Some(seq) = name.strip_suffix(".json").and_then(|seq| seq.parse::<u64>().ok()) {
+                available.push(seq);
+            } else if let Some(seq) = name.strip_suffix(".json.used").and_then(|seq| seq.parse::<u64>().ok()) {
+                used.push(seq);
+            }
+        }
+        available.sort();
+        used.sort();
+        (available, used)
+    }
+
+    fn next_sequence(&self) -> u64 {
+        let (available, used) = self.sequences();
+        available.iter().chain(used.iter()).max().map(|seq| seq + 1).unwrap_or(0)
+    }
+
+    fn presignature_path(&self, seq: u64) -> String {
+        format!("{}/{}.json", self.dir, seq)
+    }
+
+    pub fn metrics(&self) -> serde_json::Value {
+        let (available, used) = self.sequences();
+        json!({
+            "depth": available.len(),
+            "target": self.size,
+            "consumed": used.len(),
+            "next_sequence": self.next_sequence(),
+        })
+    }
+
+    /// Signs with the oldest presignature all parties hold. The parties first exchange the sequence numbers
+    /// they hold through the manager, so they use the same presignature even when one of them lost some.
+    /// Older presignatures a peer no longer holds can't be used anymore and are marked used here as well
+    pub fn sign(&self, message: &[u8]) -> Result<serde_json::Value, String> {
+        let (available, _) = self.sequences();
+        let room = format!("pool-claim-{}-{}", self.key_id, sha256_digest(message));
+        let (held, _) = self.exchange(&room, "pool-claim", &available);
+        let agreed = available.iter().find(|seq| held.iter().all(|h| h.contains(seq))).copied();
+        let seq = match agreed {
+            Some(seq) => seq,
+            None => return Err("No presignature is held by all parties".to_string()),
+        };
+        for stale in available.iter().filter(|stale| **stale < seq) {
+            presign::mark_used(&self.presignature_path(*stale));
+            eprintln!("{}", json!({"status": "presignature_dropped", "sequence": stale}).to_string());
+        }
+        Ok(presign::sign_with_presignature(
+            self.manager_addr.clone(),
+            &self.presignature_path(seq),
+            message,
+            Some(&self.context),
+            self.approver.as_ref(),
+            DuplicateGuard::Off,
+        ))
+    }
+}
+
+// Keeps the pool topped up. Before each presigning the parties agree the sequence number, the highest
+// any of them would use next, and the room label carries the uuid of that agreement round, so a label is
+// never reused, not even by pools restarted with the same sequence numbers
+fn fill_pool(
+    keysfile_path: String,
+    params: Params,
+    path: String,
+    f_l_new: FE,
+    y_sum: GE,
+    sign_at_path: bool,
+    signers: u16,
+    pool: &PresignPool,
+) {
+    loop {
+        let (available, _) = pool.sequences();
+        if available.len() >= pool.size as usize {
+            thread::sleep(Duration::from_secs(1));
+            continue;
+        }
+        let keysfile_path = keysfile_path.clone();
+        let params = params.clone();
+        let manager_addr = pool.manager_addr.clone();
+        let agreement_pool = pool.clone();
+        // A dropout panics the agreement or the presigning, retry instead of killing the pool
+        let result = thread::spawn(move || {
+            let (proposals, generation) = agreement_pool.exchange(
+                &format!("pool-fill-{}", agreement_pool.key_id),
+                "pool-fill",
+                &agreement_pool.next_sequence(),
+            );
+            let seq = proposals.into_iter().max().unwrap();
+            let label = format!("pool-{}-{}-{}", agreement_pool.key_id, seq, generation);
+            let keyfile = KeyFile::load(&keysfile_path);
+            let mut vss_scheme_vec = keyfile.vss_scheme_vec.clone();
+            let presig = signer::presign(
+                manager_addr,
+                keyfile.party_keys,
+                keyfile.shared_keys,
+                keyfile.party_id,
+                &mut vss_scheme_vec,
+                keyfile.paillier_key_vec,
+                &y_sum,
+                &params,
+                &label,
+                &f_l_new,
+                sign_at_path,
+                signers,
+                &agreement_pool.context,
+            );
+            (seq, presig)
+        })
+        .join();
+        match result {
+            Ok((seq, mut presig)) => {
+                presig.path = path.clone();
+                eprintln!("{}", presign::write_presignature(&pool.presignature_path(seq), &presig).to_string());
+            }
+            Err(_) => {
+                eprintln!("{}", json!({"status": "presign_failed", "sequence": pool.next_sequence()}).to_string());
+                thread::sleep(Duration::from_secs(5));
+            }
+        }
+    }
+}
+
+#[rocket::main]
+pub async fn run_pool(
+    keysfile_path: String,
+    params: Params,
+    path: String,
+    f_l_new: FE,
+    y_sum: GE,
+    sign_at_path: bool,
+    signers: u16,
+    pool: PresignPool,
+    port: u16,
+) -> Result<(), rocket::Error> {
+    let filler_pool = pool.clone();
+    thread::spawn(move || fill_pool(keysfile_path, params, path, f_l_new, y_sum, sign_at_path, signers, &filler_pool));
+
+    // Local API only, the pool holds secret material
+    let figment = rocket::Config::figment()
+        .merge(("address", "127.0.0.1"))
+        .merge(("port", port));
+    rocket::custom(figment)
+        .mount("/", routes![metrics, sign])
+        .manage(pool)
+        .launch()
+        .await
+}
+
+#[get("/metrics")]
+fn metrics(pool: &State<PresignPool>) -> Json<serde_json::Value> {
+    Json(pool.metrics())
+}
+
+#[post("/sign", format = "json", data = "<request>")]
+async fn sign(pool: &State<PresignPool>, request: Json<PoolSignRequest>) -> Json<Result<serde_json::Value, String>> {
+    let message = match hex::decode(&request.0.message) {
+        Ok(x) => x,
+        Err(_e) => request.0.message.as_bytes().to_vec(),
+    };
+    let pool = pool.inner().clone();
+    // Online signing talks to the manager with a blocking client
+    let result = rocket::tokio::task::spawn_blocking(move || pool.sign(&message)).await;
+    match result {
+        Ok(result) => Json(result),
+        Err(_) => Json(Err("Signing failed".to_string())),
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::BTreeSet;
+use std::time;
+
+use curv::elliptic::curves::secp256_k1::GE;
+use curv::elliptic::curves::traits::ECPoint;
+use curv::arithmetic::Converter;
+use curv::BigInt;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::common::keyfile::KeyMetadata;
+use crate::common::{new_client, broadcast, poll_for_broadcasts, Params, PartySignup};
+use crate::common::signer::signup;
+
+pub const CURVE_NAME: &str = "secp256k1";
+pub const PROTOCOL_NAME: &str = "gg18";
+pub const HASH_MODES: [&str; 1] = ["none"];
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct CapabilityManifest {
+    pub version: String,
+    pub party_id: u16,
+    pub threshold: u16,
+    pub parties: u16,
+    pub curves: Vec<String>,
+    pub protocols: Vec<String>,
+    pub hash_modes: Vec<String>,
+    pub public_key: String,
+    /// Fingerprint of the key from the keys file metadata, empty from older versions which the version check reports
+    #[serde(default)]
+    pub fingerprint: String,
+}
+
+impl CapabilityManifest {
+    /// Manifest of this party, threshold params and fingerprint are the ones stored in the keys file
+    pub fn local(party_id: u16, metadata: &KeyMetadata, y_sum: &GE) -> Self {
+        CapabilityManifest {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            party_id,
+            threshold: metadata.threshold,
+            parties: metadata.parties,
+            curves: vec![CURVE_NAME.to_string()],
+            protocols: vec![PROTOCOL_NAME.to_string()],
+            hash_modes: HASH_MODES.iter().map(|m| m.to_string()).collect(),
+            public_key: hex::encode(BigInt::to_bytes(&y_sum.bytes_compressed_to_big_int())),
+            fingerprint: metadata.fingerprint.clone(),
+        }
+    }
+}
+
+pub fn run_preflight(addr: &String, room: &String, party_id: u16, params: &Params, metadata: &KeyMetadata, y_sum: &GE) {
+    let client = new_client();
+    let delay = time::Duration::from_millis(25);
+    let threshold = params.threshold.parse::<u16>().unwrap();
+    let room_id = format!("preflight-{}", room);
+
+    let (party_num_int, uuid, total_parties) = match signup(&addr, &client, threshold, room_id, party_id, 0).unwrap() {
+        (PartySignup { number, uuid }, total_parties) => (number, uuid, total_parties),
+    };
+
+    let manifest = CapabilityManifest::local(party_id, metadata, y_sum);
+    assert!(broadcast(
+        &addr,
+        &client,
+        party_num_int,
+        "preflight",
+        serde_json::to_string(&manifest).unwrap(),
+        uuid.clone(),
+    )
+    .is_ok());
+    let answers = poll_for_broadcasts(
+        &addr,
+        &client,
+        party_num_int,
+        total_parties,
+        delay,
+        "preflight",
+        uuid.clone(),
+    );
+
+    let mut manifests: Vec<CapabilityManifest> = answers
+        .iter()
+        .map(|m| serde_json::from_str::<CapabilityManifest>(m).unwrap())
+        .collect();
+    manifests.insert(party_num_int as usize - 1, manifest);
+
+    // Params given on the command line must be the ones of the key, the others compare the stored ones
+    let negotiated = match negotiate(&manifests) {
+        Ok(_) if params.threshold != metadata.threshold.to_string() || params.parties != metadata.parties.to_string() => {
+            Err(vec![format!(
+                "Party {} was given params {}/{}, its keys file holds a {}/{} key",
+                party_id, params.threshold, params.parties, metadata.threshold, metadata.parties
+            )])
+        }
+        negotiated => negotiated,
+    };
+    let ret_dict = match negotiated {
+        Ok(configuration) => json!({
+            "status": "ready",
+            "configuration": configuration,
+        }),
+        Err(issues) => json!({
+            "status": "incompatible",
+            "issues": issues,
+        }),
+    };
+    println!("{}", ret_dict.to_string());
+}
+
+// Returns the configuration every party agrees on, or the list of reasons the ceremony can not proceed
+pub fn negotiate(manifests: &Vec<CapabilityManifest>) -> Result<serde_json::Value, Vec<String>> {
+    let mut issues: Vec<String> = Vec::new();
+    let first = &manifests[0];
+
+    let mut party_ids = BTreeSet::new();
+    for manifest in manifests.iter() {
+        if !party_ids.insert(manifest.party_id) {
+            issues.push(format!("Party id {} joined more than once", manifest.party_id));
+        }
+        if manifest.version != first.version {
+            issues.push(format!(
+                "Party {} runs version {}, party {} runs version {}",
+                manifest.party_id, manifest.version, first.party_id, first.version
+            ));
+        }
+        if manifest.threshold != first.threshold || manifest.parties != first.parties {
+            issues.push(format!(
+                "Party {} uses params {}/{}, party {} uses {}/{}",
+                manifest.party_id, manifest.threshold, manifest.parties,
+                first.party_id, first.threshold, first.parties
+            ));
+        }
+        if manifest.public_key != first.public_key || manifest.fingerprint != first.fingerprint {
+            issues.push(format!("Party {} holds a share of a different public key", manifest.party_id));
+        }
+    }
+
+    let curves = intersect(manifests.iter().map(|m| &m.curves).collect());
+    let protocols = intersect(manifests.iter().map(|m| &m.protocols).collect());
+    let hash_modes = intersect(manifests.iter().map(|m| &m.hash_modes).collect());
+    if curves.is_empty() {
+        issues.push("No curve is supported by all parties".to_string());
+    }
+    if protocols.is_empty() {
+        issues.push("No protocol is supported by all parties".to_string());
+    }
+    if hash_modes.is_empty() {
+        issues.push("No hash mode is supported by all parties".to_string());
+    }
+
+    if !issues.is_empty() {
+        return Err(issues);
+    }
+    Ok(json!({
+        "version": first.version,
+        "curve": curves[0],
+        "protocol": protocols[0],
+        "hash_modes": hash_modes,
+        "threshold": first.threshold,
+        "parties": first.parties,
+        "signers": party_ids.into_iter().collect::<Vec<u16>>(),
+        "public_key": first.public_key,
+        "fingerprint": first.fingerprint,
+    }))
+}
+
+fn intersect(lists: Vec<&Vec<String>>) -> Vec<String> {
+    lists[0]
+        .iter()
+        .filter(|item| lists.iter().all(|list| list.contains(item)))
+        .cloned()
+        .collect()
+}
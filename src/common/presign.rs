@@ -0,0 +1,91 @@
+use std::fs;
+
+use serde_json::json;
+
+use crate::common::approval::{self, Approver};
+use crate::common::atomic_file;
+use crate::common::duplicates::{self, DuplicateGuard};
+use crate::common::keyfile;
+use crate::common::signer::{self, Presignature};
+
+pub fn presignature_path(keysfile_path: &str, label: &str) -> String {
+    format!("{}.presig.{}.json", keysfile_path, label)
+}
+
+/// Writes a presignature sealed like the keys file it was made from, it holds secret material as well.
+/// Returns the presignature_ready status, the result of presign and a progress note of the pool
+pub fn write_presignature(presig_path: &str, presig: &Presignature) -> serde_json::Value {
+    let name = format!("presig-{}-{}", presig.id, presig.party_num);
+    let sealed = keyfile::seal_secret(&name, serde_json::to_string(presig).unwrap().as_bytes());
+    atomic_file::write_atomic(presig_path, sealed.as_bytes());
+    json!({
+        "status": "presignature_ready",
+        "presignature": presig_path,
+        "path": presig.path,
+    })
+}
+
+/// Replaces a presignature by an empty `<file>.used` marker. It is emptied before the rename, k_i and sigma_i
+/// must not be readable after use, not even with the passphrase
+pub fn mark_used(presig_path: &str) {
+    atomic_file::write_atomic(presig_path, b"");
+    fs::rename(presig_path, format!("{}.used", presig_path)).expect("Unable to mark presignature as used !");
+}
+
+/// Consumes a presignature file and signs the message with the online rounds.
+/// The file is emptied before approval and signing, so it can't be used twice even if signing fails
+/// and a rejected request consumes it the same as at the other parties. Without an approver of a pool
+/// the one of --approval-url is asked, logging next to the presignature.
+/// The duplicate guard keeps its history next to the keys file the presignature was made from.
+/// A context label given must be the one the presignature was made under, its presign room is bound to it
+pub fn sign_with_presignature(
+    addr: String,
+    presig_path: &str,
+    message: &[u8],
+    context: Option<&str>,
+    approver: Option<&Approver>,
+    duplicate_guard: DuplicateGuard,
+) -> serde_json::Value {
+    let presig: Presignature =
+        serde_json::from_slice(&keyfile::open_secret_once(presig_path)).expect("Invalid presignature file !");
+    mark_used(presig_path);
+    if let Some(context) = context.filter(|context| *context != presig.context) {
+        clap::Error::with_description(
+            &format!(
+                "Presignature {} was made for context '{}', not '{}', it is consumed",
+                presig_path, presig.context, context
+            ),
+            clap::ErrorKind::InvalidValue,
+        )
+        .exit();
+    }
+
+    let signing_target = duplicates::SigningTarget::with_fingerprint(&presig.key_fingerprint, &presig.path, message);
+    let duplicate_guard = match presig.key_fingerprint.is_empty() {
+        // Presignatures of earlier versions don't name their key
+        true if duplicate_guard != DuplicateGuard::Off => {
+            eprintln!("{}", json!({"status": "duplicate_guard_unavailable", "presignature": presig_path}).to_string());
+            DuplicateGuard::Off
+        }
+        _ => duplicate_guard,
+    };
+    duplicates::check_duplicate(duplicate_guard, &addr, &presig.keysfile, &signing_target);
+
+    let configured = match approver {
+        Some(_) => None,
+        None => approval::configured(presig.party_id, presig_path),
+    };
+    let approval = approver
+        .or(configured.as_ref())
+        .map(|approver| approver.approve(approver.request(&presig.y_sum, &presig.path, message, &presig.context)));
+    let sig = signer::sign_online(addr.clone(), &presig, message);
+    if duplicate_guard != DuplicateGuard::Off {
+        duplicates::record_signing(&addr, &presig.keysfile, &signing_target, &sig);
+    }
+    let mut ret_dict = signer::signature_json(&sig, &presig.y_sum, message, &presig.context);
+    ret_dict["path"] = json!(presig.path);
+    if let Some(approval) = approval {
+        ret_dict["approval"] = json!(approval.request.request_id);
+    }
+    ret_dict
+}
@@ -0,0 +1,452 @@
+use std::fs;
+
+use curv::{
+    arithmetic::traits::Converter,
+    elliptic::curves::secp256_k1::{FE, GE},
+    elliptic::curves::traits::ECScalar,
+    BigInt,
+};
+use ring::digest::{digest, SHA256};
+use serde_json::{json, Value};
+
+use crate::common::approval::Approver;
+use crate::common::bitcoin::hash160;
+use crate::common::duplicates::{self, DuplicateGuard};
+use crate::common::encoding::{compressed_public_key, der_signature};
+use crate::common::keyfile::KeyFile;
+use crate::common::{check_sig, hd_keys, signer, Params};
+
+const PSBT_MAGIC: &[u8] = b"psbt\xff";
+const PSBT_GLOBAL_UNSIGNED_TX: u8 = 0x00;
+const PSBT_IN_NON_WITNESS_UTXO: u8 = 0x00;
+const PSBT_IN_WITNESS_UTXO: u8 = 0x01;
+const PSBT_IN_PARTIAL_SIG: u8 = 0x02;
+const PSBT_IN_SIGHASH_TYPE: u8 = 0x03;
+const PSBT_IN_REDEEM_SCRIPT: u8 = 0x04;
+const PSBT_IN_BIP32_DERIVATION: u8 = 0x06;
+const SIGHASH_ALL: u32 = 1;
+
+fn double_sha256(data: &[u8]) -> Vec<u8> {
+    digest(&SHA256, digest(&SHA256, data).as_ref()).as_ref().to_vec()
+}
+
+fn compact_size(n: usize) -> Vec<u8> {
+    match n {
+        0..=0xfc => vec![n as u8],
+        0xfd..=0xffff => [vec![0xfd], (n as u16).to_le_bytes().to_vec()].concat(),
+        0x1_0000..=0xffff_ffff => [vec![0xfe], (n as u32).to_le_bytes().to_vec()].concat(),
+        _ => [vec![0xff], (n as u64).to_le_bytes().to_vec()].concat(),
+    }
+}
+
+// Reads BIP174 and Bitcoin transaction serializations
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data, pos: 0 }
+    }
+
+    fn bytes(&mut self, len: usize) -> &'a [u8] {
+        let bytes = self.data.get(self.pos..self.pos + len).expect("PSBT is truncated");
+        self.pos += len;
+        bytes
+    }
+
+    fn u32(&mut self) -> u32 {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(self.bytes(4));
+        u32::from_le_bytes(bytes)
+    }
+
+    fn u64(&mut self) -> u64 {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.bytes(8));
+        u64::from_le_bytes(bytes)
+    }
+
+    fn compact_size(&mut self) -> usize {
+        match self.bytes(1)[0] {
+            0xfd => {
+                let bytes = self.bytes(2);
+                u16::from_le_bytes([bytes[0], bytes[1]]) as usize
+            }
+            0xfe => self.u32() as usize,
+            0xff => self.u64() as usize,
+            n => n as usize,
+        }
+    }
+
+    fn var_bytes(&mut self) -> &'a [u8] {
+        let len = self.compact_size();
+        self.bytes(len)
+    }
+
+    // Key-value map up to its 0x00 separator, pairs are kept in order and raw
+    fn map(&mut self) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut pairs = Vec::new();
+        loop {
+            let key = self.var_bytes();
+            if key.is_empty() {
+                return pairs;
+            }
+            pairs.push((key.to_vec(), self.var_bytes().to_vec()));
+        }
+    }
+}
+
+pub struct TxInput {
+    pub txid: Vec<u8>,
+    pub vout: u32,
+    pub sequence: u32,
+}
+
+pub struct TxOutput {
+    pub value: u64,
+    pub script: Vec<u8>,
+}
+
+/// Transaction without witnesses, as the PSBT unsigned transaction and non-witness UTXOs are serialized
+pub struct Transaction {
+    pub version: u32,
+    pub inputs: Vec<TxInput>,
+    pub outputs: Vec<TxOutput>,
+    pub lock_time: u32,
+}
+
+impl Transaction {
+    fn parse(data: &[u8]) -> Self {
+        let mut reader = Reader::new(data);
+        let version = reader.u32();
+        // Segwit marker and flag, witnesses follow the outputs
+        let segwit = data.get(4..6) == Some(&[0x00, 0x01][..]);
+        if segwit {
+            reader.bytes(2);
+        }
+        let inputs: Vec<TxInput> = (0..reader.compact_size())
+            .map(|_| {
+                let txid = reader.bytes(32).to_vec();
+                let vout = reader.u32();
+                reader.var_bytes();
+                TxInput { txid, vout, sequence: reader.u32() }
+            })
+            .collect();
+        let outputs: Vec<TxOutput> = (0..reader.compact_size())
+            .map(|_| {
+                let value = reader.u64();
+                TxOutput { value, script: reader.var_bytes().to_vec() }
+            })
+            .collect();
+        if segwit {
+            for _ in 0..inputs.len() {
+                for _ in 0..reader.compact_size() {
+                    reader.var_bytes();
+                }
+            }
+        }
+        let lock_time = reader.u32();
+        Transaction { version, inputs, outputs, lock_time }
+    }
+
+    /// Serialization without witnesses, script_sigs per input
+    fn serialize(&self, script_sigs: &[Vec<u8>]) -> Vec<u8> {
+        let mut out = self.version.to_le_bytes().to_vec();
+        out.extend(compact_size(self.inputs.len()));
+        for (input, script_sig) in self.inputs.iter().zip(script_sigs) {
+            out.extend(&input.txid);
+            out.extend(&input.vout.to_le_bytes());
+            out.extend(compact_size(script_sig.len()));
+            out.extend(script_sig);
+            out.extend(&input.sequence.to_le_bytes());
+        }
+        out.extend(compact_size(self.outputs.len()));
+        for output in &self.outputs {
+            out.extend(self.output_bytes(output));
+        }
+        out.extend(&self.lock_time.to_le_bytes());
+        out
+    }
+
+    fn output_bytes(&self, output: &TxOutput) -> Vec<u8> {
+        [output.value.to_le_bytes().to_vec(), compact_size(output.script.len()), output.script.clone()].concat()
+    }
+
+    fn txid(&self) -> Vec<u8> {
+        double_sha256(&self.serialize(&vec![vec![]; self.inputs.len()]))
+    }
+
+    /// Legacy sighash with SIGHASH_ALL: the script code stands in for the script_sig of the signed input
+    fn legacy_sighash(&self, index: usize, script_code: &[u8]) -> Vec<u8> {
+        let script_sigs: Vec<Vec<u8>> = (0..self.inputs.len())
+            .map(|i| if i == index { script_code.to_vec() } else { vec![] })
+            .collect();
+        let mut preimage = self.serialize(&script_sigs);
+        preimage.extend(&SIGHASH_ALL.to_le_bytes());
+        double_sha256(&preimage)
+    }
+
+    /// BIP143 segwit v0 sighash with SIGHASH_ALL
+    fn segwit_sighash(&self, index: usize, script_code: &[u8], amount: u64) -> Vec<u8> {
+        let prevouts: Vec<u8> = self.inputs.iter().flat_map(|input| [input.txid.clone(), input.vout.to_le_bytes().to_vec()].concat()).collect();
+        let sequences: Vec<u8> = self.inputs.iter().flat_map(|input| input.sequence.to_le_bytes().to_vec()).collect();
+        let outputs: Vec<u8> = self.outputs.iter().flat_map(|output| self.output_bytes(output)).collect();
+        let input = &self.inputs[index];
+        let preimage = [
+            self.version.to_le_bytes().to_vec(),
+            double_sha256(&prevouts),
+            double_sha256(&sequences),
+            input.txid.clone(),
+            input.vout.to_le_bytes().to_vec(),
+            compact_size(script_code.len()),
+            script_code.to_vec(),
+            amount.to_le_bytes().to_vec(),
+            input.sequence.to_le_bytes().to_vec(),
+            double_sha256(&outputs),
+            self.lock_time.to_le_bytes().to_vec(),
+            SIGHASH_ALL.to_le_bytes().to_vec(),
+        ]
+        .concat();
+        double_sha256(&preimage)
+    }
+}
+
+/// BIP174 partially signed transaction, maps are kept raw so unknown fields survive signing
+pub struct Psbt {
+    global: Vec<(Vec<u8>, Vec<u8>)>,
+    inputs: Vec<Vec<(Vec<u8>, Vec<u8>)>>,
+    outputs: Vec<Vec<(Vec<u8>, Vec<u8>)>>,
+    pub tx: Transaction,
+}
+
+impl Psbt {
+    pub fn parse(data: &[u8]) -> Self {
+        if !data.starts_with(PSBT_MAGIC) {
+            panic!("Not a PSBT, magic bytes missing");
+        }
+        let mut reader = Reader::new(&data[PSBT_MAGIC.len()..]);
+        let global = reader.map();
+        let tx = global
+            .iter()
+            .find(|(key, _)| key == &vec![PSBT_GLOBAL_UNSIGNED_TX])
+            .map(|(_, value)| Transaction::parse(value))
+            .expect("PSBT has no unsigned transaction, only version 0 PSBTs are supported");
+        let inputs = (0..tx.inputs.len()).map(|_| reader.map()).collect();
+        let outputs = (0..tx.outputs.len()).map(|_| reader.map()).collect();
+        Psbt { global, inputs, outputs, tx }
+    }
+
+    /// PSBT from a file or a string, base64 or hex
+    pub fn load(input: &str) -> Self {
+        let content = fs::read(input).unwrap_or_else(|_| input.as_bytes().to_vec());
+        if content.starts_with(PSBT_MAGIC) {
+            return Psbt::parse(&content);
+        }
+        let text = String::from_utf8(content).expect("PSBT must be binary, base64 or hex");
+        let text = text.trim();
+        let data = hex::decode(text)
+            .or_else(|_| base64::decode(text))
+            .expect("PSBT must be binary, base64 or hex");
+        Psbt::parse(&data)
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let map_bytes = |map: &Vec<(Vec<u8>, Vec<u8>)>| -> Vec<u8> {
+            let mut out: Vec<u8> = map
+                .iter()
+                .flat_map(|(key, value)| [compact_size(key.len()), key.clone(), compact_size(value.len()), value.clone()].concat())
+                .collect();
+            out.push(0x00);
+            out
+        };
+        let mut out = PSBT_MAGIC.to_vec();
+        out.extend(map_bytes(&self.global));
+        self.inputs.iter().for_each(|map| out.extend(map_bytes(map)));
+        self.outputs.iter().for_each(|map| out.extend(map_bytes(map)));
+        out
+    }
+
+    fn input_field(&self, index: usize, key_type: u8) -> Option<&Vec<u8>> {
+        self.inputs[index].iter().find(|(key, _)| key == &vec![key_type]).map(|(_, value)| value)
+    }
+
+    // (script_pubkey, amount) of the output an input spends
+    fn spent_output(&self, index: usize) -> Option<(Vec<u8>, Option<u64>)> {
+        if let Some(utxo) = self.input_field(index, PSBT_IN_WITNESS_UTXO) {
+            let mut reader = Reader::new(utxo);
+            let value = reader.u64();
+            return Some((reader.var_bytes().to_vec(), Some(value)));
+        }
+        let prev_tx = Transaction::parse(self.input_field(index, PSBT_IN_NON_WITNESS_UTXO)?);
+        let input = &self.tx.inputs[index];
+        if prev_tx.txid() != input.txid {
+            panic!("PSBT input {} non-witness UTXO does not match the transaction it spends", index);
+        }
+        let output = prev_tx.outputs.get(input.vout as usize).expect("PSBT input spends a missing output");
+        Some((output.script.clone(), Some(output.value)))
+    }
+}
+
+/// Key of the threshold key that may sign an input: derivation path, public key and the derivation tweak
+struct InputKey {
+    path: String,
+    public_key: GE,
+    f_l_new: FE,
+}
+
+fn derive(y_sum: &GE, path: &str) -> InputKey {
+    match path.is_empty() {
+        true => InputKey { path: String::new(), public_key: *y_sum, f_l_new: ECScalar::zero() },
+        false => {
+            let path_vector: Vec<BigInt> = hd_keys::parse_path(path).unwrap_or_else(|e| e.exit());
+            let (public_key, f_l_new) = hd_keys::get_hd_key(y_sum, path_vector);
+            InputKey { path: path.to_string(), public_key, f_l_new }
+        }
+    }
+}
+
+// Keys an input names in its BIP32 derivations that the threshold key derives to, the default path otherwise
+fn input_keys(psbt: &Psbt, index: usize, y_sum: &GE, default_path: &str) -> Vec<InputKey> {
+    let mut keys: Vec<InputKey> = psbt.inputs[index]
+        .iter()
+        .filter(|(key, value)| key.len() == 34 && key[0] == PSBT_IN_BIP32_DERIVATION && value.len() >= 4 && value.len() % 4 == 0)
+        .filter_map(|(key, value)| {
+            let path: Vec<u32> = value[4..]
+                .chunks(4)
+                .map(|index| u32::from_le_bytes([index[0], index[1], index[2], index[3]]))
+                .collect();
+            // Hardened derivations are of other keys, the threshold key has no hardened children
+            if path.iter().any(|index| *index > hd_keys::MAX_PATH_INDEX) {
+                return None;
+            }
+            let path: Vec<String> = path.iter().map(|index| index.to_string()).collect();
+            let input_key = derive(y_sum, &path.join("/"));
+            match compressed_public_key(&input_key.public_key) == key[1..] {
+                true => Some(input_key),
+                false => None,
+            }
+        })
+        .collect();
+    if keys.is_empty() {
+        keys.push(derive(y_sum, default_path));
+    }
+    keys
+}
+
+/// Sighash of an input for a key, or why the key can't sign it
+pub fn input_sighash(psbt: &Psbt, index: usize, public_key: &GE) -> Result<Vec<u8>, String> {
+    if let Some(sighash_type) = psbt.input_field(index, PSBT_IN_SIGHASH_TYPE) {
+        if sighash_type.as_slice() != &SIGHASH_ALL.to_le_bytes()[..] {
+            return Err("only SIGHASH_ALL is supported".to_string());
+        }
+    }
+    let (script_pubkey, amount) = psbt.spent_output(index).ok_or("input has no UTXO".to_string())?;
+    let key_hash = hash160(&compressed_public_key(public_key));
+    let p2wpkh = [vec![0x00, 0x14], key_hash.clone()].concat();
+    let p2pkh = [vec![0x76, 0xa9, 0x14], key_hash, vec![0x88, 0xac]].concat();
+    let segwit_program = match psbt.input_field(index, PSBT_IN_REDEEM_SCRIPT) {
+        Some(redeem_script) if script_pubkey == [vec![0xa9, 0x14], hash160(redeem_script), vec![0x87]].concat() => redeem_script.clone(),
+        Some(_) => return Err("redeem script does not match the UTXO".to_string()),
+        None => script_pubkey.clone(),
+    };
+    if segwit_program == p2wpkh {
+        let amount = amount.ok_or("segwit input has no UTXO amount".to_string())?;
+        return Ok(psbt.tx.segwit_sighash(index, &p2pkh, amount));
+    }
+    if script_pubkey == p2pkh {
+        return Ok(psbt.tx.legacy_sighash(index, &p2pkh));
+    }
+    if script_pubkey.len() == 34 && script_pubkey[0] == 0x51 {
+        return Err("taproot inputs need Schnorr signatures".to_string());
+    }
+    Err("UTXO is not P2PKH, P2WPKH or P2SH-P2WPKH of the key".to_string())
+}
+
+/// Signs every input of a PSBT the threshold key can spend, one signing round per input. Inputs are matched by
+/// their BIP32 derivations when the key derives to a listed public key (the fingerprint is not used, derivation
+/// here is not BIP32), by the key at the default path otherwise. Partial signatures are added to the PSBT.
+/// With an approver every input's sighash is approved before its signing round, the duplicate guard checks and
+/// records every input's sighash under its path
+pub fn sign_psbt(
+    addr: &String,
+    keysfile_path: &str,
+    keyfile: KeyFile,
+    params: &Params,
+    psbt_input: &str,
+    default_path: &str,
+    out_path: Option<&str>,
+    signers: u16,
+    context: &str,
+    approver: Option<&Approver>,
+    duplicate_guard: DuplicateGuard,
+) {
+    let mut psbt = Psbt::load(psbt_input);
+    let mut signed: Vec<Value> = Vec::new();
+    let mut skipped: Vec<Value> = Vec::new();
+    for index in 0..psbt.inputs.len() {
+        for input_key in input_keys(&psbt, index, &keyfile.y_sum, default_path) {
+            let sighash = match input_sighash(&psbt, index, &input_key.public_key) {
+                Ok(sighash) => sighash,
+                Err(reason) => {
+                    skipped.push(json!({"input": index, "path": input_key.path, "reason": reason}));
+                    continue;
+                }
+            };
+            let signing_target = duplicates::SigningTarget::new(&keyfile.y_sum, &input_key.path, &sighash);
+            duplicates::check_duplicate(duplicate_guard, addr, keysfile_path, &signing_target);
+            if let Some(approver) = approver {
+                approver.approve(approver.request(&input_key.public_key, &input_key.path, &sighash, context));
+            }
+            let mut vss_scheme_vec = keyfile.vss_scheme_vec.clone();
+            let sig = signer::sign(
+                addr.clone(),
+                keyfile.party_keys.clone(),
+                keyfile.shared_keys.clone(),
+                keyfile.party_id,
+                &mut vss_scheme_vec,
+                keyfile.paillier_key_vec.clone(),
+                &input_key.public_key,
+                params,
+                &sighash,
+                &input_key.f_l_new,
+                !input_key.path.is_empty(),
+                signers,
+                context,
+            );
+            check_sig(&sig.r, &sig.s, &BigInt::from_bytes(&sighash), &input_key.public_key);
+            if duplicate_guard != DuplicateGuard::Off {
+                duplicates::record_signing(addr, keysfile_path, &signing_target, &sig);
+            }
+            let public_key = compressed_public_key(&input_key.public_key);
+            let mut signature = der_signature(&sig.r.to_big_int(), &sig.s.to_big_int());
+            signature.push(SIGHASH_ALL as u8);
+            psbt.inputs[index].retain(|(key, _)| key != &[vec![PSBT_IN_PARTIAL_SIG], public_key.clone()].concat());
+            psbt.inputs[index].push(([vec![PSBT_IN_PARTIAL_SIG], public_key.clone()].concat(), signature));
+            signed.push(json!({
+                "input": index,
+                "path": input_key.path,
+                "public_key": hex::encode(&public_key),
+                "sighash": hex::encode(&sighash),
+            }));
+        }
+    }
+    let encoded = base64::encode(psbt.serialize());
+    let mut ret_dict = json!({
+        "status": "psbt_signed",
+        "signed_inputs": signed,
+        "skipped_inputs": skipped,
+    });
+    match out_path {
+        Some(out_path) => {
+            fs::write(out_path, &encoded).expect("Unable to save PSBT !");
+            ret_dict["out"] = json!(out_path);
+        }
+        None => ret_dict["psbt"] = json!(encoded),
+    }
+    if !context.is_empty() {
+        ret_dict["context"] = json!(context);
+    }
+    println!("{}", ret_dict.to_string());
+}
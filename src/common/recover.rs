@@ -0,0 +1,230 @@
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, Write};
+use std::time::{self, Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use curv::{
+    arithmetic::traits::Converter,
+    elliptic::curves::secp256_k1::{FE, GE},
+    elliptic::curves::traits::{ECPoint, ECScalar},
+    BigInt,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::common::encoding::{compressed_public_key, to_32_bytes};
+use crate::common::keyfile::{fingerprint, KeyFile};
+use crate::common::options;
+use crate::common::signer::signup;
+use crate::common::{
+    aes_decrypt_round, aes_encrypt_round, broadcast, new_client, poll_for_broadcasts, sendp2p, try_poll_for_p2p,
+    sha256_digest, AEAD,
+};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecoverHello {
+    pub party_id: u16,
+    pub threshold: usize,
+    pub y_sum: GE,
+    pub dh_point: GE,
+    pub recoverer: bool,
+}
+
+fn read_answer(prompt: &str) -> String {
+    eprint!("{}", prompt);
+    io::stderr().flush().unwrap();
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer).expect("Unable to read confirmation");
+    answer.trim().to_string()
+}
+
+/// Asks the operator of a share to release it, anything but the exact phrase aborts
+fn confirm_release(party_id: u16) {
+    let phrase = format!("RECOVER {}", party_id);
+    let prompt = format!(
+        "Party {} share is about to be combined into the full private key. Type \"{}\" to confirm: ",
+        party_id, phrase
+    );
+    if read_answer(&prompt) != phrase {
+        panic!("Party {} did not confirm, recovery aborted", party_id);
+    }
+}
+
+/// The manager relays the transport keys and could swap the recoverer's key for its own, so a sending operator
+/// types the fingerprint the recoverer's operator reads out over another channel (phone, in person)
+fn confirm_recoverer_key(recoverer_id: u16, dh_point: &GE) {
+    let prompt = format!(
+        "Type the transport key fingerprint party {} shows, as read out by its operator: ",
+        recoverer_id
+    );
+    if read_answer(&prompt).to_lowercase() != fingerprint(dh_point) {
+        panic!(
+            "Transport key fingerprint of party {} does not match, the manager may be intercepting shares, recovery aborted",
+            recoverer_id
+        );
+    }
+}
+
+fn write_audit_record(audit_path: &str, mode: &str, party_ids: &Vec<u16>, y_sum: &GE, recovered: bool) {
+    let record = json!({
+        "event": "key_recovery",
+        "mode": mode,
+        "parties": party_ids,
+        "public_key": hex::encode(compressed_public_key(y_sum)),
+        "recovered": recovered,
+        "timestamp": SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+    });
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(audit_path)
+        .expect("Unable to open recovery audit log !");
+    writeln!(file, "{}", record.to_string()).expect("Unable to save recovery audit log !");
+}
+
+// Share of party_id weighted with its Lagrange coefficient for the given set, the weighted shares add up to the key
+fn weighted_share(keyfile: &KeyFile, party_ids: &Vec<u16>) -> FE {
+    keyfile.lagrange_coefficient(keyfile.party_id, party_ids) * keyfile.shared_keys.x_i
+}
+
+fn check_quorum(party_ids: &Vec<u16>, threshold: usize) {
+    let mut distinct = party_ids.clone();
+    distinct.sort();
+    distinct.dedup();
+    if distinct.len() != party_ids.len() || party_ids.len() <= threshold {
+        panic!("Recovery needs {} distinct parties, got {:?}", threshold + 1, party_ids);
+    }
+}
+
+fn print_private_key(x: &FE, y_sum: &GE) {
+    let g: GE = ECPoint::generator();
+    if g * x != *y_sum {
+        panic!("Recovered key does not match the public key, a share is corrupted");
+    }
+    let ret_dict = json!({
+        "status": "key_recovered",
+        "private_key": hex::encode(to_32_bytes(&x.to_big_int())),
+        "x": y_sum.x_coor(),
+        "y": y_sum.y_coor(),
+    });
+    println!("{}", ret_dict.to_string());
+}
+
+/// Air-gapped recovery: t+1 keys files are combined on one machine, the operator of every share
+/// confirms its release on the terminal
+pub fn recover_from_files(keysfile_paths: Vec<&str>, audit_path: &str) {
+    let keyfiles: Vec<KeyFile> = keysfile_paths.iter().map(|path| KeyFile::load(path)).collect();
+    let y_sum = keyfiles[0].y_sum;
+    if keyfiles.iter().any(|keyfile| keyfile.y_sum != y_sum) {
+        panic!("Keys files belong to different keys");
+    }
+    let party_ids: Vec<u16> = keyfiles.iter().map(|keyfile| keyfile.party_id).collect();
+    check_quorum(&party_ids, keyfiles[0].vss_scheme_vec[0].parameters.threshold);
+    for party_id in party_ids.iter() {
+        confirm_release(*party_id);
+    }
+    let x = keyfiles
+        .iter()
+        .fold(FE::zero(), |acc, keyfile| acc + &weighted_share(keyfile, &party_ids));
+    write_audit_record(audit_path, "files", &party_ids, &y_sum, true);
+    print_private_key(&x, &y_sum);
+}
+
+/// Networked recovery: parties join a room, confirm on their terminal and send their weighted share
+/// encrypted with a DH key to the recoverer, the only participant that learns the private key
+pub fn recover_over_manager(addr: &String, keysfile_path: &str, room: &str, parties: u16, recoverer: bool, audit_path: &str) {
+    let keyfile = KeyFile::load(keysfile_path);
+
+    let client = new_client();
+    let delay = time::Duration::from_millis(25);
+    let room_id = sha256_digest(format!("recover-{}", room).as_bytes());
+    let (party_signup, total_parties) = signup(addr, &client, 0, room_id, keyfile.party_id, parties).unwrap();
+    let party_num_int = party_signup.number;
+    let uuid = party_signup.uuid;
+
+    // round 0: party ids and keys for the share transport
+    let dh_secret: FE = ECScalar::new_random();
+    let g: GE = ECPoint::generator();
+    let hello = RecoverHello {
+        party_id: keyfile.party_id,
+        threshold: keyfile.vss_scheme_vec[0].parameters.threshold,
+        y_sum: keyfile.y_sum,
+        dh_point: g * &dh_secret,
+        recoverer,
+    };
+    assert!(broadcast(&addr, &client, party_num_int, "recover0", serde_json::to_string(&hello).unwrap(), uuid.clone()).is_ok());
+    let round0_ans_vec = poll_for_broadcasts(&addr, &client, party_num_int, total_parties, delay, "recover0", uuid.clone());
+    let mut hello_vec: Vec<RecoverHello> = round0_ans_vec
+        .iter()
+        .map(|m| serde_json::from_str::<RecoverHello>(m).unwrap())
+        .collect();
+    hello_vec.insert(party_num_int as usize - 1, hello.clone());
+
+    if hello_vec.iter().any(|h| h.y_sum != keyfile.y_sum || h.threshold != hello.threshold) {
+        panic!("Participants hold shares of different keys");
+    }
+    let recoverers: Vec<usize> = (0..hello_vec.len()).filter(|k| hello_vec[*k].recoverer).collect();
+    if recoverers.len() != 1 {
+        panic!("Exactly one participant must be the recoverer");
+    }
+    let recoverer_num = recoverers[0] as u16 + 1;
+    let party_ids: Vec<u16> = hello_vec.iter().map(|h| h.party_id).collect();
+    check_quorum(&party_ids, hello.threshold);
+    let recoverer_id = hello_vec[recoverer_num as usize - 1].party_id;
+
+    // The operator sees who takes part before releasing anything
+    eprintln!(
+        "{}",
+        json!({
+            "status": "recovery_participants",
+            "fingerprint": fingerprint(&keyfile.y_sum),
+            "parties": party_ids,
+            "recoverer": recoverer_id,
+        })
+        .to_string()
+    );
+    if recoverer {
+        // Read out to the operators of the other parties
+        eprintln!(
+            "{}",
+            json!({"status": "transport_key", "transport_key_fingerprint": fingerprint(&hello.dh_point)}).to_string()
+        );
+    } else {
+        confirm_recoverer_key(recoverer_id, &hello_vec[recoverer_num as usize - 1].dh_point);
+    }
+    confirm_release(keyfile.party_id);
+    let w_i = weighted_share(&keyfile, &party_ids);
+
+    // round 1: weighted shares go to the recoverer only
+    if !recoverer {
+        let key = BigInt::to_bytes(&(hello_vec[recoverer_num as usize - 1].dh_point * &dh_secret).x_coor().unwrap());
+        let aead_pack = aes_encrypt_round(&key, "recover1", party_num_int, recoverer_num, &to_32_bytes(&w_i.to_big_int()));
+        assert!(sendp2p(&addr, &client, party_num_int, recoverer_num, "recover1", serde_json::to_string(&aead_pack).unwrap(), uuid.clone()).is_ok());
+        write_audit_record(audit_path, "manager", &party_ids, &keyfile.y_sum, false);
+        println!("{}", json!({"status": "share_released", "recoverer": recoverer_id}).to_string());
+        return;
+    }
+    // The other operators check the fingerprint and confirm first, they get as long as an approval
+    let deadline = Instant::now() + Duration::from_secs(options::get().approval_timeout);
+    let round1_ans_vec = loop {
+        match try_poll_for_p2p(&addr, &client, party_num_int, total_parties, delay, "recover1", uuid.clone()) {
+            Ok(ans_vec) => break ans_vec,
+            Err(_) if Instant::now() < deadline => continue,
+            Err(missing) => panic!("Participants {:?} did not release their shares in time, recovery aborted", missing),
+        }
+    };
+    let mut x = w_i;
+    let mut j = 0;
+    for i in 1..=total_parties {
+        if i != party_num_int {
+            let aead_pack: AEAD = serde_json::from_str(&round1_ans_vec[j]).unwrap();
+            let key = BigInt::to_bytes(&(hello_vec[i as usize - 1].dh_point * &dh_secret).x_coor().unwrap());
+            let w_j = aes_decrypt_round(&key, "recover1", i, party_num_int, aead_pack)
+                .unwrap_or_else(|e| panic!("Recovery aborted: {}", e));
+            let w_j: FE = ECScalar::from(&BigInt::from_bytes(&w_j));
+            x = x + &w_j;
+            j += 1;
+        }
+    }
+    write_audit_record(audit_path, "manager", &party_ids, &keyfile.y_sum, true);
+    print_private_key(&x, &keyfile.y_sum);
+}
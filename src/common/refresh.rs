@@ -0,0 +1,131 @@
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::common::atomic_file;
+use crate::common::reshare;
+use crate::common::{new_client, post_json, Params};
+
+/// Progress of scheduled refreshes, kept in <keysfile>.refresh.json
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct RefreshState {
+    pub last_refresh: u64,
+    pub last_slot: Option<u64>,
+    pub refreshes: u64,
+    pub failures: u64,
+    pub last_error: String,
+}
+
+fn state_path(keysfile_path: &str) -> String {
+    format!("{}.refresh.json", keysfile_path)
+}
+
+fn load_state(keysfile_path: &str) -> RefreshState {
+    let path = state_path(keysfile_path);
+    if !Path::new(&path).exists() {
+        return RefreshState::default();
+    }
+    let data = fs::read_to_string(&path).expect("Unable to load refresh state !");
+    serde_json::from_str(&data).expect("Invalid refresh state file !")
+}
+
+fn save_state(keysfile_path: &str, state: &RefreshState) {
+    atomic_file::write_atomic(state_path(keysfile_path), serde_json::to_string(state).unwrap().as_bytes());
+}
+
+/// Parses durations like 30d, 12h, 15m, 90s or plain seconds
+pub fn parse_duration(duration: &str) -> u64 {
+    let (number, unit) = duration.split_at(duration.trim_end_matches(char::is_alphabetic).len());
+    let number = number.parse::<u64>().unwrap_or_else(|_| panic!("Invalid duration: {}", duration));
+    let multiplier = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => panic!("Invalid duration unit: {}, use s, m, h or d", unit),
+    };
+    if number == 0 {
+        panic!("Duration must be positive");
+    }
+    number * multiplier
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+// Manager's clock, the one clock all daemons read. A manager outage only delays the next attempt
+fn manager_now(addr: &String, retry: u64) -> u64 {
+    loop {
+        match post_json::<_, u64>(addr, &new_client(), "time", ()) {
+            Ok(server_time) => return server_time,
+            Err(e) => {
+                eprintln!(
+                    "{}",
+                    json!({"status": "manager_time_unavailable", "retry_in": retry, "error": e.to_string()}).to_string()
+                );
+                thread::sleep(Duration::from_secs(retry));
+            }
+        }
+    }
+}
+
+/// Refreshes the share once per interval. Time of the manager is cut into slots of interval seconds and
+/// attempts start at multiples of retry seconds, so parties running the daemon meet in the same room
+/// (<room>-<slot>-<attempt>) whatever their own clocks say. A failed attempt is retried at the next
+/// multiple of retry until the slot is done. Every attempt first reconciles the sharings the parties
+/// hold, so a party that missed the end of a refresh catches up instead of drifting apart
+pub fn run_refresh_daemon(addr: &String, keysfile_path: &str, params: &Params, room: &str, interval: u64, retry: u64) {
+    loop {
+        let state = load_state(keysfile_path);
+        let manager_time = manager_now(addr, retry);
+        let slot = manager_time / interval;
+        if state.last_slot.map_or(false, |last_slot| last_slot >= slot) {
+            let next_slot_start = (slot + 1) * interval;
+            thread::sleep(Duration::from_secs(next_slot_start.saturating_sub(manager_time).min(retry)));
+            continue;
+        }
+        let attempt = manager_time / retry + 1;
+        thread::sleep(Duration::from_secs((attempt * retry).saturating_sub(manager_now(addr, retry))));
+
+        let attempt_room = format!("{}-{}-{}", room, slot, attempt);
+        let addr_c = addr.clone();
+        let keysfile_path_c = keysfile_path.to_string();
+        let params_c = params.clone();
+        // A failed refresh panics, the keys file is left unchanged in that case
+        let result = thread::spawn(move || reshare::refresh(&addr_c, &keysfile_path_c, &params_c, &attempt_room)).join();
+
+        let mut state = state;
+        match result {
+            Ok(_) => {
+                state.last_refresh = now();
+                state.last_slot = Some(slot);
+                state.refreshes += 1;
+                state.last_error = String::new();
+            }
+            Err(e) => {
+                state.failures += 1;
+                state.last_error = e
+                    .downcast_ref::<String>()
+                    .cloned()
+                    .or_else(|| e.downcast_ref::<&str>().map(|s| s.to_string()))
+                    .unwrap_or("refresh failed".to_string());
+            }
+        }
+        save_state(keysfile_path, &state);
+        println!(
+            "{}",
+            json!({
+                "status": if state.last_slot == Some(slot) { "refresh_scheduled_done" } else { "refresh_scheduled_failed" },
+                "slot": slot,
+                "attempt": attempt,
+                "state": state,
+            })
+            .to_string()
+        );
+    }
+}
@@ -0,0 +1,131 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+
+use crate::common::atomic_file;
+use crate::common::integrity;
+use crate::common::key_encryption::{self, EncryptedKeyFile};
+use crate::common::keys_lock::KeysFileLock;
+
+/// Argon2id cost of rekeyed files: memory in KiB, passes and lanes
+#[derive(Clone, Copy, Debug)]
+pub struct KdfCost {
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+impl Default for KdfCost {
+    fn default() -> Self {
+        KdfCost {
+            m_cost: key_encryption::M_COST,
+            t_cost: key_encryption::T_COST,
+            p_cost: key_encryption::P_COST,
+        }
+    }
+}
+
+// Encrypted keys file at path, None for plaintext keys files and anything that is not JSON
+fn read_encrypted(path: &Path) -> Option<EncryptedKeyFile> {
+    let data: Value = fs::read_to_string(path).ok().and_then(|data| serde_json::from_str(&data).ok())?;
+    if !key_encryption::is_encrypted(&data) {
+        return None;
+    }
+    Some(serde_json::from_value(data).expect("Invalid encrypted keys file"))
+}
+
+/// Re-encrypts one keys file with a new passphrase and cost. The original is kept as
+/// <keysfile>.<unix time>.bak and the new file is renamed over it. The backup still opens with the old passphrase,
+/// with remove_backup it is deleted once the new file is checked to open to the same share
+pub fn rekey_file(keysfile_path: &str, old_passphrase: &str, new_passphrase: &str, cost: KdfCost, remove_backup: bool) -> Value {
+    let _lock = KeysFileLock::acquire(keysfile_path, "rekey");
+    let encrypted = read_encrypted(Path::new(keysfile_path))
+        .unwrap_or_else(|| panic!("{} is not an encrypted keys file, use encrypt-keys", keysfile_path));
+    let original = key_encryption::decrypt(&encrypted, old_passphrase);
+    let plaintext = integrity::rekey(&original, old_passphrase, new_passphrase);
+    let rekeyed = key_encryption::encrypt_with_cost(&plaintext, new_passphrase, cost.m_cost, cost.t_cost, cost.p_cost);
+
+    let backup_path = atomic_file::backup(keysfile_path).unwrap();
+    atomic_file::write_atomic(keysfile_path, serde_json::to_string(&rekeyed).unwrap().as_bytes());
+    let mut record = json!({
+        "keysfile": keysfile_path,
+        "m_cost": cost.m_cost,
+        "t_cost": cost.t_cost,
+        "p_cost": cost.p_cost,
+    });
+    if !remove_backup {
+        record["backup"] = json!(backup_path);
+        record["warning"] = json!("The backup still opens with the old passphrase, delete it or use --remove-backup");
+        return record;
+    }
+    let written = read_encrypted(Path::new(keysfile_path)).unwrap();
+    if integrity::without_integrity(&key_encryption::decrypt(&written, new_passphrase)) != integrity::without_integrity(&original) {
+        panic!("Rekeyed keys file does not match the original, restore {}", backup_path);
+    }
+    // A leaked old passphrase must not open the share
+    fs::remove_file(&backup_path).expect("Unable to remove the keys file encrypted with the old passphrase !");
+    record
+}
+
+/// Rekeys a keys file, or every encrypted keys file directly inside a keystore directory.
+/// All files must open with the same old passphrase, backups and temporary files are left alone
+pub fn rekey(path: &str, new_passphrase: &str, cost: KdfCost, remove_backup: bool) {
+    let old_passphrase = key_encryption::passphrase();
+    if !Path::new(path).is_dir() {
+        let result = rekey_file(path, &old_passphrase, new_passphrase, cost, remove_backup);
+        println!("{}", json!({"status": "keys_rekeyed", "rekeyed": [result]}).to_string());
+        return;
+    }
+
+    let mut paths: Vec<String> = fs::read_dir(path)
+        .expect("Unable to read keystore directory !")
+        .map(|entry| entry.unwrap().path())
+        .filter(|entry| entry.is_file())
+        .filter(|entry| !entry.to_string_lossy().ends_with(".bak") && !entry.to_string_lossy().ends_with(".tmp"))
+        .filter(|entry| read_encrypted(entry).is_some())
+        .map(|entry| entry.to_string_lossy().to_string())
+        .collect();
+    paths.sort();
+    // Every file is checked with the old passphrase first, so a mistake does not leave the keystore half rotated
+    for keysfile_path in paths.iter() {
+        key_encryption::decrypt(&read_encrypted(Path::new(keysfile_path)).unwrap(), &old_passphrase);
+    }
+    let rekeyed: Vec<Value> = paths
+        .iter()
+        .map(|keysfile_path| rekey_file(keysfile_path, &old_passphrase, new_passphrase, cost, remove_backup))
+        .collect();
+    println!("{}", json!({"status": "keys_rekeyed", "rekeyed": rekeyed}).to_string());
+}
+
+/// Rotates the passphrase of one keys file: rekeys it, checks the new file opens with the new passphrase to the
+/// same share, deletes the copy still encrypted under the old passphrase and appends the rotation with the new
+/// AES-GCM tag to the audit log
+pub fn rotate_passphrase(keysfile_path: &str, new_passphrase: &str, cost: KdfCost, audit_path: &str) {
+    let old_passphrase = key_encryption::passphrase();
+    if old_passphrase == new_passphrase {
+        panic!("New passphrase must differ from the old one");
+    }
+    let encrypted = read_encrypted(Path::new(keysfile_path))
+        .unwrap_or_else(|| panic!("{} is not an encrypted keys file, use encrypt-keys", keysfile_path));
+
+    let mut record = rekey_file(keysfile_path, &old_passphrase, new_passphrase, cost, true);
+    let rotated = read_encrypted(Path::new(keysfile_path)).unwrap();
+    // The last 16 bytes of the ciphertext are the GCM tag, it ties the record to the file content
+    let ciphertext = hex::decode(&rotated.ciphertext).unwrap();
+    record["event"] = json!("passphrase_rotation");
+    record["mac"] = json!(hex::encode(&ciphertext[ciphertext.len() - 16..]));
+    record["previous_mac"] = json!(&encrypted.ciphertext[encrypted.ciphertext.len() - 32..]);
+    record["timestamp"] = json!(SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs());
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(audit_path)
+        .expect("Unable to open passphrase audit log !");
+    writeln!(file, "{}", record.to_string()).expect("Unable to save passphrase audit log !");
+    record["status"] = json!("passphrase_rotated");
+    println!("{}", record.to_string());
+}
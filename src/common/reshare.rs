@@ -0,0 +1,659 @@
+use std::fs;
+use std::time;
+
+use curv::{
+    arithmetic::traits::Converter,
+    cryptographic_primitives::secret_sharing::feldman_vss::VerifiableSS,
+    elliptic::curves::traits::{ECPoint, ECScalar},
+    BigInt,
+    elliptic::curves::secp256_k1::{FE, GE}
+};
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2018::party_i::{Keys, SharedKeys};
+use paillier::EncryptionKey;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::common::canary::canary_signature;
+use crate::common::chain_code;
+use crate::common::keychain;
+use crate::common::keyfile::{forget_keychain_entry, keychain_reference, KeyFile};
+use crate::common::keys_lock::KeysFileLock;
+use crate::common::signer::signup;
+use crate::common::{
+    aes_decrypt_round, aes_encrypt_round, broadcast, new_client, poll_for_broadcasts, poll_for_p2p, sendp2p, sha256_digest, Params, AEAD,
+};
+
+/// Signup numbers of parties joining only with a new share, kept apart from old party ids
+const NEW_PARTY_OFFSET: u16 = 1000;
+
+/// Old share held by a participant, it deals its Lagrange weighted share to the new parties
+pub struct OldShare {
+    pub keyfile: KeyFile,
+    pub params: Params,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReshareHello {
+    pub old_party_id: Option<u16>,
+    pub old_threshold: Option<u16>,
+    pub new_party_id: Option<u16>,
+    pub new_threshold: u16,
+    pub new_parties: u16,
+    pub y_sum: Option<GE>,
+    pub dh_point: GE,
+    pub ek: Option<EncryptionKey>,
+    /// Fingerprint of the HD chain code the new shares are saved with, None for the default
+    pub chain_code_fingerprint: Option<String>,
+}
+
+/// Shares of a reshare for a new party, not yet written anywhere
+pub struct ReshareResult {
+    pub keyfile: Option<KeyFile>,
+    pub dealers: Vec<u16>,
+    pub new_parties: Vec<u16>,
+    pub y_sum: GE,
+    /// Uuid of the reshare room, shared by all participants of this run
+    pub uuid: String,
+}
+
+// Aggregate of the dealers' commitments, stored as n equal schemes scaled by 1/n:
+// signing only ever sums the schemes, so this is what keygen's per-party schemes add up to
+pub fn aggregate_vss_scheme_vec(dealer_vss: &Vec<VerifiableSS<GE>>, new_parties: u16) -> Vec<VerifiableSS<GE>> {
+    let commitments_len = dealer_vss[0].commitments.len();
+    let n_inv: FE = ECScalar::from(&BigInt::from(new_parties as u64));
+    let n_inv = n_inv.invert();
+    let commitments = (0..commitments_len)
+        .map(|k| {
+            let (head, tail) = dealer_vss.split_at(1);
+            tail.iter().fold(head[0].commitments[k], |acc, vss| acc + vss.commitments[k]) * &n_inv
+        })
+        .collect::<Vec<GE>>();
+    let vss_scheme = VerifiableSS {
+        parameters: dealer_vss[0].parameters.clone(),
+        commitments,
+    };
+    (0..new_parties).map(|_| vss_scheme.clone()).collect()
+}
+
+/// Re-deals the secret of an existing quorum to a new set of parties, t-of-n may change, the public key does not.
+/// Every participant is an old share holder dealing its share, a new party receiving a share, or both.
+/// All old participants deal, so there must be more of them than the old threshold
+pub fn run_reshare(
+    addr: &String,
+    room: &str,
+    participants: u16,
+    old_share: Option<OldShare>,
+    new_party_id: Option<u16>,
+    new_params: &Params,
+) -> ReshareResult {
+    let client = new_client();
+    let delay = time::Duration::from_millis(25);
+    let new_threshold = new_params.threshold.parse::<u16>().unwrap();
+    let new_parties = new_params.parties.parse::<u16>().unwrap();
+    if new_threshold >= new_parties {
+        panic!("New threshold must be lower than the number of new parties");
+    }
+    if let Some(new_party_id) = new_party_id {
+        if new_party_id == 0 || new_party_id > new_parties {
+            panic!("New party id must be between 1 and {}", new_parties);
+        }
+    }
+    let old_party_id = old_share.as_ref().map(|old| old.keyfile.party_id);
+    let signup_number = match (old_party_id, new_party_id) {
+        (Some(old_party_id), _) => old_party_id,
+        (None, Some(new_party_id)) => NEW_PARTY_OFFSET + new_party_id,
+        (None, None) => panic!("Reshare needs an old keys file, a new party id or both"),
+    };
+
+    let room_id = sha256_digest(format!("reshare-{}", room).as_bytes());
+    let (party_signup, total_parties) = signup(addr, &client, 0, room_id, signup_number, participants).unwrap();
+    let party_num_int = party_signup.number;
+    let uuid = party_signup.uuid;
+    let debug = json!({"manager_addr": &addr, "party_num": party_num_int, "uuid": uuid});
+    println!("{}", serde_json::to_string_pretty(&debug).unwrap());
+
+    // round 0: roles, public key and keys for the share transport
+    let new_keys = new_party_id.map(|new_party_id| Keys::create(new_party_id as usize));
+    let dh_secret: FE = ECScalar::new_random();
+    let g: GE = ECPoint::generator();
+    let hello = ReshareHello {
+        old_party_id,
+        old_threshold: old_share.as_ref().map(|old| old.params.threshold.parse::<u16>().unwrap()),
+        new_party_id,
+        new_threshold,
+        new_parties,
+        y_sum: old_share.as_ref().map(|old| old.keyfile.y_sum),
+        dh_point: g * &dh_secret,
+        ek: new_keys.as_ref().map(|keys| keys.ek.clone()),
+        chain_code_fingerprint: chain_code::chain_code().map(|chain_code| chain_code::fingerprint(&chain_code)),
+    };
+    assert!(broadcast(
+        &addr,
+        &client,
+        party_num_int,
+        "reshare0",
+        serde_json::to_string(&hello).unwrap(),
+        uuid.clone(),
+    )
+    .is_ok());
+    let round0_ans_vec = poll_for_broadcasts(
+        &addr,
+        &client,
+        party_num_int,
+        total_parties,
+        delay,
+        "reshare0",
+        uuid.clone(),
+    );
+    let mut hello_vec: Vec<ReshareHello> = round0_ans_vec
+        .iter()
+        .map(|m| serde_json::from_str::<ReshareHello>(m).unwrap())
+        .collect();
+    hello_vec.insert(party_num_int as usize - 1, hello.clone());
+
+    if hello_vec.iter().any(|h| h.new_threshold != new_threshold || h.new_parties != new_parties) {
+        panic!("Participants disagree on the new threshold params");
+    }
+    // New parties store the chain code with their share, a wrong one would derive other keys from then on
+    if hello_vec.iter().any(|h| h.chain_code_fingerprint != hello.chain_code_fingerprint) {
+        panic!("Participants use different chain codes, give new parties the --chain-code of the key");
+    }
+    let y_sum_vec: Vec<GE> = hello_vec.iter().filter_map(|h| h.y_sum).collect();
+    if y_sum_vec.is_empty() || y_sum_vec.iter().any(|y| *y != y_sum_vec[0]) {
+        panic!("Old share holders disagree on the public key");
+    }
+    let y_sum = y_sum_vec[0];
+    let old_thresholds: Vec<u16> = hello_vec.iter().filter_map(|h| h.old_threshold).collect();
+    if old_thresholds.iter().any(|t| *t != old_thresholds[0]) {
+        panic!("Old share holders disagree on the old threshold");
+    }
+    let mut dealers: Vec<u16> = hello_vec.iter().filter_map(|h| h.old_party_id).collect();
+    dealers.sort();
+    dealers.dedup();
+    if dealers.len() != old_thresholds.len() || dealers.len() <= old_thresholds[0] as usize {
+        panic!("Need {} distinct old share holders, got {:?}", old_thresholds[0] + 1, dealers);
+    }
+    let mut new_party_ids: Vec<u16> = hello_vec.iter().filter_map(|h| h.new_party_id).collect();
+    new_party_ids.sort();
+    if new_party_ids != (1..=new_parties).collect::<Vec<u16>>() {
+        panic!("All new parties 1..{} must take part exactly once, got {:?}", new_parties, new_party_ids);
+    }
+
+    // round 1: dealers share their Lagrange weighted share w_i with a fresh polynomial. The weights are taken at
+    // the points the old shares are evaluated at, which also moves imported shares onto the party ids
+    let dealing = old_share.as_ref().map(|old| {
+        let li = old.keyfile.lagrange_coefficient(old.keyfile.party_id, &dealers);
+        let w_i = li * old.keyfile.shared_keys.x_i;
+        VerifiableSS::<GE>::share(new_threshold as usize, new_parties as usize, &w_i)
+    });
+    let dealer_vss = dealing.as_ref().map(|(vss, _)| vss.clone());
+    assert!(broadcast(
+        &addr,
+        &client,
+        party_num_int,
+        "reshare1",
+        serde_json::to_string(&dealer_vss).unwrap(),
+        uuid.clone(),
+    )
+    .is_ok());
+    let round1_ans_vec = poll_for_broadcasts(
+        &addr,
+        &client,
+        party_num_int,
+        total_parties,
+        delay,
+        "reshare1",
+        uuid.clone(),
+    );
+    let mut dealer_vss_vec: Vec<Option<VerifiableSS<GE>>> = round1_ans_vec
+        .iter()
+        .map(|m| serde_json::from_str::<Option<VerifiableSS<GE>>>(m).unwrap())
+        .collect();
+    dealer_vss_vec.insert(party_num_int as usize - 1, dealer_vss);
+
+    for (hello_j, vss_j) in hello_vec.iter().zip(dealer_vss_vec.iter()) {
+        match (hello_j.old_party_id, vss_j) {
+            (Some(_), Some(vss_j)) => {
+                if vss_j.parameters.threshold != new_threshold as usize
+                    || vss_j.parameters.share_count != new_parties as usize
+                {
+                    panic!("Dealer {:?} used wrong sharing params", hello_j.old_party_id);
+                }
+            }
+            (None, None) => {}
+            _ => panic!("Participant {:?} sent an unexpected dealing", hello_j.old_party_id),
+        }
+    }
+    // Old share holders can check every dealer dealt exactly its weighted share
+    if let Some(old) = old_share.as_ref() {
+        for (hello_j, vss_j) in hello_vec.iter().zip(dealer_vss_vec.iter()) {
+            if let (Some(old_party_id), Some(vss_j)) = (hello_j.old_party_id, vss_j) {
+                let g_w_j = old.keyfile.public_share(old_party_id) * &old.keyfile.lagrange_coefficient(old_party_id, &dealers);
+                if vss_j.commitments[0] != g_w_j {
+                    panic!("Dealer {} did not deal its own share", old_party_id);
+                }
+            }
+        }
+    }
+    let dealer_vss_vec: Vec<VerifiableSS<GE>> = dealer_vss_vec.into_iter().filter_map(|vss| vss).collect();
+    let (head, tail) = dealer_vss_vec.split_at(1);
+    let dealt_y_sum = tail.iter().fold(head[0].commitments[0], |acc, vss| acc + vss.commitments[0]);
+    if dealt_y_sum != y_sum {
+        panic!("Dealt shares don't add up to the public key");
+    }
+
+    // round 2: dealers send each new party its share, encrypted with a DH key
+    for (k, i) in (1..=total_parties).enumerate() {
+        if i != party_num_int {
+            let aead_pack_i: Option<AEAD> = match (&dealing, hello_vec[k].new_party_id) {
+                (Some((_, shares)), Some(new_party_id_i)) => {
+                    let key_i = BigInt::to_bytes(&(hello_vec[k].dh_point * &dh_secret).x_coor().unwrap());
+                    let plaintext = BigInt::to_bytes(&shares[(new_party_id_i - 1) as usize].to_big_int());
+                    Some(aes_encrypt_round(&key_i, "reshare2", party_num_int, i, &plaintext))
+                }
+                _ => None,
+            };
+            assert!(sendp2p(
+                &addr,
+                &client,
+                party_num_int,
+                i,
+                "reshare2",
+                serde_json::to_string(&aead_pack_i).unwrap(),
+                uuid.clone(),
+            )
+            .is_ok());
+        }
+    }
+    let round2_ans_vec = poll_for_p2p(
+        &addr,
+        &client,
+        party_num_int,
+        total_parties,
+        delay,
+        "reshare2",
+        uuid.clone(),
+    );
+
+    let vss_scheme_vec = aggregate_vss_scheme_vec(&dealer_vss_vec, new_parties);
+    let paillier_key_vec: Vec<EncryptionKey> = (1..=new_parties)
+        .map(|id| hello_vec.iter().find(|h| h.new_party_id == Some(id)).unwrap().ek.clone().unwrap())
+        .collect();
+
+    let keyfile = new_party_id.map(|new_party_id| {
+        let mut x_i: FE = FE::zero();
+        let mut j = 0;
+        for (k, i) in (1..=total_parties).enumerate() {
+            let share = if i == party_num_int {
+                dealing.as_ref().map(|(_, shares)| shares[(new_party_id - 1) as usize])
+            } else {
+                let aead_pack: Option<AEAD> = serde_json::from_str(&round2_ans_vec[j]).unwrap();
+                j += 1;
+                aead_pack.map(|aead_pack| {
+                    let key_i = BigInt::to_bytes(&(hello_vec[k].dh_point * &dh_secret).x_coor().unwrap());
+                    let out = aes_decrypt_round(&key_i, "reshare2", i, party_num_int, aead_pack)
+                        .unwrap_or_else(|e| panic!("Reshare aborted, no keys file was written: {}", e));
+                    let out_bn = BigInt::from_bytes(&out);
+                    let out_fe: FE = ECScalar::from(&out_bn);
+                    out_fe
+                })
+            };
+            match (share, &dealer_vss_j(&hello_vec, &dealer_vss_vec, k)) {
+                (Some(share), Some(vss_k)) => {
+                    vss_k
+                        .validate_share(&share, new_party_id as usize)
+                        .unwrap_or_else(|e| panic!("Invalid share from dealer {:?}: {:?}", hello_vec[k].old_party_id, e));
+                    x_i = x_i + &share;
+                }
+                (None, None) => {}
+                _ => panic!("Missing share from dealer {:?}", hello_vec[k].old_party_id),
+            }
+        }
+        KeyFile {
+            party_keys: new_keys.unwrap(),
+            shared_keys: SharedKeys { y: y_sum, x_i },
+            party_id: new_party_id,
+            vss_scheme_vec: vss_scheme_vec.clone(),
+            paillier_key_vec: paillier_key_vec.clone(),
+            y_sum,
+            chain_code: chain_code::chain_code(),
+            canary: None,
+            share_points: None,
+        }
+    });
+
+    // round 3: new parties confirm they hold valid shares of the same sharing
+    let transcript = sha256_digest(serde_json::to_string(&(&vss_scheme_vec, &paillier_key_vec)).unwrap().as_bytes());
+    let confirmation = keyfile.as_ref().map(|_| transcript.clone());
+    assert!(broadcast(
+        &addr,
+        &client,
+        party_num_int,
+        "reshare3",
+        serde_json::to_string(&confirmation).unwrap(),
+        uuid.clone(),
+    )
+    .is_ok());
+    let round3_ans_vec = poll_for_broadcasts(
+        &addr,
+        &client,
+        party_num_int,
+        total_parties,
+        delay,
+        "reshare3",
+        uuid.clone(),
+    );
+    let confirmations: Vec<Option<String>> = round3_ans_vec
+        .iter()
+        .map(|m| serde_json::from_str::<Option<String>>(m).unwrap())
+        .collect();
+    if confirmations.iter().flatten().any(|c| *c != transcript) {
+        panic!("New parties ended up with different sharings, discard the new shares");
+    }
+
+    ReshareResult {
+        keyfile,
+        dealers,
+        new_parties: new_party_ids,
+        y_sum,
+        uuid,
+    }
+}
+
+// Dealing of the participant at position k of the room, dealer_vss_vec only holds dealers
+fn dealer_vss_j(
+    hello_vec: &Vec<ReshareHello>,
+    dealer_vss_vec: &Vec<VerifiableSS<GE>>,
+    k: usize,
+) -> Option<VerifiableSS<GE>> {
+    hello_vec[k].old_party_id.map(|_| {
+        let position = hello_vec[..k].iter().filter(|h| h.old_party_id.is_some()).count();
+        dealer_vss_vec[position].clone()
+    })
+}
+
+/// Writes the new keys file, if this participant received a share, and reports the new party set.
+/// New parties first verify their shares with a test signing round of all new parties, the share is
+/// held in a temp file next to the target until the canary passed and only then written to the target
+pub fn save_reshare_result(addr: &String, result: ReshareResult, out_path: Option<&str>, new_params: &Params) {
+    let mut ret_dict = json!({
+        "status": "reshare_done",
+        "dealers": result.dealers,
+        "new_parties": result.new_parties,
+        "x": result.y_sum.x_coor(),
+        "y": result.y_sum.y_coor(),
+    });
+    let keyfile = match result.keyfile {
+        Some(keyfile) => keyfile,
+        None => {
+            println!("{}", ret_dict.to_string());
+            return;
+        }
+    };
+    let out_path = out_path.expect("Target keys file for the new share is required").to_string();
+    let tmp_path = format!("{}.reshare.tmp", out_path);
+    keyfile.save(&tmp_path);
+    let remove_tmp = || {
+        if let Some(entry) = keychain_reference(&tmp_path) {
+            keychain::delete_entry(&entry);
+        }
+        fs::remove_file(&tmp_path).expect("Unable to remove new share temp file !");
+    };
+    let canary = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let loaded = KeyFile::load(&tmp_path);
+        if loaded.to_json() != keyfile.to_json() {
+            panic!("New share did not load back from {}", tmp_path);
+        }
+        canary_signature(addr, &loaded, new_params, &result.uuid)
+    }));
+    let canary = match canary {
+        Ok(canary) => canary,
+        Err(_) => {
+            remove_tmp();
+            panic!("Canary signature with the new share failed, {} was not written", out_path);
+        }
+    };
+    let keyfile = KeyFile { canary: Some(canary), ..keyfile };
+    keyfile.replace(&out_path);
+    remove_tmp();
+    ret_dict["party_id"] = json!(keyfile.party_id);
+    ret_dict["keysfile"] = json!(out_path);
+    println!("{}", ret_dict.to_string());
+}
+
+/// Issues a share to a new device without changing the public key: all current parties and the
+/// new device reshare to t of n+1, the new device gets party id n+1. Current keys files are replaced
+/// by the new ones
+pub fn add_party(addr: &String, keysfile_path: &str, params: &Params, room: &str, join: bool) {
+    let _lock = KeysFileLock::acquire(keysfile_path, "add-party");
+    let parties = params.parties.parse::<u16>().unwrap();
+    let new_params = Params {
+        threshold: params.threshold.clone(),
+        parties: (parties + 1).to_string(),
+    };
+    let (old_share, new_party_id) = match join {
+        true => (None, parties + 1),
+        false => {
+            let keyfile = KeyFile::load(keysfile_path);
+            let party_id = keyfile.party_id;
+            (Some(OldShare { keyfile, params: params.clone() }), party_id)
+        }
+    };
+    let room = format!("add-party-{}", room);
+    let result = run_reshare(addr, &room, parties + 1, old_share, Some(new_party_id), &new_params);
+    save_reshare_result(addr, result, Some(keysfile_path), &new_params);
+}
+
+/// Makes the share of a lost or compromised party useless: all remaining parties reshare to t of n-1
+/// without it, so their new shares can't be combined with the revoked one. Remaining parties keep
+/// their order and are renumbered 1..n-1. Keys files are replaced without keeping a copy of the old share,
+/// which would still combine with the revoked one. Backups left by older tss_cli versions are reported
+pub fn revoke_party(addr: &String, keysfile_path: &str, params: &Params, room: &str, revoked_party_id: u16) {
+    let _lock = KeysFileLock::acquire(keysfile_path, "revoke-party");
+    let threshold = params.threshold.parse::<u16>().unwrap();
+    let parties = params.parties.parse::<u16>().unwrap();
+    if revoked_party_id == 0 || revoked_party_id > parties {
+        panic!("Revoked party id must be between 1 and {}", parties);
+    }
+    if threshold + 1 >= parties {
+        panic!("Revoking a party of a {} of {} key leaves too few parties to sign", threshold + 1, parties);
+    }
+    let keyfile = KeyFile::load(keysfile_path);
+    if keyfile.party_id == revoked_party_id {
+        panic!("This keys file holds the revoked share, run revoke-party on the remaining parties");
+    }
+    let remaining: Vec<u16> = (1..=parties).filter(|id| *id != revoked_party_id).collect();
+    let new_party_id = remaining.iter().position(|id| *id == keyfile.party_id).unwrap() as u16 + 1;
+    let new_params = Params {
+        threshold: params.threshold.clone(),
+        parties: (parties - 1).to_string(),
+    };
+    let room = format!("revoke-party-{}", room);
+    let old_share = OldShare { keyfile, params: params.clone() };
+    let result = run_reshare(addr, &room, parties - 1, Some(old_share), Some(new_party_id), &new_params);
+    // Reported only once the new share is saved, a failed save must not look like a completed revocation
+    save_reshare_result(addr, result, Some(keysfile_path), &new_params);
+    let party_set: Vec<serde_json::Value> = remaining
+        .iter()
+        .enumerate()
+        .map(|(k, old_id)| json!({"old_party_id": old_id, "new_party_id": k + 1}))
+        .collect();
+    println!(
+        "{}",
+        json!({
+            "status": "party_revoked",
+            "revoked_party_id": revoked_party_id,
+            "parties": party_set,
+            "params": format!("{}/{}", new_params.threshold, new_params.parties),
+        })
+        .to_string()
+    );
+    let backups = old_share_backups(keysfile_path);
+    if !backups.is_empty() {
+        // Copies of the share from before the revocation, they still combine with the revoked party's share
+        eprintln!("{}", json!({"status": "old_share_backups_found", "backups": backups}).to_string());
+    }
+}
+
+// <keysfile>.<unix time>.bak copies written by tss_cli versions that backed up replaced keys files
+fn old_share_backups(keysfile_path: &str) -> Vec<String> {
+    let path = std::path::Path::new(keysfile_path);
+    let prefix = format!("{}.", path.file_name().unwrap().to_string_lossy());
+    let dir = match path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        Some(dir) => dir.to_path_buf(),
+        None => std::path::PathBuf::from("."),
+    };
+    let mut backups: Vec<String> = fs::read_dir(&dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .filter(|name| name.starts_with(&prefix) && name.ends_with(".bak"))
+                .map(|name| dir.join(name).display().to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    backups.sort();
+    backups
+}
+
+/// Hash of the VSS schemes of a sharing, equal for all shares of the same polynomials
+pub fn vss_hash(keyfile: &KeyFile) -> String {
+    sha256_digest(serde_json::to_string(&keyfile.vss_scheme_vec).unwrap().as_bytes())
+}
+
+/// New share of a refresh that has not replaced the keys file yet
+pub fn pending_refresh_path(keysfile_path: &str) -> String {
+    format!("{}.refresh.tmp", keysfile_path)
+}
+
+/// Sharing a party holds in its keys file and, after an interrupted refresh, in the pending file
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct RefreshHolding {
+    pub current: String,
+    pub pending: Option<String>,
+}
+
+/// Resolves a refresh interrupted between the commit round and the rename, before the next one starts.
+/// Parties exchange the VSS hashes of their keys file and pending share. A pending share every party
+/// holds, as keys file or pending, replaces the keys file, otherwise it is dropped. All parties see the
+/// same holdings and decide the same way; they must hold the same sharing afterwards
+pub fn reconcile_refresh(addr: &String, keysfile_path: &str, party_id: u16, parties: u16, room: &str) -> KeyFile {
+    let tmp_path = pending_refresh_path(keysfile_path);
+    let has_pending = fs::metadata(&tmp_path).is_ok();
+    let holding = RefreshHolding {
+        current: vss_hash(&KeyFile::load(keysfile_path)),
+        pending: if has_pending { Some(vss_hash(&KeyFile::load(&tmp_path))) } else { None },
+    };
+
+    let client = new_client();
+    let delay = time::Duration::from_millis(25);
+    let room_id = sha256_digest(format!("refresh-reconcile-{}", room).as_bytes());
+    let (party_signup, total_parties) = signup(addr, &client, 0, room_id, party_id, parties).unwrap();
+    assert!(broadcast(
+        &addr,
+        &client,
+        party_signup.number,
+        "refresh-reconcile",
+        serde_json::to_string(&holding).unwrap(),
+        party_signup.uuid.clone(),
+    )
+    .is_ok());
+    let mut holdings: Vec<RefreshHolding> = poll_for_broadcasts(
+        &addr,
+        &client,
+        party_signup.number,
+        total_parties,
+        delay,
+        "refresh-reconcile",
+        party_signup.uuid.clone(),
+    )
+    .iter()
+    .map(|m| serde_json::from_str(m).unwrap())
+    .collect();
+    holdings.push(holding.clone());
+
+    let held_by_all = |hash: &String| holdings.iter().all(|h| h.current == *hash || h.pending.as_ref() == Some(hash));
+    let resolve = |h: &RefreshHolding| h.pending.clone().filter(|pending| held_by_all(pending)).unwrap_or(h.current.clone());
+    let resolved: Vec<String> = holdings.iter().map(|h| resolve(h)).collect();
+    if resolved.iter().any(|hash| *hash != resolved[0]) {
+        panic!("Parties hold shares of different sharings, the key needs a reshare from t+1 matching shares");
+    }
+    if has_pending {
+        let replaced_entry = keychain_reference(keysfile_path);
+        if resolve(&holding) != holding.current {
+            eprintln!("{}", json!({"status": "refresh_completed", "pending": tmp_path, "keysfile": keysfile_path}).to_string());
+            fs::rename(&tmp_path, keysfile_path).expect("Unable to replace keys file !");
+            forget_keychain_entry(replaced_entry, keysfile_path);
+        } else {
+            eprintln!("{}", json!({"status": "refresh_dropped", "pending": tmp_path, "keysfile": keysfile_path}).to_string());
+            forget_keychain_entry(keychain_reference(&tmp_path), keysfile_path);
+            fs::remove_file(&tmp_path).expect("Unable to remove pending refresh !");
+        }
+    }
+    KeyFile::load(keysfile_path)
+}
+
+/// Re-randomizes all shares without changing the public key or the party set: every party reshares
+/// its share to the same t of n, which adds a fresh sharing of zero to the key. Shares stolen before
+/// and after a refresh can't be combined. The new share is written next to the keys file first and
+/// replaces it only after every party confirmed it saved its new share. A party stopped before the
+/// replace keeps the new share pending, the next refresh resolves it first
+pub fn refresh(addr: &String, keysfile_path: &str, params: &Params, room: &str) {
+    let parties = params.parties.parse::<u16>().unwrap();
+    let _lock = KeysFileLock::acquire(keysfile_path, "refresh");
+    let party_id = KeyFile::load(keysfile_path).party_id;
+    let keyfile = reconcile_refresh(addr, keysfile_path, party_id, parties, room);
+    let old_share = OldShare { keyfile, params: params.clone() };
+    let result = run_reshare(addr, &format!("refresh-{}", room), parties, Some(old_share), Some(party_id), params);
+    let keyfile = result.keyfile.unwrap();
+    let tmp_path = pending_refresh_path(keysfile_path);
+    keyfile.save(&tmp_path);
+    if KeyFile::load(&tmp_path).to_json() != keyfile.to_json() {
+        panic!("New share did not load back from {}, keys file left unchanged", tmp_path);
+    }
+
+    // commit round: nobody switches to the new share unless all parties hold theirs
+    let client = new_client();
+    let delay = time::Duration::from_millis(25);
+    let room_id = sha256_digest(format!("refresh-commit-{}", room).as_bytes());
+    let (party_signup, total_parties) = signup(addr, &client, 0, room_id, party_id, parties).unwrap();
+    let commitment = vss_hash(&keyfile);
+    assert!(broadcast(
+        &addr,
+        &client,
+        party_signup.number,
+        "refresh-commit",
+        serde_json::to_string(&commitment).unwrap(),
+        party_signup.uuid.clone(),
+    )
+    .is_ok());
+    let commit_ans_vec = poll_for_broadcasts(
+        &addr,
+        &client,
+        party_signup.number,
+        total_parties,
+        delay,
+        "refresh-commit",
+        party_signup.uuid.clone(),
+    );
+    if commit_ans_vec.iter().any(|m| serde_json::from_str::<String>(m).unwrap() != commitment) {
+        panic!("Parties saved different sharings, keys file left unchanged, the next refresh resolves {}", tmp_path);
+    }
+    let replaced_entry = keychain_reference(keysfile_path);
+    fs::rename(&tmp_path, keysfile_path).expect("Unable to replace keys file !");
+    forget_keychain_entry(replaced_entry, keysfile_path);
+
+    println!(
+        "{}",
+        json!({
+            "status": "refresh_done",
+            "party_id": party_id,
+            "parties": result.new_parties,
+            "keysfile": keysfile_path,
+            "x": result.y_sum.x_coor(),
+            "y": result.y_sum.y_coor(),
+        })
+        .to_string()
+    );
+}
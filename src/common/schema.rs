@@ -0,0 +1,297 @@
+use serde_json::{json, Value};
+
+const SCHEMA_DRAFT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// Names accepted by schema dump, in the order of schemas()
+pub const SCHEMA_NAMES: [&str; 12] = [
+    "keys-file",
+    "signature",
+    "pubkey",
+    "presignature",
+    "manager-set",
+    "manager-get",
+    "manager-get-response",
+    "manager-signupkeygen",
+    "manager-signupsign",
+    "manager-signupsign-response",
+    "manager-room-request",
+    "manager-observe-response",
+];
+
+// Shapes shared by several schemas, as serialized by curv and the manager
+fn definitions() -> Value {
+    json!({
+        "hex": {"type": "string", "pattern": "^[0-9a-f]*$"},
+        "scalar": {"$ref": "#/$defs/hex", "description": "secp256k1 scalar, big endian hex"},
+        "point": {
+            "type": "object",
+            "description": "secp256k1 point, affine coordinates in hex",
+            "required": ["x", "y"],
+            "properties": {"x": {"$ref": "#/$defs/hex"}, "y": {"$ref": "#/$defs/hex"}}
+        },
+        "manager_error": {
+            "type": "object",
+            "required": ["error"],
+            "properties": {"error": {"type": "string"}}
+        }
+    })
+}
+
+// Manager answers are serialized Rust results: {"Ok": value} or {"Err": error}
+fn manager_result(ok: Value, err: Value) -> Value {
+    json!({
+        "oneOf": [
+            {"type": "object", "required": ["Ok"], "properties": {"Ok": ok}, "additionalProperties": false},
+            {"type": "object", "required": ["Err"], "properties": {"Err": err}, "additionalProperties": false}
+        ]
+    })
+}
+
+fn object(required: &[&str], properties: Value) -> Value {
+    json!({
+        "type": "object",
+        "required": required,
+        "properties": properties,
+    })
+}
+
+fn schema(name: &str, description: &str, body: Value) -> Value {
+    let mut schema = json!({
+        "$schema": SCHEMA_DRAFT,
+        "$id": format!("tss-cli/{}", name),
+        "title": name,
+        "description": description,
+        "$defs": definitions(),
+    });
+    for (key, value) in body.as_object().unwrap() {
+        schema[key] = value.clone();
+    }
+    schema
+}
+
+/// JSON schemas of files and outputs integrators rely on, by name
+pub fn schemas() -> Vec<(&'static str, Value)> {
+    vec![
+        ("keys-file", schema(
+            "keys-file",
+            "Keys file v6, older versions are upgraded by keys-version --upgrade. Unknown fields are rejected. \
+             Contains the secret share, never publish it",
+            json!({
+                "type": "object",
+                "required": ["format", "version", "curve", "protocol", "data", "integrity"],
+                "additionalProperties": false,
+                "properties": {
+                    "format": {"const": "tss-cli-keys"},
+                    "version": {"const": 6},
+                    "curve": {"const": "secp256k1"},
+                    "protocol": {"const": "gg18"},
+                    "integrity": object(&["alg", "key", "mac"], json!({
+                        "alg": {"enum": ["hmac-sha256", "sha256"]},
+                        "key": {"type": "string", "description": "file:<key id> for an integrity key, passphrase, or none for a checksum"},
+                        "mac": {"$ref": "#/$defs/hex"}
+                    })),
+                    "data": {
+                        "type": "object",
+                        "required": ["party_keys", "shared_keys", "party_id", "vss_scheme_vec", "paillier_key_vec", "y_sum", "metadata"],
+                        "additionalProperties": false,
+                        "properties": {
+                            "party_keys": object(&["u_i", "y_i", "dk", "ek", "party_index"], json!({
+                                "u_i": {"$ref": "#/$defs/scalar"},
+                                "y_i": {"$ref": "#/$defs/point"},
+                                "dk": {"type": "object", "description": "Paillier decryption key"},
+                                "ek": {"type": "object", "description": "Paillier encryption key"},
+                                "party_index": {"type": "integer"}
+                            })),
+                            "shared_keys": object(&["y", "x_i"], json!({
+                                "y": {"$ref": "#/$defs/point"},
+                                "x_i": {"$ref": "#/$defs/scalar"}
+                            })),
+                            "party_id": {"type": "integer", "minimum": 1},
+                            "vss_scheme_vec": {"type": "array", "description": "Feldman VSS schemes, one per party", "items": object(
+                                &["parameters", "commitments"],
+                                json!({
+                                    "parameters": object(&["threshold", "share_count"], json!({
+                                        "threshold": {"type": "integer"},
+                                        "share_count": {"type": "integer"}
+                                    })),
+                                    "commitments": {"type": "array", "items": {"$ref": "#/$defs/point"}}
+                                })
+                            )},
+                            "paillier_key_vec": {"type": "array", "description": "Paillier encryption keys, one per party", "items": {"type": "object"}},
+                            "y_sum": {"$ref": "#/$defs/point"},
+                            "chain_code": {"$ref": "#/$defs/hex", "description": "HD chain code fixed at keygen, absent for the default"},
+                            "metadata": object(&["curve", "threshold", "parties", "fingerprint"], json!({
+                                "curve": {"const": "secp256k1"},
+                                "threshold": {"type": "integer", "description": "t of the t+1 of n key"},
+                                "parties": {"type": "integer"},
+                                "fingerprint": {"type": "string", "description": "First 8 bytes of sha256 of the compressed public key, hex"},
+                                "chain_code_fingerprint": {"type": "string", "description": "First 8 bytes of sha256 of the chain code, hex"},
+                                "canary": object(&["path", "r", "s", "recid", "timestamp"], json!({
+                                    "path": {"type": "string", "description": "Canary derivation path"},
+                                    "r": {"$ref": "#/$defs/hex"},
+                                    "s": {"$ref": "#/$defs/hex"},
+                                    "recid": {"type": "integer", "minimum": 0, "maximum": 3},
+                                    "timestamp": {"type": "integer", "description": "Unix time of the canary signature"}
+                                })),
+                                "share_points": {
+                                    "type": "array",
+                                    "description": "Points the shares are evaluated at, one per party in decimal, absent for the party ids 1..n",
+                                    "items": {"type": "string", "pattern": "^[0-9]+$"}
+                                }
+                            }))
+                        }
+                    }
+                }
+            }),
+        )),
+        ("signature", schema(
+            "signature",
+            "Output of sign and sign-online",
+            object(&["r", "s", "status", "recid", "v", "x", "y", "msg_int"], json!({
+                "r": {"$ref": "#/$defs/hex"},
+                "s": {"$ref": "#/$defs/hex"},
+                "status": {"const": "signature_ready"},
+                "recid": {"type": "integer", "minimum": 0, "maximum": 3},
+                "v": {"type": "integer", "minimum": 27, "maximum": 30},
+                "x": {"$ref": "#/$defs/hex"},
+                "y": {"$ref": "#/$defs/hex"},
+                "msg_int": {"description": "Signed digest as integer"},
+                "context": {"type": "string"},
+                "path": {"type": "string", "description": "Derivation path, sign-online only"}
+            })),
+        )),
+        ("pubkey", schema(
+            "pubkey",
+            "Output of pubkey",
+            object(&["x", "y", "path"], json!({
+                "x": {"$ref": "#/$defs/hex"},
+                "y": {"$ref": "#/$defs/hex"},
+                "path": {"type": "string"},
+                "taproot_output_key": {"$ref": "#/$defs/hex"},
+                "taproot_output_key_parity": {"enum": [0, 1]}
+            })),
+        )),
+        ("presignature", schema(
+            "presignature",
+            "Presignature written by presign, before it is sealed like the keys file. Contains secret material, \
+             use at most once",
+            object(&["id", "party_num", "total_parties", "path", "k_i", "sigma_i", "R", "y_sum"], json!({
+                "id": {"type": "string"},
+                "party_num": {"type": "integer"},
+                "total_parties": {"type": "integer"},
+                "party_id": {"type": "integer"},
+                "keysfile": {"type": "string"},
+                "key_fingerprint": {"type": "string"},
+                "context": {"type": "string"},
+                "path": {"type": "string"},
+                "k_i": {"$ref": "#/$defs/scalar"},
+                "sigma_i": {"$ref": "#/$defs/scalar"},
+                "R": {"$ref": "#/$defs/point"},
+                "y_sum": {"$ref": "#/$defs/point"}
+            })),
+        )),
+        ("manager-set", schema(
+            "manager-set",
+            "Request body of POST /set, answered with {\"Ok\": null}. Clients store round messages as a JSON \
+             envelope {format: \"tss-cli-round\", version: 1, round, from, payload} in value",
+            object(&["key", "value"], json!({"key": {"type": "string"}, "value": {"type": "string"}})),
+        )),
+        ("manager-get", schema(
+            "manager-get",
+            "Request body of POST /get and POST /signed",
+            object(&["key"], json!({"key": {"type": "string"}})),
+        )),
+        ("manager-get-response", schema(
+            "manager-get-response",
+            "Answer of POST /get",
+            manager_result(
+                object(&["key", "value"], json!({"key": {"type": "string"}, "value": {"type": "string"}})),
+                json!({"$ref": "#/$defs/manager_error"}),
+            ),
+        )),
+        ("manager-signupkeygen", schema(
+            "manager-signupkeygen",
+            "Request body of POST /signupkeygen",
+            object(&["parties", "threshold"], json!({
+                "parties": {"type": "string"},
+                "threshold": {"type": "string"}
+            })),
+        )),
+        ("manager-signupsign", schema(
+            "manager-signupsign",
+            "Request body of POST /signupsign",
+            object(&["threshold", "room_id", "party_number", "party_uuid"], json!({
+                "threshold": {"type": "integer"},
+                "room_id": {"type": "string"},
+                "party_number": {"type": "integer"},
+                "party_uuid": {"type": "string"},
+                "room_size": {"type": "integer"}
+            })),
+        )),
+        ("manager-signupsign-response", schema(
+            "manager-signupsign-response",
+            "Answer of POST /signupsign",
+            manager_result(
+                object(&["party_order", "party_uuid", "room_uuid", "total_joined"], json!({
+                    "party_order": {"type": "integer"},
+                    "party_uuid": {"type": "string"},
+                    "room_uuid": {"type": "string"},
+                    "total_joined": {"type": "integer"},
+                    "server_time": {"type": "integer"}
+                })),
+                json!({"$ref": "#/$defs/manager_error"}),
+            ),
+        )),
+        ("manager-room-request", schema(
+            "manager-room-request",
+            "Request body of POST /observe",
+            object(&["room_id"], json!({"room_id": {"type": "string"}})),
+        )),
+        ("manager-dropout", schema(
+            "manager-dropout",
+            "Request body of POST /dropout, answered with the missing parties of the first report",
+            object(&["room_uuid", "missing"], json!({
+                "room_uuid": {"type": "string"},
+                "missing": {"type": "array", "items": {"type": "integer"}}
+            })),
+        )),
+        ("manager-record-signed", schema(
+            "manager-record-signed",
+            "Request body of POST /record-signed, key is the sha256 of key fingerprint, path and digest",
+            object(&["key", "r"], json!({"key": {"type": "string"}, "r": {"$ref": "#/$defs/hex"}})),
+        )),
+        ("manager-observe-response", schema(
+            "manager-observe-response",
+            "Answer of POST /observe",
+            manager_result(
+                object(&["room_id", "last_stage", "room_size", "total_joined", "messages"], json!({
+                    "room_id": {"type": "string"},
+                    "last_stage": {"type": "string"},
+                    "room_size": {"type": "integer"},
+                    "total_joined": {"type": "integer"},
+                    "messages": {"type": "array", "items": object(&["key", "digest", "size", "timestamp"], json!({
+                        "key": {"type": "string"},
+                        "digest": {"$ref": "#/$defs/hex"},
+                        "size": {"type": "integer"},
+                        "timestamp": {"type": "integer"}
+                    }))}
+                })),
+                json!({"$ref": "#/$defs/manager_error"}),
+            ),
+        )),
+    ]
+}
+
+/// Prints one schema by name, or all of them keyed by name
+pub fn dump(name: Option<&str>) {
+    let schemas = schemas();
+    let output = match name {
+        Some(name) => schemas
+            .into_iter()
+            .find(|(schema_name, _)| *schema_name == name)
+            .map(|(_, schema)| schema)
+            .unwrap_or_else(|| panic!("Unknown schema: {}", name)),
+        None => Value::Object(schemas.into_iter().map(|(name, schema)| (name.to_string(), schema)).collect()),
+    };
+    println!("{}", serde_json::to_string_pretty(&output).unwrap());
+}
@@ -0,0 +1,183 @@
+use std::fs;
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use curv::{arithmetic::traits::Converter, elliptic::curves::secp256_k1::FE, elliptic::curves::traits::ECScalar, BigInt};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::common::encoding::to_32_bytes;
+use crate::common::keyfile::KeyFile;
+use crate::common::{check_sig, hd_keys, keygen, manager, new_client, options, post_json, signer, Params};
+
+/// One ceremony of the matrix: keygen of t/n, then signing with t+1 parties at path.
+/// With abort the last signer never joins and every party must fail instead of signing
+struct Case {
+    threshold: u16,
+    parties: u16,
+    path: &'static str,
+    abort: bool,
+}
+
+impl Case {
+    fn name(&self) -> String {
+        let path = if self.path.is_empty() { "root" } else { self.path };
+        let abort = if self.abort { " abort" } else { "" };
+        format!("secp256k1 {}/{} {}{}", self.threshold, self.parties, path, abort)
+    }
+}
+
+fn cases(quick: bool) -> Vec<Case> {
+    let mut cases = vec![
+        Case { threshold: 1, parties: 2, path: "", abort: false },
+        Case { threshold: 1, parties: 2, path: "0/1/2", abort: false },
+    ];
+    if !quick {
+        cases.extend(vec![
+            Case { threshold: 1, parties: 3, path: "", abort: false },
+            Case { threshold: 2, parties: 3, path: "44/0/7", abort: false },
+            Case { threshold: 2, parties: 4, path: "", abort: false },
+            Case { threshold: 1, parties: 3, path: "", abort: true },
+        ]);
+    }
+    cases
+}
+
+fn panic_message(e: Box<dyn std::any::Any + Send>) -> String {
+    e.downcast_ref::<String>()
+        .cloned()
+        .or_else(|| e.downcast_ref::<&str>().map(|s| s.to_string()))
+        .unwrap_or("party failed".to_string())
+}
+
+// Runs one thread per party number and collects their results, the first failure is returned as error
+fn run_parties<T, F>(party_nums: Vec<u16>, party: F) -> Result<Vec<T>, String>
+where
+    T: Send + 'static,
+    F: Fn(u16) -> T + Send + Sync + 'static,
+{
+    let party = Arc::new(party);
+    let handles: Vec<_> = party_nums
+        .into_iter()
+        .map(|party_num| {
+            let party = party.clone();
+            thread::spawn(move || party(party_num))
+        })
+        .collect();
+    let results: Vec<Result<T, String>> = handles.into_iter().map(|h| h.join().map_err(panic_message)).collect();
+    results.into_iter().collect()
+}
+
+fn run_case(addr: &String, dir: &String, case: &Case) -> Result<(), String> {
+    let case_dir = format!("{}/{}-{}-{}", dir, case.threshold, case.parties, Uuid::new_v4());
+    fs::create_dir_all(&case_dir).expect("Unable to create selftest directory !");
+    let (threshold, parties) = (case.threshold, case.parties);
+
+    let (addr_c, dir_c) = (addr.clone(), case_dir.clone());
+    run_parties((1..=parties).collect(), move |i| {
+        let params = vec![threshold.to_string(), parties.to_string()];
+        let params: Vec<&str> = params.iter().map(|p| p.as_str()).collect();
+        keygen::run_keygen(&addr_c, &format!("{}/keys{}.store", dir_c, i), &params, false);
+    })?;
+
+    let random: FE = ECScalar::new_random();
+    let message = to_32_bytes(&random.to_big_int());
+    let signers = threshold + 1;
+    let joining = if case.abort { signers - 1 } else { signers };
+    let (addr_c, dir_c, path) = (addr.clone(), case_dir.clone(), case.path.to_string());
+    let message_c = message.clone();
+    let result = run_parties((1..=joining).collect(), move |i| {
+        let keyfile = KeyFile::load(&format!("{}/keys{}.store", dir_c, i));
+        let (f_l_new, y_sum) = match path.is_empty() {
+            true => (FE::zero(), keyfile.y_sum),
+            false => {
+                let path_vector: Vec<BigInt> = hd_keys::parse_path(path).expect("Invalid selftest path");
+                let (y_child, f_l_new) = hd_keys::get_hd_key(&keyfile.y_sum, path_vector);
+                (f_l_new, y_child)
+            }
+        };
+        let params = Params { threshold: threshold.to_string(), parties: parties.to_string() };
+        let sig = signer::sign(
+            addr_c.clone(),
+            keyfile.party_keys,
+            keyfile.shared_keys,
+            keyfile.party_id,
+            &mut keyfile.vss_scheme_vec.clone(),
+            keyfile.paillier_key_vec,
+            &y_sum,
+            &params,
+            &message_c,
+            &f_l_new,
+            !path.is_empty(),
+            signers,
+            "selftest",
+        );
+        check_sig(&sig.r, &sig.s, &BigInt::from_bytes(&message_c), &y_sum);
+        (sig.r.to_big_int(), sig.s.to_big_int())
+    });
+    fs::remove_dir_all(&case_dir).ok();
+
+    match (result, case.abort) {
+        (Ok(signatures), false) if signatures.iter().all(|sig| *sig == signatures[0]) => Ok(()),
+        (Ok(_), false) => Err("parties produced different signatures".to_string()),
+        (Err(e), false) => Err(e),
+        (Ok(_), true) => Err("signing succeeded without a quorum".to_string()),
+        (Err(_), true) => Ok(()),
+    }
+}
+
+// In-process manager on a free local port, returns its address once it answers
+fn start_manager() -> String {
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    std::env::set_var("ROCKET_ADDRESS", "127.0.0.1");
+    std::env::set_var("ROCKET_PORT", port.to_string());
+    std::env::set_var("ROCKET_LOG_LEVEL", "off");
+    thread::spawn(|| manager::run_manager().expect("Selftest manager failed"));
+
+    let addr = format!("http://127.0.0.1:{}", port);
+    let client = new_client();
+    let started = Instant::now();
+    while post_json::<(), u64>(&addr, &client, "time", ()).is_err() {
+        if started.elapsed().as_secs() > 10 {
+            panic!("Selftest manager did not start");
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    addr
+}
+
+/// Runs the ceremony matrix against an in-process manager and reports pass/fail per case.
+/// Returns whether every case passed
+pub fn run_selftest(quick: bool) -> bool {
+    let addr = start_manager();
+    let dir = format!("{}/tss-cli-selftest-{}", std::env::temp_dir().display(), Uuid::new_v4());
+    let options = options::get();
+    let mut report: Vec<Value> = Vec::new();
+    for case in cases(quick) {
+        // Abort cases give up after a few seconds instead of the signup timeout in use
+        let signup_timeout = if case.abort { 5 } else { options.signup_timeout };
+        options::init(options::Options { signup_timeout, ..options.clone() });
+        let started = Instant::now();
+        let result = run_case(&addr, &dir, &case);
+        report.push(json!({
+            "case": case.name(),
+            "passed": result.is_ok(),
+            "error": result.err(),
+            "ms": started.elapsed().as_millis() as u64,
+        }));
+    }
+    fs::remove_dir_all(&dir).ok();
+
+    let passed = report.iter().all(|case| case["passed"] == json!(true));
+    println!(
+        "{}",
+        json!({
+            "status": if passed { "selftest_passed" } else { "selftest_failed" },
+            "cases": report,
+        })
+        .to_string()
+    );
+    passed
+}
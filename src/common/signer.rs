@@ -6,7 +6,7 @@ extern crate reqwest;
 extern crate serde_json;
 
 use std::{thread, time};
-use std::time::Duration;
+use std::sync::Mutex;
 
 use curv::cryptographic_primitives::proofs::sigma_correct_homomorphic_elgamal_enc::HomoELGamalProof;
 use curv::cryptographic_primitives::proofs::sigma_dlog::DLogProof;
@@ -20,11 +20,15 @@ use curv::{
 use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2018::party_i::*;
 use multi_party_ecdsa::utilities::mta::*;
 use paillier::*;
+use rayon::prelude::*;
 use reqwest::blocking::Client;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
-use crate::common::{broadcast, poll_for_broadcasts, poll_for_p2p, sendp2p, Params, PartySignup, PartySignupRequestBody, sha256_digest, SigningPartySignup, ManagerError};
+use crate::common::keygen::Outgoing;
+use crate::common::options;
+use crate::common::{new_client, broadcast, try_poll_for_broadcasts, try_poll_for_p2p, sendp2p, DropoutReport, Params, PartySignup, PartySignupRequestBody, sha256_digest, SigningPartySignup, ManagerError, post_json, clock_skew, recovery_id, CLOCK_SKEW_WARNING};
 
 #[derive(Hash, PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
 pub struct TupleKey {
@@ -34,6 +38,126 @@ pub struct TupleKey {
     pub fourth: String,
 }
 
+/// Signing room id, the message digest.
+/// Context label is bound into the room, parties using different labels never meet
+pub fn room_id(message: &[u8], context: &str) -> String {
+    match context.is_empty() {
+        true => sha256_digest(message),
+        false => sha256_digest(&[message, &b"\0"[..], context.as_bytes()].concat()),
+    }
+}
+
+/// Worker pool for the per-counterparty MtA work, --threads threads, one per core when unset
+fn mta_thread_pool() -> rayon::ThreadPool {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(options::get().threads)
+        .build()
+        .expect("Unable to create worker pool")
+}
+
+// --paranoid: a secret x is split into x + ρ and −ρ with a fresh random ρ. Each part is uniformly random and
+// independent of x, and the parts sum back to x
+fn blind(x: &FE) -> (FE, FE) {
+    let rho: FE = ECScalar::new_random();
+    let minus_rho: FE = ECScalar::from(&(FE::q() - rho.to_big_int()));
+    (*x + rho, minus_rho)
+}
+
+// k·x for a secret x, under --paranoid as k·(x + ρ) + k·(−ρ) so the variable time multiplication never sees x
+fn mul_secret(k: &FE, x: &FE) -> FE {
+    match options::get().paranoid {
+        true => {
+            let (x_blinded, minus_rho) = blind(x);
+            *k * x_blinded + *k * minus_rho
+        }
+        false => *k * x,
+    }
+}
+
+// phase5_local_sig of the library, with s_i = m·k_i + r·sigma_i computed on blinded k_i and sigma_i under --paranoid
+fn local_signature(k_i: &FE, message_bn: &BigInt, R: &GE, sigma: &FE, y_sum: &GE) -> LocalSignature {
+    if !options::get().paranoid {
+        return LocalSignature::phase5_local_sig(k_i, message_bn, R, sigma, y_sum);
+    }
+    let m: FE = ECScalar::from(message_bn);
+    let r: FE = ECScalar::from(&R.x_coor().unwrap().modulus(&FE::q()));
+    LocalSignature {
+        l_i: ECScalar::new_random(),
+        rho_i: ECScalar::new_random(),
+        R: *R,
+        s_i: mul_secret(&m, k_i) + mul_secret(&r, sigma),
+        m: message_bn.clone(),
+        y: *y_sum,
+    }
+}
+
+// Round 2 of presign: the MtA replies to a party's k_i for gamma_i and for w_i. Under --paranoid w_i goes through
+// the MtA in its two blinded parts, the receiver adds both alphas and checks the parts' g^b add up to g^w_i
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum MtaReplies {
+    Blinded(MessageB, MessageB, MessageB),
+    Plain(MessageB, MessageB),
+}
+
+// output_signature always returns the low-s form of BIP-62 and EIP-2. With --no-normalize s is put back to
+// the sum of the parties' s_i
+fn finish_signature(sig: SignatureRecid, local_sig: &LocalSignature, s_i_vec: &[FE], y_sum: &GE, message_bn: &BigInt) -> SignatureRecid {
+    let s = s_i_vec.iter().fold(local_sig.s_i, |acc, s_j| acc + *s_j);
+    normalize_signature(sig.r, s, message_bn, y_sum, !options::get().no_normalize)
+}
+
+/// Signature with s in the low-s form of BIP-62 and EIP-2 when normalize is set, as given otherwise, and the
+/// recovery id of the s returned
+pub fn normalize_signature(r: FE, s: FE, message_bn: &BigInt, y_sum: &GE, normalize: bool) -> SignatureRecid {
+    let s_bn = s.to_big_int();
+    let s = match normalize && s_bn > (&FE::q() >> 1) {
+        true => ECScalar::from(&(FE::q() - s_bn)),
+        false => s,
+    };
+    SignatureRecid { r, s, recid: recovery_id(&r, &s, message_bn, y_sum) }
+}
+
+// Party ids of the last signing room this process took part in, read by healthcheck
+static LAST_SIGNERS: Mutex<Vec<u16>> = Mutex::new(Vec::new());
+
+pub fn last_signers() -> Vec<u16> {
+    LAST_SIGNERS.lock().unwrap().clone()
+}
+
+/// Parties that did not respond in time during a signing round, numbered in the room, party_num is this party
+pub struct Dropout {
+    pub room_uuid: String,
+    pub party_num: u16,
+    pub total_parties: u16,
+    pub missing: Vec<u16>,
+}
+
+/// Offline part of a signature: everything up to R, independent of the message.
+/// Must be used for at most one signature, reusing it leaks the key share.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Presignature {
+    pub id: String, // uuid of the presign room, shared by all parties
+    pub party_num: u16,
+    pub total_parties: u16,
+    /// Party id of the keys file it was made with, asked for by the approval service
+    #[serde(default)]
+    pub party_id: u16,
+    /// Keys file and fingerprint of its root key, for the duplicate guard of sign-online
+    #[serde(default)]
+    pub keysfile: String,
+    #[serde(default)]
+    pub key_fingerprint: String,
+    /// Context label of the presign room, sign-online signs under the same one
+    #[serde(default)]
+    pub context: String,
+    pub path: String,
+    pub k_i: FE,
+    pub sigma_i: FE,
+    pub R: GE,
+    pub y_sum: GE,
+}
+
 pub fn sign(
     addr: String,
     party_keys: Keys,
@@ -46,603 +170,890 @@ pub fn sign(
     message: &[u8],
     f_l_new: &FE,
     sign_at_path: bool,
-) {
-    let client = Client::new();
+    signers: u16,
+    context: &str,
+) -> SignatureRecid {
+    let THRESHOLD = params.threshold.parse::<u16>().unwrap();
+    with_dropout_retry(&addr, THRESHOLD, room_id(message, context), signers, |room_id, room_size| {
+        sign_in_room(
+            addr.clone(),
+            party_keys.clone(),
+            shared_keys.clone(),
+            party_id,
+            &mut vss_scheme_vec.clone(),
+            paillier_key_vector.clone(),
+            y_sum,
+            params,
+            message,
+            f_l_new,
+            sign_at_path,
+            room_id,
+            room_size,
+        )
+    })
+}
+
+/// Runs the offline signing rounds in a room agreed by label and bound to the context label like the room of sign,
+/// see sign_online for the online part
+pub fn presign(
+    addr: String,
+    party_keys: Keys,
+    shared_keys: SharedKeys,
+    party_id: u16,
+    vss_scheme_vec: &mut Vec<VerifiableSS<GE>>,
+    paillier_key_vector: Vec<EncryptionKey>,
+    y_sum: &GE,
+    params: &Params,
+    label: &str,
+    f_l_new: &FE,
+    sign_at_path: bool,
+    signers: u16,
+    context: &str,
+) -> Presignature {
+    let THRESHOLD = params.threshold.parse::<u16>().unwrap();
+    let room_id = room_id(format!("presign-{}", label).as_bytes(), context);
+    let mut presig = with_dropout_retry(&addr, THRESHOLD, room_id, signers, |room_id, room_size| {
+        presign_in_room(
+            addr.clone(),
+            party_keys.clone(),
+            shared_keys.clone(),
+            party_id,
+            &mut vss_scheme_vec.clone(),
+            paillier_key_vector.clone(),
+            y_sum,
+            params,
+            f_l_new,
+            sign_at_path,
+            room_id,
+            room_size,
+        )
+    });
+    presig.context = context.to_string();
+    presig
+}
+
+// Parties that dropped out of a room as the manager records them: the first party to report a dropout fixes
+// the set, so parties that saw different rounds time out still restart with the same signers
+fn agreed_missing(addr: &String, room_uuid: &str, missing: Vec<u16>) -> Vec<u16> {
+    let report = DropoutReport { room_uuid: room_uuid.to_string(), missing };
+    match post_json::<_, Result<Vec<u16>, ManagerError>>(addr, &new_client(), "dropout", report) {
+        Ok(Ok(missing)) => missing,
+        Ok(Err(ManagerError { error })) => panic!("Manager did not record the dropout: {}", error),
+        Err(e) => panic!("Manager did not record the dropout: {}", e),
+    }
+}
+
+fn with_dropout_retry<T, F>(addr: &String, threshold: u16, room_id: String, signers: u16, mut run_in_room: F) -> T
+where
+    F: FnMut(String, u16) -> Result<T, Dropout>,
+{
+    let mut room_size = signers;
+    let mut attempt = 0;
+
+    loop {
+        // Every retry happens in a fresh room, so the signer set is derived again from the survivors
+        let attempt_room_id = match attempt {
+            0 => room_id.clone(),
+            _ => format!("{}-{}", room_id, attempt),
+        };
+        match run_in_room(attempt_room_id, room_size) {
+            Ok(result) => return result,
+            Err(Dropout { room_uuid, party_num, total_parties, missing }) => {
+                let missing = agreed_missing(addr, &room_uuid, missing);
+                if missing.contains(&party_num) {
+                    panic!("Other parties saw this party drop out of the room, the signing restarts without it");
+                }
+                let survivors = total_parties - missing.len() as u16;
+                if survivors <= threshold {
+                    panic!(
+                        "Parties {:?} dropped out, only {:?} of required {:?} signers left",
+                        missing, survivors, threshold + 1
+                    );
+                }
+                let debug = json!({
+                    "message": "Parties dropped out, restarting signing with the surviving quorum",
+                    "missing": missing,
+                    "survivors": survivors,
+                    "attempt": attempt + 1,
+                });
+                println!("{}", serde_json::to_string_pretty(&debug).unwrap());
+                room_size = survivors;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+fn sign_in_room(
+    addr: String,
+    party_keys: Keys,
+    shared_keys: SharedKeys,
+    party_id: u16,
+    vss_scheme_vec: &mut Vec<VerifiableSS<GE>>,
+    paillier_key_vector: Vec<EncryptionKey>,
+    y_sum: &GE,
+    params: &Params,
+    message: &[u8],
+    f_l_new: &FE,
+    sign_at_path: bool,
+    room_id: String,
+    room_size: u16,
+) -> Result<SignatureRecid, Dropout> {
+    let presig = presign_in_room(
+        addr.clone(),
+        party_keys,
+        shared_keys,
+        party_id,
+        vss_scheme_vec,
+        paillier_key_vector,
+        y_sum,
+        params,
+        f_l_new,
+        sign_at_path,
+        room_id,
+        room_size,
+    )?;
+    phase5(&addr, &presig, message)
+}
+
+// Sends this party's message of a round and collects the other parties' messages of it, in party order.
+// A party missing from the round is reported as a dropout of the room
+fn exchange(
+    addr: &String,
+    client: &Client,
+    party_num: u16,
+    total_parties: u16,
+    uuid: &str,
+    round: &str,
+    outgoing: Outgoing,
+) -> Result<Vec<String>, Dropout> {
     let delay = time::Duration::from_millis(25);
+    let incoming = match outgoing {
+        Outgoing::Broadcast(message) => {
+            assert!(broadcast(addr, client, party_num, round, message, uuid.to_string()).is_ok());
+            try_poll_for_broadcasts(addr, client, party_num, total_parties, delay, round, uuid.to_string())
+        }
+        Outgoing::P2p(messages) => {
+            for (to, message) in messages {
+                assert!(sendp2p(addr, client, party_num, to, round, message, uuid.to_string()).is_ok());
+            }
+            try_poll_for_p2p(addr, client, party_num, total_parties, delay, round, uuid.to_string())
+        }
+    };
+    incoming.map_err(|missing| Dropout { room_uuid: uuid.to_string(), party_num, total_parties, missing })
+}
+
+// Phase 5 of GG18 on a presignature, see OnlineState. The rounds run under the presignature id, the id of its
+// presign room
+fn phase5(addr: &String, presig: &Presignature, message: &[u8]) -> Result<SignatureRecid, Dropout> {
+    let client = new_client();
+    let mut state = OnlineState::start(presig, message);
+    loop {
+        let round = state.round();
+        let incoming = exchange(addr, &client, presig.party_num, presig.total_parties, &presig.id, round, state.outgoing())?;
+        match state.next(&incoming) {
+            OnlineTransition::Next(next) => state = next,
+            OnlineTransition::Done(sig) => return Ok(sig),
+        }
+    }
+}
+
+fn presign_in_room(
+    addr: String,
+    party_keys: Keys,
+    shared_keys: SharedKeys,
+    party_id: u16,
+    vss_scheme_vec: &mut Vec<VerifiableSS<GE>>,
+    paillier_key_vector: Vec<EncryptionKey>,
+    y_sum: &GE,
+    params: &Params,
+    f_l_new: &FE,
+    sign_at_path: bool,
+    room_id: String,
+    room_size: u16,
+) -> Result<Presignature, Dropout> {
+    let client = new_client();
     let THRESHOLD = params.threshold.parse::<u16>().unwrap();
-    let room_id = sha256_digest(message);
 
     // Signup
-    let (party_num_int, uuid, total_parties) = match signup(&addr, &client, THRESHOLD, room_id, party_id).unwrap() {
+    let (party_num_int, uuid, total_parties) = match signup(&addr, &client, THRESHOLD, room_id, party_id, room_size).unwrap() {
         (PartySignup { number, uuid }, total_parties) => (number, uuid, total_parties),
     };
 
     let debug = json!({"manager_addr": &addr, "party_num": party_num_int, "uuid": uuid});
     println!("{}", serde_json::to_string_pretty(&debug).unwrap());
 
-    // round 0: collect signers IDs
-    assert!(broadcast(
-        &addr,
-        &client,
-        party_num_int,
-        "round0",
-        serde_json::to_string(&party_id).unwrap(),
-        uuid.clone(),
-    )
-    .is_ok());
-
-    let round0_ans_vec = poll_for_broadcasts(
-        &addr,
-        &client,
-        party_num_int,
-        total_parties,
-        delay,
-        "round0",
-        uuid.clone(),
+    let setup = PresignSetup { id: uuid.clone(), party_num: party_num_int, total_parties, party_id, y_sum: *y_sum };
+    let mut state = PresignState::start(
+        setup,
+        party_keys,
+        shared_keys,
+        vss_scheme_vec.clone(),
+        paillier_key_vector,
+        f_l_new,
+        sign_at_path,
     );
-    let mut j = 0;
-    let mut signers_vec: Vec<usize> = Vec::new();
-    for i in 1..=total_parties {
-        if i == party_num_int {
-            signers_vec.push((party_id - 1) as usize);
-        } else {
-            let signer_j: u16 = serde_json::from_str(&round0_ans_vec[j]).unwrap();
-            signers_vec.push((signer_j - 1) as usize);
-            j = j + 1;
+    loop {
+        let round = state.round();
+        let incoming = exchange(&addr, &client, party_num_int, total_parties, &uuid, round, state.outgoing())?;
+        match state.next(&incoming) {
+            PresignTransition::Next(next) => state = next,
+            PresignTransition::Done(presig) => return Ok(presig),
+        }
+        if let PresignState::Commit(round) = &state {
+            *LAST_SIGNERS.lock().unwrap() = round.signers_vec.iter().map(|index| *index as u16 + 1).collect();
         }
     }
+}
 
-    if sign_at_path == true {
-        // optimize!
-        let g: GE = ECPoint::generator();
-        // apply on first commitment for leader (leader is party with num=1)
-        let com_zero_new = vss_scheme_vec[0].commitments[0] + g * f_l_new;
-        // println!("old zero: {:?}, new zero: {:?}", vss_scheme_vec[0].commitments[0], com_zero_new);
-        // get iterator of all commitments and skip first zero commitment
-        let mut com_iter_unchanged = vss_scheme_vec[0].commitments.iter();
-        com_iter_unchanged.next().unwrap();
-        // iterate commitments and inject changed commitments in the beginning then aggregate into vector
-        let com_vec_new = (0..vss_scheme_vec[1].commitments.len())
-            .map(|i| {
-                if i == 0 {
-                    com_zero_new
-                } else {
-                    com_iter_unchanged.next().unwrap().clone()
-                }
-            })
-            .collect::<Vec<GE>>();
-        let new_vss = VerifiableSS {
-            parameters: vss_scheme_vec[0].parameters.clone(),
-            commitments: com_vec_new,
+/// Online part of a signature: the phase 5 rounds of sign on a presignature, under its id. These are five broadcast
+/// rounds, not one: the checks of R and of the message in 5C and 5D run before any party reveals its s_i, so a bad
+/// party or a different message aborts there. The parties' presignatures must come from the same presign room,
+/// which binds their context label
+pub fn sign_online(addr: String, presig: &Presignature, message: &[u8]) -> SignatureRecid {
+    phase5(&addr, presig, message).unwrap_or_else(|Dropout { missing, .. }| {
+        panic!("Parties {:?} dropped out, the presignature is consumed and signing must start over", missing)
+    })
+}
+
+pub enum PresignTransition {
+    Next(PresignState),
+    Done(Presignature),
+}
+
+/// The offline signing rounds of one party as typed round states, decoupled from the transport like KeygenState.
+/// `outgoing` is the party's message of the round and `next` takes the messages of the other parties in party
+/// order. Failed checks panic as in the rounds before
+#[derive(Serialize, Deserialize)]
+pub enum PresignState {
+    Signers(SignersRound),
+    Commit(SignCommitRound),
+    Mta(MtaRound),
+    Delta(DeltaRound),
+    Decommit(SignDecommitRound),
+}
+
+impl PresignState {
+    /// The first round, in a room the party signed up to. With sign_at_path the key share and the leader's VSS
+    /// commitment are moved to the child key of f_l_new
+    pub fn start(
+        setup: PresignSetup,
+        party_keys: Keys,
+        shared_keys: SharedKeys,
+        mut vss_scheme_vec: Vec<VerifiableSS<GE>>,
+        paillier_key_vector: Vec<EncryptionKey>,
+        f_l_new: &FE,
+        sign_at_path: bool,
+    ) -> Self {
+        let key_shift = match (sign_at_path, setup.party_num) {
+            (false, _) => None,
+            // update u_i and x_i for the leader, the party with num=1
+            (true, 1) => Some((*f_l_new, *f_l_new)),
+            // only update x_i for non-leaders
+            (true, _) => Some((FE::zero(), *f_l_new)),
         };
-        // replace old vss_scheme for leader with new one at position 0
-        //    println!("comparing vectors: \n{:?} \nand \n{:?}", vss_scheme_vec[0], new_vss);
+        if sign_at_path {
+            // apply on the first commitment, the leader's
+            let g: GE = ECPoint::generator();
+            vss_scheme_vec[0].commitments[0] = vss_scheme_vec[0].commitments[0] + g * f_l_new;
+        }
+        PresignState::Signers(SignersRound { setup, party_keys, shared_keys, vss_scheme_vec, paillier_key_vector, key_shift })
+    }
 
-        vss_scheme_vec.remove(0);
-        vss_scheme_vec.insert(0, new_vss);
-        //    println!("NEW VSS VECTOR: {:?}", vss_scheme_vec);
+    /// Name of the round on the manager
+    pub fn round(&self) -> &'static str {
+        match self {
+            PresignState::Signers(_) => "round0",
+            PresignState::Commit(_) => "round1",
+            PresignState::Mta(_) => "round2",
+            PresignState::Delta(_) => "round3",
+            PresignState::Decommit(_) => "round4",
+        }
     }
 
-    let mut private = PartyPrivate::set_private(party_keys.clone(), shared_keys);
+    pub fn outgoing(&self) -> Outgoing {
+        match self {
+            PresignState::Signers(state) => Outgoing::Broadcast(serde_json::to_string(&state.setup.party_id).unwrap()),
+            PresignState::Commit(state) => {
+                Outgoing::Broadcast(serde_json::to_string(&(state.com.clone(), state.m_a_k.clone())).unwrap())
+            }
+            PresignState::Mta(state) => Outgoing::P2p(
+                state
+                    .setup
+                    .counterparties()
+                    .into_iter()
+                    .zip(state.mta_send_vec.iter())
+                    .map(|(i, replies)| (i, serde_json::to_string(replies).unwrap()))
+                    .collect(),
+            ),
+            PresignState::Delta(state) => Outgoing::Broadcast(serde_json::to_string(&state.delta_i).unwrap()),
+            PresignState::Decommit(state) => Outgoing::Broadcast(serde_json::to_string(&state.decommit).unwrap()),
+        }
+    }
 
-    if sign_at_path == true {
-        if party_num_int == 1 {
-            // update u_i and x_i for leader
-            private = private.update_private_key(&f_l_new, &f_l_new);
-        } else {
-            // only update x_i for non-leaders
-            private = private.update_private_key(&FE::zero(), &f_l_new);
+    pub fn next(self, incoming: &[String]) -> PresignTransition {
+        match self {
+            PresignState::Signers(state) => PresignTransition::Next(PresignState::Commit(state.next(incoming))),
+            PresignState::Commit(state) => PresignTransition::Next(PresignState::Mta(state.next(incoming))),
+            PresignState::Mta(state) => PresignTransition::Next(PresignState::Delta(state.next(incoming))),
+            PresignState::Delta(state) => PresignTransition::Next(PresignState::Decommit(state.next(incoming))),
+            PresignState::Decommit(state) => PresignTransition::Done(state.finish(incoming)),
         }
     }
+}
 
-    let sign_keys = SignKeys::create(
-        &private,
-        &vss_scheme_vec[signers_vec[(party_num_int - 1) as usize]],
-        signers_vec[(party_num_int - 1) as usize],
-        &signers_vec,
-    );
+/// The room of a presigning: its uuid, the presignature id, and this party's number in it
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PresignSetup {
+    pub id: String,
+    pub party_num: u16,
+    pub total_parties: u16,
+    pub party_id: u16,
+    pub y_sum: GE,
+}
 
-    //////////////////////////////////////////////////////////////////////////////
-    let (com, decommit) = sign_keys.phase1_broadcast();
-    let (m_a_k, _) = MessageA::a(&sign_keys.k_i, &party_keys.ek, &[]);
-    assert!(broadcast(
-        &addr,
-        &client,
-        party_num_int,
-        "round1",
-        serde_json::to_string(&(com.clone(), m_a_k.clone())).unwrap(),
-        uuid.clone(),
-    )
-    .is_ok());
-    let round1_ans_vec = poll_for_broadcasts(
-        &addr,
-        &client,
-        party_num_int,
-        total_parties,
-        delay,
-        "round1",
-        uuid.clone(),
-    );
+impl PresignSetup {
+    // Room numbers of the other parties, in order
+    fn counterparties(&self) -> Vec<u16> {
+        (1..=self.total_parties).filter(|i| *i != self.party_num).collect()
+    }
+
+    // Messages of all parties in party order, the own one included
+    fn with_own<T: DeserializeOwned>(&self, incoming: &[String], own: T) -> Vec<T> {
+        with_own(self.party_num, incoming, own)
+    }
+}
+
+fn with_own<T: DeserializeOwned>(party_num: u16, incoming: &[String], own: T) -> Vec<T> {
+    let mut messages: Vec<T> = incoming.iter().map(|m| serde_json::from_str(m).unwrap()).collect();
+    messages.insert(party_num as usize - 1, own);
+    messages
+}
+
+/// Round 0: the party ids of the signers, their shares are combined with Lagrange coefficients of these ids
+#[derive(Serialize, Deserialize)]
+pub struct SignersRound {
+    setup: PresignSetup,
+    party_keys: Keys,
+    shared_keys: SharedKeys,
+    vss_scheme_vec: Vec<VerifiableSS<GE>>,
+    paillier_key_vector: Vec<EncryptionKey>,
+    // Shifts of u_i and x_i to the child key at a path
+    key_shift: Option<(FE, FE)>,
+}
 
-    let mut j = 0;
-    let mut bc1_vec: Vec<SignBroadcastPhase1> = Vec::new();
-    let mut m_a_vec: Vec<MessageA> = Vec::new();
-
-    for i in 1..total_parties + 1 {
-        if i == party_num_int {
-            bc1_vec.push(com.clone());
-        //   m_a_vec.push(m_a_k.clone());
-        } else {
-            //     if signers_vec.contains(&(i as usize)) {
-            let (bc1_j, m_a_party_j): (SignBroadcastPhase1, MessageA) =
-                serde_json::from_str(&round1_ans_vec[j]).unwrap();
-            bc1_vec.push(bc1_j);
-            m_a_vec.push(m_a_party_j);
-
-            j = j + 1;
-            //       }
+impl SignersRound {
+    pub fn next(self, incoming: &[String]) -> SignCommitRound {
+        let signers_vec: Vec<usize> = self
+            .setup
+            .with_own(incoming, self.setup.party_id)
+            .into_iter()
+            .map(|party_id: u16| (party_id - 1) as usize)
+            .collect();
+        let mut private = PartyPrivate::set_private(self.party_keys.clone(), self.shared_keys);
+        if let Some((u_i, x_i)) = &self.key_shift {
+            private = private.update_private_key(u_i, x_i);
+        }
+        let own_index = signers_vec[(self.setup.party_num - 1) as usize];
+        let sign_keys = SignKeys::create(&private, &self.vss_scheme_vec[own_index], own_index, &signers_vec);
+        let (com, decommit) = sign_keys.phase1_broadcast();
+        let (m_a_k, _) = MessageA::a(&sign_keys.k_i, &self.party_keys.ek, &[]);
+        SignCommitRound {
+            setup: self.setup,
+            party_keys: self.party_keys,
+            vss_scheme_vec: self.vss_scheme_vec,
+            paillier_key_vector: self.paillier_key_vector,
+            signers_vec,
+            sign_keys,
+            com,
+            decommit,
+            m_a_k,
         }
     }
-    assert_eq!(signers_vec.len(), bc1_vec.len());
-
-    //////////////////////////////////////////////////////////////////////////////
-    let mut m_b_gamma_send_vec: Vec<MessageB> = Vec::new();
-    let mut beta_vec: Vec<FE> = Vec::new();
-    let mut m_b_w_send_vec: Vec<MessageB> = Vec::new();
-    let mut ni_vec: Vec<FE> = Vec::new();
-    let mut j = 0;
-    for i in 1..total_parties + 1 {
-        if i != party_num_int {
-            let (m_b_gamma, beta_gamma, _, _) = MessageB::b(
-                &sign_keys.gamma_i,
-                &paillier_key_vector[signers_vec[(i - 1) as usize]],
-                m_a_vec[j].clone(),
-                &[]
-            )
-            .unwrap();
-            let (m_b_w, beta_wi, _, _) = MessageB::b(
-                &sign_keys.w_i,
-                &paillier_key_vector[signers_vec[(i - 1) as usize]],
-                m_a_vec[j].clone(),
-                &[]
-            )
-            .unwrap();
-            m_b_gamma_send_vec.push(m_b_gamma);
-            m_b_w_send_vec.push(m_b_w);
+}
+
+/// Round 1: commitment to g^gamma_i and the Paillier encryption of k_i, the MtA requests
+#[derive(Serialize, Deserialize)]
+pub struct SignCommitRound {
+    setup: PresignSetup,
+    party_keys: Keys,
+    vss_scheme_vec: Vec<VerifiableSS<GE>>,
+    paillier_key_vector: Vec<EncryptionKey>,
+    signers_vec: Vec<usize>,
+    sign_keys: SignKeys,
+    com: SignBroadcastPhase1,
+    decommit: SignDecommitPhase1,
+    m_a_k: MessageA,
+}
+
+impl SignCommitRound {
+    pub fn next(self, incoming: &[String]) -> MtaRound {
+        let (bc1_vec, m_a_vec): (Vec<SignBroadcastPhase1>, Vec<MessageA>) =
+            incoming.iter().map(|m| serde_json::from_str::<(SignBroadcastPhase1, MessageA)>(m).unwrap()).unzip();
+        let mut bc1_vec = bc1_vec;
+        bc1_vec.insert((self.setup.party_num - 1) as usize, self.com);
+        assert_eq!(self.signers_vec.len(), bc1_vec.len());
+
+        // MtA with every counterparty is independent, run them on the worker pool
+        let counterparties = self.setup.counterparties();
+        let sign_keys = &self.sign_keys;
+        let signers_vec = &self.signers_vec;
+        let paillier_key_vector = &self.paillier_key_vector;
+        let paranoid = options::get().paranoid;
+        let mta_results: Vec<(MtaReplies, FE, FE)> = mta_thread_pool().install(|| {
+            counterparties
+                .par_iter()
+                .enumerate()
+                .map(|(j, i)| {
+                    let m_b = |b: &FE| {
+                        MessageB::b(b, &paillier_key_vector[signers_vec[(i - 1) as usize]], m_a_vec[j].clone(), &[]).unwrap()
+                    };
+                    let (m_b_gamma, beta_gamma, _, _) = m_b(&sign_keys.gamma_i);
+                    match paranoid {
+                        true => {
+                            let (w_i_blinded, minus_rho) = blind(&sign_keys.w_i);
+                            let (m_b_w, beta_wi, _, _) = m_b(&w_i_blinded);
+                            let (m_b_rho, beta_rho, _, _) = m_b(&minus_rho);
+                            (MtaReplies::Blinded(m_b_gamma, m_b_w, m_b_rho), beta_gamma, beta_wi + beta_rho)
+                        }
+                        false => {
+                            let (m_b_w, beta_wi, _, _) = m_b(&sign_keys.w_i);
+                            (MtaReplies::Plain(m_b_gamma, m_b_w), beta_gamma, beta_wi)
+                        }
+                    }
+                })
+                .collect()
+        });
+        let mut mta_send_vec: Vec<MtaReplies> = Vec::new();
+        let mut beta_vec: Vec<FE> = Vec::new();
+        let mut ni_vec: Vec<FE> = Vec::new();
+        for (replies, beta_gamma, beta_wi) in mta_results {
+            mta_send_vec.push(replies);
             beta_vec.push(beta_gamma);
             ni_vec.push(beta_wi);
-            j = j + 1;
+        }
+        MtaRound {
+            setup: self.setup,
+            party_keys: self.party_keys,
+            vss_scheme_vec: self.vss_scheme_vec,
+            signers_vec: self.signers_vec,
+            sign_keys: self.sign_keys,
+            decommit: self.decommit,
+            bc1_vec,
+            mta_send_vec,
+            beta_vec,
+            ni_vec,
         }
     }
+}
 
-    let mut j = 0;
-    for i in 1..total_parties + 1 {
-        if i != party_num_int {
-            assert!(sendp2p(
-                &addr,
-                &client,
-                party_num_int.clone(),
-                i.clone(),
-                "round2",
-                serde_json::to_string(&(m_b_gamma_send_vec[j].clone(), m_b_w_send_vec[j].clone()))
-                    .unwrap(),
-                uuid.clone(),
-            )
-            .is_ok());
-            j = j + 1;
+/// Round 2: the MtA replies to each counterparty's k_i for gamma_i and w_i, checked against the VSS commitments
+#[derive(Serialize, Deserialize)]
+pub struct MtaRound {
+    setup: PresignSetup,
+    party_keys: Keys,
+    vss_scheme_vec: Vec<VerifiableSS<GE>>,
+    signers_vec: Vec<usize>,
+    sign_keys: SignKeys,
+    decommit: SignDecommitPhase1,
+    bc1_vec: Vec<SignBroadcastPhase1>,
+    mta_send_vec: Vec<MtaReplies>,
+    beta_vec: Vec<FE>,
+    ni_vec: Vec<FE>,
+}
+
+impl MtaRound {
+    pub fn next(self, incoming: &[String]) -> DeltaRound {
+        let mut m_b_gamma_rec_vec: Vec<MessageB> = Vec::new();
+        let mut m_b_w_rec_vec: Vec<Vec<MessageB>> = Vec::new();
+        for message in incoming {
+            match serde_json::from_str(message).unwrap() {
+                MtaReplies::Blinded(m_b_gamma_i, m_b_w_i, m_b_rho_i) => {
+                    m_b_gamma_rec_vec.push(m_b_gamma_i);
+                    m_b_w_rec_vec.push(vec![m_b_w_i, m_b_rho_i]);
+                }
+                MtaReplies::Plain(m_b_gamma_i, m_b_w_i) => {
+                    m_b_gamma_rec_vec.push(m_b_gamma_i);
+                    m_b_w_rec_vec.push(vec![m_b_w_i]);
+                }
+            }
+        }
+
+        let counterparties = self.setup.counterparties();
+        let sign_keys = &self.sign_keys;
+        let signers_vec = &self.signers_vec;
+        let vss_scheme_vec = &self.vss_scheme_vec;
+        let dk = &self.party_keys.dk;
+        let xi_com_vec = Keys::get_commitments_to_xi(vss_scheme_vec);
+        let alpha_miu_vec: Vec<(FE, FE)> = mta_thread_pool().install(|| {
+            counterparties
+                .par_iter()
+                .enumerate()
+                .map(|(j, i)| {
+                    let alpha_ij_gamma = m_b_gamma_rec_vec[j]
+                        .verify_proofs_get_alpha(dk, &sign_keys.k_i)
+                        .expect("wrong dlog or m_b");
+                    let alphas_ij_wi: Vec<FE> = m_b_w_rec_vec[j]
+                        .iter()
+                        .map(|m_b| m_b.verify_proofs_get_alpha(dk, &sign_keys.k_i).expect("wrong dlog or m_b").0)
+                        .collect();
+                    let alpha_ij_wi = alphas_ij_wi[1..].iter().fold(alphas_ij_wi[0], |acc, alpha| acc + *alpha);
+                    let g_w_i = Keys::update_commitments_to_xi(
+                        &xi_com_vec[signers_vec[(i - 1) as usize]],
+                        &vss_scheme_vec[signers_vec[(i - 1) as usize]],
+                        signers_vec[(i - 1) as usize],
+                        signers_vec,
+                    );
+                    let m_b_w = &m_b_w_rec_vec[j];
+                    let g_b = m_b_w[1..].iter().fold(m_b_w[0].b_proof.pk, |acc, m_b| acc + m_b.b_proof.pk);
+                    assert_eq!(g_b, g_w_i);
+                    (alpha_ij_gamma.0, alpha_ij_wi)
+                })
+                .collect()
+        });
+        let (alpha_vec, miu_vec): (Vec<FE>, Vec<FE>) = alpha_miu_vec.into_iter().unzip();
+        let (delta_i, sigma) = match options::get().paranoid {
+            // phase2_delta_i and phase2_sigma_i with k_i·gamma_i and k_i·w_i on blinded values
+            true => (
+                alpha_vec.iter().chain(self.beta_vec.iter()).fold(mul_secret(&sign_keys.k_i, &sign_keys.gamma_i), |acc, x| acc + *x),
+                miu_vec.iter().chain(self.ni_vec.iter()).fold(mul_secret(&sign_keys.k_i, &sign_keys.w_i), |acc, x| acc + *x),
+            ),
+            false => (sign_keys.phase2_delta_i(&alpha_vec, &self.beta_vec), sign_keys.phase2_sigma_i(&miu_vec, &self.ni_vec)),
+        };
+        DeltaRound {
+            setup: self.setup,
+            k_i: self.sign_keys.k_i,
+            decommit: self.decommit,
+            bc1_vec: self.bc1_vec,
+            m_b_gamma_rec_vec,
+            delta_i,
+            sigma,
         }
     }
+}
 
-    let round2_ans_vec = poll_for_p2p(
-        &addr,
-        &client,
-        party_num_int,
-        total_parties,
-        delay,
-        "round2",
-        uuid.clone(),
-    );
+/// Round 3: the delta_i, summed to the inverse of k·gamma
+#[derive(Serialize, Deserialize)]
+pub struct DeltaRound {
+    setup: PresignSetup,
+    k_i: FE,
+    decommit: SignDecommitPhase1,
+    bc1_vec: Vec<SignBroadcastPhase1>,
+    m_b_gamma_rec_vec: Vec<MessageB>,
+    delta_i: FE,
+    sigma: FE,
+}
 
-    let mut m_b_gamma_rec_vec: Vec<MessageB> = Vec::new();
-    let mut m_b_w_rec_vec: Vec<MessageB> = Vec::new();
-
-    for i in 0..total_parties-1 {
-        //  if signers_vec.contains(&(i as usize)) {
-        let (m_b_gamma_i, m_b_w_i): (MessageB, MessageB) =
-            serde_json::from_str(&round2_ans_vec[i as usize]).unwrap();
-        m_b_gamma_rec_vec.push(m_b_gamma_i);
-        m_b_w_rec_vec.push(m_b_w_i);
-        //     }
-    }
-
-    let mut alpha_vec: Vec<FE> = Vec::new();
-    let mut miu_vec: Vec<FE> = Vec::new();
-
-    let xi_com_vec = Keys::get_commitments_to_xi(&vss_scheme_vec);
-    let mut j = 0;
-    for i in 1..total_parties + 1 {
-        //        println!("mbproof p={}, i={}, j={}", party_num_int, i, j);
-        if i != party_num_int {
-            //            println!("verifying: p={}, i={}, j={}", party_num_int, i, j);
-            let m_b = m_b_gamma_rec_vec[j].clone();
-
-            let alpha_ij_gamma = m_b
-                .verify_proofs_get_alpha(&party_keys.dk, &sign_keys.k_i)
-                .expect("wrong dlog or m_b");
-            let m_b = m_b_w_rec_vec[j].clone();
-            let alpha_ij_wi = m_b
-                .verify_proofs_get_alpha(&party_keys.dk, &sign_keys.k_i)
-                .expect("wrong dlog or m_b");
-            alpha_vec.push(alpha_ij_gamma.0);
-            miu_vec.push(alpha_ij_wi.0);
-            let g_w_i = Keys::update_commitments_to_xi(
-                &xi_com_vec[signers_vec[(i - 1) as usize]],
-                &vss_scheme_vec[signers_vec[(i - 1) as usize]],
-                signers_vec[(i - 1) as usize],
-                &signers_vec,
-            );
-            //println!("Verifying client {}", party_num_int);
-            assert_eq!(m_b.b_proof.pk.clone(), g_w_i);
-            //println!("Verified client {}", party_num_int);
-            j = j + 1;
+impl DeltaRound {
+    pub fn next(self, incoming: &[String]) -> SignDecommitRound {
+        let delta_vec = self.setup.with_own(incoming, self.delta_i);
+        let delta_inv = SignKeys::phase3_reconstruct_delta(&delta_vec);
+        SignDecommitRound {
+            setup: self.setup,
+            k_i: self.k_i,
+            decommit: self.decommit,
+            bc1_vec: self.bc1_vec,
+            m_b_gamma_rec_vec: self.m_b_gamma_rec_vec,
+            delta_inv,
+            sigma: self.sigma,
         }
     }
-    //////////////////////////////////////////////////////////////////////////////
-    let delta_i = sign_keys.phase2_delta_i(&alpha_vec, &beta_vec);
-    let sigma = sign_keys.phase2_sigma_i(&miu_vec, &ni_vec);
-
-    assert!(broadcast(
-        &addr,
-        &client,
-        party_num_int,
-        "round3",
-        serde_json::to_string(&delta_i).unwrap(),
-        uuid.clone(),
-    )
-    .is_ok());
-    let round3_ans_vec = poll_for_broadcasts(
-        &addr,
-        &client,
-        party_num_int,
-        total_parties,
-        delay,
-        "round3",
-        uuid.clone(),
-    );
-    let mut delta_vec: Vec<FE> = Vec::new();
-    format_vec_from_reads(
-        &round3_ans_vec,
-        party_num_int as usize,
-        delta_i,
-        &mut delta_vec,
-    );
-    let delta_inv = SignKeys::phase3_reconstruct_delta(&delta_vec);
-
-    //////////////////////////////////////////////////////////////////////////////
-    // decommit to gamma_i
-    assert!(broadcast(
-        &addr,
-        &client,
-        party_num_int,
-        "round4",
-        serde_json::to_string(&decommit).unwrap(),
-        uuid.clone(),
-    )
-    .is_ok());
-    let round4_ans_vec = poll_for_broadcasts(
-        &addr,
-        &client,
-        party_num_int,
-        total_parties,
-        delay,
-        "round4",
-        uuid.clone(),
-    );
+}
 
-    let mut decommit_vec: Vec<SignDecommitPhase1> = Vec::new();
-    format_vec_from_reads(
-        &round4_ans_vec,
-        party_num_int as usize,
-        decommit,
-        &mut decommit_vec,
-    );
-    let decomm_i = decommit_vec.remove((party_num_int - 1) as usize);
-    bc1_vec.remove((party_num_int - 1) as usize);
-    let b_proof_vec = (0..m_b_gamma_rec_vec.len())
-        .map(|i| &m_b_gamma_rec_vec[i].b_proof)
-        .collect::<Vec<&DLogProof<GE>>>();
-    let R = SignKeys::phase4(&delta_inv, &b_proof_vec, decommit_vec, &bc1_vec)
-        .expect("bad gamma_i decommit");
-
-    // adding local g_gamma_i
-    let R = R + decomm_i.g_gamma_i * &delta_inv;
-
-    // we assume the message is already hashed (by the signer).
-    let message_bn = BigInt::from_bytes(message);
-    //    println!("message_bn INT: {}", message_bn);
-    let message_int = BigInt::from_bytes(message);
-    let two = BigInt::from(2);
-    let message_bn = message_bn.modulus(&two.pow(256));
-    let local_sig =
-        LocalSignature::phase5_local_sig(&sign_keys.k_i, &message_bn, &R, &sigma, &y_sum);
-
-    let (phase5_com, phase_5a_decom, helgamal_proof, dlog_proof_rho) = local_sig.phase5a_broadcast_5b_zkproof();
-
-    //phase (5A)  broadcast commit
-    assert!(broadcast(
-        &addr,
-        &client,
-        party_num_int.clone(),
-        "round5",
-        serde_json::to_string(&phase5_com).unwrap(),
-        uuid.clone(),
-    )
-    .is_ok());
-    let round5_ans_vec = poll_for_broadcasts(
-        &addr,
-        &client,
-        party_num_int.clone(),
-        total_parties,
-        delay.clone(),
-        "round5",
-        uuid.clone(),
-    );
+/// Round 4: the decommitments to g^gamma_i, checked against round 1 and the MtA proofs, give R
+#[derive(Serialize, Deserialize)]
+pub struct SignDecommitRound {
+    setup: PresignSetup,
+    k_i: FE,
+    decommit: SignDecommitPhase1,
+    bc1_vec: Vec<SignBroadcastPhase1>,
+    m_b_gamma_rec_vec: Vec<MessageB>,
+    delta_inv: FE,
+    sigma: FE,
+}
 
-    let mut commit5a_vec: Vec<Phase5Com1> = Vec::new();
-    format_vec_from_reads(
-        &round5_ans_vec,
-        party_num_int.clone() as usize,
-        phase5_com,
-        &mut commit5a_vec,
-    );
+impl SignDecommitRound {
+    pub fn finish(self, incoming: &[String]) -> Presignature {
+        let own = (self.setup.party_num - 1) as usize;
+        let mut decommit_vec = self.setup.with_own(incoming, self.decommit);
+        let decomm_i = decommit_vec.remove(own);
+        let mut bc1_vec = self.bc1_vec;
+        bc1_vec.remove(own);
+        let b_proof_vec = self.m_b_gamma_rec_vec.iter().map(|m_b| &m_b.b_proof).collect::<Vec<&DLogProof<GE>>>();
+        let R = SignKeys::phase4(&self.delta_inv, &b_proof_vec, decommit_vec, &bc1_vec).expect("bad gamma_i decommit");
+        // adding local g_gamma_i
+        let R = R + decomm_i.g_gamma_i * &self.delta_inv;
 
-    //phase (5B)  broadcast decommit and (5B) ZK proof
-    assert!(broadcast(
-        &addr,
-        &client,
-        party_num_int.clone(),
-        "round6",
-        serde_json::to_string(&(
-            phase_5a_decom.clone(),
-            helgamal_proof.clone(),
-            dlog_proof_rho.clone()
-        ))
-        .unwrap(),
-        uuid.clone(),
-    )
-    .is_ok());
-    let round6_ans_vec = poll_for_broadcasts(
-        &addr,
-        &client,
-        party_num_int.clone(),
-        total_parties,
-        delay.clone(),
-        "round6",
-        uuid.clone(),
-    );
+        Presignature {
+            id: self.setup.id,
+            party_num: self.setup.party_num,
+            total_parties: self.setup.total_parties,
+            party_id: self.setup.party_id,
+            keysfile: "".to_string(),
+            key_fingerprint: "".to_string(),
+            context: "".to_string(),
+            path: "".to_string(),
+            k_i: self.k_i,
+            sigma_i: self.sigma,
+            R,
+            y_sum: self.setup.y_sum,
+        }
+    }
+}
 
-    let mut decommit5a_and_elgamal_and_dlog_vec: Vec<(
-        Phase5ADecom1,
-        HomoELGamalProof<GE>,
-        DLogProof<GE>,
-    )> = Vec::new();
-    format_vec_from_reads(
-        &round6_ans_vec,
-        party_num_int as usize,
-        (
-            phase_5a_decom.clone(),
-            helgamal_proof.clone(),
-            dlog_proof_rho.clone(),
-        ),
-        &mut decommit5a_and_elgamal_and_dlog_vec,
-    );
-    let decommit5a_and_elgamal_vec_includes_i = decommit5a_and_elgamal_and_dlog_vec.clone();
-    decommit5a_and_elgamal_and_dlog_vec.remove((party_num_int - 1) as usize);
-    commit5a_vec.remove((party_num_int - 1) as usize);
-    let phase_5a_decomm_vec = (0..total_parties - 1)
-        .map(|i| decommit5a_and_elgamal_and_dlog_vec[i as usize].0.clone())
-        .collect::<Vec<Phase5ADecom1>>();
-    let phase_5a_elgamal_vec = (0..total_parties - 1)
-        .map(|i| decommit5a_and_elgamal_and_dlog_vec[i as usize].1.clone())
-        .collect::<Vec<HomoELGamalProof<GE>>>();
-    let phase_5a_dlog_vec = (0..total_parties - 1)
-        .map(|i| decommit5a_and_elgamal_and_dlog_vec[i as usize].2.clone())
-        .collect::<Vec<DLogProof<GE>>>();
-    let (phase5_com2, phase_5d_decom2) = local_sig
-        .phase5c(
-            &phase_5a_decomm_vec,
-            &commit5a_vec,
-            &phase_5a_elgamal_vec,
-            &phase_5a_dlog_vec,
-            &phase_5a_decom.V_i,
-            &R.clone(),
-        )
-        .expect("error phase5");
-
-    //////////////////////////////////////////////////////////////////////////////
-    assert!(broadcast(
-        &addr,
-        &client,
-        party_num_int.clone(),
-        "round7",
-        serde_json::to_string(&phase5_com2).unwrap(),
-        uuid.clone(),
-    )
-    .is_ok());
-    let round7_ans_vec = poll_for_broadcasts(
-        &addr,
-        &client,
-        party_num_int.clone(),
-        total_parties,
-        delay.clone(),
-        "round7",
-        uuid.clone(),
-    );
+pub enum OnlineTransition {
+    Next(OnlineState),
+    Done(SignatureRecid),
+}
 
-    let mut commit5c_vec: Vec<Phase5Com2> = Vec::new();
-    format_vec_from_reads(
-        &round7_ans_vec,
-        party_num_int.clone() as usize,
-        phase5_com2,
-        &mut commit5c_vec,
-    );
+/// Phase 5 of GG18 on a presignature as typed round states: commitments to V_i and A_i (5A), their decommitments
+/// with the ElGamal and dlog proofs (5B), commitments to U_i and T_i (5C) and their decommitments, checked against
+/// R, the message and the public key (5D), and only then the s_i (5E)
+#[derive(Serialize, Deserialize)]
+pub enum OnlineState {
+    Commit5a(Commit5aRound),
+    Decommit5b(Decommit5bRound),
+    Commit5c(Commit5cRound),
+    Decommit5d(Decommit5dRound),
+    Share(ShareRound),
+}
 
-    //phase (5B)  broadcast decommit and (5B) ZK proof
-    assert!(broadcast(
-        &addr,
-        &client,
-        party_num_int.clone(),
-        "round8",
-        serde_json::to_string(&phase_5d_decom2).unwrap(),
-        uuid.clone(),
-    )
-    .is_ok());
-    let round8_ans_vec = poll_for_broadcasts(
-        &addr,
-        &client,
-        party_num_int.clone(),
-        total_parties,
-        delay.clone(),
-        "round8",
-        uuid.clone(),
-    );
+impl OnlineState {
+    pub fn start(presig: &Presignature, message: &[u8]) -> Self {
+        // we assume the message is already hashed (by the signer).
+        let message_bn = BigInt::from_bytes(message).modulus(&BigInt::from(2).pow(256));
+        let local_sig = local_signature(&presig.k_i, &message_bn, &presig.R, &presig.sigma_i, &presig.y_sum);
+        let (phase5_com, phase_5a_decom, helgamal_proof, dlog_proof_rho) = local_sig.phase5a_broadcast_5b_zkproof();
+        OnlineState::Commit5a(Commit5aRound {
+            party_num: presig.party_num,
+            local_sig,
+            phase5_com,
+            phase_5a_decom,
+            helgamal_proof,
+            dlog_proof_rho,
+        })
+    }
 
-    let mut decommit5d_vec: Vec<Phase5DDecom2> = Vec::new();
-    format_vec_from_reads(
-        &round8_ans_vec,
-        party_num_int.clone() as usize,
-        phase_5d_decom2.clone(),
-        &mut decommit5d_vec,
-    );
+    /// Name of the round on the manager, after the presign rounds of the same room
+    pub fn round(&self) -> &'static str {
+        match self {
+            OnlineState::Commit5a(_) => "round5",
+            OnlineState::Decommit5b(_) => "round6",
+            OnlineState::Commit5c(_) => "round7",
+            OnlineState::Decommit5d(_) => "round8",
+            OnlineState::Share(_) => "round9",
+        }
+    }
 
-    let phase_5a_decomm_vec_includes_i = (0..total_parties)
-        .map(|i| decommit5a_and_elgamal_vec_includes_i[i as usize].0.clone())
-        .collect::<Vec<Phase5ADecom1>>();
-    let s_i = local_sig
-        .phase5d(
-            &decommit5d_vec,
-            &commit5c_vec,
-            &phase_5a_decomm_vec_includes_i,
-        )
-        .expect("bad com 5d");
-
-    //////////////////////////////////////////////////////////////////////////////
-    assert!(broadcast(
-        &addr,
-        &client,
-        party_num_int.clone(),
-        "round9",
-        serde_json::to_string(&s_i).unwrap(),
-        uuid.clone(),
-    )
-    .is_ok());
-    let round9_ans_vec = poll_for_broadcasts(
-        &addr,
-        &client,
-        party_num_int.clone(),
-        total_parties,
-        delay.clone(),
-        "round9",
-        uuid.clone(),
-    );
+    pub fn outgoing(&self) -> Outgoing {
+        let message = match self {
+            OnlineState::Commit5a(state) => serde_json::to_string(&state.phase5_com),
+            OnlineState::Decommit5b(state) => serde_json::to_string(&state.decommit5a()),
+            OnlineState::Commit5c(state) => serde_json::to_string(&state.phase5_com2),
+            OnlineState::Decommit5d(state) => serde_json::to_string(&state.phase_5d_decom2),
+            OnlineState::Share(state) => serde_json::to_string(&state.s_i),
+        };
+        Outgoing::Broadcast(message.unwrap())
+    }
 
-    let mut s_i_vec: Vec<FE> = Vec::new();
-    format_vec_from_reads(
-        &round9_ans_vec,
-        party_num_int.clone() as usize,
-        s_i,
-        &mut s_i_vec,
-    );
+    pub fn next(self, incoming: &[String]) -> OnlineTransition {
+        match self {
+            OnlineState::Commit5a(state) => OnlineTransition::Next(OnlineState::Decommit5b(state.next(incoming))),
+            OnlineState::Decommit5b(state) => OnlineTransition::Next(OnlineState::Commit5c(state.next(incoming))),
+            OnlineState::Commit5c(state) => OnlineTransition::Next(OnlineState::Decommit5d(state.next(incoming))),
+            OnlineState::Decommit5d(state) => OnlineTransition::Next(OnlineState::Share(state.next(incoming))),
+            OnlineState::Share(state) => OnlineTransition::Done(state.finish(incoming)),
+        }
+    }
+}
+
+/// Round 5A: commitment to V_i and A_i
+#[derive(Serialize, Deserialize)]
+pub struct Commit5aRound {
+    party_num: u16,
+    local_sig: LocalSignature,
+    phase5_com: Phase5Com1,
+    phase_5a_decom: Phase5ADecom1,
+    helgamal_proof: HomoELGamalProof<GE>,
+    dlog_proof_rho: DLogProof<GE>,
+}
+
+impl Commit5aRound {
+    pub fn next(self, incoming: &[String]) -> Decommit5bRound {
+        let commit5a_vec = with_own(self.party_num, incoming, self.phase5_com);
+        Decommit5bRound {
+            party_num: self.party_num,
+            local_sig: self.local_sig,
+            commit5a_vec,
+            phase_5a_decom: self.phase_5a_decom,
+            helgamal_proof: self.helgamal_proof,
+            dlog_proof_rho: self.dlog_proof_rho,
+        }
+    }
+}
+
+/// Round 5B: decommitment to V_i and A_i with the ElGamal and dlog proofs
+#[derive(Serialize, Deserialize)]
+pub struct Decommit5bRound {
+    party_num: u16,
+    local_sig: LocalSignature,
+    commit5a_vec: Vec<Phase5Com1>,
+    phase_5a_decom: Phase5ADecom1,
+    helgamal_proof: HomoELGamalProof<GE>,
+    dlog_proof_rho: DLogProof<GE>,
+}
+
+impl Decommit5bRound {
+    fn decommit5a(&self) -> (Phase5ADecom1, HomoELGamalProof<GE>, DLogProof<GE>) {
+        (self.phase_5a_decom.clone(), self.helgamal_proof.clone(), self.dlog_proof_rho.clone())
+    }
 
-    s_i_vec.remove((party_num_int - 1) as usize);
-    let sig = local_sig
-        .output_signature(&s_i_vec)
-        .expect("verification failed");
-    //    println!(" \n");
-    //    println!("party {:?} Output Signature: \n", party_num_int);
-    //    println!("SIG msg: {:?}", sig.m);
-    //    println!("R: {:?}", sig.r);
-    //    println!("s: {:?} \n", sig.s);
-    //    println!("child pubkey: {:?} \n", y_sum);
-
-    //    println!("pubkey: {:?} \n", y_sum);
-    //    println!("verifying signature with public key");
-    verify(&sig, &y_sum, &message_bn).expect("false");
-    //    println!("verifying signature with child pub key");
-    //    verify(&sig, &new_key, &message_bn).expect("false");
-
-    //    println!("{:?}", sig.recid.clone());
-    //    print(sig.recid.clone()
-
-    let ret_dict = json!({
+    pub fn next(self, incoming: &[String]) -> Commit5cRound {
+        let own = (self.party_num - 1) as usize;
+        let mut decommit5a_vec = with_own(self.party_num, incoming, self.decommit5a());
+        let phase_5a_decomm_vec_includes_i: Vec<Phase5ADecom1> =
+            decommit5a_vec.iter().map(|decommit| decommit.0.clone()).collect();
+        decommit5a_vec.remove(own);
+        let mut commit5a_vec = self.commit5a_vec;
+        commit5a_vec.remove(own);
+        let phase_5a_decomm_vec: Vec<Phase5ADecom1> = decommit5a_vec.iter().map(|decommit| decommit.0.clone()).collect();
+        let phase_5a_elgamal_vec: Vec<HomoELGamalProof<GE>> = decommit5a_vec.iter().map(|decommit| decommit.1.clone()).collect();
+        let phase_5a_dlog_vec: Vec<DLogProof<GE>> = decommit5a_vec.iter().map(|decommit| decommit.2.clone()).collect();
+        let (phase5_com2, phase_5d_decom2) = self
+            .local_sig
+            .phase5c(
+                &phase_5a_decomm_vec,
+                &commit5a_vec,
+                &phase_5a_elgamal_vec,
+                &phase_5a_dlog_vec,
+                &self.phase_5a_decom.V_i,
+                &self.local_sig.R,
+            )
+            .expect("error phase5");
+        Commit5cRound {
+            party_num: self.party_num,
+            local_sig: self.local_sig,
+            phase_5a_decomm_vec_includes_i,
+            phase5_com2,
+            phase_5d_decom2,
+        }
+    }
+}
+
+/// Round 5C: commitment to U_i and T_i
+#[derive(Serialize, Deserialize)]
+pub struct Commit5cRound {
+    party_num: u16,
+    local_sig: LocalSignature,
+    phase_5a_decomm_vec_includes_i: Vec<Phase5ADecom1>,
+    phase5_com2: Phase5Com2,
+    phase_5d_decom2: Phase5DDecom2,
+}
+
+impl Commit5cRound {
+    pub fn next(self, incoming: &[String]) -> Decommit5dRound {
+        let commit5c_vec = with_own(self.party_num, incoming, self.phase5_com2);
+        Decommit5dRound {
+            party_num: self.party_num,
+            local_sig: self.local_sig,
+            phase_5a_decomm_vec_includes_i: self.phase_5a_decomm_vec_includes_i,
+            commit5c_vec,
+            phase_5d_decom2: self.phase_5d_decom2,
+        }
+    }
+}
+
+/// Round 5D: decommitment to U_i and T_i, the sums are checked before s_i is released
+#[derive(Serialize, Deserialize)]
+pub struct Decommit5dRound {
+    party_num: u16,
+    local_sig: LocalSignature,
+    phase_5a_decomm_vec_includes_i: Vec<Phase5ADecom1>,
+    commit5c_vec: Vec<Phase5Com2>,
+    phase_5d_decom2: Phase5DDecom2,
+}
+
+impl Decommit5dRound {
+    pub fn next(self, incoming: &[String]) -> ShareRound {
+        let decommit5d_vec = with_own(self.party_num, incoming, self.phase_5d_decom2);
+        let s_i = self
+            .local_sig
+            .phase5d(&decommit5d_vec, &self.commit5c_vec, &self.phase_5a_decomm_vec_includes_i)
+            .expect("bad com 5d");
+        ShareRound { party_num: self.party_num, local_sig: self.local_sig, s_i }
+    }
+}
+
+/// Round 5E: the s_i, summed to the signature
+#[derive(Serialize, Deserialize)]
+pub struct ShareRound {
+    party_num: u16,
+    local_sig: LocalSignature,
+    s_i: FE,
+}
+
+impl ShareRound {
+    pub fn finish(self, incoming: &[String]) -> SignatureRecid {
+        let mut s_i_vec = with_own(self.party_num, incoming, self.s_i);
+        s_i_vec.remove((self.party_num - 1) as usize);
+        let sig = self.local_sig.output_signature(&s_i_vec).expect("verification failed");
+        verify(&sig, &self.local_sig.y, &self.local_sig.m).expect("false");
+        finish_signature(sig, &self.local_sig, &s_i_vec, &self.local_sig.y, &self.local_sig.m)
+    }
+}
+
+pub fn signature_json(sig: &SignatureRecid, y_sum: &GE, message: &[u8], context: &str) -> serde_json::Value {
+    let mut ret_dict = json!({
         "r": (BigInt::from_bytes(&(sig.r.get_element())[..])).to_str_radix(16),
         "s": (BigInt::from_bytes(&(sig.s.get_element())[..])).to_str_radix(16),
         "status": "signature_ready",
         "recid": sig.recid.clone(),
+        "v": 27 + sig.recid,
         "x": &y_sum.x_coor(),
         "y": &y_sum.y_coor(),
-        "msg_int": message_int,
+        "msg_int": BigInt::from_bytes(message),
     });
-    println!("{}", ret_dict.to_string());
-
-    //    fs::write("signature".to_string(), sign_json).expect("Unable to save !");
-
-    //    println!("Public key Y: {:?}", to_bitcoin_public_key(y_sum.get_element()).to_bytes());
-    //    println!("Public child key X: {:?}", &new_key.x_coor());
-    //    println!("Public child key Y: {:?}", &new_key.y_coor());
-    //    println!("Public key big int: {:?}", &y_sum.bytes_compressed_to_big_int());
-    //    println!("Public key ge: {:?}", &y_sum.get_element().serialize());
-    //    println!("Public key ge: {:?}", PK::serialize_uncompressed(&y_sum.get_element()));
-    //    println!("New public key: {:?}", &y_sum.x_coor);
-}
-
-fn format_vec_from_reads<'a, T: serde::Deserialize<'a> + Clone>(
-    ans_vec: &'a Vec<String>,
-    party_num: usize,
-    value_i: T,
-    new_vec: &'a mut Vec<T>,
-) {
-    let mut j = 0;
-    for i in 1..ans_vec.len() + 2 {
-        if i == party_num {
-            new_vec.push(value_i.clone());
-        } else {
-            let value_j: T = serde_json::from_str(&ans_vec[j]).unwrap();
-            new_vec.push(value_j);
-            j = j + 1;
-        }
+    if !context.is_empty() {
+        ret_dict["context"] = json!(context);
     }
+    ret_dict
 }
 
-pub fn postb<T>(addr: &String, client: &Client, path: &str, body: T) -> Option<String>
-where
-    T: serde::ser::Serialize,
-{
-    let res = client
-        .post(&format!("{}/{}", addr, path))
-        .json(&body)
-        .timeout(Duration::from_secs(600))
-        .send();
-    Some(res.unwrap().text().unwrap())
+/// Room membership is timed by the manager's clock, a large offset here usually explains parties
+/// that look inactive to the manager while they are still polling
+fn warn_clock_skew(server_time: u64) {
+    // Older managers don't send their time
+    if server_time == 0 {
+        return;
+    }
+    let skew = clock_skew(server_time);
+    if skew.abs() > CLOCK_SKEW_WARNING {
+        let debug = json!({"message": "Local clock differs from manager clock", "clock_skew_s": skew});
+        println!("{}", serde_json::to_string_pretty(&debug).unwrap());
+    }
 }
 
-pub fn signup(addr: &String, client: &Client, threshold: u16, room_id: String, party_id: u16) -> Result<(PartySignup, u16), ()> {
+pub fn signup(addr: &String, client: &Client, threshold: u16, room_id: String, party_id: u16, room_size: u16) -> Result<(PartySignup, u16), ()> {
     let mut request_body = PartySignupRequestBody{
         threshold,
         room_id: room_id.clone(),
         party_number: party_id,
-        party_uuid: "".to_string()
+        party_uuid: "".to_string(),
+        room_size
     };
     let path = "signupsign";
     let delay = time::Duration::from_millis(100);
-    let timeout = std::env::var("TSS_CLI_SIGNUP_TIMEOUT")
-        .unwrap_or("30".to_string()).parse::<u64>().unwrap();
-    let res_body = postb(&addr, &client, path, request_body.clone()).unwrap();
-    let answer: Result<SigningPartySignup, ManagerError> = serde_json::from_str(&res_body).unwrap();
+    let timeout = options::get().signup_timeout;
+    let answer: Result<SigningPartySignup, ManagerError> =
+        post_json(&addr, &client, path, request_body.clone()).unwrap_or_else(|e| panic!("{}", e));
     let (output, total_parties) = match answer {
-        Ok(SigningPartySignup{party_order, party_uuid, room_uuid, total_joined}) => {
+        Ok(SigningPartySignup{party_order, party_uuid, room_uuid, total_joined, server_time}) => {
             println!("Signed up, party order: {:?}, joined so far: {:?}, waiting for room uuid", party_order, total_joined);
-            let mut now = time::SystemTime::now();
+            warn_clock_skew(server_time);
+            // The deadline is a unix time on the manager's clock, so parties in other time zones or with drifted
+            // clocks give up together. Older managers send no time, the local monotonic clock is used then
+            let mut deadline = server_time + timeout;
+            let mut now = time::Instant::now();
             let mut last_total_joined = total_joined;
             let mut party_signup = PartySignup {
                 number: party_order,
@@ -651,10 +1062,10 @@ pub fn signup(addr: &String, client: &Client, threshold: u16, room_id: String, p
             while party_signup.uuid.is_empty() {
                 thread::sleep(delay);
                 request_body.party_uuid = party_uuid.clone();
-                let res_body = postb(&addr, &client, path, request_body.clone()).unwrap();
-                let answer: Result<SigningPartySignup, ManagerError> = serde_json::from_str(&res_body).unwrap();
+                let answer: Result<SigningPartySignup, ManagerError> =
+                    post_json(&addr, &client, path, request_body.clone()).unwrap_or_else(|e| panic!("{}", e));
                 match answer {
-                    Ok(SigningPartySignup{party_order, party_uuid, room_uuid, total_joined}) => {
+                    Ok(SigningPartySignup{party_order, party_uuid, room_uuid, total_joined, server_time}) => {
                         request_body.party_uuid = party_uuid;
                         if party_signup.number != party_order {
                             println!("Order is changed: {:?}", party_order);
@@ -665,16 +1076,21 @@ pub fn signup(addr: &String, client: &Client, threshold: u16, room_id: String, p
                             println!("Joined so far: {:?}", total_joined);
                             last_total_joined = total_joined;
                             //Reset the signup timeout
-                            now = time::SystemTime::now();
+                            deadline = server_time + timeout;
+                            now = time::Instant::now();
+                        }
+                        let expired = match server_time {
+                            0 => now.elapsed().as_secs() > timeout,
+                            server_time => server_time > deadline,
+                        };
+                        if expired {
+                            break;
                         }
                     },
                     Err(ManagerError{error}) => {
                         panic!("{}", error);
                     }
                 };
-                if now.elapsed().unwrap().as_secs() > timeout{
-                    break;
-                }
             }
             if party_signup.uuid.is_empty() {
                 panic!("Could not get room uuid after {:?} seconds of tries", timeout);
@@ -5,7 +5,7 @@ use std::str::FromStr;
 use std::time::{SystemTime, UNIX_EPOCH};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
-use crate::common::{SigningPartyInfo, SigningPartySignup};
+use crate::common::{ObservedMessage, RoomObservation, SigningPartyInfo, SigningPartySignup};
 
 pub const SIGNUP_TIMEOUT_ENV: &str = "TSS_MANAGER_SIGNUP_TIMEOUT";
 pub const SIGNUP_TIMEOUT_DEFAULT: &str = "2";
@@ -36,7 +36,8 @@ impl SigningRoom {
             party_order,
             room_uuid: "".to_string(),
             party_uuid: Uuid::new_v4().to_string(),
-            total_joined: 0
+            total_joined: 0,
+            server_time: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
         }
     }
 
@@ -50,7 +51,7 @@ impl SigningRoom {
             env::var(SIGNUP_TIMEOUT_ENV).unwrap_or(SIGNUP_TIMEOUT_DEFAULT.to_string()).as_str()
         ).unwrap();
 
-        party.last_ping < now - timeout
+        party.last_ping < now.saturating_sub(timeout)
     }
 
     pub fn add_party(&mut self, party_number: u16) -> SigningPartySignup {
@@ -84,6 +85,10 @@ impl SigningRoom {
         )
     }
 
+    pub fn has_active_members(&self) -> bool {
+        !self.active_members().is_empty()
+    }
+
     pub fn are_all_members_inactive(&self) -> bool {
         self.is_full() && self.member_info.values().all(
             |x| SigningRoom::is_timeout(x)
@@ -141,7 +146,18 @@ impl SigningRoom {
             party_order: member_info.party_order,
             party_uuid: member_info.party_id.clone(),
             room_uuid,
-            total_joined: u16::try_from(self.active_members().len()).unwrap()
+            total_joined: u16::try_from(self.active_members().len()).unwrap(),
+            server_time: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+        }
+    }
+
+    pub fn observe(&self, messages: Vec<ObservedMessage>) -> RoomObservation {
+        RoomObservation {
+            room_id: self.room_id.clone(),
+            last_stage: self.last_stage.clone(),
+            room_size: self.room_size,
+            total_joined: u16::try_from(self.active_members().len()).unwrap(),
+            messages
         }
     }
 }
@@ -0,0 +1,42 @@
+use curv::{BigInt, arithmetic::Converter};
+use curv::cryptographic_primitives::hashing::hash_sha256::HSha256;
+use curv::cryptographic_primitives::hashing::traits::Hash;
+use curv::elliptic::curves::secp256_k1::{FE, GE};
+use curv::elliptic::curves::traits::{ECPoint, ECScalar};
+
+use crate::common::encoding::{compressed_public_key, to_32_bytes};
+
+/// BIP340 tagged hash: sha256(sha256(tag) || sha256(tag) || data)
+pub fn tagged_hash(tag: &str, data: &[u8]) -> Vec<u8> {
+    let tag_hash = to_32_bytes(&HSha256::create_hash_from_slice(tag.as_bytes()));
+    let message = [tag_hash.clone(), tag_hash, data.to_vec()].concat();
+    to_32_bytes(&HSha256::create_hash_from_slice(&message))
+}
+
+// Point with the same x and even y, BIP340 keys are x-only
+fn with_even_y(point: &GE) -> GE {
+    if compressed_public_key(point)[0] == 0x02 {
+        return *point;
+    }
+    let minus_one: FE = ECScalar::from(&(FE::q() - BigInt::from(1)));
+    *point * &minus_one
+}
+
+/// BIP341 output key Q = lift_x(x(P)) + t*G with t = hash_TapTweak(x(P) || merkle_root), returns x(Q) and
+/// whether Q has odd y. An empty merkle root gives the key path only output key.
+/// For display only: spending a taproot output takes a BIP340 Schnorr signature, which threshold ECDSA can't produce
+pub fn taproot_output_key(y_sum: &GE, merkle_root: &[u8]) -> (Vec<u8>, bool) {
+    if !merkle_root.is_empty() && merkle_root.len() != 32 {
+        panic!("Taproot merkle root must be 32 bytes");
+    }
+    let internal_key = with_even_y(y_sum);
+    let data = [to_32_bytes(&internal_key.x_coor().unwrap()), merkle_root.to_vec()].concat();
+    let t = BigInt::from_bytes(&tagged_hash("TapTweak", &data));
+    if t >= FE::q() {
+        panic!("Taproot tweak is not a valid scalar");
+    }
+    let t_fe: FE = ECScalar::from(&t);
+    let g: GE = ECPoint::generator();
+    let output_key = internal_key + g * &t_fe;
+    (to_32_bytes(&output_key.x_coor().unwrap()), compressed_public_key(&output_key)[0] == 0x03)
+}
@@ -0,0 +1,65 @@
+use std::fs;
+
+use curv::{
+    arithmetic::traits::Converter,
+    elliptic::curves::secp256_k1::{FE, GE},
+    elliptic::curves::traits::{ECPoint, ECScalar},
+    BigInt,
+};
+use serde_json::{json, Value};
+
+use crate::common::encoding::compressed_public_key;
+use crate::common::verify_sig;
+
+fn hex_number(value: &Value, field: &str) -> BigInt {
+    let text = value[field].as_str().unwrap_or_else(|| panic!("Signature has no {} hex string", field));
+    let digits = text.strip_prefix("0x").unwrap_or(text);
+    BigInt::from_str_radix(digits, 16).unwrap_or_else(|_| panic!("Signature {} is not valid hex: {}", field, text))
+}
+
+fn scalar(value: &Value, field: &str) -> FE {
+    let n = hex_number(value, field);
+    if n <= BigInt::from(0) || n >= FE::q() {
+        panic!("Signature {} is out of range", field);
+    }
+    ECScalar::from(&n)
+}
+
+fn point(x: &BigInt, y: &BigInt) -> GE {
+    let point = GE::from_coor(x, y);
+    if point.x_coor().as_ref() != Some(x) || point.y_coor().as_ref() != Some(y) {
+        panic!("Public key is not a point on secp256k1");
+    }
+    point
+}
+
+/// Public key from hex, compressed or uncompressed
+pub fn public_key_from_hex(public_key: &str) -> GE {
+    let raw_key = hex::decode(public_key).expect("Public key must be in hex format");
+    let key = secp256k1::PublicKey::parse_slice(&raw_key, None).expect("Invalid secp256k1 public key");
+    let uncompressed = key.serialize();
+    point(&BigInt::from_bytes(&uncompressed[1..33]), &BigInt::from_bytes(&uncompressed[33..]))
+}
+
+/// Verifies a signature as sign prints it, r and s in hex, against a public key (the child at path when one is
+/// given) or the x and y of the signature JSON when none is given. Prints the result and returns whether the
+/// signature is valid
+pub fn run_verify(public_key: Option<GE>, path: &str, message: &[u8], signature: &str) -> bool {
+    let content = fs::read_to_string(signature).unwrap_or_else(|_| signature.to_string());
+    let signature: Value = serde_json::from_str(&content).expect("Signature must be a JSON file or JSON");
+    let public_key = public_key.unwrap_or_else(|| point(&hex_number(&signature, "x"), &hex_number(&signature, "y")));
+    let (r, s) = (scalar(&signature, "r"), scalar(&signature, "s"));
+    let valid = verify_sig(&r, &s, &BigInt::from_bytes(message), &public_key);
+    println!(
+        "{}",
+        json!({
+            "status": if valid { "signature_valid" } else { "signature_invalid" },
+            "valid": valid,
+            "public_key": hex::encode(compressed_public_key(&public_key)),
+            "path": path,
+            "msg_int": BigInt::from_bytes(message),
+        })
+        .to_string()
+    );
+    valid
+}
@@ -0,0 +1,65 @@
+use curv::elliptic::curves::secp256_k1::GE;
+use curv::elliptic::curves::traits::ECPoint;
+use serde_json::json;
+
+use crate::common::keyfile::KeyFile;
+
+/// Checks a key share on its own: the share x_i against the public share the VSS commitments imply,
+/// the public key against the sum of the constant term commitments, and the internal copies of both.
+/// Returns the names of the failed checks, other parties are not needed
+pub fn share_failures(keyfile: &KeyFile) -> Vec<&'static str> {
+    let parties = keyfile.vss_scheme_vec.len();
+    let index = keyfile.party_id.saturating_sub(1) as usize;
+    let mut failures: Vec<&str> = Vec::new();
+
+    if keyfile.party_id == 0 || index >= parties {
+        failures.push("party_id");
+    }
+    if keyfile.paillier_key_vec.len() != parties {
+        failures.push("paillier_key_count");
+    }
+    let params = &keyfile.vss_scheme_vec[0].parameters;
+    if keyfile.vss_scheme_vec.iter().any(|vss| {
+        vss.parameters.threshold != params.threshold
+            || vss.parameters.share_count != parties
+            || vss.commitments.len() != params.threshold + 1
+    }) {
+        failures.push("vss_parameters");
+    }
+    if failures.is_empty() {
+        let g: GE = ECPoint::generator();
+        if g * &keyfile.shared_keys.x_i != keyfile.public_share(keyfile.party_id) {
+            failures.push("share_commitment");
+        }
+        let (head, tail) = keyfile.vss_scheme_vec.split_at(1);
+        let y_sum = tail.iter().fold(head[0].commitments[0], |acc, vss| acc + vss.commitments[0]);
+        if y_sum != keyfile.y_sum {
+            failures.push("public_key_commitment");
+        }
+        if keyfile.shared_keys.y != keyfile.y_sum {
+            failures.push("shared_public_key");
+        }
+        if keyfile.paillier_key_vec[index].n != keyfile.party_keys.ek.n {
+            failures.push("paillier_key");
+        }
+    }
+    failures
+}
+
+/// Checks a keys file with share_failures and prints every failed check
+pub fn verify_share(keysfile_path: &str) {
+    let keyfile = KeyFile::load(keysfile_path);
+    let failures = share_failures(&keyfile);
+    let params = &keyfile.vss_scheme_vec[0].parameters;
+    let parties = keyfile.vss_scheme_vec.len();
+    let ret_dict = json!({
+        "status": if failures.is_empty() { "share_valid" } else { "share_invalid" },
+        "party_id": keyfile.party_id,
+        "threshold": params.threshold,
+        "parties": parties,
+        "failures": failures,
+        "x": keyfile.y_sum.x_coor(),
+        "y": keyfile.y_sum.y_coor(),
+    });
+    println!("{}", ret_dict.to_string());
+}
@@ -0,0 +1,80 @@
+use curv::elliptic::curves::secp256_k1::GE;
+use curv::BigInt;
+
+use crate::common::bitcoin::{base58check, hash160};
+use crate::common::encoding::compressed_public_key;
+use crate::common::hd_keys::{parse_path, slip10_derive};
+
+/// Named version bytes of extended public keys: xpub and tpub of BIP32, ypub of BIP49 and zpub of BIP84
+pub const XPUB_VERSIONS: [(&str, u32); 4] = [
+    ("xpub", 0x0488_b21e),
+    ("tpub", 0x0435_87cf),
+    ("ypub", 0x049d_7cb2),
+    ("zpub", 0x04b2_4746),
+];
+
+/// Version bytes of --xpub-version: one of XPUB_VERSIONS by name, or 4 bytes in hex for other networks
+pub fn version_bytes(version: &str) -> Result<u32, clap::Error> {
+    if let Some((_, bytes)) = XPUB_VERSIONS.iter().find(|(name, _)| *name == version) {
+        return Ok(*bytes);
+    }
+    let hex_version = version.trim_start_matches("0x");
+    match hex::decode(hex_version) {
+        Ok(bytes) if bytes.len() == 4 => Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+        _ => Err(clap::Error::with_description(
+            &format!(
+                "Unknown extended key version {}, use {} or 4 bytes in hex",
+                version,
+                XPUB_VERSIONS.iter().map(|(name, _)| *name).collect::<Vec<&str>>().join(", ")
+            ),
+            clap::ErrorKind::InvalidValue,
+        )),
+    }
+}
+
+/// BIP32 extended public key in base58check: version || depth || parent fingerprint || child number ||
+/// chain code || compressed key. The parent fingerprint is the first 4 bytes of hash160 of the parent key
+pub fn serialize(version: u32, depth: u8, parent_key: Option<&GE>, child_number: u32, chain_code: &[u8], public_key: &GE) -> String {
+    let parent_fingerprint = match parent_key {
+        Some(parent_key) => hash160(&compressed_public_key(parent_key))[..4].to_vec(),
+        None => vec![0u8; 4],
+    };
+    let payload = [
+        &version.to_be_bytes()[..],
+        &[depth],
+        &parent_fingerprint,
+        &child_number.to_be_bytes(),
+        chain_code,
+        &compressed_public_key(public_key),
+    ]
+    .concat();
+    base58check(&payload)
+}
+
+/// Extended public key of the threshold key, or of its slip10 child at path, with the chain code of the key.
+/// Wallets derive from it the non-hardened children signing with --derivation slip10 signs for
+pub fn extended_public_key(y_sum: &GE, path: &str, version: u32) -> Result<String, clap::Error> {
+    let path_vector: Vec<BigInt> = match path.is_empty() {
+        true => Vec::new(),
+        false => parse_path(path)?,
+    };
+    if path_vector.len() > 255 {
+        return Err(clap::Error::with_description(
+            &format!("Derivation path {} is deeper than the 255 levels of an extended key", path),
+            clap::ErrorKind::InvalidValue,
+        ));
+    }
+    let child = slip10_derive(y_sum, &path_vector);
+    let child_number: u32 = match path.is_empty() {
+        true => 0,
+        false => path.rsplit('/').next().unwrap().trim().parse().unwrap(),
+    };
+    Ok(serialize(
+        version,
+        path_vector.len() as u8,
+        child.parent_key.as_ref(),
+        child_number,
+        &child.chain_code,
+        &child.public_key,
+    ))
+}
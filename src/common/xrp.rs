@@ -0,0 +1,350 @@
+use std::fs;
+
+use curv::elliptic::curves::secp256_k1::GE;
+use curv::elliptic::curves::traits::ECScalar;
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2018::party_i::SignatureRecid;
+use ring::digest::{digest, SHA512};
+use serde_json::{json, Map, Value};
+
+use crate::common::bitcoin::{base58_decode, base58_encode, checksum, hash160};
+use crate::common::encoding::{compressed_public_key, der_signature};
+
+const XRP_ALPHABET: &[u8] = b"rpshnaf39wBUDNEGHJKLM4PQRST7VWXYZ2bcdeCg65jkm8oFqi1tuvAxyz";
+// Hash prefixes: "STX\0" for single signing, "TXN\0" for the transaction id
+const SIGNING_PREFIX: [u8; 4] = [0x53, 0x54, 0x58, 0x00];
+const TRANSACTION_ID_PREFIX: [u8; 4] = [0x54, 0x58, 0x4e, 0x00];
+const OBJECT_END_MARKER: u8 = 0xe1;
+const ARRAY_END_MARKER: u8 = 0xf1;
+
+// Serialized type codes
+const UINT16: u8 = 1;
+const UINT32: u8 = 2;
+const UINT64: u8 = 3;
+const HASH256: u8 = 5;
+const AMOUNT: u8 = 6;
+const BLOB: u8 = 7;
+const ACCOUNT_ID: u8 = 8;
+const OBJECT: u8 = 14;
+const ARRAY: u8 = 15;
+
+// (name, type code, field code) of the transaction fields that can be signed
+const FIELDS: [(&str, u8, u8); 38] = [
+    ("TransactionType", UINT16, 2),
+    ("NetworkID", UINT32, 1),
+    ("Flags", UINT32, 2),
+    ("SourceTag", UINT32, 3),
+    ("Sequence", UINT32, 4),
+    ("Expiration", UINT32, 10),
+    ("TransferRate", UINT32, 11),
+    ("DestinationTag", UINT32, 14),
+    ("QualityIn", UINT32, 20),
+    ("QualityOut", UINT32, 21),
+    ("OfferSequence", UINT32, 25),
+    ("LastLedgerSequence", UINT32, 27),
+    ("CancelAfter", UINT32, 36),
+    ("FinishAfter", UINT32, 37),
+    ("SetFlag", UINT32, 33),
+    ("ClearFlag", UINT32, 34),
+    ("TicketCount", UINT32, 40),
+    ("TicketSequence", UINT32, 41),
+    ("AccountTxnID", HASH256, 9),
+    ("InvoiceID", HASH256, 17),
+    ("Amount", AMOUNT, 1),
+    ("LimitAmount", AMOUNT, 3),
+    ("TakerPays", AMOUNT, 4),
+    ("TakerGets", AMOUNT, 5),
+    ("Fee", AMOUNT, 8),
+    ("SendMax", AMOUNT, 9),
+    ("DeliverMin", AMOUNT, 10),
+    ("SigningPubKey", BLOB, 3),
+    ("TxnSignature", BLOB, 4),
+    ("Domain", BLOB, 7),
+    ("MemoType", BLOB, 12),
+    ("MemoData", BLOB, 13),
+    ("MemoFormat", BLOB, 14),
+    ("Account", ACCOUNT_ID, 1),
+    ("Destination", ACCOUNT_ID, 3),
+    ("RegularKey", ACCOUNT_ID, 8),
+    ("Memo", OBJECT, 10),
+    ("Memos", ARRAY, 9),
+];
+
+const TRANSACTION_TYPES: [(&str, u16); 11] = [
+    ("Payment", 0),
+    ("EscrowCreate", 1),
+    ("EscrowFinish", 2),
+    ("AccountSet", 3),
+    ("EscrowCancel", 4),
+    ("SetRegularKey", 5),
+    ("OfferCreate", 7),
+    ("OfferCancel", 8),
+    ("TicketCreate", 10),
+    ("TrustSet", 20),
+    ("AccountDelete", 21),
+];
+
+fn sha512_half(data: &[u8]) -> Vec<u8> {
+    digest(&SHA512, data).as_ref()[..32].to_vec()
+}
+
+/// Classic address of a public key: base58check in the XRP alphabet of 0x00 || ripemd160(sha256(compressed key))
+pub fn address(y_sum: &GE) -> String {
+    let payload = [vec![0x00], hash160(&compressed_public_key(y_sum))].concat();
+    base58_encode(&[payload.clone(), checksum(&payload)].concat(), XRP_ALPHABET)
+}
+
+fn account_id(address: &str) -> Vec<u8> {
+    let data = base58_decode(address, XRP_ALPHABET)
+        .filter(|data| data.len() == 25 && data[0] == 0x00)
+        .unwrap_or_else(|| panic!("Invalid XRP Ledger address {}", address));
+    if checksum(&data[..21]) != data[21..] {
+        panic!("Invalid XRP Ledger address {}, checksum mismatch", address);
+    }
+    data[1..21].to_vec()
+}
+
+fn field_id(type_code: u8, field_code: u8) -> Vec<u8> {
+    match (type_code < 16, field_code < 16) {
+        (true, true) => vec![type_code << 4 | field_code],
+        (false, true) => vec![field_code, type_code],
+        (true, false) => vec![type_code << 4, field_code],
+        (false, false) => vec![0, type_code, field_code],
+    }
+}
+
+// Variable length prefix of blobs and account ids
+fn length_prefix(len: usize) -> Vec<u8> {
+    match len {
+        0..=192 => vec![len as u8],
+        193..=12480 => {
+            let len = len - 193;
+            vec![193 + (len >> 8) as u8, (len & 0xff) as u8]
+        }
+        12481..=918_744 => {
+            let len = len - 12481;
+            vec![241 + (len >> 16) as u8, ((len >> 8) & 0xff) as u8, (len & 0xff) as u8]
+        }
+        _ => panic!("XRP Ledger blob of {} bytes is too long", len),
+    }
+}
+
+fn unsigned(value: &Value, max: u64, name: &str) -> u64 {
+    let n = match value {
+        Value::Number(number) => number.as_u64(),
+        Value::String(text) => text.parse::<u64>().ok(),
+        _ => None,
+    };
+    n.filter(|n| *n <= max).unwrap_or_else(|| panic!("{} must be an integer up to {}", name, max))
+}
+
+fn hex_field(value: &Value, name: &str) -> Vec<u8> {
+    let text = value.as_str().unwrap_or_else(|| panic!("{} must be a hex string", name));
+    hex::decode(text).unwrap_or_else(|_| panic!("{} is not valid hex: {}", name, text))
+}
+
+fn currency_code(currency: &str) -> Vec<u8> {
+    match currency.len() {
+        3 if currency != "XRP" && currency.is_ascii() => [vec![0u8; 12], currency.as_bytes().to_vec(), vec![0u8; 5]].concat(),
+        40 => hex::decode(currency).unwrap_or_else(|_| panic!("Invalid currency code {}", currency)),
+        _ => panic!("Invalid currency code {}, use a 3 letter code other than XRP or 40 hex digits", currency),
+    }
+}
+
+fn invalid_value(name: &str, text: &str) -> ! {
+    panic!("{} value is not a decimal number: {}", name, text)
+}
+
+// Issued currency value: sign, normalized mantissa in [10^15, 10^16) and exponent in -96..=80
+fn issued_value(text: &str, name: &str) -> u64 {
+    let (negative, unsigned_text) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let (number, exponent) = match unsigned_text.find(|c| c == 'e' || c == 'E') {
+        Some(i) => (&unsigned_text[..i], unsigned_text[i + 1..].parse::<i32>().unwrap_or_else(|_| invalid_value(name, text))),
+        None => (unsigned_text, 0),
+    };
+    let (integer, fraction) = number.split_once('.').unwrap_or((number, ""));
+    let digits = format!("{}{}", integer, fraction);
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        invalid_value(name, text);
+    }
+    let digits = digits.trim_start_matches('0');
+    let trimmed = digits.trim_end_matches('0');
+    let mut exponent = exponent - fraction.len() as i32 + (digits.len() - trimmed.len()) as i32;
+    if trimmed.is_empty() {
+        return 1 << 63;
+    }
+    if trimmed.len() > 16 {
+        panic!("{} value {} has more than 16 significant digits", name, text);
+    }
+    let mut mantissa = trimmed.parse::<u64>().unwrap();
+    while mantissa < 1_000_000_000_000_000 {
+        mantissa *= 10;
+        exponent -= 1;
+    }
+    if exponent < -96 || exponent > 80 {
+        panic!("{} value {} is out of range", name, text);
+    }
+    let sign: u64 = if negative { 0 } else { 1 << 62 };
+    1 << 63 | sign | ((exponent + 97) as u64) << 54 | mantissa
+}
+
+fn amount(value: &Value, name: &str) -> Vec<u8> {
+    match value {
+        Value::Object(fields) => {
+            let part = |key: &str| {
+                fields.get(key).and_then(|v| v.as_str()).unwrap_or_else(|| panic!("{} must have a {} string", name, key))
+            };
+            [
+                issued_value(part("value"), name).to_be_bytes().to_vec(),
+                currency_code(part("currency")),
+                account_id(part("issuer")),
+            ]
+            .concat()
+        }
+        // XRP in drops, positive with bit 62 set
+        _ => (1u64 << 62 | unsigned(value, 100_000_000_000_000_000, name)).to_be_bytes().to_vec(),
+    }
+}
+
+fn field(name: &str) -> (u8, u8) {
+    FIELDS
+        .iter()
+        .find(|(field, _, _)| *field == name)
+        .map(|(_, type_code, field_code)| (*type_code, *field_code))
+        .unwrap_or_else(|| panic!("XRP Ledger field {} is not supported", name))
+}
+
+fn serialize_value(name: &str, type_code: u8, value: &Value) -> Vec<u8> {
+    match type_code {
+        UINT16 if name == "TransactionType" => {
+            let code = match value {
+                Value::String(tx_type) => TRANSACTION_TYPES
+                    .iter()
+                    .find(|(known, _)| known == tx_type)
+                    .map(|(_, code)| *code)
+                    .unwrap_or_else(|| panic!("XRP Ledger transaction type {} is not supported", tx_type)),
+                _ => unsigned(value, u16::MAX as u64, name) as u16,
+            };
+            code.to_be_bytes().to_vec()
+        }
+        UINT16 => (unsigned(value, u16::MAX as u64, name) as u16).to_be_bytes().to_vec(),
+        UINT32 => (unsigned(value, u32::MAX as u64, name) as u32).to_be_bytes().to_vec(),
+        UINT64 => unsigned(value, u64::MAX, name).to_be_bytes().to_vec(),
+        HASH256 => {
+            let hash = hex_field(value, name);
+            if hash.len() != 32 {
+                panic!("{} must be 32 bytes", name);
+            }
+            hash
+        }
+        AMOUNT => amount(value, name),
+        BLOB => {
+            let blob = hex_field(value, name);
+            [length_prefix(blob.len()), blob].concat()
+        }
+        ACCOUNT_ID => {
+            let address = value.as_str().unwrap_or_else(|| panic!("{} must be an address", name));
+            [vec![20], account_id(address)].concat()
+        }
+        OBJECT => {
+            let fields = value.as_object().unwrap_or_else(|| panic!("{} must be an object", name));
+            [serialize_fields(fields), vec![OBJECT_END_MARKER]].concat()
+        }
+        ARRAY => {
+            let items = value.as_array().unwrap_or_else(|| panic!("{} must be an array", name));
+            let mut out: Vec<u8> = items
+                .iter()
+                .flat_map(|item| {
+                    let wrapper = item.as_object().filter(|wrapper| wrapper.len() == 1);
+                    serialize_fields(wrapper.unwrap_or_else(|| panic!("{} items must be objects with a single field", name)))
+                })
+                .collect();
+            out.push(ARRAY_END_MARKER);
+            out
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Fields in canonical order, by type code then field code
+pub fn serialize_fields(fields: &Map<String, Value>) -> Vec<u8> {
+    let mut sorted: Vec<(u8, u8, &String, &Value)> = fields
+        .iter()
+        .map(|(name, value)| {
+            let (type_code, field_code) = field(name);
+            (type_code, field_code, name, value)
+        })
+        .collect();
+    sorted.sort_by_key(|(type_code, field_code, _, _)| (*type_code, *field_code));
+    sorted
+        .into_iter()
+        .flat_map(|(type_code, field_code, name, value)| [field_id(type_code, field_code), serialize_value(name, type_code, value)].concat())
+        .collect()
+}
+
+/// XRP Ledger transaction in its JSON form, signed with a single key
+pub struct XrpTransaction {
+    fields: Map<String, Value>,
+}
+
+impl XrpTransaction {
+    /// Transaction from a JSON file or a JSON string. SigningPubKey is set to the signing key
+    pub fn load(input: &str, y_sum: &GE) -> Self {
+        let content = fs::read_to_string(input).unwrap_or_else(|_| input.to_string());
+        let tx: Value = serde_json::from_str(&content).expect("XRP Ledger transaction must be a JSON file or JSON");
+        let mut fields = tx.as_object().expect("XRP Ledger transaction must be a JSON object").clone();
+        if fields.contains_key("TxnSignature") || fields.contains_key("Signers") {
+            panic!("XRP Ledger transaction is already signed");
+        }
+        for required in &["TransactionType", "Account", "Fee", "Sequence"] {
+            if !fields.contains_key(*required) {
+                panic!("XRP Ledger transaction has no {}", required);
+            }
+        }
+        let public_key = hex::encode_upper(compressed_public_key(y_sum));
+        match fields.get("SigningPubKey").and_then(|key| key.as_str()) {
+            Some(key) if !key.eq_ignore_ascii_case(&public_key) => {
+                panic!("SigningPubKey {} of the transaction is not the signing key {}", key, public_key)
+            }
+            _ => fields.insert("SigningPubKey".to_string(), json!(public_key)),
+        };
+        if fields["Account"] != json!(address(y_sum)) {
+            // The signing key must then be the regular key of the account
+            eprintln!(
+                "{}",
+                json!({"status": "regular_key_signing", "account": fields["Account"], "address": address(y_sum)}).to_string()
+            );
+        }
+        XrpTransaction { fields }
+    }
+
+    /// SHA-512Half of the "STX\0" prefix and the transaction without signature
+    pub fn signing_hash(&self) -> Vec<u8> {
+        sha512_half(&[SIGNING_PREFIX.to_vec(), serialize_fields(&self.fields)].concat())
+    }
+
+    /// Serialized signed transaction, the DER signature as TxnSignature
+    pub fn signed(&self, signature: &[u8]) -> Vec<u8> {
+        let mut fields = self.fields.clone();
+        fields.insert("TxnSignature".to_string(), json!(hex::encode_upper(signature)));
+        serialize_fields(&fields)
+    }
+}
+
+pub fn signed_transaction_json(tx: &XrpTransaction, sig: &SignatureRecid, y_sum: &GE, context: &str) -> Value {
+    let signature = der_signature(&sig.r.to_big_int(), &sig.s.to_big_int());
+    let blob = tx.signed(&signature);
+    let mut ret_dict = json!({
+        "status": "transaction_signed",
+        "account": address(y_sum),
+        "signature": hex::encode_upper(&signature),
+        "tx_blob": hex::encode_upper(&blob),
+        "hash": hex::encode_upper(sha512_half(&[TRANSACTION_ID_PREFIX.to_vec(), blob].concat())),
+    });
+    if !context.is_empty() {
+        ret_dict["context"] = json!(context);
+    }
+    ret_dict
+}
@@ -4,68 +4,892 @@
 extern crate clap;
 extern crate curv;
 extern crate hex;
+#[cfg(feature = "signer")]
 extern crate multi_party_ecdsa;
+#[cfg(feature = "signer")]
 extern crate paillier;
 extern crate reqwest;
 extern crate serde_json;
 
-use std::fs;
-
 use clap::{App, AppSettings, Arg, SubCommand};
-use curv::cryptographic_primitives::secret_sharing::feldman_vss::VerifiableSS;
+#[cfg(feature = "signer")]
+use clap::ArgMatches;
+#[cfg(feature = "signer")]
 use curv::elliptic::curves::traits::*;
 
+#[cfg(feature = "signer")]
 use curv::{
     BigInt,
     elliptic::curves::secp256_k1::{GE},
-    arithmetic::Converter
 };
+#[cfg(feature = "signer")]
 use curv::elliptic::curves::secp256_k1::FE;
-use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2018::party_i::*;
-use paillier::*;
-use serde_json::json;
+#[cfg(feature = "signer")]
+use serde_json::{json, Value};
 
-use common::{hd_keys, keygen, manager, signer, Params};
+#[cfg(feature = "manager")]
+use common::manager;
+#[cfg(all(feature = "signer", feature = "manager"))]
+use common::selftest;
+#[cfg(all(feature = "signer", feature = "manager"))]
+use common::pool;
+#[cfg(feature = "signer")]
+use common::{approval, artifact, bench, bitcoin, chain_code, compare, cose, cosmos, csr, doctor, doh, duplicates, encoding, ethereum, export, hd_keys, healthcheck, hsm, import, interchange, jwt, key_dir, key_encryption, keychain, keygen, keystore, message_hash, migrations, observer, options, preflight, presign, psbt, recover, refresh, rekey, reshare, schema, signer, taproot, verify, verify_share, xpub, xrp, Params};
+#[cfg(feature = "signer")]
+use common::duplicates::DuplicateGuard;
+#[cfg(feature = "signer")]
+use common::keyfile::{KeyFile, KeyMetadata};
+#[cfg(feature = "signer")]
+use common::keys_lock::KeysFileLock;
 
 mod common;
+#[cfg(feature = "signer")]
 mod test;
 
 fn main() {
-    let matches = App::new("TSS CLI Utility")
+    let app = App::new("TSS CLI Utility")
         .version("0.1.0")
         .author("Kaspars Sprogis <darklow@gmail.com>")
 //        .about("")
         .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(Arg::with_name("local_addr")
+            .long("local-addr")
+            .takes_value(true)
+            .global(true)
+            .help("Local IP address to bind outbound connections to, selects the network interface"))
+        .arg(Arg::with_name("bind_interface")
+            .long("bind-interface")
+            .takes_value(true)
+            .global(true)
+            .help("Network interface to bind outbound connections to, e.g. wg0, instead of --local-addr"))
+        .arg(Arg::with_name("no_compression")
+            .long("no-compression")
+            .global(true)
+            .help("Do not ask the manager for gzip-compressed responses"))
+        .arg(Arg::with_name("password_file")
+            .long("password-file")
+            .takes_value(true)
+            .global(true)
+            .help("File with the keys file passphrase, asked on the terminal for encrypted keys files otherwise"))
+        .arg(Arg::with_name("age_identity")
+            .long("age-identity")
+            .takes_value(true)
+            .global(true)
+            .help("age identity file decrypting keys files encrypted to age recipients"))
+        .arg(Arg::with_name("age_recipient")
+            .long("age-recipient")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .global(true)
+            .help("age X25519 recipient to encrypt keys files to, repeatable. Rewrites keep the recipients of the \
+                   loaded file unless given"))
+        .arg(Arg::with_name("kms_key_id")
+            .long("kms-key-id")
+            .takes_value(true)
+            .global(true)
+            .help("AWS KMS key to envelope encrypt keys files with (keygen), or to require of a loaded keys file (sign)"))
+        .arg(Arg::with_name("pkcs11_module")
+            .long("pkcs11-module")
+            .takes_value(true)
+            .global(true)
+            .help("PKCS#11 module library of the token wrapping keys files, e.g. /usr/lib/softhsm/libsofthsm2.so"))
+        .arg(Arg::with_name("lock_wait")
+            .long("wait-lock")
+            .alias("lock-wait")
+            .takes_value(true)
+            .global(true)
+            .help("Seconds to wait for a keys file in use by another command, fails at once by default"))
+        .arg(Arg::with_name("integrity_key")
+            .long("integrity-key")
+            .takes_value(true)
+            .global(true)
+            .help("File with a hex key of at least 32 bytes keys files are MACed with, the passphrase is used otherwise"))
+        .arg(Arg::with_name("keys_dir")
+            .long("keys-dir")
+            .takes_value(true)
+            .global(true)
+            .help("Directory of named keys used by --key and the key commands, ~/.tss-cli/keys by default"))
+        .arg(Arg::with_name("paranoid")
+            .long("paranoid")
+            .global(true)
+            .help("Blind the key share and nonce in the signing arithmetic, all signers of a room need a tss_cli that knows it"))
+        .arg(Arg::with_name("threads")
+            .long("threads")
+            .takes_value(true)
+            .global(true)
+            .help("Worker threads of the signing MtA rounds, one per core by default"))
+        .arg(Arg::with_name("approval_url")
+            .long("approval-url")
+            .takes_value(true)
+            .global(true)
+            .help("Approval service to POST every signing request to, signing waits for its signed approval"))
+        .arg(Arg::with_name("approval_key")
+            .long("approval-key")
+            .takes_value(true)
+            .global(true)
+            .help("secp256k1 public key in hex the approval service signs its decisions with"))
+        .arg(Arg::with_name("approval_timeout")
+            .long("approval-timeout")
+            .takes_value(true)
+            .global(true)
+            .help("Seconds to wait for the approval service before refusing to sign, 300 by default"))
+        .arg(Arg::with_name("manager_timeout")
+            .long("manager-timeout")
+            .takes_value(true)
+            .global(true)
+            .help("Seconds a manager request may take before it is retried, 30 by default, 0 for no limit"))
+        .arg(Arg::with_name("chain_code")
+            .long("chain-code")
+            .takes_value(true)
+            .global(true)
+            .help("HD chain code in hex (32 bytes), set at keygen and given to every command that derives keys"))
+        .arg(Arg::with_name("chain_code_mnemonic")
+            .long("chain-code-mnemonic")
+            .takes_value(true)
+            .global(true)
+            .help("File with a BIP39 mnemonic the HD chain code is derived from, all parties must use the same"))
+        .arg(Arg::with_name("mnemonic_passphrase_file")
+            .long("mnemonic-passphrase-file")
+            .takes_value(true)
+            .global(true)
+            .help("File with the BIP39 passphrase of --chain-code-mnemonic, empty by default"))
+        .arg(Arg::with_name("derivation")
+            .long("derivation")
+            .takes_value(true)
+            .global(true)
+            .possible_values(&["tss-additive", "slip10"])
+            .help("HD derivation of child keys: tss-additive (default) or slip10, the BIP32 public derivation of \
+                   other wallets, which needs a chain code"));
+
+    // Role-separated builds leave out the commands of the other role
+    #[cfg(feature = "manager")]
+    let app = app.subcommand(SubCommand::with_name("manager").about("Run state manager"));
+    #[cfg(feature = "signer")]
+    let app = app
         .subcommands(vec![
-            SubCommand::with_name("manager").about("Run state manager"),
+            SubCommand::with_name("healthcheck").about("Sign and verify a test message with the online quorum, report participation and round latencies")
+                .arg(Arg::with_name("keysfile")
+                    .required(true)
+                    .index(1)
+                    .takes_value(true)
+                    .help("Keys file"))
+                .arg(Arg::with_name("params")
+                    .index(2)
+                    .required(true)
+                    .takes_value(true)
+                    .help("Threshold params: threshold/parties (t+1/n). E.g. 1/3 for 2 of 3 schema."))
+                .arg(signers_arg().help("Number of parties expected online, between t+1 and n. Defaults to t+1"))
+                .arg(Arg::with_name("nonce")
+                    .long("nonce")
+                    .required(true)
+                    .takes_value(true)
+                    .help("Run id given by all parties of this run, e.g. the scheduled time. Each run needs a new one"))
+                .arg(manager_addr_arg()),
+            SubCommand::with_name("doctor").about("Check manager connectivity and clock skew")
+                .arg(manager_addr_arg()),
+            SubCommand::with_name("manager-bench").about("Load test a manager with simulated signing rooms")
+                .arg(Arg::with_name("rooms")
+                    .short("r")
+                    .long("rooms")
+                    .takes_value(true)
+                    .default_value("10")
+                    .help("Number of concurrent rooms"))
+                .arg(Arg::with_name("parties")
+                    .short("n")
+                    .long("parties")
+                    .takes_value(true)
+                    .default_value("3")
+                    .help("Number of parties in each room"))
+                .arg(Arg::with_name("rounds")
+                    .long("rounds")
+                    .takes_value(true)
+                    .default_value("10")
+                    .help("Number of broadcast rounds each room runs"))
+                .arg(Arg::with_name("payload_size")
+                    .long("payload-size")
+                    .takes_value(true)
+                    .default_value("1024")
+                    .help("Size of each fake round payload in bytes"))
+                .arg(manager_addr_arg()),
+            SubCommand::with_name("observe").about("Observe signing room lifecycle and message digests")
+                .arg(Arg::with_name("room_id")
+                    .required(true)
+                    .index(1)
+                    .takes_value(true)
+                    .help("Room id (sha256 of the message being signed)"))
+                .arg(manager_addr_arg()),
             SubCommand::with_name("keygen").about("Run keygen")
+                .arg(Arg::with_name("keysfile")
+                    .required_unless("key")
+                    .index(1)
+                    .takes_value(true)
+                    .help("Target keys file"))
+                .arg(Arg::with_name("params")
+                    .index(2)
+                    .required_unless("key")
+                    .takes_value(true)
+                    .help("Threshold params: threshold/parties (t+1/n). E.g. 1/3 for 2 of 3 schema."))
+                .arg(Arg::with_name("key")
+                    .long("key")
+                    .takes_value(true)
+                    .help("Save the new key under this name in the keys directory instead of a keys file"))
+                .arg(manager_addr_arg().help("URL to manager. E.g. http://127.0.0.2:8002"))
+                .arg(Arg::with_name("canary")
+                    .long("canary")
+                    .help("Sign and verify a test message with all parties after keygen"))
+                .arg(Arg::with_name("encrypt")
+                    .long("encrypt")
+                    .help("Encrypt the keys file with a passphrase, asked on the terminal unless --password-file is given"))
+                .arg(Arg::with_name("keychain")
+                    .long("keychain")
+                    .help("Keep the secret share in the OS keychain, the keys file holds public data only"))
+                .arg(Arg::with_name("hsm_key_label")
+                    .long("hsm-key-label")
+                    .takes_value(true)
+                    .help("Wrap the secret share with the AES key of this label on the --pkcs11-module token")),
+            SubCommand::with_name("encrypt-keys").about("Encrypt a plaintext keys file with a passphrase")
+                .arg(Arg::with_name("keysfile")
+                    .required(true)
+                    .index(1)
+                    .takes_value(true)
+                    .help("Keys file, replaced by the encrypted one")),
+            SubCommand::with_name("keychain").about("Move the secret share of a keys file to or from the OS keychain")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(SubCommand::with_name("store").about("Move the secret share into the keychain")
+                    .arg(Arg::with_name("keysfile")
+                        .required(true)
+                        .index(1)
+                        .takes_value(true)
+                        .help("Keys file, rewritten with public data and the keychain reference")))
+                .subcommand(SubCommand::with_name("restore").about("Move the secret share back into the keys file")
+                    .arg(Arg::with_name("keysfile")
+                        .required(true)
+                        .index(1)
+                        .takes_value(true)
+                        .help("Keychain backed keys file"))),
+            SubCommand::with_name("hsm").about("Wrap the secret share of a keys file with an HSM key or unwrap it")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(SubCommand::with_name("wrap").about("Wrap the secret share with an AES key on the token")
+                    .arg(Arg::with_name("keysfile")
+                        .required(true)
+                        .index(1)
+                        .takes_value(true)
+                        .help("Keys file, rewritten with the wrapped share"))
+                    .arg(Arg::with_name("key_label")
+                        .long("key-label")
+                        .required(true)
+                        .takes_value(true)
+                        .help("Label of the AES key on the --pkcs11-module token")))
+                .subcommand(SubCommand::with_name("unwrap").about("Write the secret share back into the keys file")
+                    .arg(Arg::with_name("keysfile")
+                        .required(true)
+                        .index(1)
+                        .takes_value(true)
+                        .help("HSM wrapped keys file"))),
+            SubCommand::with_name("key").about("Manage the named keys of the keys directory")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(SubCommand::with_name("list").about("List named keys with their metadata"))
+                .subcommand(SubCommand::with_name("show").about("Show the metadata of a named key")
+                    .arg(Arg::with_name("name")
+                        .required(true)
+                        .index(1)
+                        .takes_value(true)
+                        .help("Key name")))
+                .subcommand(SubCommand::with_name("rename").about("Rename a named key")
+                    .arg(Arg::with_name("name")
+                        .required(true)
+                        .index(1)
+                        .takes_value(true)
+                        .help("Key name"))
+                    .arg(Arg::with_name("new_name")
+                        .required(true)
+                        .index(2)
+                        .takes_value(true)
+                        .help("New key name")))
+                .subcommand(SubCommand::with_name("delete").about("Delete the share of a named key")
+                    .arg(Arg::with_name("name")
+                        .required(true)
+                        .index(1)
+                        .takes_value(true)
+                        .help("Key name"))
+                    .arg(Arg::with_name("yes")
+                        .long("yes")
+                        .help("Delete without typing the key name again"))),
+            SubCommand::with_name("keystore").about("Convert keys files to and from EIP-2335 style keystores")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(SubCommand::with_name("export").about("Write a keys file as a keystore")
+                    .arg(Arg::with_name("keysfile")
+                        .required(true)
+                        .index(1)
+                        .takes_value(true)
+                        .help("Keys file"))
+                    .arg(Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .required(true)
+                        .takes_value(true)
+                        .help("Keystore file")))
+                .subcommand(SubCommand::with_name("import").about("Write a keystore as a keys file")
+                    .arg(Arg::with_name("keystore")
+                        .required(true)
+                        .index(1)
+                        .takes_value(true)
+                        .help("Keystore file"))
+                    .arg(Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .required(true)
+                        .takes_value(true)
+                        .help("Keys file"))
+                    .arg(Arg::with_name("plaintext")
+                        .long("plaintext")
+                        .help("Write an unencrypted keys file, it is encrypted with the keystore passphrase otherwise"))),
+            SubCommand::with_name("verify").about("Verify a signature locally, exits with 1 when it is invalid")
+                .arg(Arg::with_name("message")
+                    .required(true)
+                    .index(1)
+                    .takes_value(true)
+                    .help("Message that was signed, hex or string as given to sign"))
+                .arg(Arg::with_name("signature")
+                    .required(true)
+                    .index(2)
+                    .takes_value(true)
+                    .help("Signature JSON as printed by sign: file or JSON"))
+                .arg(Arg::with_name("keysfile")
+                    .long("keysfile")
+                    .takes_value(true)
+                    .conflicts_with_all(&["key", "public_key"])
+                    .help("Keys file whose public key the signature is verified against"))
+                .arg(Arg::with_name("key")
+                    .long("key")
+                    .takes_value(true)
+                    .conflicts_with("public_key")
+                    .help("Named key of the keys directory whose public key the signature is verified against"))
+                .arg(Arg::with_name("public_key")
+                    .long("public-key")
+                    .takes_value(true)
+                    .help("secp256k1 public key in hex. Without a key, the x and y of the signature JSON are used"))
+                .arg(Arg::with_name("path")
+                    .short("p")
+                    .long("path")
+                    .takes_value(true)
+                    .help("Derivation path, the signature is verified against the child of the given key"))
+                .arg(Arg::with_name("hash")
+                    .long("hash")
+                    .takes_value(true)
+                    .possible_values(&message_hash::MESSAGE_HASHES)
+                    .default_value("none")
+                    .help("Hash the message was signed with")),
+            SubCommand::with_name("import").about("Convert a key share of another threshold signing tool into a keys file")
+                .arg(Arg::with_name("input")
+                    .required(true)
+                    .index(1)
+                    .takes_value(true)
+                    .help("Key share file of the other tool"))
+                .arg(Arg::with_name("format")
+                    .long("format")
+                    .required(true)
+                    .takes_value(true)
+                    .possible_values(&interchange::IMPORT_FORMATS)
+                    .help("Format of the key share: tss-lib for bnb-chain/tss-lib ECDSA save data, zengo for ZenGo-X gg18 keys files"))
+                .arg(Arg::with_name("output")
+                    .short("o")
+                    .long("output")
+                    .required(true)
+                    .takes_value(true)
+                    .help("Keys file")),
+            SubCommand::with_name("export").about("Write a keys file as a key share of another threshold signing tool")
+                .arg(Arg::with_name("keysfile")
+                    .required(true)
+                    .index(1)
+                    .takes_value(true)
+                    .help("Keys file"))
+                .arg(Arg::with_name("format")
+                    .long("format")
+                    .required(true)
+                    .takes_value(true)
+                    .possible_values(&interchange::EXPORT_FORMATS)
+                    .help("Format of the key share: zengo for ZenGo-X gg18 keys files"))
+                .arg(Arg::with_name("output")
+                    .short("o")
+                    .long("output")
+                    .required(true)
+                    .takes_value(true)
+                    .help("Plaintext key share file")),
+            SubCommand::with_name("rekey").about("Change the passphrase and Argon2id cost of encrypted keys files")
+                .arg(Arg::with_name("path")
+                    .required(true)
+                    .index(1)
+                    .takes_value(true)
+                    .help("Encrypted keys file, or keystore directory to rekey every encrypted keys file in it"))
+                .arg(Arg::with_name("new_password_file")
+                    .long("new-password-file")
+                    .takes_value(true)
+                    .help("File with the new passphrase, asked on the terminal otherwise"))
+                .arg(Arg::with_name("m_cost")
+                    .long("m-cost")
+                    .takes_value(true)
+                    .help("Argon2id memory in KiB, default 65536"))
+                .arg(Arg::with_name("t_cost")
+                    .long("t-cost")
+                    .takes_value(true)
+                    .help("Argon2id passes, default 3"))
+                .arg(Arg::with_name("p_cost")
+                    .long("p-cost")
+                    .takes_value(true)
+                    .help("Argon2id lanes, default 1"))
+                .arg(Arg::with_name("remove_backup")
+                    .long("remove-backup")
+                    .help("Delete the backup encrypted with the old passphrase once the new file is checked to open")),
+            SubCommand::with_name("rotate-passphrase").about("Rotate the passphrase of an encrypted keys file, recorded in an audit log")
+                .arg(Arg::with_name("keysfile")
+                    .required(true)
+                    .index(1)
+                    .takes_value(true)
+                    .help("Encrypted keys file"))
+                .arg(Arg::with_name("new_password_file")
+                    .long("new-password-file")
+                    .takes_value(true)
+                    .help("File with the new passphrase, asked on the terminal otherwise"))
+                .arg(Arg::with_name("audit")
+                    .long("audit")
+                    .takes_value(true)
+                    .default_value("passphrase-audit.jsonl")
+                    .help("Audit log the rotation is appended to")),
+            SubCommand::with_name("compare-keys").about("Check whether two keys files hold the same key share")
+                .arg(Arg::with_name("first")
+                    .required(true)
+                    .index(1)
+                    .takes_value(true)
+                    .help("Keys file, e.g. a backup"))
+                .arg(Arg::with_name("second")
+                    .required(true)
+                    .index(2)
+                    .takes_value(true)
+                    .help("Keys file to compare with, e.g. the live copy")),
+            SubCommand::with_name("export-public").about("Export the public key material of a keys file for watch-only use")
+                .arg(Arg::with_name("keysfile")
+                    .required(true)
+                    .index(1)
+                    .takes_value(true)
+                    .help("Keys file"))
+                .arg(Arg::with_name("out")
+                    .short("o")
+                    .long("out")
+                    .takes_value(true)
+                    .help("Target JSON file, printed when omitted")),
+            SubCommand::with_name("xpub").about("Print the BIP32 extended public key of the key and its chain code for watch-only wallets, \
+                    needs --derivation slip10")
+                .arg(Arg::with_name("keysfile")
+                    .required(true)
+                    .index(1)
+                    .takes_value(true)
+                    .help("Keys file"))
+                .arg(Arg::with_name("path")
+                    .short("p")
+                    .long("path")
+                    .takes_value(true)
+                    .help("Derivation path of the account key, e.g. 0/1, derived with slip10. The key itself when omitted"))
+                .arg(Arg::with_name("xpub_version")
+                    .long("xpub-version")
+                    .takes_value(true)
+                    .default_value("xpub")
+                    .help("Version bytes: xpub, tpub, ypub, zpub or 4 bytes in hex")),
+            SubCommand::with_name("verify-share").about("Check a keys file's share against its VSS commitments and public key")
+                .arg(Arg::with_name("keysfile")
+                    .required(true)
+                    .index(1)
+                    .takes_value(true)
+                    .help("Keys file")),
+            SubCommand::with_name("keys-version").about("Show keys file format version and optionally upgrade it")
+                .arg(Arg::with_name("keysfile")
+                    .required(true)
+                    .index(1)
+                    .takes_value(true)
+                    .help("Keys file"))
+                .arg(Arg::with_name("upgrade")
+                    .long("upgrade")
+                    .conflicts_with("downgrade")
+                    .help("Rewrite the keys file in the current format"))
+                .arg(Arg::with_name("downgrade")
+                    .long("downgrade")
+                    .takes_value(true)
+                    .requires("out")
+                    .help("Write the keys file in this older format version, e.g. 1 for the tuple layout, to --out"))
+                .arg(Arg::with_name("out")
+                    .short("o")
+                    .long("out")
+                    .takes_value(true)
+                    .help("Plaintext keys file written by --downgrade, the original is left as is")),
+            SubCommand::with_name("schema").about("JSON Schemas of keys files, command outputs and manager API bodies")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(SubCommand::with_name("dump").about("Print JSON Schemas")
+                    .arg(Arg::with_name("name")
+                        .index(1)
+                        .takes_value(true)
+                        .possible_values(&schema::SCHEMA_NAMES)
+                        .help("Schema to print, all schemas keyed by name when omitted"))),
+            SubCommand::with_name("pubkey").about("Get X,Y of a pub key")
+                .arg(Arg::with_name("keysfile")
+                    .required_unless("key")
+                    .index(1)
+                    .takes_value(true)
+                    .help("Keys file"))
+                .arg(Arg::with_name("key")
+                    .long("key")
+                    .takes_value(true)
+                    .help("Named key of the keys directory to use instead of a keys file"))
+                .arg(Arg::with_name("path")
+                    .short("p")
+                    .long("path")
+                    .takes_value(true)
+                    .help("Derivation path (Optional)"))
+                .arg(Arg::with_name("taproot_tweak")
+                    .long("taproot-tweak")
+                    .takes_value(true)
+                    .help("Also output the BIP341 taproot output key for this merkle root in hex, empty for a key \
+                           path only output key. Display only, taproot spends need Schnorr signatures"))
+                .arg(Arg::with_name("format")
+                    .long("format")
+                    .takes_value(true)
+                    .possible_values(&encoding::PUBLIC_KEY_FORMATS)
+                    .help("Also output the key as public_key in this encoding: compressed or uncompressed SEC1 hex, \
+                           spki-der-hex for DER SubjectPublicKeyInfo hex, pem, jwk"))
+                .arg(Arg::with_name("paths")
+                    .long("paths")
+                    .takes_value(true)
+                    .conflicts_with_all(&["path", "taproot_tweak"])
+                    .help("Derive many paths in one run: comma separated paths and ranges like 0/0..0/1000 (both ends \
+                           included), or @file for a file with one path per line")),
+            SubCommand::with_name("address").about("Get the address of a pub key on a chain")
+                .arg(Arg::with_name("keysfile")
+                    .required_unless("key")
+                    .index(1)
+                    .takes_value(true)
+                    .help("Keys file"))
+                .arg(Arg::with_name("key")
+                    .long("key")
+                    .takes_value(true)
+                    .help("Named key of the keys directory to use instead of a keys file"))
+                .arg(Arg::with_name("chain")
+                    .long("chain")
+                    .required(true)
+                    .takes_value(true)
+                    .possible_values(&["eth", "btc", "cosmos", "xrp"])
+                    .help("Chain to derive the address for: eth for Ethereum and EVM chains, btc for Bitcoin, cosmos for Cosmos SDK chains, xrp for the XRP Ledger"))
+                .arg(Arg::with_name("prefix")
+                    .long("prefix")
+                    .takes_value(true)
+                    .default_value(cosmos::DEFAULT_PREFIX)
+                    .help("Cosmos bech32 account prefix, e.g. osmo"))
+                .arg(Arg::with_name("address_type")
+                    .long("address-type")
+                    .takes_value(true)
+                    .possible_values(&bitcoin::ADDRESS_TYPES)
+                    .default_value("p2wpkh")
+                    .help("Bitcoin address type: p2pkh or p2wpkh (bech32 segwit)"))
+                .arg(Arg::with_name("network")
+                    .long("network")
+                    .takes_value(true)
+                    .possible_values(&bitcoin::NETWORKS)
+                    .default_value("mainnet")
+                    .help("Bitcoin network, testnet addresses are also used on signet"))
+                .arg(Arg::with_name("path")
+                    .short("p")
+                    .long("path")
+                    .takes_value(true)
+                    .help("Derivation path (Optional)"))
+                .arg(Arg::with_name("paths")
+                    .long("paths")
+                    .takes_value(true)
+                    .conflicts_with("path")
+                    .help("Derive many paths in one run: comma separated paths and ranges like 0/0..0/1000 (both ends \
+                           included), or @file for a file with one path per line")),
+            SubCommand::with_name("sign").about("Run signer")
+                .arg(Arg::with_name("keysfile")
+                    .required_unless("key")
+                    .index(1)
+                    .takes_value(true)
+                    .help("Keys file"))
+                .arg(Arg::with_name("params")
+                    .index(2)
+                    .takes_value(true)
+                    .help("Threshold params: threshold/parties (t+1/n). E.g. 1/3 for 2 of 3 schema. \
+                           Read from the keys file when omitted, give the message with --message or --message-file then"))
+                .arg(Arg::with_name("message")
+                    .index(3)
+                    .takes_value(true)
+                    .help("Message to sign in hex format, - to read the raw message from stdin"))
+                .arg(Arg::with_name("message_option")
+                    .short("m")
+                    .long("message")
+                    .takes_value(true)
+                    .conflicts_with_all(&["message", "message_file"])
+                    .help("Message to sign in hex format, - for stdin, instead of the message argument"))
+                .arg(Arg::with_name("message_file")
+                    .long("message-file")
+                    .takes_value(true)
+                    .conflicts_with("message")
+                    .help("File with the raw message to sign, - for stdin, instead of the message argument"))
+                .arg(Arg::with_name("key")
+                    .long("key")
+                    .takes_value(true)
+                    .help("Named key of the keys directory to use instead of a keys file"))
+                .arg(Arg::with_name("path")
+                    .short("p")
+                    .long("path")
+                    .takes_value(true)
+                    .help("Derivation path"))
+                .arg(context_arg())
+                .arg(Arg::with_name("hash")
+                    .long("hash")
+                    .takes_value(true)
+                    .possible_values(&message_hash::MESSAGE_HASHES)
+                    .default_value("none")
+                    .help("Hash the message is signed with: none signs the message itself, a digest of at most 32 bytes, sha256, keccak256"))
+                .arg(Arg::with_name("strict_digest")
+                    .long("strict-digest")
+                    .help("Refuse messages over 32 bytes under --hash none instead of signing their last 32 bytes with a warning"))
+                .arg(Arg::with_name("eip712")
+                    .long("eip712")
+                    .takes_value(true)
+                    .conflicts_with_all(&["message", "message_file", "hash"])
+                    .help("EIP-712 typed data JSON file (types, primaryType, domain, message) to sign instead of a message"))
+                .arg(Arg::with_name("no_normalize")
+                    .long("no-normalize")
+                    .help("Keep s as computed instead of its low-s form"))
+                .arg(duplicate_guard_arg())
+                .arg(signers_arg())
+                .arg(manager_addr_arg()),
+            SubCommand::with_name("sign-artifact").about("Sign sha256 digest of a file and write a detached signature")
+                .arg(Arg::with_name("keysfile")
+                    .required(true)
+                    .index(1)
+                    .takes_value(true)
+                    .help("Keys file"))
+                .arg(Arg::with_name("params")
+                    .index(2)
+                    .required(true)
+                    .takes_value(true)
+                    .help("Threshold params: threshold/parties (t+1/n). E.g. 1/3 for 2 of 3 schema."))
+                .arg(Arg::with_name("artifact")
+                    .index(3)
+                    .required(true)
+                    .takes_value(true)
+                    .help("File to sign"))
+                .arg(Arg::with_name("path")
+                    .short("p")
+                    .long("path")
+                    .takes_value(true)
+                    .help("Derivation path"))
+                .arg(context_arg())
+                .arg(duplicate_guard_arg())
+                .arg(signers_arg())
+                .arg(manager_addr_arg()),
+            SubCommand::with_name("sign-eth-tx").about("Sign an Ethereum transaction and output it ready for broadcast")
+                .arg(Arg::with_name("keysfile")
+                    .required(true)
+                    .index(1)
+                    .takes_value(true)
+                    .help("Keys file"))
+                .arg(Arg::with_name("params")
+                    .index(2)
+                    .required(true)
+                    .takes_value(true)
+                    .help("Threshold params: threshold/parties (t+1/n). E.g. 1/3 for 2 of 3 schema."))
+                .arg(Arg::with_name("transaction")
+                    .index(3)
+                    .required(true)
+                    .takes_value(true)
+                    .help("Unsigned legacy (EIP-155) or EIP-1559 transaction: JSON file, JSON or RLP hex"))
+                .arg(Arg::with_name("path")
+                    .short("p")
+                    .long("path")
+                    .takes_value(true)
+                    .help("Derivation path"))
+                .arg(context_arg())
+                .arg(duplicate_guard_arg())
+                .arg(signers_arg())
+                .arg(manager_addr_arg()),
+            SubCommand::with_name("sign-jwt").about("Sign a JWT with ES256K and output the compact JWS")
+                .arg(Arg::with_name("keysfile")
+                    .required(true)
+                    .index(1)
+                    .takes_value(true)
+                    .help("Keys file"))
+                .arg(Arg::with_name("params")
+                    .index(2)
+                    .required(true)
+                    .takes_value(true)
+                    .help("Threshold params: threshold/parties (t+1/n). E.g. 1/3 for 2 of 3 schema."))
+                .arg(Arg::with_name("claims")
+                    .index(3)
+                    .required(true)
+                    .takes_value(true)
+                    .help("Claims: JSON file or JSON"))
+                .arg(Arg::with_name("header")
+                    .long("header")
+                    .takes_value(true)
+                    .help("JOSE header: JSON file or JSON, e.g. with kid. Defaults to {\"alg\":\"ES256K\",\"typ\":\"JWT\"}"))
+                .arg(Arg::with_name("path")
+                    .short("p")
+                    .long("path")
+                    .takes_value(true)
+                    .help("Derivation path"))
+                .arg(context_arg())
+                .arg(duplicate_guard_arg())
+                .arg(signers_arg())
+                .arg(manager_addr_arg()),
+            SubCommand::with_name("sign-cose").about("Sign a payload as COSE_Sign1 with ES256K")
+                .arg(Arg::with_name("keysfile")
+                    .required(true)
+                    .index(1)
+                    .takes_value(true)
+                    .help("Keys file"))
+                .arg(Arg::with_name("params")
+                    .index(2)
+                    .required(true)
+                    .takes_value(true)
+                    .help("Threshold params: threshold/parties (t+1/n). E.g. 1/3 for 2 of 3 schema."))
+                .arg(Arg::with_name("payload")
+                    .index(3)
+                    .required(true)
+                    .takes_value(true)
+                    .help("Payload: file or string"))
+                .arg(Arg::with_name("protected")
+                    .long("protected")
+                    .takes_value(true)
+                    .help("Protected header: JSON file or JSON with integer labels as keys, e.g. {\"3\":\"application/cwt\"}"))
+                .arg(Arg::with_name("unprotected")
+                    .long("unprotected")
+                    .takes_value(true)
+                    .help("Unprotected header: JSON file or JSON, byte strings as h'<hex>', e.g. {\"4\":\"h'6b6964'\"}"))
+                .arg(Arg::with_name("path")
+                    .short("p")
+                    .long("path")
+                    .takes_value(true)
+                    .help("Derivation path"))
+                .arg(context_arg())
+                .arg(duplicate_guard_arg())
+                .arg(signers_arg())
+                .arg(manager_addr_arg()),
+            SubCommand::with_name("sign-xrp-tx").about("Sign an XRP Ledger transaction and output the blob ready for submit")
+                .arg(Arg::with_name("keysfile")
+                    .required(true)
+                    .index(1)
+                    .takes_value(true)
+                    .help("Keys file"))
+                .arg(Arg::with_name("params")
+                    .index(2)
+                    .required(true)
+                    .takes_value(true)
+                    .help("Threshold params: threshold/parties (t+1/n). E.g. 1/3 for 2 of 3 schema."))
+                .arg(Arg::with_name("transaction")
+                    .index(3)
+                    .required(true)
+                    .takes_value(true)
+                    .help("Unsigned transaction in JSON: file or JSON"))
+                .arg(Arg::with_name("path")
+                    .short("p")
+                    .long("path")
+                    .takes_value(true)
+                    .help("Derivation path"))
+                .arg(context_arg())
+                .arg(duplicate_guard_arg())
+                .arg(signers_arg())
+                .arg(manager_addr_arg()),
+            SubCommand::with_name("sign-cosmos").about("Sign a Cosmos SDK SignDoc (SIGN_MODE_DIRECT)")
+                .arg(Arg::with_name("keysfile")
+                    .required(true)
+                    .index(1)
+                    .takes_value(true)
+                    .help("Keys file"))
+                .arg(Arg::with_name("params")
+                    .index(2)
+                    .required(true)
+                    .takes_value(true)
+                    .help("Threshold params: threshold/parties (t+1/n). E.g. 1/3 for 2 of 3 schema."))
+                .arg(Arg::with_name("sign_doc")
+                    .index(3)
+                    .required(true)
+                    .takes_value(true)
+                    .help("Protobuf encoded SignDoc: file, hex or base64"))
+                .arg(Arg::with_name("path")
+                    .short("p")
+                    .long("path")
+                    .takes_value(true)
+                    .help("Derivation path"))
+                .arg(Arg::with_name("prefix")
+                    .long("prefix")
+                    .takes_value(true)
+                    .default_value(cosmos::DEFAULT_PREFIX)
+                    .help("Bech32 account prefix of the chain, e.g. osmo"))
+                .arg(context_arg())
+                .arg(duplicate_guard_arg())
+                .arg(signers_arg())
+                .arg(manager_addr_arg()),
+            SubCommand::with_name("sign-psbt").about("Sign the inputs of a PSBT the threshold key can spend, one signing round per input")
                 .arg(Arg::with_name("keysfile")
                     .required(true)
                     .index(1)
                     .takes_value(true)
-                    .help("Target keys file"))
+                    .help("Keys file"))
                 .arg(Arg::with_name("params")
                     .index(2)
                     .required(true)
                     .takes_value(true)
                     .help("Threshold params: threshold/parties (t+1/n). E.g. 1/3 for 2 of 3 schema."))
-                .arg(Arg::with_name("manager_addr")
-                    .short("a")
-                    .long("addr")
+                .arg(Arg::with_name("psbt")
+                    .index(3)
+                    .required(true)
                     .takes_value(true)
-                    .help("URL to manager. E.g. http://127.0.0.2:8002")),
-            SubCommand::with_name("pubkey").about("Get X,Y of a pub key")
+                    .help("PSBT: file, base64 or hex"))
+                .arg(Arg::with_name("path")
+                    .short("p")
+                    .long("path")
+                    .takes_value(true)
+                    .help("Derivation path of inputs without BIP32 derivations of the key"))
+                .arg(Arg::with_name("output")
+                    .short("o")
+                    .long("output")
+                    .takes_value(true)
+                    .help("File to write the signed PSBT to in base64, printed otherwise"))
+                .arg(context_arg())
+                .arg(duplicate_guard_arg())
+                .arg(signers_arg())
+                .arg(manager_addr_arg()),
+            SubCommand::with_name("sign-csr").about("Create a certificate signing request signed by the threshold key")
                 .arg(Arg::with_name("keysfile")
                     .required(true)
                     .index(1)
                     .takes_value(true)
                     .help("Keys file"))
+                .arg(Arg::with_name("params")
+                    .index(2)
+                    .required(true)
+                    .takes_value(true)
+                    .help("Threshold params: threshold/parties (t+1/n). E.g. 1/3 for 2 of 3 schema."))
+                .arg(Arg::with_name("subject")
+                    .index(3)
+                    .required(true)
+                    .takes_value(true)
+                    .help("Certificate subject. E.g. \"CN=Example CA,O=Example,C=LV\""))
+                .arg(Arg::with_name("out")
+                    .short("o")
+                    .long("out")
+                    .takes_value(true)
+                    .help("Target CSR file in PEM format. Defaults to request.csr"))
                 .arg(Arg::with_name("path")
                     .short("p")
                     .long("path")
                     .takes_value(true)
-                    .help("Derivation path (Optional)")),
-            SubCommand::with_name("sign").about("Run signer")
+                    .help("Derivation path"))
+                .arg(context_arg())
+                .arg(duplicate_guard_arg())
+                .arg(signers_arg())
+                .arg(manager_addr_arg()),
+            SubCommand::with_name("presign").about("Run the offline signing rounds and store a presignature")
                 .arg(Arg::with_name("keysfile")
                     .required(true)
                     .index(1)
@@ -76,122 +900,1136 @@ fn main() {
                     .required(true)
                     .takes_value(true)
                     .help("Threshold params: threshold/parties (t+1/n). E.g. 1/3 for 2 of 3 schema."))
-                .arg(Arg::with_name("message")
+                .arg(Arg::with_name("label")
                     .index(3)
                     .required(true)
                     .takes_value(true)
-                    .help("Message to sign in hex format"))
+                    .help("Label agreed between the parties, identifies the presigning room"))
+                .arg(Arg::with_name("out")
+                    .short("o")
+                    .long("out")
+                    .takes_value(true)
+                    .help("Target presignature file. Defaults to <keysfile>.presig.<label>.json"))
+                .arg(Arg::with_name("path")
+                    .short("p")
+                    .long("path")
+                    .takes_value(true)
+                    .help("Derivation path"))
+                .arg(signers_arg())
+                .arg(context_arg().help("Context label, e.g. ticket number, bound into the presign room and kept in the presignature"))
+                .arg(manager_addr_arg()),
+            #[cfg(feature = "manager")]
+            SubCommand::with_name("presign-pool").about("Keep a pool of presignatures topped up and serve online signing locally")
+                .arg(Arg::with_name("keysfile")
+                    .required(true)
+                    .index(1)
+                    .takes_value(true)
+                    .help("Keys file"))
+                .arg(Arg::with_name("params")
+                    .index(2)
+                    .required(true)
+                    .takes_value(true)
+                    .help("Threshold params: threshold/parties (t+1/n). E.g. 1/3 for 2 of 3 schema."))
+                .arg(Arg::with_name("size")
+                    .short("n")
+                    .long("size")
+                    .takes_value(true)
+                    .default_value("10")
+                    .help("Number of presignatures to keep in the pool"))
+                .arg(Arg::with_name("port")
+                    .long("port")
+                    .takes_value(true)
+                    .default_value("8011")
+                    .help("Local API port, listens on 127.0.0.1"))
                 .arg(Arg::with_name("path")
                     .short("p")
                     .long("path")
                     .takes_value(true)
                     .help("Derivation path"))
-                .arg(Arg::with_name("manager_addr")
-                    .short("a")
-                    .long("addr")
+                .arg(signers_arg())
+                .arg(context_arg().help("Context label the pool presigns and signs under, all pools of the key use the same"))
+                .arg(manager_addr_arg()),
+            SubCommand::with_name("sign-online").about("Sign a message on a stored presignature, running the five phase 5 rounds (round5-round9) with all checks")
+                .arg(Arg::with_name("presignature")
+                    .required(true)
+                    .index(1)
+                    .takes_value(true)
+                    .help("Presignature file, consumed by signing"))
+                .arg(Arg::with_name("message")
+                    .index(2)
+                    .required(true)
+                    .takes_value(true)
+                    .help("Message to sign in hex format"))
+                .arg(Arg::with_name("hash")
+                    .long("hash")
+                    .takes_value(true)
+                    .possible_values(&message_hash::MESSAGE_HASHES)
+                    .default_value("none")
+                    .help("Hash the message is signed with: none signs the message itself, a digest of at most 32 bytes, sha256, keccak256"))
+                .arg(Arg::with_name("strict_digest")
+                    .long("strict-digest")
+                    .help("Refuse messages over 32 bytes under --hash none instead of signing their last 32 bytes with a warning"))
+                .arg(Arg::with_name("no_normalize")
+                    .long("no-normalize")
+                    .help("Keep s as computed instead of its low-s form"))
+                .arg(duplicate_guard_arg())
+                .arg(context_arg().help("Context label the presignature was made under, checked before signing and kept in the output"))
+                .arg(manager_addr_arg()),
+            SubCommand::with_name("reshare").about("Re-deal an existing key to a new set of parties, keeping the public key")
+                .arg(Arg::with_name("room")
+                    .short("r")
+                    .long("room")
+                    .required(true)
+                    .takes_value(true)
+                    .help("Room id agreed between all participants"))
+                .arg(Arg::with_name("participants")
+                    .long("participants")
+                    .required(true)
+                    .takes_value(true)
+                    .help("Number of processes taking part, old share holders and new parties"))
+                .arg(Arg::with_name("new_threshold")
+                    .long("new-threshold")
+                    .required(true)
+                    .takes_value(true)
+                    .help("Threshold of the new shares, t in t+1 of n"))
+                .arg(Arg::with_name("new_parties")
+                    .long("new-parties")
+                    .required(true)
+                    .takes_value(true)
+                    .help("Number of new parties"))
+                .arg(Arg::with_name("keysfile")
+                    .short("k")
+                    .long("keysfile")
+                    .takes_value(true)
+                    .requires("params")
+                    .help("Keys file of an old share holder, its share is dealt to the new parties"))
+                .arg(Arg::with_name("params")
+                    .long("params")
+                    .takes_value(true)
+                    .help("Threshold params of the old keys file: threshold/parties (t+1/n)"))
+                .arg(Arg::with_name("new_id")
+                    .long("new-id")
+                    .takes_value(true)
+                    .requires("out")
+                    .help("Party id to receive a new share as, between 1 and the new number of parties"))
+                .arg(Arg::with_name("out")
+                    .short("o")
+                    .long("out")
+                    .takes_value(true)
+                    .help("Target keys file for the new share"))
+                .arg(manager_addr_arg()),
+            SubCommand::with_name("add-party").about("Issue a share of an existing key to a new party")
+                .arg(Arg::with_name("keysfile")
+                    .required(true)
+                    .index(1)
+                    .takes_value(true)
+                    .help("Keys file, replaced by the new share. Target keys file with --join"))
+                .arg(Arg::with_name("params")
+                    .index(2)
+                    .required(true)
+                    .takes_value(true)
+                    .help("Current threshold params: threshold/parties (t+1/n). E.g. 1/3 for 2 of 3 schema."))
+                .arg(Arg::with_name("room")
+                    .short("r")
+                    .long("room")
+                    .required(true)
+                    .takes_value(true)
+                    .help("Room id agreed between all parties and the new party"))
+                .arg(Arg::with_name("join")
+                    .long("join")
+                    .help("Join as the new party, gets party id n+1"))
+                .arg(manager_addr_arg()),
+            SubCommand::with_name("import-key").about("Deal an existing private key to parties as a trusted dealer")
+                .arg(Arg::with_name("params")
+                    .index(1)
+                    .required(true)
+                    .takes_value(true)
+                    .help("Threshold params of the new key: threshold/parties (t+1/n). E.g. 1/3 for 2 of 3 schema."))
+                .arg(Arg::with_name("room")
+                    .short("r")
+                    .long("room")
+                    .required(true)
+                    .takes_value(true)
+                    .help("Room id agreed between the dealer and all parties"))
+                .arg(Arg::with_name("private_key")
+                    .long("private-key")
+                    .takes_value(true)
+                    .conflicts_with("id")
+                    .help("File with the secp256k1 private key in hex, run as the dealer"))
+                .arg(Arg::with_name("id")
+                    .long("id")
+                    .takes_value(true)
+                    .requires("out")
+                    .help("Party id to receive a share as, between 1 and the number of parties"))
+                .arg(Arg::with_name("out")
+                    .short("o")
+                    .long("out")
+                    .takes_value(true)
+                    .help("Target keys file for the share"))
+                .arg(manager_addr_arg()),
+            SubCommand::with_name("recover").about("Reconstruct the full private key from t+1 shares for disaster recovery")
+                .arg(Arg::with_name("keysfiles")
+                    .required(true)
+                    .index(1)
+                    .multiple(true)
+                    .takes_value(true)
+                    .help("Keys files of t+1 parties, or this party's keys file with --room"))
+                .arg(Arg::with_name("room")
+                    .short("r")
+                    .long("room")
+                    .takes_value(true)
+                    .requires("parties")
+                    .help("Recover over the manager in this room instead of from local files"))
+                .arg(Arg::with_name("parties")
+                    .long("parties")
+                    .takes_value(true)
+                    .help("Number of parties taking part in the recovery room"))
+                .arg(Arg::with_name("recoverer")
+                    .long("recoverer")
+                    .help("Receive the shares and print the private key, exactly one party in the room"))
+                .arg(Arg::with_name("audit")
+                    .long("audit")
+                    .takes_value(true)
+                    .default_value("recovery-audit.jsonl")
+                    .help("Audit log the recovery is appended to"))
+                .arg(manager_addr_arg()),
+            SubCommand::with_name("refresh").about("Re-randomize all shares, keeping the public key and the parties")
+                .arg(Arg::with_name("keysfile")
+                    .required(true)
+                    .index(1)
+                    .takes_value(true)
+                    .help("Keys file, replaced by the new share once all parties confirmed"))
+                .arg(Arg::with_name("params")
+                    .index(2)
+                    .required(true)
+                    .takes_value(true)
+                    .help("Threshold params: threshold/parties (t+1/n). E.g. 1/3 for 2 of 3 schema."))
+                .arg(Arg::with_name("room")
+                    .short("r")
+                    .long("room")
+                    .required(true)
+                    .takes_value(true)
+                    .help("Room id agreed between all parties"))
+                .arg(Arg::with_name("daemon")
+                    .long("daemon")
+                    .help("Keep running and refresh once per interval, retrying failed refreshes"))
+                .arg(Arg::with_name("interval")
+                    .long("interval")
+                    .takes_value(true)
+                    .default_value("30d")
+                    .help("Time between scheduled refreshes, e.g. 30d, 12h, 15m"))
+                .arg(Arg::with_name("retry")
+                    .long("retry")
+                    .takes_value(true)
+                    .default_value("10m")
+                    .help("Time between attempts of a failed scheduled refresh"))
+                .arg(manager_addr_arg()),
+            SubCommand::with_name("revoke-party").about("Re-randomize the remaining shares so a revoked party's share becomes useless")
+                .arg(Arg::with_name("keysfile")
+                    .required(true)
+                    .index(1)
+                    .takes_value(true)
+                    .help("Keys file of a remaining party, replaced by the new share"))
+                .arg(Arg::with_name("params")
+                    .index(2)
+                    .required(true)
+                    .takes_value(true)
+                    .help("Current threshold params: threshold/parties (t+1/n). E.g. 1/3 for 2 of 3 schema."))
+                .arg(Arg::with_name("revoke")
+                    .long("revoke")
+                    .required(true)
+                    .takes_value(true)
+                    .help("Party id to revoke"))
+                .arg(Arg::with_name("room")
+                    .short("r")
+                    .long("room")
+                    .required(true)
+                    .takes_value(true)
+                    .help("Room id agreed between all remaining parties"))
+                .arg(manager_addr_arg()),
+            SubCommand::with_name("preflight").about("Check compatibility between co-signers before a ceremony")
+                .arg(Arg::with_name("keysfile")
+                    .required(true)
+                    .index(1)
+                    .takes_value(true)
+                    .help("Keys file"))
+                .arg(Arg::with_name("params")
+                    .index(2)
+                    .required(true)
+                    .takes_value(true)
+                    .help("Threshold params: threshold/parties (t+1/n). E.g. 1/3 for 2 of 3 schema."))
+                .arg(Arg::with_name("room")
+                    .short("r")
+                    .long("room")
+                    .required(true)
                     .takes_value(true)
-                    .help("URL to manager"))
-        ])
-        .get_matches();
+                    .help("Room id agreed between the intended parties"))
+                .arg(manager_addr_arg())
+        ]);
+    // Selftest runs parties against an in-process manager, it needs both roles
+    #[cfg(all(feature = "signer", feature = "manager"))]
+    let app = app.subcommand(SubCommand::with_name("selftest")
+        .about("Run keygen and signing ceremonies in-process and report pass/fail per case")
+        .arg(Arg::with_name("quick")
+            .long("quick")
+            .help("Run the 1/2 cases only")));
+    let matches = app.get_matches();
 
     match matches.subcommand() {
-        ("pubkey", Some(sub_matches)) | ("sign", Some(sub_matches)) => {
-            let keysfile_path = sub_matches.value_of("keysfile").unwrap_or("");
+        #[cfg(feature = "manager")]
+        ("manager", Some(_matches)) => {
+            manager::run_manager();
+        }
+        #[cfg(all(feature = "signer", feature = "manager"))]
+        ("selftest", Some(sub_matches)) => {
+            if !selftest::run_selftest(sub_matches.is_present("quick")) {
+                std::process::exit(1);
+            }
+        }
+        #[cfg(feature = "signer")]
+        _ => run_signer(&matches),
+        #[cfg(not(feature = "signer"))]
+        _ => {}
+    }
+}
+
+
+#[cfg(feature = "signer")]
+fn run_signer(matches: &ArgMatches) {
+    // Clients, worker pools and storage backends are created deep inside the commands, they read the options
+    options::init(options::Options::from_matches(matches));
+    chain_code::check_configured().unwrap_or_else(|e| e.exit());
+
+    match matches.subcommand() {
+        ("pubkey", Some(sub_matches)) | ("address", Some(sub_matches)) | ("sign", Some(sub_matches)) | ("sign-artifact", Some(sub_matches)) | ("sign-csr", Some(sub_matches))
+        | ("sign-eth-tx", Some(sub_matches)) | ("sign-cosmos", Some(sub_matches)) | ("sign-xrp-tx", Some(sub_matches))
+        | ("sign-jwt", Some(sub_matches)) | ("sign-cose", Some(sub_matches)) | ("preflight", Some(sub_matches)) | ("presign", Some(sub_matches))
+        | ("presign-pool", Some(sub_matches)) => {
+            let (keysfile_path, positionals) = keysfile_args(sub_matches, &["params", "message"], key_dir::existing_key_path)
+                    .unwrap_or_else(|e| e.exit());
+            let keysfile_path = keysfile_path.as_str();
+            // The argument after the keys file is always the params. sign reads them from the keys file when they are
+            // left out, the message is then given with --message or --message-file
+            let params_arg = positionals[0];
+            let message_arg = sub_matches.value_of("message_option").or(positionals[1]);
+            // Only one command at a time may use the share, pubkey and address read public data only
+            let _lock = match matches.subcommand_name() {
+                Some("pubkey") | Some("address") | None => KeysFileLock::shared(keysfile_path),
+                Some(command) => KeysFileLock::acquire(keysfile_path, command),
+            };
 
             // Read data from keys file
-            let data = fs::read_to_string(keysfile_path).expect(
-                format!("Unable to load keys file at location: {}", keysfile_path).as_str(),
-            );
-            let (party_keys, shared_keys, party_id, mut vss_scheme_vec, paillier_key_vector, y_sum): (
-                Keys,
-                SharedKeys,
-                u16,
-                Vec<VerifiableSS<GE>>,
-                Vec<EncryptionKey>,
-                GE,
-            ) = serde_json::from_str(&data).unwrap();
+            let keyfile = KeyFile::load(keysfile_path);
+            if !matches!(matches.subcommand_name(), Some("pubkey") | Some("address")) {
+                keyfile.check_signable(keysfile_path);
+            }
+            let KeyFile {
+                party_keys,
+                shared_keys,
+                party_id,
+                mut vss_scheme_vec,
+                paillier_key_vec,
+                y_sum,
+                chain_code,
+                ..
+            } = keyfile;
+            let metadata = KeyMetadata::new(&vss_scheme_vec, &y_sum, chain_code.as_deref());
 
             // Get root pub key or HD pub key at specified path
+            let root_y_sum = y_sum;
             let path = sub_matches.value_of("path").unwrap_or("");
             let (f_l_new, y_sum) = match path.is_empty() {
                 true => (ECScalar::zero(), y_sum),
                 false => call_hd_key(path, y_sum)
             };
+            let sign_at_path = !path.is_empty();
 
             // Return pub key as x,y
-            if let Some(_sub_matches) = matches.subcommand_matches("pubkey") {
-                let ret_dict = json!({
+            if let (Some(sub_matches), Some(paths)) = (matches.subcommand_matches("pubkey"), sub_matches.value_of("paths")) {
+                let format = sub_matches.value_of("format");
+                let keys: Vec<Value> = hd_keys::expand_paths(paths)
+                    .unwrap_or_else(|e| e.exit())
+                    .iter()
+                    .map(|path| {
+                        let (_, y_child) = call_hd_key(path, root_y_sum);
+                        let mut key = json!({"path": path, "x": &y_child.x_coor(), "y": &y_child.y_coor()});
+                        if let Some(format) = format {
+                            key["public_key"] = encoding::formatted_public_key(&y_child, format);
+                        }
+                        key
+                    })
+                    .collect();
+                let mut ret_dict = json!({
+                    "keys": keys,
+                    "threshold": metadata.threshold,
+                    "parties": metadata.parties,
+                    "fingerprint": metadata.fingerprint,
+                });
+                if let Some(format) = format {
+                    ret_dict["format"] = json!(format);
+                }
+                if let Some(chain_code) = chain_code::chain_code() {
+                    ret_dict["chain_code_fingerprint"] = json!(chain_code::fingerprint(&chain_code));
+                }
+                println!("{}", ret_dict.to_string());
+            } else if let Some(sub_matches) = matches.subcommand_matches("pubkey") {
+                let mut ret_dict = json!({
                     "x": &y_sum.x_coor(),
                     "y": &y_sum.y_coor(),
                     "path": path,
+                    "threshold": metadata.threshold,
+                    "parties": metadata.parties,
+                    "fingerprint": metadata.fingerprint,
                 });
+                if let Some(format) = sub_matches.value_of("format") {
+                    ret_dict["format"] = json!(format);
+                    ret_dict["public_key"] = encoding::formatted_public_key(&y_sum, format);
+                }
+                if let Some(chain_code) = chain_code::chain_code() {
+                    ret_dict["chain_code_fingerprint"] = json!(chain_code::fingerprint(&chain_code));
+                }
+                if let Some(merkle_root) = sub_matches.value_of("taproot_tweak") {
+                    let merkle_root = hex::decode(merkle_root).unwrap_or_else(|_| {
+                        clap::Error::with_description(
+                            &format!("Invalid taproot merkle root '{}', expected hex", merkle_root),
+                            clap::ErrorKind::InvalidValue,
+                        )
+                        .exit()
+                    });
+                    let (output_key, odd_y) = taproot::taproot_output_key(&y_sum, &merkle_root);
+                    ret_dict["taproot_output_key"] = json!(hex::encode(output_key));
+                    ret_dict["taproot_output_key_parity"] = json!(odd_y as u8);
+                }
+                println!("{}", ret_dict.to_string());
+            } else if let Some(sub_matches) = matches.subcommand_matches("address") {
+                let chain = sub_matches.value_of("chain").unwrap();
+                let (address_type, network) = (sub_matches.value_of("address_type").unwrap(), sub_matches.value_of("network").unwrap());
+                let prefix = sub_matches.value_of("prefix").unwrap();
+                let address = |y_sum: &GE| match chain {
+                    "eth" => ethereum::address(y_sum),
+                    "btc" => bitcoin::address(y_sum, address_type, network),
+                    "cosmos" => cosmos::address(y_sum, prefix),
+                    "xrp" => xrp::address(y_sum),
+                    _ => clap::Error::with_description(&format!("Unknown chain {}", chain), clap::ErrorKind::InvalidValue)
+                        .exit(),
+                };
+                let mut ret_dict = match sub_matches.value_of("paths") {
+                    Some(paths) => {
+                        let addresses: Vec<Value> = hd_keys::expand_paths(paths)
+                            .unwrap_or_else(|e| e.exit())
+                            .iter()
+                            .map(|path| json!({"path": path, "address": address(&call_hd_key(path, root_y_sum).1)}))
+                            .collect();
+                        json!({"addresses": addresses})
+                    }
+                    None => json!({"address": address(&y_sum), "path": path}),
+                };
+                match chain {
+                    "btc" => {
+                        ret_dict["address_type"] = json!(address_type);
+                        ret_dict["network"] = json!(network);
+                    }
+                    "cosmos" => ret_dict["prefix"] = json!(prefix),
+                    _ => {}
+                }
+                ret_dict["chain"] = json!(chain);
                 println!("{}", ret_dict.to_string());
-            } else if let Some(sub_matches) = matches.subcommand_matches("sign") {
+            } else if let ("sign", Some(sub_matches)) | ("sign-artifact", Some(sub_matches))
+                | ("sign-csr", Some(sub_matches)) | ("sign-eth-tx", Some(sub_matches))
+                | ("sign-cosmos", Some(sub_matches)) | ("sign-xrp-tx", Some(sub_matches))
+                | ("sign-jwt", Some(sub_matches)) | ("sign-cose", Some(sub_matches)) = matches.subcommand() {
+                let request_info = sub_matches
+                    .value_of("subject")
+                    .map(|subject| csr::certification_request_info(subject, &y_sum));
+                let (eth_tx, xrp_tx) = match (matches.subcommand_name(), sub_matches.value_of("transaction")) {
+                    (Some("sign-xrp-tx"), Some(transaction)) => (None, Some(xrp::XrpTransaction::load(transaction, &y_sum))),
+                    (_, transaction) => (transaction.map(ethereum::EthTransaction::load), None),
+                };
+                let sign_doc = sub_matches.value_of("sign_doc").map(cosmos::SignDoc::load);
+                let token = sub_matches.value_of("claims").map(|claims| jwt::Jwt::load(claims, sub_matches.value_of("header")));
+                let cose_sign1 = sub_matches
+                    .value_of("payload")
+                    .map(|payload| cose::CoseSign1::load(payload, sub_matches.value_of("protected"), sub_matches.value_of("unprotected")));
                 // Parse message to sign
-                let message_str = sub_matches.value_of("message").unwrap_or("");
-                let message = match hex::decode(message_str.clone()) {
-                    Ok(x) => x,
-                    Err(_e) => message_str.as_bytes().to_vec(),
+                let message = match (sub_matches.value_of("artifact"), &request_info) {
+                    (Some(artifact_path), _) => artifact::artifact_digest(artifact_path),
+                    (_, Some(request_info)) => csr::csr_digest(request_info),
+                    _ if eth_tx.is_some() => eth_tx.as_ref().unwrap().signing_hash(),
+                    _ if sign_doc.is_some() => sign_doc.as_ref().unwrap().digest(),
+                    _ if xrp_tx.is_some() => xrp_tx.as_ref().unwrap().signing_hash(),
+                    _ if token.is_some() => token.as_ref().unwrap().digest(),
+                    _ if cose_sign1.is_some() => cose_sign1.as_ref().unwrap().digest(),
+                    _ if sub_matches.value_of("eip712").is_some() => {
+                        ethereum::TypedData::load(sub_matches.value_of("eip712").unwrap()).digest()
+                    }
+                    _ => {
+                        let message = match (sub_matches.value_of("message_file"), message_arg) {
+                            (Some(message_file), _) | (None, Some(message_file @ "-")) => message_hash::read_message_file(message_file),
+                            (None, Some(message_str)) => match hex::decode(message_str.clone()) {
+                                Ok(x) => x,
+                                Err(_e) => message_str.as_bytes().to_vec(),
+                            },
+                            (None, None) => clap::Error::with_description(
+                                "Message to sign missing",
+                                clap::ErrorKind::MissingRequiredArgument,
+                            )
+                            .exit(),
+                        };
+                        message_hash::hash_message(&message, sub_matches.value_of("hash").unwrap(), sub_matches.is_present("strict_digest"))
+                            .unwrap_or_else(|e| e.exit())
+                    }
                 };
                 let message = &message[..];
-                let manager_addr = sub_matches
-                    .value_of("manager_addr")
-                    .unwrap_or("http://127.0.0.1:8001")
-                    .to_string();
-
-                // Parse threshold params
-                let params: Vec<&str> = sub_matches
-                    .value_of("params")
-                    .unwrap_or("")
-                    .split("/")
-                    .collect();
-                //            println!("sign me {:?} / {:?} / {:?}", manager_addr, message, params);
-                let params = Params {
-                    threshold: params[0].to_string(),
-                    parties: params[1].to_string(),
+                let manager_addr = manager_addr(sub_matches);
+
+                // Threshold params of the keys file, params given on the command line override them
+                let params = match params_arg {
+                    Some(params_arg) => {
+                        let params = parse_params(params_arg).unwrap_or_else(|e| e.exit());
+                        if params.threshold != metadata.threshold.to_string() || params.parties != metadata.parties.to_string() {
+                            eprintln!(
+                                "{}",
+                                json!({
+                                    "status": "params_override",
+                                    "params": params_arg,
+                                    "stored_params": format!("{}/{}", metadata.threshold, metadata.parties),
+                                })
+                                .to_string()
+                            );
+                        }
+                        params
+                    }
+                    None => metadata.params(),
                 };
-                signer::sign(
-                    manager_addr,
+                let context = sub_matches.value_of("context").unwrap_or("");
+                let signers = parse_signers(sub_matches.value_of("signers"), &params).unwrap_or_else(|e| e.exit());
+                let duplicate_guard = DuplicateGuard::from_str(sub_matches.value_of("duplicate_guard").unwrap());
+                let signing_target = duplicates::SigningTarget::new(&root_y_sum, path, &message);
+                duplicates::check_duplicate(duplicate_guard, &manager_addr, keysfile_path, &signing_target);
+                if let Some(approver) = approval::configured(party_id, keysfile_path) {
+                    approver.approve(approver.request(&y_sum, path, &message, context));
+                }
+                let sig = signer::sign(
+                    manager_addr.clone(),
                     party_keys,
                     shared_keys,
                     party_id,
                     &mut vss_scheme_vec,
-                    paillier_key_vector,
+                    paillier_key_vec,
                     &y_sum,
                     &params,
                     &message,
                     &f_l_new,
-                    !path.is_empty(),
+                    sign_at_path,
+                    signers,
+                    context,
+                );
+                if duplicate_guard != DuplicateGuard::Off {
+                    duplicates::record_signing(&manager_addr, keysfile_path, &signing_target, &sig);
+                }
+                match (sub_matches.value_of("artifact"), &request_info) {
+                    (Some(artifact_path), _) => artifact::write_artifact_signature(artifact_path, &sig, &y_sum, context),
+                    (_, Some(request_info)) => {
+                        let csr_path = sub_matches.value_of("out").unwrap_or("request.csr");
+                        csr::write_csr(csr_path, request_info, &sig, context)
+                    }
+                    _ if eth_tx.is_some() => {
+                        println!("{}", ethereum::signed_transaction_json(eth_tx.as_ref().unwrap(), &sig, context).to_string())
+                    }
+                    _ if cose_sign1.is_some() => {
+                        println!("{}", cose::signed_cose_json(cose_sign1.as_ref().unwrap(), &sig, context).to_string())
+                    }
+                    _ if token.is_some() => println!("{}", jwt::signed_jwt_json(token.as_ref().unwrap(), &sig, context).to_string()),
+                    _ if xrp_tx.is_some() => {
+                        println!("{}", xrp::signed_transaction_json(xrp_tx.as_ref().unwrap(), &sig, &y_sum, context).to_string())
+                    }
+                    _ if sign_doc.is_some() => {
+                        let prefix = sub_matches.value_of("prefix").unwrap();
+                        println!("{}", cosmos::signed_sign_doc_json(sign_doc.as_ref().unwrap(), &sig, &y_sum, prefix, context).to_string())
+                    }
+                    _ => println!("{}", signer::signature_json(&sig, &y_sum, &message, context).to_string()),
+                }
+            } else if let Some(sub_matches) = matches.subcommand_matches("preflight") {
+                let manager_addr = manager_addr(sub_matches);
+                let room = sub_matches.value_of("room").unwrap_or("").to_string();
+                let params = parse_params(sub_matches.value_of("params").unwrap_or("")).unwrap_or_else(|e| e.exit());
+                preflight::run_preflight(&manager_addr, &room, party_id, &params, &metadata, &y_sum);
+            } else if let Some(sub_matches) = matches.subcommand_matches("presign") {
+                let manager_addr = manager_addr(sub_matches);
+                let label = sub_matches.value_of("label").unwrap_or("");
+                let params = parse_params(sub_matches.value_of("params").unwrap_or("")).unwrap_or_else(|e| e.exit());
+                let signers = parse_signers(sub_matches.value_of("signers"), &params).unwrap_or_else(|e| e.exit());
+                let mut presig = signer::presign(
+                    manager_addr,
+                    party_keys,
+                    shared_keys,
+                    party_id,
+                    &mut vss_scheme_vec,
+                    paillier_key_vec,
+                    &y_sum,
+                    &params,
+                    label,
+                    &f_l_new,
+                    sign_at_path,
+                    signers,
+                    sub_matches.value_of("context").unwrap_or(""),
+                );
+                presig.path = path.to_string();
+                presig.keysfile = keysfile_path.to_string();
+                presig.key_fingerprint = common::keyfile::fingerprint(&root_y_sum);
+                let presig_path = match sub_matches.value_of("out") {
+                    Some(out) => out.to_string(),
+                    None => presign::presignature_path(keysfile_path, label),
+                };
+                println!("{}", presign::write_presignature(&presig_path, &presig).to_string());
+            }
+            // The pool runs presigning rooms on its own manager, it is only built with both roles
+            #[cfg(all(feature = "signer", feature = "manager"))]
+            if let Some(sub_matches) = matches.subcommand_matches("presign-pool") {
+                let manager_addr = manager_addr(sub_matches);
+                let params = parse_params(sub_matches.value_of("params").unwrap_or("")).unwrap_or_else(|e| e.exit());
+                let signers = parse_signers(sub_matches.value_of("signers"), &params).unwrap_or_else(|e| e.exit());
+                let size = parse_number::<u16>(sub_matches, "size").unwrap_or_else(|e| e.exit());
+                let port = parse_number::<u16>(sub_matches, "port").unwrap_or_else(|e| e.exit());
+                let approver = approval::configured(party_id, keysfile_path);
+                let presign_pool =
+                    pool::PresignPool::new(
+                    keysfile_path,
+                    &y_sum,
+                    party_id,
+                    &params,
+                    signers,
+                    size,
+                    manager_addr,
+                    approver,
+                    sub_matches.value_of("context").unwrap_or(""),
+                );
+                pool::run_pool(
+                    keysfile_path.to_string(),
+                    params,
+                    path.to_string(),
+                    f_l_new,
+                    y_sum,
+                    sign_at_path,
+                    signers,
+                    presign_pool,
+                    port,
+                ).expect("Presignature pool failed");
+            }
+        }
+        ("sign-online", Some(sub_matches)) => {
+            let manager_addr = manager_addr(sub_matches);
+            let presig_path = sub_matches.value_of("presignature").unwrap_or("");
+            let message_str = sub_matches.value_of("message").unwrap_or("");
+            let message = match hex::decode(message_str.clone()) {
+                Ok(x) => x,
+                Err(_e) => message_str.as_bytes().to_vec(),
+            };
+            let message = message_hash::hash_message(&message, sub_matches.value_of("hash").unwrap(), sub_matches.is_present("strict_digest"))
+                .unwrap_or_else(|e| e.exit());
+            let duplicate_guard = DuplicateGuard::from_str(sub_matches.value_of("duplicate_guard").unwrap());
+            let ret_dict = presign::sign_with_presignature(
+                manager_addr,
+                presig_path,
+                &message,
+                sub_matches.value_of("context"),
+                None,
+                duplicate_guard,
+            );
+            println!("{}", ret_dict.to_string());
+        }
+        ("healthcheck", Some(sub_matches)) => {
+            let addr = manager_addr(sub_matches);
+            let keysfile_path = sub_matches.value_of("keysfile").unwrap_or("");
+            let params = parse_params(sub_matches.value_of("params").unwrap()).unwrap_or_else(|e| e.exit());
+            let signers = parse_signers(sub_matches.value_of("signers"), &params).unwrap_or_else(|e| e.exit());
+            let _lock = KeysFileLock::acquire(keysfile_path, "healthcheck");
+            healthcheck::run_healthcheck(&addr, keysfile_path, &params, signers, sub_matches.value_of("nonce").unwrap());
+        }
+        ("sign-psbt", Some(sub_matches)) => {
+            let addr = manager_addr(sub_matches);
+            let keysfile_path = sub_matches.value_of("keysfile").unwrap();
+            let params = parse_params(sub_matches.value_of("params").unwrap()).unwrap_or_else(|e| e.exit());
+            let signers = parse_signers(sub_matches.value_of("signers"), &params).unwrap_or_else(|e| e.exit());
+            let _lock = KeysFileLock::acquire(keysfile_path, "sign-psbt");
+            let keyfile = KeyFile::load(keysfile_path);
+            let approver = approval::configured(keyfile.party_id, keysfile_path);
+            psbt::sign_psbt(
+                &addr,
+                keysfile_path,
+                keyfile,
+                &params,
+                sub_matches.value_of("psbt").unwrap(),
+                sub_matches.value_of("path").unwrap_or(""),
+                sub_matches.value_of("output"),
+                signers,
+                sub_matches.value_of("context").unwrap_or(""),
+                approver.as_ref(),
+                DuplicateGuard::from_str(sub_matches.value_of("duplicate_guard").unwrap()),
+            );
+        }
+        ("doctor", Some(sub_matches)) => {
+            let addr = manager_addr(sub_matches);
+            doctor::run_doctor(&addr);
+        }
+        ("manager-bench", Some(sub_matches)) => {
+            let addr = manager_addr(sub_matches);
+            let rooms = parse_number::<u16>(sub_matches, "rooms").unwrap_or_else(|e| e.exit());
+            let parties = parse_number::<u16>(sub_matches, "parties").unwrap_or_else(|e| e.exit());
+            let rounds = parse_number::<u16>(sub_matches, "rounds").unwrap_or_else(|e| e.exit());
+            let payload_size = parse_number::<usize>(sub_matches, "payload_size").unwrap_or_else(|e| e.exit());
+            bench::run_bench(&addr, rooms, parties, rounds, payload_size);
+        }
+        ("reshare", Some(sub_matches)) => {
+            let addr = manager_addr(sub_matches);
+            let room = sub_matches.value_of("room").unwrap_or("");
+            let participants = parse_number::<u16>(sub_matches, "participants").unwrap_or_else(|e| e.exit());
+            let _lock = sub_matches
+                .value_of("keysfile")
+                .map(|keysfile_path| KeysFileLock::acquire(keysfile_path, "reshare"));
+            let old_share = sub_matches.value_of("keysfile").map(|keysfile_path| reshare::OldShare {
+                keyfile: KeyFile::load(keysfile_path),
+                params: parse_params(sub_matches.value_of("params").unwrap()).unwrap_or_else(|e| e.exit()),
+            });
+            let new_party_id = sub_matches
+                .value_of("new_id")
+                .map(|_| parse_number::<u16>(sub_matches, "new_id").unwrap_or_else(|e| e.exit()));
+            let new_params = Params {
+                threshold: sub_matches.value_of("new_threshold").unwrap().to_string(),
+                parties: sub_matches.value_of("new_parties").unwrap().to_string(),
+            };
+            let result = reshare::run_reshare(&addr, room, participants, old_share, new_party_id, &new_params);
+            reshare::save_reshare_result(&addr, result, sub_matches.value_of("out"), &new_params);
+        }
+        ("add-party", Some(sub_matches)) => {
+            let addr = manager_addr(sub_matches);
+            let keysfile_path = sub_matches.value_of("keysfile").unwrap_or("");
+            let params = parse_params(sub_matches.value_of("params").unwrap()).unwrap_or_else(|e| e.exit());
+            let room = sub_matches.value_of("room").unwrap_or("");
+            reshare::add_party(&addr, keysfile_path, &params, room, sub_matches.is_present("join"));
+        }
+        ("import-key", Some(sub_matches)) => {
+            let addr = manager_addr(sub_matches);
+            let params = parse_params(sub_matches.value_of("params").unwrap()).unwrap_or_else(|e| e.exit());
+            let room = sub_matches.value_of("room").unwrap_or("");
+            let party_id = sub_matches
+                .value_of("id")
+                .map(|_| parse_number::<u16>(sub_matches, "id").unwrap_or_else(|e| e.exit()));
+            import::import_key(
+                &addr,
+                room,
+                &params,
+                sub_matches.value_of("private_key"),
+                party_id,
+                sub_matches.value_of("out"),
+            );
+        }
+        ("recover", Some(sub_matches)) => {
+            let keysfile_paths: Vec<&str> = sub_matches.values_of("keysfiles").unwrap().collect();
+            let _locks: Vec<KeysFileLock> = keysfile_paths.iter().map(|path| KeysFileLock::shared(path)).collect();
+            let audit_path = sub_matches.value_of("audit").unwrap();
+            match sub_matches.value_of("room") {
+                Some(room) => {
+                    if keysfile_paths.len() != 1 {
+                        clap::Error::with_description(
+                            "Recovery over the manager takes exactly one keys file",
+                            clap::ErrorKind::WrongNumberOfValues,
+                        )
+                        .exit();
+                    }
+                    let addr = manager_addr(sub_matches);
+                    let parties = parse_number::<u16>(sub_matches, "parties").unwrap_or_else(|e| e.exit());
+                    recover::recover_over_manager(
+                        &addr,
+                        keysfile_paths[0],
+                        room,
+                        parties,
+                        sub_matches.is_present("recoverer"),
+                        audit_path,
+                    );
+                }
+                None => recover::recover_from_files(keysfile_paths, audit_path),
+            }
+        }
+        ("refresh", Some(sub_matches)) => {
+            let addr = manager_addr(sub_matches);
+            let keysfile_path = sub_matches.value_of("keysfile").unwrap_or("");
+            let params = parse_params(sub_matches.value_of("params").unwrap()).unwrap_or_else(|e| e.exit());
+            let room = sub_matches.value_of("room").unwrap_or("");
+            if sub_matches.is_present("daemon") {
+                let interval = refresh::parse_duration(sub_matches.value_of("interval").unwrap());
+                let retry = refresh::parse_duration(sub_matches.value_of("retry").unwrap());
+                refresh::run_refresh_daemon(&addr, keysfile_path, &params, room, interval, retry);
+            } else {
+                reshare::refresh(&addr, keysfile_path, &params, room);
+            }
+        }
+        ("revoke-party", Some(sub_matches)) => {
+            let addr = manager_addr(sub_matches);
+            let keysfile_path = sub_matches.value_of("keysfile").unwrap_or("");
+            let params = parse_params(sub_matches.value_of("params").unwrap()).unwrap_or_else(|e| e.exit());
+            let room = sub_matches.value_of("room").unwrap_or("");
+            let revoked_party_id = parse_number::<u16>(sub_matches, "revoke").unwrap_or_else(|e| e.exit());
+            reshare::revoke_party(&addr, keysfile_path, &params, room, revoked_party_id);
+        }
+        ("export-public", Some(sub_matches)) => {
+            let keysfile_path = sub_matches.value_of("keysfile").unwrap_or("");
+            let _lock = KeysFileLock::shared(keysfile_path);
+            export::export_public(keysfile_path, sub_matches.value_of("out"));
+        }
+        ("xpub", Some(sub_matches)) => {
+            let keysfile_path = sub_matches.value_of("keysfile").unwrap_or("");
+            let _lock = KeysFileLock::shared(keysfile_path);
+            let keyfile = KeyFile::load(keysfile_path);
+            let path = sub_matches.value_of("path").unwrap_or("");
+            let version = sub_matches.value_of("xpub_version").unwrap();
+            // children of an xpub are slip10 children, signing with tss-additive would sign for other keys
+            if hd_keys::derivation() != "slip10" {
+                clap::Error::with_description(
+                    "xpub exports the key for slip10 derivation, give --derivation slip10 to sign for its children",
+                    clap::ErrorKind::MissingRequiredArgument,
                 )
+                .exit();
             }
+            let chain_code = chain_code::chain_code()
+                .ok_or_else(|| {
+                    clap::Error::with_description(
+                        "xpub needs the chain code of the key, stored at keygen or given with --chain-code or \
+                         --chain-code-mnemonic",
+                        clap::ErrorKind::MissingRequiredArgument,
+                    )
+                })
+                .unwrap_or_else(|e| e.exit());
+            let version_bytes = xpub::version_bytes(version).unwrap_or_else(|e| e.exit());
+            let extended_key =
+                xpub::extended_public_key(&keyfile.y_sum, path, version_bytes).unwrap_or_else(|e| e.exit());
+            println!(
+                "{}",
+                json!({
+                    "xpub": extended_key,
+                    "path": path,
+                    "version": version,
+                    "derivation": "slip10",
+                    "fingerprint": keyfile.metadata().fingerprint,
+                    "chain_code_fingerprint": chain_code::fingerprint(&chain_code),
+                })
+                .to_string()
+            );
         }
-        ("manager", Some(_matches)) => {
-            manager::run_manager();
+        ("verify-share", Some(sub_matches)) => {
+            let keysfile_path = sub_matches.value_of("keysfile").unwrap_or("");
+            let _lock = KeysFileLock::shared(keysfile_path);
+            verify_share::verify_share(keysfile_path);
+        }
+        ("keys-version", Some(sub_matches)) => {
+            let keysfile_path = sub_matches.value_of("keysfile").unwrap_or("");
+            let _lock = match sub_matches.is_present("upgrade") {
+                true => KeysFileLock::acquire(keysfile_path, "keys-version"),
+                false => KeysFileLock::shared(keysfile_path),
+            };
+            match sub_matches.value_of("downgrade") {
+                Some(version) => migrations::downgrade(
+                    keysfile_path,
+                    parse_number::<u32>(sub_matches, "downgrade").unwrap_or_else(|e| e.exit()),
+                    sub_matches.value_of("out").unwrap(),
+                ),
+                None => migrations::keys_version(keysfile_path, sub_matches.is_present("upgrade")),
+            }
+        }
+        ("schema", Some(sub_matches)) => {
+            if let Some(dump_matches) = sub_matches.subcommand_matches("dump") {
+                schema::dump(dump_matches.value_of("name"));
+            }
+        }
+        ("compare-keys", Some(sub_matches)) => {
+            let first = sub_matches.value_of("first").unwrap_or("");
+            let second = sub_matches.value_of("second").unwrap_or("");
+            let _locks = (KeysFileLock::shared(first), KeysFileLock::shared(second));
+            compare::compare_keys(first, second);
+        }
+        ("observe", Some(sub_matches)) => {
+            let addr = manager_addr(sub_matches);
+            let room_id = sub_matches.value_of("room_id").unwrap_or("").to_string();
+            observer::observe_room(&addr, &room_id);
         }
         ("keygen", Some(sub_matches)) => {
-            let addr = sub_matches
-                .value_of("manager_addr")
-                .unwrap_or("http://127.0.0.1:8001")
-                .to_string();
-            let keysfile_path = sub_matches.value_of("keysfile").unwrap_or("").to_string();
-
-            let params: Vec<&str> = sub_matches
-                .value_of("params")
-                .unwrap_or("")
-                .split("/")
-                .collect();
-            keygen::run_keygen(&addr, &keysfile_path, &params);
+            let addr = manager_addr(sub_matches);
+            let (keysfile_path, positionals) = keysfile_args(sub_matches, &["params"], key_dir::new_key_path).unwrap_or_else(|e| e.exit());
+            let _lock = KeysFileLock::acquire(&keysfile_path, "keygen");
+
+            let params_arg = positionals[0].unwrap_or_else(|| {
+                clap::Error::with_description("Threshold params missing", clap::ErrorKind::MissingRequiredArgument).exit()
+            });
+            parse_params(params_arg).unwrap_or_else(|e| e.exit());
+            let params: Vec<&str> = params_arg.split("/").collect();
+            if sub_matches.is_present("encrypt") {
+                key_encryption::new_passphrase();
+            }
+            if sub_matches.is_present("keychain") {
+                keychain::use_keychain();
+            }
+            if let Some(key_label) = sub_matches.value_of("hsm_key_label") {
+                hsm::use_hsm(Some(hsm::hsm_key_from_options(key_label)));
+            }
+            keygen::run_keygen(&addr, &keysfile_path, &params, sub_matches.is_present("canary"));
+        }
+        ("encrypt-keys", Some(sub_matches)) => {
+            let keysfile_path = sub_matches.value_of("keysfile").unwrap_or("");
+            let _lock = KeysFileLock::acquire(keysfile_path, "encrypt-keys");
+            let keyfile = KeyFile::load(keysfile_path);
+            key_encryption::new_passphrase();
+            keyfile.replace(keysfile_path);
+            println!("{}", json!({"status": "keys_encrypted", "keysfile": keysfile_path}).to_string());
+        }
+        ("hsm", Some(sub_matches)) => match sub_matches.subcommand() {
+            ("wrap", Some(wrap_matches)) => hsm::wrap_keys_file(
+                wrap_matches.value_of("keysfile").unwrap(),
+                hsm::hsm_key_from_options(wrap_matches.value_of("key_label").unwrap()),
+            ),
+            ("unwrap", Some(unwrap_matches)) => hsm::unwrap_keys_file(unwrap_matches.value_of("keysfile").unwrap()),
+            _ => {}
+        },
+        ("keychain", Some(sub_matches)) => match sub_matches.subcommand() {
+            ("store", Some(store_matches)) => keychain::move_to_keychain(store_matches.value_of("keysfile").unwrap()),
+            ("restore", Some(restore_matches)) => keychain::move_from_keychain(restore_matches.value_of("keysfile").unwrap()),
+            _ => {}
+        },
+        ("key", Some(sub_matches)) => match sub_matches.subcommand() {
+            ("list", Some(_)) => key_dir::list(),
+            ("show", Some(show_matches)) => key_dir::show(show_matches.value_of("name").unwrap()),
+            ("rename", Some(rename_matches)) => key_dir::rename(
+                rename_matches.value_of("name").unwrap(),
+                rename_matches.value_of("new_name").unwrap(),
+            ),
+            ("delete", Some(delete_matches)) => {
+                key_dir::delete(delete_matches.value_of("name").unwrap(), delete_matches.is_present("yes"))
+            }
+            _ => {}
+        },
+        ("keystore", Some(sub_matches)) => match sub_matches.subcommand() {
+            ("export", Some(export_matches)) => keystore::export(
+                export_matches.value_of("keysfile").unwrap(),
+                export_matches.value_of("output").unwrap(),
+            ),
+            ("import", Some(import_matches)) => keystore::import(
+                import_matches.value_of("keystore").unwrap(),
+                import_matches.value_of("output").unwrap(),
+                import_matches.is_present("plaintext"),
+            ),
+            _ => {}
+        },
+        ("verify", Some(sub_matches)) => {
+            let keysfile_path = match (sub_matches.value_of("keysfile"), sub_matches.value_of("key")) {
+                (Some(keysfile_path), _) => Some(keysfile_path.to_string()),
+                (_, Some(name)) => Some(key_dir::existing_key_path(name)),
+                _ => None,
+            };
+            let public_key = match (keysfile_path, sub_matches.value_of("public_key")) {
+                (Some(keysfile_path), _) => {
+                    let _lock = KeysFileLock::shared(&keysfile_path);
+                    Some(KeyFile::load(&keysfile_path).y_sum)
+                }
+                (None, Some(public_key)) => Some(verify::public_key_from_hex(public_key)),
+                (None, None) if sub_matches.is_present("path") => clap::Error::with_description(
+                    "--path needs the root key from --keysfile, --key or --public-key",
+                    clap::ErrorKind::MissingRequiredArgument,
+                )
+                .exit(),
+                (None, None) => None,
+            };
+            // Signatures at a path are checked against the child key, derived as sign derives it
+            let path = sub_matches.value_of("path").unwrap_or("");
+            let public_key = match path.is_empty() {
+                true => public_key,
+                false => public_key.map(|public_key| call_hd_key(path, public_key).1),
+            };
+            let message_str = sub_matches.value_of("message").unwrap();
+            let message = hex::decode(message_str).unwrap_or_else(|_| message_str.as_bytes().to_vec());
+            let message = message_hash::hash_message(&message, sub_matches.value_of("hash").unwrap(), false).unwrap_or_else(|e| e.exit());
+            if !verify::run_verify(public_key, path, &message, sub_matches.value_of("signature").unwrap()) {
+                std::process::exit(1);
+            }
+        }
+        ("import", Some(sub_matches)) => interchange::import(
+            sub_matches.value_of("format").unwrap(),
+            sub_matches.value_of("input").unwrap(),
+            sub_matches.value_of("output").unwrap(),
+        ),
+        ("export", Some(sub_matches)) => {
+            let keysfile_path = sub_matches.value_of("keysfile").unwrap();
+            let _lock = KeysFileLock::shared(keysfile_path);
+            interchange::export(
+                sub_matches.value_of("format").unwrap(),
+                keysfile_path,
+                sub_matches.value_of("output").unwrap(),
+            );
+        }
+        ("rekey", Some(sub_matches)) => {
+            let default_cost = rekey::KdfCost::default();
+            let cost = rekey::KdfCost {
+                m_cost: sub_matches
+                    .value_of("m_cost")
+                    .map_or(default_cost.m_cost, |_| parse_number::<u32>(sub_matches, "m_cost").unwrap_or_else(|e| e.exit())),
+                t_cost: sub_matches
+                    .value_of("t_cost")
+                    .map_or(default_cost.t_cost, |_| parse_number::<u32>(sub_matches, "t_cost").unwrap_or_else(|e| e.exit())),
+                p_cost: sub_matches
+                    .value_of("p_cost")
+                    .map_or(default_cost.p_cost, |_| parse_number::<u32>(sub_matches, "p_cost").unwrap_or_else(|e| e.exit())),
+            };
+            rekey::rekey(
+                sub_matches.value_of("path").unwrap(),
+                &new_passphrase(sub_matches).unwrap_or_else(|e| e.exit()),
+                cost,
+                sub_matches.is_present("remove_backup"),
+            );
+        }
+        ("rotate-passphrase", Some(sub_matches)) => {
+            rekey::rotate_passphrase(
+                sub_matches.value_of("keysfile").unwrap(),
+                &new_passphrase(sub_matches).unwrap_or_else(|e| e.exit()),
+                rekey::KdfCost::default(),
+                sub_matches.value_of("audit").unwrap(),
+            );
         }
         _ => {}
     }
 }
 
+// Arguments many commands take, defined once so their flags and help stay the same everywhere. Commands that
+// mean something more specific override the help
+#[cfg(feature = "signer")]
+fn manager_addr_arg() -> Arg<'static, 'static> {
+    Arg::with_name("manager_addr")
+        .short("a")
+        .long("addr")
+        .takes_value(true)
+        .help("URL to manager")
+}
+
+#[cfg(feature = "signer")]
+fn signers_arg() -> Arg<'static, 'static> {
+    Arg::with_name("signers")
+        .short("s")
+        .long("signers")
+        .takes_value(true)
+        .help("Number of parties taking part in signing, between t+1 and n. Defaults to t+1")
+}
+
+#[cfg(feature = "signer")]
+fn context_arg() -> Arg<'static, 'static> {
+    Arg::with_name("context")
+        .short("c")
+        .long("context")
+        .takes_value(true)
+        .help("Context label, e.g. ticket number, bound into the signing room and output")
+}
+
+#[cfg(feature = "signer")]
+fn duplicate_guard_arg() -> Arg<'static, 'static> {
+    Arg::with_name("duplicate_guard")
+        .long("duplicate-guard")
+        .takes_value(true)
+        .possible_values(&["off", "warn", "block"])
+        .default_value("warn")
+        .help("Action when the same digest was already signed under this key and path")
+}
+
+// Keys file and the positional arguments following it. With --key <name> the keys file is the named key,
+// resolved by key_path, and the positionals given move up one place as none of them is the keys file
+#[cfg(feature = "signer")]
+fn keysfile_args<'a>(
+    sub_matches: &'a ArgMatches,
+    names: &[&str],
+    key_path: fn(&str) -> String,
+) -> Result<(String, Vec<Option<&'a str>>), clap::Error> {
+    let values: Vec<Option<&str>> = names.iter().map(|name| sub_matches.value_of(name)).collect();
+    match sub_matches.value_of("key") {
+        Some(name) => {
+            if values.last().map_or(sub_matches.value_of("keysfile").is_some(), |value| value.is_some()) {
+                return Err(clap::Error::with_description(
+                    &format!("Too many arguments, --key {} replaces the keys file argument", name),
+                    clap::ErrorKind::TooManyValues,
+                ));
+            }
+            let mut shifted = vec![sub_matches.value_of("keysfile")];
+            shifted.extend(values);
+            shifted.pop();
+            Ok((key_path(name), shifted))
+        }
+        None => Ok((sub_matches.value_of("keysfile").unwrap_or("").to_string(), values)),
+    }
+}
+
+// New keys file passphrase from --new-password-file, asked on the terminal otherwise
+#[cfg(feature = "signer")]
+fn new_passphrase(sub_matches: &ArgMatches) -> Result<String, clap::Error> {
+    let new_passphrase = match sub_matches.value_of("new_password_file") {
+        Some(path) => key_encryption::read_passphrase_file(path),
+        None => key_encryption::prompt_new_passphrase(),
+    };
+    if new_passphrase.is_empty() {
+        return Err(clap::Error::with_description("Passphrase must not be empty", clap::ErrorKind::EmptyValue));
+    }
+    Ok(new_passphrase)
+}
+
+#[cfg(feature = "signer")]
+// Threshold params given as <threshold>/<parties>, malformed ones are reported like any other invalid argument
+fn parse_params(params: &str) -> Result<Params, clap::Error> {
+    match params.split('/').collect::<Vec<&str>>()[..] {
+        [threshold, parties] if threshold.parse::<u16>().is_ok() && parties.parse::<u16>().is_ok() => Ok(Params {
+            threshold: threshold.to_string(),
+            parties: parties.to_string(),
+        }),
+        _ => Err(clap::Error::with_description(
+            &format!("Invalid params '{}', expected <threshold>/<parties> e.g. 1/3", params),
+            clap::ErrorKind::InvalidValue,
+        )),
+    }
+}
+
+#[cfg(feature = "signer")]
+// Value of a numeric argument, anything else is reported like any other invalid argument
+fn parse_number<T: std::str::FromStr>(sub_matches: &ArgMatches, name: &str) -> Result<T, clap::Error> {
+    let value = sub_matches.value_of(name).unwrap_or("");
+    value.parse::<T>().map_err(|_| {
+        clap::Error::with_description(
+            &format!("Invalid value '{}' for --{}, expected a number", value, name.replace('_', "-")),
+            clap::ErrorKind::InvalidValue,
+        )
+    })
+}
+
+#[cfg(feature = "signer")]
+// --signers, between t+1 and n of the params. 0 when not given, signing then takes t+1 parties
+fn parse_signers(signers: Option<&str>, params: &Params) -> Result<u16, clap::Error> {
+    let threshold = params.threshold.parse::<u16>().unwrap();
+    let parties = params.parties.parse::<u16>().unwrap();
+    match signers.map(|signers| signers.parse::<u16>()) {
+        None | Some(Ok(0)) => Ok(0),
+        Some(Ok(signers)) if signers > threshold && signers <= parties => Ok(signers),
+        _ => Err(clap::Error::with_description(
+            &format!("Signers count must be between {} and {}", threshold + 1, parties),
+            clap::ErrorKind::InvalidValue,
+        )),
+    }
+}
+
+// Manager URL from --addr, resolved over DNS-over-HTTPS when TSS_CLI_DOH_URL is set
+#[cfg(feature = "signer")]
+fn manager_addr(sub_matches: &ArgMatches) -> String {
+    let addr = sub_matches
+        .value_of("manager_addr")
+        .unwrap_or("http://127.0.0.1:8001")
+        .to_string();
+    doh::resolve_manager_addr(addr)
+}
+
+#[cfg(feature = "signer")]
 fn call_hd_key(path: &str, public_key: GE) -> (FE, GE) {
 
-    let path_vector: Vec<BigInt> = path
-        .split('/')
-        .map(|s| BigInt::from_str_radix(s.trim(), 10).unwrap())
-        .collect();
+    let path_vector: Vec<BigInt> = hd_keys::parse_path(path).unwrap_or_else(|e| e.exit());
     let (public_key_child, f_l_new) = hd_keys::get_hd_key(&public_key, path_vector.clone());
     (f_l_new, public_key_child.clone())
 